@@ -1,13 +1,31 @@
 //! smolusb hal implementation for luna eptri peripherals
+//!
+//! # Isochronous transfers
+//!
+//! Isochronous transfers are not supported on this target. The eptri
+//! peripheral does not expose start-of-frame packets or the frame
+//! number and has a single IN FIFO shared by all endpoints, so the
+//! `IsochronousEndpoint` implementation can not meet the timing an
+//! isochronous endpoint requires:
+//!
+//! - `UsbEvent::StartOfFrame` is never raised and `frame_number()`
+//!   always returns `None`, applications have to pace their writes
+//!   themselves.
+//! - `write_isochronous()` drops the packet and returns `0` while
+//!   the IN FIFO holds a packet for another endpoint.
+//!
+//! It is only suitable for experiments where occasional dropped
+//! packets are acceptable.
 
 /// Re-export smolusb error type
 pub use smolusb::error::ErrorKind as Error;
 
 /*use smolusb::device::Speed;
+use smolusb::error::ErrorKind;
 use smolusb::setup::Direction;
 use smolusb::traits::{
-    ReadControl, ReadEndpoint, UnsafeUsbDriverOperations, UsbDriver, UsbDriverOperations,
-    WriteEndpoint,
+    AsyncUsbDriver, IsochronousEndpoint, ReadControl, ReadEndpoint, UnsafeUsbDriverOperations,
+    UsbDriver, UsbDriverOperations, WriteEndpoint,
 };
 
 use crate::pac;
@@ -123,6 +141,59 @@ macro_rules! impl_usb {
                 }
             }
 
+            // - async wakers -------------------------------------------------
+
+            impl $USBX {
+                /// Clears the in-flight flag of the given IN endpoint and
+                /// wakes the task waiting on it.
+                ///
+                /// Call from the `USBx_EP_IN` interrupt handler instead of
+                /// `clear_tx_ack_active()` when using [`AsyncUsbDriver`].
+                pub fn wake_send_complete(&self, endpoint_number: u8) {
+                    let endpoint = endpoint_number as usize;
+                    let waker = riscv::interrupt::free(|| unsafe {
+                        self.clear_tx_ack_active(endpoint_number);
+                        core::ptr::replace(core::ptr::addr_of_mut!($IDX::WAKERS_IN[endpoint]), None)
+                    });
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+
+                /// Marks a packet as received on the given OUT endpoint and
+                /// wakes the task waiting on it.
+                ///
+                /// Call from the `USBx_EP_OUT` interrupt handler when using
+                /// [`AsyncUsbDriver`]. The packet must be left in the FIFO.
+                pub fn wake_receive_packet(&self, endpoint_number: u8) {
+                    let endpoint = endpoint_number as usize;
+                    let waker = riscv::interrupt::free(|| unsafe {
+                        $IDX::RX_READY[endpoint] = true;
+                        core::ptr::replace(core::ptr::addr_of_mut!($IDX::WAKERS_OUT[endpoint]), None)
+                    });
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+
+                /// Marks a setup packet as received on the given control
+                /// endpoint and wakes the task waiting on it.
+                ///
+                /// Call from the `USBx_EP_CONTROL` interrupt handler when
+                /// using [`AsyncUsbDriver`]. The setup packet must be left
+                /// in the FIFO.
+                pub fn wake_setup_packet(&self, endpoint_number: u8) {
+                    let endpoint = endpoint_number as usize;
+                    let waker = riscv::interrupt::free(|| unsafe {
+                        $IDX::SETUP_READY[endpoint] = true;
+                        core::ptr::replace(core::ptr::addr_of_mut!($IDX::WAKERS_SETUP[endpoint]), None)
+                    });
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+
             // - trait: UsbDriverOperations -----------------------------------
 
             impl UsbDriverOperations for $USBX {
@@ -217,9 +288,9 @@ macro_rules! impl_usb {
                     self.ep_in.reset().write(|w| w.reset().bit(true));
                     self.ep_out.reset().write(|w| w.reset().bit(true));
 
-                    // clear status for all IN endpoints
+                    // clear status for all IN endpoints and wake any waiting tasks
                     for endpoint in 0..(smolusb::EP_MAX_ENDPOINTS as u8) {
-                        unsafe { self.clear_tx_ack_active(endpoint); }
+                        self.wake_send_complete(endpoint);
                     }
 
                     // re-enable interrupt events
@@ -266,7 +337,46 @@ macro_rules! impl_usb {
                     self.ep_out.stall().write(|w| w.stall().bit(true));
                 }
 
-                /// Clear the PID toggle bit for the given endpoint address.
+                /// Returns `true` if the device can enter the given USB 2.0 test mode.
+                ///
+                /// TODO the eptri gateware does not expose the PHY test
+                /// modes so, for now, only `Test_SE0_NAK` is supported.
+                fn supports_test_mode(&self, test_mode: TestMode) -> bool {
+                    matches!(test_mode, TestMode::TestSe0Nak)
+                }
+
+                /// Put the device into the given USB 2.0 test mode.
+                fn set_test_mode(&self, test_mode: TestMode) {
+                    match test_mode {
+                        TestMode::TestSe0Nak => {
+                            // stop handling events, any further IN or OUT
+                            // tokens will be NAK'd by the unprimed endpoints
+                            self.disable_events();
+                            self.ep_in.reset().write(|w| w.reset().bit(true));
+                            self.ep_out.reset().write(|w| w.reset().bit(true));
+                            self.ep_out.enable().write(|w| w.enable().bit(false));
+                        }
+                        _ => {
+                            log::warn!("UsbInterface::set_test_mode() unsupported test mode: {:?}", test_mode);
+                        }
+                    }
+                }
+
+                /// Stall the given endpoint address until the halt condition is cleared.
+                fn set_feature_endpoint_halt(&self, endpoint_number: u8, direction: Direction) {
+                    match direction {
+                        Direction::HostToDevice => { // OUT
+                            self.ep_out.epno().write(|w| unsafe { w.epno().bits(endpoint_number) });
+                            self.ep_out.stall().write(|w| w.stall().bit(true));
+                        }
+                        Direction::DeviceToHost => { // IN
+                            self.ep_in.epno().write(|w| unsafe { w.epno().bits(endpoint_number) });
+                            self.ep_in.stall().write(|w| w.stall().bit(true));
+                        }
+                    }
+                }
+
+                /// Clear the stall and PID toggle bits for the given endpoint address.
                 ///
                 /// TODO this works most of the time, but not always ...
                 ///
@@ -275,15 +385,27 @@ macro_rules! impl_usb {
                     match direction {
                         Direction::HostToDevice => { // OUT
                             self.ep_out.epno().write(|w| unsafe { w.epno().bits(endpoint_number) });
+                            self.ep_out.stall().write(|w| w.stall().bit(false));
                             self.ep_out.pid().write(|w| w.pid().bit(false));
 
                         }
                         Direction::DeviceToHost => { // IN
                             self.ep_in.epno().write(|w| unsafe { w.epno().bits(endpoint_number) });
+                            self.ep_in.stall().write(|w| w.stall().bit(false));
                             self.ep_in.pid().write(|w| w.pid().bit(false));
                         }
                     }
                 }
+
+                /// Drive resume signalling on the bus.
+                ///
+                /// The eptri peripheral does not detect bus suspend or
+                /// support driving resume signalling so this always
+                /// returns `false`.
+                fn remote_wakeup(&self) -> bool {
+                    log::warn!("UsbInterface::remote_wakeup() is not supported by the eptri peripheral");
+                    false
+                }
             }
 
             // - trait: UnsafeUsbDriverOperations -----------------------------
@@ -292,6 +414,15 @@ macro_rules! impl_usb {
             mod $IDX {
                 use lunasoc_hal::smolusb::EP_MAX_ENDPOINTS;
 
+                const NO_WAKER: Option<core::task::Waker> = None;
+
+                // async wakers and ready flags, only accessed with interrupts disabled
+                pub static mut WAKERS_IN: [Option<core::task::Waker>; EP_MAX_ENDPOINTS] = [NO_WAKER; EP_MAX_ENDPOINTS];
+                pub static mut WAKERS_OUT: [Option<core::task::Waker>; EP_MAX_ENDPOINTS] = [NO_WAKER; EP_MAX_ENDPOINTS];
+                pub static mut WAKERS_SETUP: [Option<core::task::Waker>; EP_MAX_ENDPOINTS] = [NO_WAKER; EP_MAX_ENDPOINTS];
+                pub static mut RX_READY: [bool; EP_MAX_ENDPOINTS] = [false; EP_MAX_ENDPOINTS];
+                pub static mut SETUP_READY: [bool; EP_MAX_ENDPOINTS] = [false; EP_MAX_ENDPOINTS];
+
                 #[cfg(target_has_atomic)]
                 #[allow(clippy::declare_interior_mutable_const)]
                 const ATOMIC_FALSE: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
@@ -415,7 +546,7 @@ macro_rules! impl_usb {
                 }
 
                 #[inline(always)]
-                fn read(&self, endpoint_number: u8, buffer: &mut [u8]) -> usize {
+                fn try_read(&self, endpoint_number: u8, buffer: &mut [u8]) -> Result<usize, ErrorKind> {
                     let mut bytes_read = 0;
                     let mut did_overflow = true;
                     for b in buffer.iter_mut() {
@@ -437,34 +568,34 @@ macro_rules! impl_usb {
 
                     if overflow == 0 {
                         log::trace!("  RX {} OUT {} {} bytes read", stringify!($USBX), endpoint_number, bytes_read);
+                        Ok(bytes_read)
                     } else {
                         log::warn!("  RX {} OUT {} {} bytes read + {} bytes overflow",
                               stringify!($USBX),
                               endpoint_number, bytes_read, overflow);
+                        Err(ErrorKind::Overflow(bytes_read + overflow))
                     }
-
-                    bytes_read + overflow
                 }
             }
 
             impl WriteEndpoint for $USBX {
-                fn write<'a, I>(&self, endpoint_number: u8, iter: I) -> usize
+                fn try_write<'a, I>(&self, endpoint_number: u8, iter: I) -> Result<usize, ErrorKind>
                 where
                     I: Iterator<Item = u8>
                 {
                     let max_packet_size = smolusb::max_packet_size(self.device_speed, endpoint_number);
-                    self.write_with_packet_size(endpoint_number, None, iter, max_packet_size)
+                    self.try_write_with_packet_size(endpoint_number, None, iter, max_packet_size)
                 }
 
-                fn write_requested<'a, I>(&self, endpoint_number: u8, requested_length: usize, iter: I) -> usize
+                fn try_write_requested<'a, I>(&self, endpoint_number: u8, requested_length: usize, iter: I) -> Result<usize, ErrorKind>
                 where
                     I: Iterator<Item = u8>
                 {
                     let max_packet_size = smolusb::max_packet_size(self.device_speed, endpoint_number);
-                    self.write_with_packet_size(endpoint_number, Some(requested_length), iter, max_packet_size)
+                    self.try_write_with_packet_size(endpoint_number, Some(requested_length), iter, max_packet_size)
                 }
 
-                fn write_with_packet_size<'a, I>(&self, endpoint_number: u8, requested_length: Option<usize>, iter: I, packet_size: usize) -> usize
+                fn try_write_with_packet_size<'a, I>(&self, endpoint_number: u8, requested_length: Option<usize>, iter: I, packet_size: usize) -> Result<usize, ErrorKind>
                 where
                     I: Iterator<Item = u8>
                 {
@@ -481,7 +612,7 @@ macro_rules! impl_usb {
                                 self.clear_tx_ack_active(endpoint_number);
                             }
                             log::error!("  {} clear tx timeout", stringify!($USBX));
-                            return 0;
+                            return Err(ErrorKind::Timeout(0));
                         }
                         timeout += 1;
                     }
@@ -515,7 +646,7 @@ macro_rules! impl_usb {
                                         stringify!($USBX),
                                         bytes_written
                                     );
-                                    return bytes_written;
+                                    return Err(ErrorKind::Timeout(bytes_written));
                                 }
                             }
                         }
@@ -555,13 +686,145 @@ macro_rules! impl_usb {
                             .write(|w| unsafe { w.epno().bits(endpoint_number) });
                     }
 
+                    Ok(bytes_written)
+                }
+
+            }
+
+            // - trait: IsochronousEndpoint ------------------------------------
+
+            /// Best-effort isochronous support, see the `lunasoc_hal::usb`
+            /// module documentation for its limitations.
+            impl IsochronousEndpoint for $USBX {
+                fn configure_isochronous(&self, endpoint_number: u8, direction: Direction, max_packet_size: usize) {
+                    // eptri has no notion of endpoint transfer types, isochronous
+                    // endpoints are serviced like any other endpoint
+                    log::debug!(
+                        "{}::configure_isochronous({}, {:?}, {})",
+                        stringify!($USBX),
+                        endpoint_number,
+                        direction,
+                        max_packet_size
+                    );
+                }
+
+                fn write_isochronous<I>(&self, endpoint_number: u8, iter: I) -> usize
+                where
+                    I: Iterator<Item = u8>
+                {
+                    // the IN FIFO is shared by all endpoints
+                    if self.ep_in.have().read().have().bit() {
+                        if unsafe { self.is_tx_ack_active(endpoint_number) } {
+                            // drop our previous packet if the host did not collect it in time
+                            self.ep_in.reset().write(|w| w.reset().bit(true));
+                        } else {
+                            // don't discard a packet queued for another endpoint
+                            log::debug!(
+                                "{}::write_isochronous fifo busy, dropping packet for endpoint {}",
+                                stringify!($USBX),
+                                endpoint_number
+                            );
+                            return 0;
+                        }
+                    }
+
+                    unsafe { self.set_tx_ack_active(endpoint_number); }
+
+                    let mut bytes_written: usize = 0;
+                    for byte in iter {
+                        self.ep_in.data().write(|w| unsafe { w.data().bits(byte) });
+                        bytes_written += 1;
+                    }
+
+                    // prime the IN endpoint without waiting for the packet to be sent
+                    self.ep_in
+                        .epno()
+                        .write(|w| unsafe { w.epno().bits(endpoint_number) });
+
                     bytes_written
                 }
 
+                fn frame_number(&self) -> Option<u16> {
+                    // eptri does not expose start-of-frame packets or the frame number
+                    None
+                }
             }
 
             // mark implementation as complete
             impl UsbDriver for $USBX {}
+
+            // - trait: AsyncUsbDriver ----------------------------------------
+
+            impl AsyncUsbDriver for $USBX {
+                fn max_packet_size(&self, endpoint_number: u8) -> usize {
+                    smolusb::max_packet_size(self.device_speed, endpoint_number)
+                }
+
+                fn write_packet<I>(&self, endpoint_number: u8, iter: I) -> usize
+                where
+                    I: Iterator<Item = u8>
+                {
+                    unsafe { self.set_tx_ack_active(endpoint_number); }
+
+                    let mut bytes_written: usize = 0;
+                    for byte in iter {
+                        self.ep_in.data().write(|w| unsafe { w.data().bits(byte) });
+                        bytes_written += 1;
+                    }
+
+                    // prime the IN endpoint, wake_send_complete() is called once the host has acknowledged it
+                    self.ep_in
+                        .epno()
+                        .write(|w| unsafe { w.epno().bits(endpoint_number) });
+
+                    bytes_written
+                }
+
+                fn poll_write(&self, endpoint_number: u8, cx: &mut core::task::Context<'_>) -> core::task::Poll<()> {
+                    let endpoint = endpoint_number as usize;
+                    riscv::interrupt::free(|| unsafe {
+                        if self.is_tx_ack_active(endpoint_number) {
+                            $IDX::WAKERS_IN[endpoint] = Some(cx.waker().clone());
+                            core::task::Poll::Pending
+                        } else {
+                            core::task::Poll::Ready(())
+                        }
+                    })
+                }
+
+                fn poll_read(&self, endpoint_number: u8, cx: &mut core::task::Context<'_>) -> core::task::Poll<()> {
+                    let endpoint = endpoint_number as usize;
+                    riscv::interrupt::free(|| unsafe {
+                        if $IDX::RX_READY[endpoint] {
+                            $IDX::RX_READY[endpoint] = false;
+                            core::task::Poll::Ready(())
+                        } else {
+                            $IDX::WAKERS_OUT[endpoint] = Some(cx.waker().clone());
+                            core::task::Poll::Pending
+                        }
+                    })
+                }
+
+                fn poll_setup(&self, endpoint_number: u8, cx: &mut core::task::Context<'_>) -> core::task::Poll<smolusb::setup::SetupPacket> {
+                    let endpoint = endpoint_number as usize;
+                    let is_ready = riscv::interrupt::free(|| unsafe {
+                        if $IDX::SETUP_READY[endpoint] {
+                            $IDX::SETUP_READY[endpoint] = false;
+                            true
+                        } else {
+                            $IDX::WAKERS_SETUP[endpoint] = Some(cx.waker().clone());
+                            false
+                        }
+                    });
+                    if !is_ready {
+                        return core::task::Poll::Pending;
+                    }
+
+                    let mut buffer = [0_u8; 8];
+                    self.read_control(&mut buffer);
+                    core::task::Poll::Ready(smolusb::setup::SetupPacket::from(buffer))
+                }
+            }
         )+
     }
 }
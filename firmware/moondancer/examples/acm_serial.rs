@@ -16,11 +16,12 @@ use moondancer::{hal, pac};
 use hal::smolusb;
 use smolusb::class::acm;
 use smolusb::control::Control;
-use smolusb::device::{Descriptors, Speed};
+use smolusb::descriptor::StringTable;
+use smolusb::device::{Descriptors, Speed, UsbDevice};
 use smolusb::event::UsbEvent;
-use smolusb::setup::{Direction, Request, RequestType, SetupPacket};
+use smolusb::setup::SetupPacket;
 use smolusb::traits::UnsafeUsbDriverOperations;
-use smolusb::traits::{ReadControl, ReadEndpoint, UsbDriverOperations, WriteEndpoint};
+use smolusb::traits::{ReadControl, UsbDriverOperations};
 
 use pac::csr::interrupt;
 
@@ -28,6 +29,7 @@ use pac::csr::interrupt;
 
 const DEVICE_SPEED: Speed = Speed::Full;
 const MAX_CONTROL_RESPONSE_SIZE: usize = 8;
+const SERIAL_BUFFER_SIZE: usize = 1024;
 
 // - global static state ------------------------------------------------------
 
@@ -83,9 +85,6 @@ extern "C" fn MachineExternal() {
         pac::Interrupt::USB0 => {
             usb0.bus_reset();
 
-            // prime the usb OUT endpoints we'll be using
-            usb0.ep_out_prime_receive(4);
-
             usb0.controller
                 .ev_pending()
                 .modify(|r, w| w.pending().bit(r.pending().bit()));
@@ -163,20 +162,30 @@ fn main() -> ! {
     );
 
     // usb0 control endpoint
-    let mut control_usb0 = Control::<_, MAX_CONTROL_RESPONSE_SIZE>::new(
+    let control_usb0 = Control::<_, MAX_CONTROL_RESPONSE_SIZE>::new(
         0,
         Descriptors {
             device_speed: DEVICE_SPEED,
             device_descriptor: acm::DEVICE_DESCRIPTOR,
-            configuration_descriptor: acm::CONFIGURATION_DESCRIPTOR_0,
-            other_speed_configuration_descriptor: Some(acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0),
+            configuration_descriptors: acm::CONFIGURATION_DESCRIPTORS,
+            other_speed_configuration_descriptors: Some(acm::OTHER_SPEED_CONFIGURATION_DESCRIPTORS),
             device_qualifier_descriptor: Some(acm::DEVICE_QUALIFIER_DESCRIPTOR),
             string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
             string_descriptors: acm::STRING_DESCRIPTORS,
-        }
-        .set_total_lengths(),
+            string_table: StringTable::new(),
+            bos_descriptor: None,
+            microsoft10: None,
+            microsoft20: None,
+        },
     );
 
+    // usb0 serial port: interface 0, notifications on IN ep 0x83, data on ep 0x04/0x84
+    let serial_usb0 = acm::AcmSerial::<SERIAL_BUFFER_SIZE, SERIAL_BUFFER_SIZE>::new(0, 3, 4)
+        .with_max_packet_size(smolusb::max_packet_size(DEVICE_SPEED, 4));
+
+    // usb0 device: routes requests and events to the serial port
+    let mut device_usb0 = UsbDevice::new(control_usb0, serial_usb0);
+
     // disconnect device
     usb0.disconnect();
     unsafe {
@@ -213,92 +222,36 @@ fn main() -> ! {
             use UsbInterface::Target;
 
             match event {
-                // Usb0 received a control event
+                // Usb0 received a control event or serial data on ep 0x04/0x84
                 Usb(
                     Target,
                     event @ (BusReset
-                    | ReceiveControl(0)
-                    | ReceiveSetupPacket(0, _)
-                    | ReceivePacket(0)
-                    | SendComplete(0)),
+                    | ReceiveControl(_)
+                    | ReceiveSetupPacket(_, _)
+                    | ReceivePacket(_)
+                    | SendComplete(_)),
                 ) => {
-                    if let Some(setup_packet) = control_usb0.dispatch_event(&usb0, event) {
-                        // class requests are not handled by control
-                        handle_class_request(&usb0, setup_packet);
+                    if let Some(setup_packet) = device_usb0.dispatch_event(&usb0, event) {
+                        error!("Unhandled control request: {:?}", setup_packet);
+                        usb0.stall_endpoint_in(0);
                     }
                 }
 
-                // Handle serial data on OUT ep 0x04
-                Usb(Target, ReceivePacket(4)) => {
-                    let mut rx_buffer: [u8; smolusb::EP_MAX_PACKET_SIZE] =
-                        [0; smolusb::EP_MAX_PACKET_SIZE];
-                    let bytes_read = usb0.read(4, &mut rx_buffer);
-
-                    // convert to uppercase
-                    for b in rx_buffer[0..bytes_read].iter_mut() {
-                        if 'a' as u8 <= *b && *b <= 'z' as u8 {
-                            *b -= 32;
-                        }
-                    }
-
-                    // echo back on IN ep 0x84
-                    usb0.write(4, rx_buffer[0..bytes_read].iter().cloned());
-                }
-
-                // On send complete, prime OUT ep 0x04 to receive next packet
-                Usb(Target, SendComplete(4)) => {
-                    usb0.ep_out_prime_receive(4);
-                }
-
                 // unhandled
                 _ => {
                     info!("Unhandled event: {:?}", event);
                 }
             }
         }
-    }
-}
 
-// - class request handler ---------------------------------------------------
-
-fn handle_class_request<D>(usb: &D, setup_packet: SetupPacket)
-where
-    D: ReadControl + ReadEndpoint + WriteEndpoint + UsbDriverOperations + UnsafeUsbDriverOperations,
-{
-    let request_type = setup_packet.request_type();
-    let request = setup_packet.request();
-    let direction = setup_packet.direction();
-
-    match (request_type, request) {
-        (RequestType::Class, Request::ClassOrVendor(class_request)) => {
-            use acm::serial::ClassRequest::{self, *};
-            let class_request = ClassRequest::from(class_request);
-
-            // In testing, macOS and Linux are fine will all requests being stalled; while Windows
-            // seems to be happy as long as SET_LINE_CODING is implemented. We'll implement only
-            // that, and stall every other handler.
-            match (direction, &class_request) {
-                (Direction::HostToDevice, SetLineCoding) => {
-                    // 32
-                    // has 7 bytes of data
-                    usb.ep_out_prime_receive(4);
-                }
-                // we can just stall the reset
-                (Direction::DeviceToHost, _) => {
-                    // IN
-                    usb.stall_endpoint_out(0);
-                }
-                (Direction::HostToDevice, _) => {
-                    // OUT
-                    usb.stall_endpoint_in(0);
-                }
-            }
-        }
-        _ => {
-            error!(
-                "handle_class_request error - unhandled control request: {:?} {:?}",
-                request_type, request
-            );
+        // echo received serial data back to the host
+        let mut buffer = [0; smolusb::EP_MAX_PACKET_SIZE];
+        let serial_usb0 = device_usb0.classes_mut();
+        let bytes_read = serial_usb0.read(&usb0, &mut buffer);
+        if bytes_read > 0 {
+            // convert to uppercase
+            buffer[..bytes_read].make_ascii_uppercase();
+            serial_usb0.write(&usb0, &buffer[..bytes_read]);
         }
     }
 }
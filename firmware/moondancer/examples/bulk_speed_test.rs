@@ -11,7 +11,7 @@ use smolusb::control::Control;
 use smolusb::descriptor::{
     ConfigurationDescriptor, ConfigurationDescriptorHeader, DescriptorType, DeviceDescriptor,
     DeviceQualifierDescriptor, EndpointDescriptor, InterfaceDescriptor, InterfaceDescriptorHeader,
-    LanguageId, StringDescriptor, StringDescriptorZero,
+    LanguageId, StringDescriptor, StringDescriptorZero, StringTable,
 };
 use smolusb::device::{Descriptors, Speed};
 use smolusb::event::UsbEvent;
@@ -184,13 +184,16 @@ fn main_loop() -> GreatResult<()> {
         Descriptors {
             device_speed: DEVICE_SPEED,
             device_descriptor: USB_DEVICE_DESCRIPTOR,
-            configuration_descriptor: USB_CONFIGURATION_DESCRIPTOR_0,
-            other_speed_configuration_descriptor: Some(USB_OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0),
+            configuration_descriptors: USB_CONFIGURATION_DESCRIPTORS,
+            other_speed_configuration_descriptors: Some(USB_OTHER_SPEED_CONFIGURATION_DESCRIPTORS),
             device_qualifier_descriptor: Some(USB_DEVICE_QUALIFIER_DESCRIPTOR),
             string_descriptor_zero: USB_STRING_DESCRIPTOR_0,
             string_descriptors: USB_STRING_DESCRIPTORS,
-        }
-        .set_total_lengths(), // TODO figure out a better solution
+            string_table: StringTable::new(),
+            bos_descriptor: None,
+            microsoft10: None,
+            microsoft20: None,
+        },
     );
 
     // connect device
@@ -602,6 +605,11 @@ static USB_OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
         )],
     );
 
+static USB_CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] =
+    &[USB_CONFIGURATION_DESCRIPTOR_0];
+static USB_OTHER_SPEED_CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] =
+    &[USB_OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0];
+
 static USB_STRING_DESCRIPTOR_0: StringDescriptorZero =
     StringDescriptorZero::new(&[LanguageId::EnglishUnitedStates]);
 static USB_STRING_DESCRIPTOR_1: StringDescriptor =
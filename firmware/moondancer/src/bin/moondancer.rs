@@ -6,7 +6,7 @@ use log::{debug, error, info, trace, warn};
 
 use crate::hal::smolusb;
 use smolusb::control::Control;
-use smolusb::descriptor::StringDescriptor;
+use smolusb::descriptor::StringTable;
 use smolusb::device::{Descriptors, Speed};
 use smolusb::setup::{Direction, Recipient, RequestType, SetupPacket};
 use smolusb::traits::{ReadEndpoint, UsbDriverOperations, WriteEndpoint};
@@ -142,30 +142,15 @@ impl<'a> Firmware<'a> {
         let uuid = util::read_flash_uuid(&peripherals.SPI0).unwrap_or([0_u8; 8]);
         let uuid = util::format_flash_uuid(uuid);
 
-        // build string descriptor table
-        //
-        // FIXME crimes should not have to be committed in order to provide smolusb with a dynamic string table!
-        #[allow(clippy::items_after_statements)]
-        let string_descriptors = {
-            static mut UUID: heapless::String<16> = heapless::String::new();
-            static mut ISERIALNUMBER: StringDescriptor = StringDescriptor::new("0000000000000000");
-            unsafe {
-                UUID = uuid.clone();
-                ISERIALNUMBER = StringDescriptor::new(UUID.as_str());
-            }
-            static mut STRING_DESCRIPTORS: [&StringDescriptor; 9] = [
-                &moondancer::usb::STRING_DESCRIPTOR_1,
-                &moondancer::usb::STRING_DESCRIPTOR_2,
-                unsafe { &*core::ptr::addr_of!(ISERIALNUMBER) },
-                &moondancer::usb::STRING_DESCRIPTOR_4,
-                &moondancer::usb::STRING_DESCRIPTOR_5,
-                &moondancer::usb::STRING_DESCRIPTOR_6,
-                &moondancer::usb::STRING_DESCRIPTOR_7,
-                &moondancer::usb::STRING_DESCRIPTOR_8,
-                &moondancer::usb::STRING_DESCRIPTOR_9,
-            ];
-            unsafe { &*core::ptr::addr_of!(STRING_DESCRIPTORS) }
-        };
+        // add the serial number to the string descriptor table
+        let mut string_table = StringTable::new();
+        if let Err(e) = string_table.insert(
+            moondancer::usb::DEVICE_DESCRIPTOR.iSerialNumber,
+            None,
+            uuid.as_str(),
+        ) {
+            error!("Failed to set serial number string descriptor: {}", e);
+        }
 
         // usb2: control (host on r0.4)
         let usb2 = hal::Usb2::new(
@@ -195,14 +180,16 @@ impl<'a> Firmware<'a> {
                     bcdDevice: bcd_device,
                     ..moondancer::usb::DEVICE_DESCRIPTOR
                 },
-                configuration_descriptor: moondancer::usb::CONFIGURATION_DESCRIPTOR_0,
+                configuration_descriptors: moondancer::usb::CONFIGURATION_DESCRIPTORS,
                 string_descriptor_zero: moondancer::usb::STRING_DESCRIPTOR_0,
-                string_descriptors,
+                string_descriptors: moondancer::usb::STRING_DESCRIPTORS,
+                string_table,
                 // optional
                 device_qualifier_descriptor: Some(moondancer::usb::DEVICE_QUALIFIER_DESCRIPTOR),
-                other_speed_configuration_descriptor: Some(
-                    moondancer::usb::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0,
+                other_speed_configuration_descriptors: Some(
+                    moondancer::usb::OTHER_SPEED_CONFIGURATION_DESCRIPTORS,
                 ),
+                bos_descriptor: None,
                 microsoft10: Some(smolusb::descriptor::microsoft10::Descriptors {
                     string_descriptor: moondancer::usb::STRING_DESCRIPTOR_0XEE,
                     compat_id_feature_descriptor:
@@ -210,6 +197,7 @@ impl<'a> Firmware<'a> {
                     extended_properties_feature_descriptor:
                         moondancer::usb::MS_OS_10_EXTENDED_PROPERTIES_FEATURE_DESCRIPTOR,
                 }),
+                microsoft20: None,
             },
         );
 
@@ -349,7 +337,7 @@ impl<'a> Firmware<'a> {
         let request_type = setup_packet.request_type();
         let recipient = setup_packet.recipient();
         let vendor_request = VendorRequest::from(setup_packet.request);
-        let vendor_value = VendorValue::from(setup_packet.value);
+        let vendor_value = VendorValue::from(setup_packet.value());
 
         log::debug!(
             "handle_vendor_request: {:?} {:?} {:?} {:?} {:?}",
@@ -514,7 +502,7 @@ impl<'a> Firmware<'a> {
     }
 
     fn dispatch_libgreat_response(&mut self, setup_packet: SetupPacket) -> GreatResult<()> {
-        let requested_length = setup_packet.length as usize;
+        let requested_length = setup_packet.length() as usize;
 
         // do we have a response ready?
         if let Some(response) = &mut self.libgreat_response {
@@ -539,7 +527,7 @@ impl<'a> Firmware<'a> {
     }
 
     fn dispatch_libgreat_abort(&mut self, setup_packet: SetupPacket) -> GreatResult<()> {
-        let requested_length = setup_packet.length as usize;
+        let requested_length = setup_packet.length() as usize;
 
         // prime to receive host zlp
         self.usb2.ep_out_prime_receive(0);
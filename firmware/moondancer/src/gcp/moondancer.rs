@@ -4,6 +4,9 @@ use log::{debug, error, trace, warn};
 use zerocopy::byteorder::{LittleEndian, U16, U32};
 use zerocopy::{FromBytes, FromZeroes, Unaligned};
 
+use heapless::spsc::Queue;
+use heapless::Vec;
+
 use crate::{hal, pac};
 use hal::smolusb;
 use pac::csr::interrupt;
@@ -31,26 +34,167 @@ pub mod QuirkFlag {
     pub const SetAddressManually: u16 = 0x0001;
 }
 
+/// Number of preallocated OUT packet buffers shared by all endpoints
+pub const PACKET_POOL_SIZE: usize = 8;
+
 struct Packet {
-    endpoint_number: u8,
     bytes_read: usize,
     buffer: [u8; smolusb::EP_MAX_PACKET_SIZE],
 }
 
 impl Packet {
-    const fn new(endpoint_number: u8, bytes_read: usize) -> Self {
+    const fn new() -> Self {
         Self {
-            endpoint_number,
-            bytes_read,
+            bytes_read: 0,
             buffer: [0; smolusb::EP_MAX_PACKET_SIZE],
         }
     }
 }
 
-// - Moondancer --------------------------------------------------------------
+/// OUT packet buffer statistics for a single endpoint
+#[derive(Clone, Copy, Default)]
+struct PacketStatistics {
+    /// Highest number of packets queued at once
+    high_water_mark: u8,
+    /// Number of primes deferred because no buffer was free
+    deferred_primes: u16,
+    /// Number of packets dropped because they overflowed their buffer
+    dropped_packets: u16,
+}
 
-use heapless::spsc::Queue;
-use heapless::Vec;
+// - PacketPool ---------------------------------------------------------------
+
+/// Preallocated OUT packet buffers
+///
+/// Every OUT endpoint has a ring of indices into a shared pool of packet
+/// buffers. A buffer is reserved when an endpoint is primed so that the
+/// FIFO can be drained straight into it. If no buffer is free the prime
+/// is deferred and the endpoint keeps NAKing the host until a buffer is
+/// released by `read_endpoint`.
+///
+/// OUT endpoints must only ever be primed by `Moondancer::prime_receive()`
+/// so that every received packet has a buffer reserved for it.
+struct PacketPool {
+    packets: [Packet; PACKET_POOL_SIZE],
+    free: Vec<u8, PACKET_POOL_SIZE>,
+    reserved: [Option<u8>; smolusb::EP_MAX_ENDPOINTS],
+    rings: [Queue<u8, { PACKET_POOL_SIZE + 1 }>; smolusb::EP_MAX_ENDPOINTS],
+    /// Bitmask of endpoints waiting for a free buffer to be primed
+    deferred: u16,
+    statistics: [PacketStatistics; smolusb::EP_MAX_ENDPOINTS],
+}
+
+impl PacketPool {
+    #[allow(clippy::cast_possible_truncation)]
+    fn new() -> Self {
+        const PACKET: Packet = Packet::new();
+        Self {
+            packets: [PACKET; PACKET_POOL_SIZE],
+            free: (0..PACKET_POOL_SIZE as u8).collect(),
+            reserved: [None; smolusb::EP_MAX_ENDPOINTS],
+            rings: core::array::from_fn(|_| Queue::new()),
+            deferred: 0,
+            statistics: [PacketStatistics::default(); smolusb::EP_MAX_ENDPOINTS],
+        }
+    }
+
+    /// Discards all queued packets, reservations and deferred primes.
+    fn reset(&mut self) {
+        let statistics = self.statistics;
+        *self = Self::new();
+        self.statistics = statistics;
+    }
+
+    fn reset_statistics(&mut self) {
+        self.statistics = [PacketStatistics::default(); smolusb::EP_MAX_ENDPOINTS];
+    }
+
+    /// Reserves a buffer for the next packet received on the endpoint.
+    ///
+    /// Returns `false` and defers the prime if no buffer is free.
+    fn reserve(&mut self, endpoint_number: u8) -> bool {
+        let endpoint = usize::from(endpoint_number);
+        if self.reserved[endpoint].is_some() {
+            return true;
+        }
+        if let Some(index) = self.free.pop() {
+            self.reserved[endpoint] = Some(index);
+            self.deferred &= !(1 << endpoint);
+            true
+        } else {
+            self.deferred |= 1 << endpoint;
+            let statistics = &mut self.statistics[endpoint];
+            statistics.deferred_primes = statistics.deferred_primes.saturating_add(1);
+            false
+        }
+    }
+
+    /// Returns `true` if a buffer is reserved for the endpoint.
+    fn is_reserved(&self, endpoint_number: u8) -> bool {
+        self.reserved[usize::from(endpoint_number)].is_some()
+    }
+
+    /// Takes the buffer reserved for the endpoint.
+    fn take_reserved(&mut self, endpoint_number: u8) -> Option<u8> {
+        self.reserved[usize::from(endpoint_number)].take()
+    }
+
+    /// Returns a buffer that was taken without being queued to the pool.
+    fn release(&mut self, index: u8) {
+        self.free.push(index).ok();
+    }
+
+    fn packet_mut(&mut self, index: u8) -> &mut Packet {
+        &mut self.packets[usize::from(index)]
+    }
+
+    fn packet(&self, index: u8) -> &Packet {
+        &self.packets[usize::from(index)]
+    }
+
+    /// Queues a received packet on the endpoint's ring.
+    #[allow(clippy::cast_possible_truncation)]
+    fn enqueue(&mut self, endpoint_number: u8, index: u8) {
+        let endpoint = usize::from(endpoint_number);
+        let ring = &mut self.rings[endpoint];
+        if ring.enqueue(index).is_err() {
+            // can't happen, the ring holds as many indices as the pool has buffers
+            self.free.push(index).ok();
+            return;
+        }
+        let queued = ring.len() as u8;
+        let statistics = &mut self.statistics[endpoint];
+        statistics.high_water_mark = statistics.high_water_mark.max(queued);
+    }
+
+    /// Removes the oldest packet from the endpoint's ring and releases its buffer.
+    ///
+    /// The buffer's contents remain valid until a packet is next received
+    /// into it.
+    fn dequeue(&mut self, endpoint_number: u8) -> Option<u8> {
+        let index = self.rings[usize::from(endpoint_number)].dequeue()?;
+        self.free.push(index).ok();
+        Some(index)
+    }
+
+    fn drop_packet(&mut self, endpoint_number: u8, index: u8) {
+        self.release(index);
+        let statistics = &mut self.statistics[usize::from(endpoint_number)];
+        statistics.dropped_packets = statistics.dropped_packets.saturating_add(1);
+    }
+
+    /// Returns the next endpoint waiting for a free buffer to be primed.
+    #[allow(clippy::cast_possible_truncation)]
+    fn next_deferred(&self) -> Option<u8> {
+        if self.deferred == 0 || self.free.is_empty() {
+            None
+        } else {
+            Some(self.deferred.trailing_zeros() as u8)
+        }
+    }
+}
+
+// - Moondancer --------------------------------------------------------------
 
 /// Moondancer
 pub struct Moondancer {
@@ -60,7 +204,7 @@ pub struct Moondancer {
     ep_out_max_packet_size: [u16; smolusb::EP_MAX_ENDPOINTS],
     irq_queue: Queue<UsbEvent, 64>,
     control_queue: Queue<SetupPacket, 8>,
-    packet_buffer: Vec<Packet, 4>,
+    packet_pool: PacketPool,
     pending_set_address: Option<u8>,
 }
 
@@ -74,11 +218,17 @@ impl Moondancer {
             ep_out_max_packet_size: [0; smolusb::EP_MAX_ENDPOINTS],
             irq_queue: Queue::new(),
             control_queue: Queue::new(),
-            packet_buffer: Vec::new(),
+            packet_pool: PacketPool::new(),
             pending_set_address: None,
         }
     }
 
+    /// Handles an interrupt event and queues it for `get_interrupt_events()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a packet is received on an OUT endpoint that was not
+    /// primed by `prime_receive()`.
     pub fn dispatch_event(&mut self, event: UsbEvent) {
         // filter interrupt events
         let event = match event {
@@ -87,6 +237,7 @@ impl Moondancer {
                 //while let Some(_) = self.irq_queue.dequeue() {}
                 //while let Some(_) = self.control_queue.dequeue() {}
                 self.pending_set_address = None;
+                self.packet_pool.reset();
                 event
             }
 
@@ -106,7 +257,7 @@ impl Moondancer {
                     )
                 ) {
                     // read the address
-                    let address: u8 = (setup_packet.value & 0x7f) as u8;
+                    let address: u8 = (setup_packet.value() & 0x7f) as u8;
 
                     // set pending flag to perform set address after SendComplete
                     self.pending_set_address = Some(address);
@@ -143,36 +294,34 @@ impl Moondancer {
             }
 
             UsbEvent::ReceivePacket(endpoint_number) => {
-                // drain FIFO
-                let mut rx_buffer: [u8; smolusb::EP_MAX_PACKET_SIZE] =
-                    [0; smolusb::EP_MAX_PACKET_SIZE];
-                let bytes_read = self.usb0.read(endpoint_number, &mut rx_buffer);
-
-                // create Packet
-                let mut packet = Packet::new(endpoint_number, bytes_read);
-                if packet.bytes_read > packet.buffer.len() {
-                    error!(
-                        "MD moondancer::dispatch_event(ReceivePacket({})) receive buffer overflow {} bytes",
-                        packet.endpoint_number, packet.bytes_read
+                // endpoints are only primed by prime_receive() once a buffer is reserved
+                let Some(index) = self.packet_pool.take_reserved(endpoint_number) else {
+                    panic!(
+                        "MD moondancer::dispatch_event(ReceivePacket({})) no packet buffer reserved",
+                        endpoint_number
                     );
-                    // TODO we can probably do better than truncating the packet
-                    packet.bytes_read = packet.buffer.len();
-                } else {
-                    packet.buffer[..packet.bytes_read]
-                        .copy_from_slice(&rx_buffer[..packet.bytes_read]);
-                }
-
-                // append to packet buffer
-                match self.packet_buffer.push(packet) {
-                    Ok(()) => {}
-                    Err(_packet) => {
+                };
+
+                // drain FIFO straight into the packet buffer
+                let packet = self.packet_pool.packet_mut(index);
+                match self.usb0.try_read(endpoint_number, &mut packet.buffer) {
+                    Ok(bytes_read) => packet.bytes_read = bytes_read,
+                    Err(e) => {
+                        // the host sent more than the endpoint's max packet size
                         error!(
-                            "MD moondancer::dispatch_event(ReceivePacket({})) packet buffer overflow",
-                            endpoint_number
+                            "MD moondancer::dispatch_event(ReceivePacket({})) receive buffer overflow: {}",
+                            endpoint_number, e
                         );
+                        self.packet_pool.drop_packet(endpoint_number, index);
+                        self.usb0.stall_endpoint_out(endpoint_number);
+                        self.prime_deferred();
+                        return;
                     }
                 }
 
+                // append to the endpoint's packet ring
+                self.packet_pool.enqueue(endpoint_number, index);
+
                 event
             }
 
@@ -180,6 +329,11 @@ impl Moondancer {
                 // no-op, just pass it on through
                 event
             }
+
+            UsbEvent::StartOfFrame(_) | UsbEvent::Suspend | UsbEvent::Resume => {
+                // drop event, because - currently - we're not using it in moondancer.py
+                return;
+            }
         };
 
         // enqueue interrupt event
@@ -192,6 +346,32 @@ impl Moondancer {
     }
 }
 
+// - OUT endpoint priming -----------------------------------------------------
+
+impl Moondancer {
+    /// Prime the OUT endpoint to receive a single packet.
+    ///
+    /// The prime is deferred, and the endpoint keeps NAKing the host, if
+    /// there is no free packet buffer for it.
+    fn prime_receive(&mut self, endpoint_number: u8) {
+        if self.packet_pool.reserve(endpoint_number) {
+            self.usb0.ep_out_prime_receive(endpoint_number);
+        } else {
+            debug!(
+                "MD moondancer::prime_receive({}) deferred, no free packet buffers",
+                endpoint_number
+            );
+        }
+    }
+
+    /// Prime any OUT endpoints that were waiting for a free packet buffer.
+    fn prime_deferred(&mut self) {
+        while let Some(endpoint_number) = self.packet_pool.next_deferred() {
+            self.prime_receive(endpoint_number);
+        }
+    }
+}
+
 // - usb0 interrupt handlers --------------------------------------------------
 
 impl Moondancer {
@@ -250,6 +430,7 @@ impl Moondancer {
         self.ep_in_max_packet_size[0] = ep0_max_packet_size;
         self.ep_out_max_packet_size[0] = ep0_max_packet_size;
         self.quirk_flags = quirk_flags;
+        self.packet_pool.reset_statistics();
 
         // connect usb0 device and enable interrupts
         self.usb0.connect(device_speed);
@@ -286,6 +467,7 @@ impl Moondancer {
         // flush queues
         while self.irq_queue.dequeue().is_some() {}
         while self.control_queue.dequeue().is_some() {}
+        self.packet_pool.reset();
 
         // clear quirk flags
         self.quirk_flags = 0;
@@ -419,7 +601,7 @@ impl Moondancer {
                     "  priming HostToDevice (OUT) endpoint address: {}",
                     endpoint.address
                 );
-                self.usb0.ep_out_prime_receive(endpoint_number);
+                self.prime_receive(endpoint_number);
             }
         }
 
@@ -501,7 +683,10 @@ impl Moondancer {
 // - verb implementations: data transfer --------------------------------------
 
 impl Moondancer {
-    pub fn read_endpoint(&mut self, arguments: &[u8]) -> GreatResult<impl Iterator<Item = u8>> {
+    pub fn read_endpoint(
+        &mut self,
+        arguments: &[u8],
+    ) -> GreatResult<impl Iterator<Item = u8> + '_> {
         #[repr(C)]
         #[derive(FromBytes, FromZeroes, Unaligned)]
         struct Args {
@@ -509,30 +694,42 @@ impl Moondancer {
         }
         let args = Args::read_from(arguments).ok_or(GreatError::InvalidArgument)?;
         let endpoint_number = args.endpoint_number;
+        if usize::from(endpoint_number) >= smolusb::EP_MAX_ENDPOINTS {
+            return Err(GreatError::InvalidArgument);
+        }
 
-        let packet = match self
-            .packet_buffer
-            .iter()
-            .position(|packet| packet.endpoint_number == endpoint_number)
-        {
-            Some(index) => self.packet_buffer.remove(index),
+        let index = self.packet_pool.dequeue(endpoint_number);
+
+        // A buffer was released so we can prime any endpoints that were
+        // waiting for one. Its contents can't be overwritten before the
+        // response has been sent as packets are only received into a
+        // buffer by `dispatch_event()`.
+        if index.is_some() {
+            self.prime_deferred();
+        }
+
+        let bytes: &[u8] = match index {
+            Some(index) => {
+                let packet = self.packet_pool.packet(index);
+                &packet.buffer[..packet.bytes_read]
+            }
             None => {
                 error!(
                     "MD moondancer::read_endpoint({}) has no packet buffered for endpoint",
                     endpoint_number
                 );
                 // TODO actually handle this case in moondancer.py
-                Packet::new(endpoint_number, 0)
+                &[]
             }
         };
 
         log::debug!(
             "MD moondancer::read_endpoint({}) -> bytes_read:{}",
-            packet.endpoint_number,
-            packet.bytes_read
+            endpoint_number,
+            bytes.len()
         );
 
-        Ok(packet.buffer.into_iter().take(packet.bytes_read))
+        Ok(bytes.iter().copied())
     }
 
     pub fn test_read_endpoint(
@@ -576,8 +773,11 @@ impl Moondancer {
             endpoint_number: u8,
         }
         let args = Args::read_from(arguments).ok_or(GreatError::InvalidArgument)?;
+        if usize::from(args.endpoint_number) >= smolusb::EP_MAX_ENDPOINTS {
+            return Err(GreatError::InvalidArgument);
+        }
 
-        self.usb0.ep_out_prime_receive(args.endpoint_number);
+        self.prime_receive(args.endpoint_number);
 
         debug!(
             "MD moondancer::ep_out_prime_receive({})",
@@ -597,8 +797,13 @@ impl Moondancer {
             self.usb0.ep_out.reset().write(|w| w.reset().bit(true));
         }
 
-        // 1. re-enable ep_out interface
-        self.usb0.ep_out.enable().write(|w| w.enable().bit(true));
+        // 1. re-prime the endpoints holding a packet buffer, which re-enables the ep_out interface
+        #[allow(clippy::cast_possible_truncation)]
+        for endpoint_number in 0..smolusb::EP_MAX_ENDPOINTS as u8 {
+            if self.packet_pool.is_reserved(endpoint_number) {
+                self.prime_receive(endpoint_number);
+            }
+        }
 
         debug!("MD moondancer::ep_out_interface_enable()");
 
@@ -636,11 +841,21 @@ impl Moondancer {
         let iter = args.payload.iter();
         let max_packet_size = self.ep_in_max_packet_size[endpoint_number as usize] as usize;
 
-        let bytes_written = self.usb0.write_requested(
-            endpoint_number,
-            requested_length.into(),
-            iter.copied().take(requested_length.into()),
-        );
+        let bytes_written = self
+            .usb0
+            .try_write_requested(
+                endpoint_number,
+                requested_length.into(),
+                iter.copied().take(requested_length.into()),
+            )
+            .map_err(|e| {
+                log::error!(
+                    "moondancer::write_control_endpoint failed during write of {} bytes: {}",
+                    payload_length,
+                    e
+                );
+                GreatError::StreamIoctlTimeout
+            })?;
 
         // wait for send to complete if we're blocking
         let mut timeout = 0;
@@ -887,6 +1102,33 @@ impl Moondancer {
         let nak_status = (self.usb0.ep_in.nak().read().bits() & 0xffff) as u16;
         Ok(nak_status.to_le_bytes().into_iter())
     }
+
+    /// Get OUT packet buffer statistics.
+    ///
+    /// # Return Value
+    ///
+    /// pool_size, buffers_free, [(queued, high_water_mark, deferred_primes, dropped_packets)]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn get_packet_buffer_statistics(
+        &mut self,
+        _arguments: &[u8],
+    ) -> GreatResult<impl Iterator<Item = u8>> {
+        const ENDPOINT_LENGTH: usize = 6;
+        let mut tx_buffer = [0_u8; 2 + smolusb::EP_MAX_ENDPOINTS * ENDPOINT_LENGTH];
+
+        let pool = &self.packet_pool;
+        tx_buffer[0] = PACKET_POOL_SIZE as u8;
+        tx_buffer[1] = pool.free.len() as u8;
+        for (endpoint, dest) in tx_buffer[2..].chunks_exact_mut(ENDPOINT_LENGTH).enumerate() {
+            let statistics = &pool.statistics[endpoint];
+            dest[0] = pool.rings[endpoint].len() as u8;
+            dest[1] = statistics.high_water_mark;
+            dest[2..4].copy_from_slice(&statistics.deferred_primes.to_le_bytes());
+            dest[4..6].copy_from_slice(&statistics.dropped_packets.to_le_bytes());
+        }
+
+        Ok(tx_buffer.into_iter())
+    }
 }
 
 // - class information --------------------------------------------------------
@@ -904,7 +1146,7 @@ pub static CLASS_DOCS: &str = "API for fine-grained control of the Target USB po
 ///
 /// Fields are `"\0"`  where C implementation has `""`
 /// Fields are `"*\0"` where C implementation has `NULL`
-pub static VERBS: [Verb; 19] = [
+pub static VERBS: [Verb; 20] = [
     // - device connection --
     Verb {
         id: 0x00,
@@ -1053,6 +1295,16 @@ pub static VERBS: [Verb; 19] = [
         out_signature: "\0",
         out_param_names: "*\0",
     },
+    Verb {
+        id: 0x10,
+        name: "get_packet_buffer_statistics\0",
+        doc: "\0", //"Return OUT packet buffer occupancy statistics.\0",
+        in_signature: "\0",
+        in_param_names: "*\0",
+        out_signature: "<BB*(BBHH)\0",
+        out_param_names:
+            "pool_size, buffers_free, queued, high_water_mark, deferred_primes, dropped_packets\0",
+    },
     // - tests --
     Verb {
         id: 0x28,
@@ -1201,6 +1453,12 @@ impl GreatDispatch for Moondancer {
                 let response = iter_to_response(iter, response_buffer);
                 Ok(response)
             }
+            0x10 => {
+                // moondancer::get_packet_buffer_statistics
+                let iter = self.get_packet_buffer_statistics(arguments)?;
+                let response = iter_to_response(iter, response_buffer);
+                Ok(response)
+            }
 
             // test APIs
             0x28 => {
@@ -16,10 +16,11 @@ lunasoc_hal::impl_timer! {
 
 pub use lunasoc_hal::smolusb;
 use lunasoc_hal::smolusb::device::Speed;
-use lunasoc_hal::smolusb::setup::Direction;
+use lunasoc_hal::smolusb::error::ErrorKind;
+use lunasoc_hal::smolusb::setup::{Direction, TestMode};
 use lunasoc_hal::smolusb::traits::{
-    ReadControl, ReadEndpoint, UnsafeUsbDriverOperations, UsbDriver, UsbDriverOperations,
-    WriteEndpoint,
+    AsyncUsbDriver, IsochronousEndpoint, ReadControl, ReadEndpoint, UnsafeUsbDriverOperations,
+    UsbDriver, UsbDriverOperations, WriteEndpoint,
 };
 use lunasoc_hal::usb::DEFAULT_TIMEOUT;
 lunasoc_hal::impl_usb! {
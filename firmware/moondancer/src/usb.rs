@@ -5,6 +5,7 @@ use smolusb::descriptor::{
     DeviceDescriptor, DeviceQualifierDescriptor, EndpointDescriptor, InterfaceDescriptor,
     InterfaceDescriptorHeader, LanguageId, StringDescriptor, StringDescriptorZero,
 };
+use smolusb::device::Speed;
 
 // - vendor request -----------------------------------------------------------
 
@@ -113,20 +114,14 @@ pub static CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDe
                 ..InterfaceDescriptorHeader::new()
             },
             &[
-                EndpointDescriptor {
-                    bEndpointAddress: cynthion::shared::libgreat::endpoints::bulk_in_address, // IN
-                    bmAttributes: 0x02, // Bulk
-                    wMaxPacketSize: 512,
-                    bInterval: 0,
-                    ..EndpointDescriptor::new()
-                },
-                EndpointDescriptor {
-                    bEndpointAddress: cynthion::shared::libgreat::endpoints::bulk_out_address, // OUT
-                    bmAttributes: 0x02, // Bulk
-                    wMaxPacketSize: 512,
-                    bInterval: 0,
-                    ..EndpointDescriptor::new()
-                },
+                EndpointDescriptor::bulk(
+                    cynthion::shared::libgreat::endpoints::bulk_in_address,
+                    512,
+                ),
+                EndpointDescriptor::bulk(
+                    cynthion::shared::libgreat::endpoints::bulk_out_address,
+                    512,
+                ),
             ],
         ),
         // Apollo stub interface
@@ -143,7 +138,8 @@ pub static CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDe
             &[],
         ),
     ],
-);
+)
+.validate(Speed::High);
 
 pub static OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
     ConfigurationDescriptor::new(
@@ -168,20 +164,14 @@ pub static OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
                     ..InterfaceDescriptorHeader::new()
                 },
                 &[
-                    EndpointDescriptor {
-                        bEndpointAddress: cynthion::shared::libgreat::endpoints::bulk_in_address, // IN
-                        bmAttributes: 0x02, // Bulk
-                        wMaxPacketSize: 64,
-                        bInterval: 0,
-                        ..EndpointDescriptor::new()
-                    },
-                    EndpointDescriptor {
-                        bEndpointAddress: cynthion::shared::libgreat::endpoints::bulk_out_address, // OUT
-                        bmAttributes: 0x02, // Bulk
-                        wMaxPacketSize: 64,
-                        bInterval: 0,
-                        ..EndpointDescriptor::new()
-                    },
+                    EndpointDescriptor::bulk(
+                        cynthion::shared::libgreat::endpoints::bulk_in_address,
+                        64,
+                    ),
+                    EndpointDescriptor::bulk(
+                        cynthion::shared::libgreat::endpoints::bulk_out_address,
+                        64,
+                    ),
                 ],
             ),
             // Apollo stub interface
@@ -198,7 +188,12 @@ pub static OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
                 &[],
             ),
         ],
-    );
+    )
+    .validate(Speed::Full);
+
+pub static CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] = &[CONFIGURATION_DESCRIPTOR_0];
+pub static OTHER_SPEED_CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] =
+    &[OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0];
 
 pub const COMPATIBLE_ID: [u8; 8] = *b"WINUSB\0\0";
 
@@ -5,15 +5,37 @@
 # Copyright (c) 2023 Great Scott Gadgets <info@greatscottgadgets.com>
 # SPDX-License-Identifier: BSD-3-Clause
 
-import logging, os, sys, traceback, unittest
+import logging, os, sys, time, traceback, unittest
+
+import usb1
 
 from luna import configure_default_logging
 
-from facedancer.backends.moondancer import InterruptEvent
+from facedancer import *
+from facedancer.backends.moondancer import InterruptEvent, MoondancerApp
 
 import cynthion
 
 
+PACKET_POOL_SIZE = 8
+
+@use_inner_classes_automatically
+class PacketPoolDevice(USBDevice):
+    """Target device with a single bulk OUT endpoint."""
+
+    vendor_id      = 0x1209
+    product_id     = 0x0001
+    product_string = "Moondancer packet pool test"
+
+    class Configuration(USBConfiguration):
+        class Interface(USBInterface):
+            class OutEndpoint(USBEndpoint):
+                number          = 1
+                direction       = USBDirection.OUT
+                transfer_type   = USBTransferType.BULK
+                max_packet_size = 64
+
+
 class TestMoondancer(unittest.TestCase):
     """Tests for libgreat class: Moondancer"""
 
@@ -82,6 +104,83 @@ class TestMoondancer(unittest.TestCase):
             logging.debug(f"test_write_endpoint() -> {len(payload)} -> {response}")
 
 
+    def packet_buffer_statistics(self, api):
+        """Returns pool_size, buffers_free and the (queued, high_water_mark, deferred_primes, dropped_packets) of endpoint 1."""
+        response = api.get_packet_buffer_statistics()
+        logging.debug(f"get_packet_buffer_statistics() -> {response}")
+        pool_size, buffers_free, endpoints = response
+        return pool_size, buffers_free, tuple(endpoints[1])
+
+
+    def test_get_packet_buffer_statistics(self):
+        """Requires the Cynthion TARGET port to be connected to the test host."""
+        api = self.board.apis.moondancer
+
+        pool_size, buffers_free, _ = self.packet_buffer_statistics(api)
+        self.assertEqual(pool_size, PACKET_POOL_SIZE)
+        self.assertEqual(buffers_free, pool_size)
+
+        device = PacketPoolDevice(backend=MoondancerApp(device=self.board))
+        device.connect()
+        try:
+            # service the host until it has configured the device
+            deadline = time.time() + 5
+            while device.configuration is None and time.time() < deadline:
+                device.backend.service_irqs()
+            if device.configuration is None:
+                self.skipTest("TARGET port is not connected to this host")
+
+            # from here on interrupts are no longer serviced so packets stay queued
+            with usb1.USBContext() as context:
+                handle = context.openByVendorIDAndProductID(device.vendor_id, device.product_id)
+                handle.claimInterface(0)
+
+                # fill the pool one packet at a time until a prime is deferred
+                queued = 0
+                while True:
+                    api.ep_out_prime_receive(1)
+                    _, buffers_free, (_, _, deferred_primes, _) = self.packet_buffer_statistics(api)
+                    if deferred_primes > 0:
+                        break
+                    handle.bulkWrite(0x01, bytes([queued]) * 64, timeout=1000)
+                    queued += 1
+                    _, _, (queued_packets, high_water_mark, _, _) = self.packet_buffer_statistics(api)
+                    self.assertEqual(queued_packets, queued)
+                    self.assertEqual(high_water_mark, queued)
+                    self.assertLessEqual(queued, pool_size)
+
+                # the exhausted pool NAKs the host instead of dropping packets
+                self.assertEqual(buffers_free, 0)
+                with self.assertRaises(usb1.USBErrorTimeout):
+                    handle.bulkWrite(0x01, bytes([0xff]) * 64, timeout=100)
+                self.assertEqual(
+                    self.packet_buffer_statistics(api),
+                    (pool_size, 0, (queued, queued, 1, 0))
+                )
+
+                # reading a packet releases its buffer to the deferred prime
+                self.assertEqual(api.read_endpoint(1), bytes([0]) * 64)
+                handle.bulkWrite(0x01, bytes([queued]) * 64, timeout=1000)
+                self.assertEqual(
+                    self.packet_buffer_statistics(api),
+                    (pool_size, 0, (queued, queued, 1, 0))
+                )
+
+                # packets are read in order and free their buffers
+                for number in range(1, queued + 1):
+                    self.assertEqual(api.read_endpoint(1), bytes([number]) * 64)
+                _, buffers_free, (queued_packets, _, _, _) = self.packet_buffer_statistics(api)
+                self.assertEqual(queued_packets, 0)
+                self.assertGreaterEqual(buffers_free, queued)
+
+                handle.releaseInterface(0)
+        finally:
+            device.disconnect()
+
+        # disconnecting releases every buffer
+        _, buffers_free, _ = self.packet_buffer_statistics(api)
+        self.assertEqual(buffers_free, pool_size)
+
 
 if __name__ == "__main__":
     unittest.main()
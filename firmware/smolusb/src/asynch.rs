@@ -0,0 +1,169 @@
+//! Async endpoint operations
+//!
+//! [`AsyncUsb`] wraps a driver implementing [`AsyncUsbDriver`] and
+//! provides `async` versions of the blocking endpoint operations.
+//! The futures are woken by the driver's interrupt handler and do not
+//! depend on any particular executor.
+
+use core::future::poll_fn;
+
+use crate::setup::SetupPacket;
+use crate::traits::AsyncUsbDriver;
+
+// - AsyncUsb -----------------------------------------------------------------
+
+/// Async endpoint operations for a USB driver.
+pub struct AsyncUsb<'a, D> {
+    usb: &'a D,
+}
+
+impl<'a, D> AsyncUsb<'a, D>
+where
+    D: AsyncUsbDriver,
+{
+    #[must_use]
+    pub fn new(usb: &'a D) -> Self {
+        Self { usb }
+    }
+
+    /// Returns a reference to the underlying driver.
+    #[must_use]
+    pub fn driver(&self) -> &'a D {
+        self.usb
+    }
+
+    /// Waits for a setup packet on the given control endpoint.
+    pub async fn wait_setup(&self, endpoint_number: u8) -> SetupPacket {
+        poll_fn(|cx| self.usb.poll_setup(endpoint_number, cx)).await
+    }
+
+    /// Primes the given OUT endpoint and waits to receive a packet.
+    ///
+    /// Returns the number of bytes read from the endpoint.
+    pub async fn read(&self, endpoint_number: u8, buffer: &mut [u8]) -> usize {
+        self.usb.ep_out_prime_receive(endpoint_number);
+        poll_fn(|cx| self.usb.poll_read(endpoint_number, cx)).await;
+        self.usb.read(endpoint_number, buffer)
+    }
+
+    /// Writes iterator to endpoint and waits for the host to receive
+    /// the transfer.
+    ///
+    /// Returns the number of bytes written to the endpoint.
+    pub async fn write<I>(&self, endpoint_number: u8, iter: I) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        self.write_with_requested_length(endpoint_number, None, iter)
+            .await
+    }
+
+    /// Writes the requested number of bytes from the iterator to
+    /// endpoint and waits for the host to receive the transfer.
+    ///
+    /// Returns the number of bytes written to the endpoint.
+    pub async fn write_requested<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: usize,
+        iter: I,
+    ) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        self.write_with_requested_length(endpoint_number, Some(requested_length), iter)
+            .await
+    }
+
+    async fn write_with_requested_length<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: Option<usize>,
+        mut iter: I,
+    ) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        let packet_size = self.usb.max_packet_size(endpoint_number);
+        let mut bytes_written: usize = 0;
+
+        // The transfer ends with a short packet, which may be a zlp,
+        // unless a full packet completes the requested length.
+        loop {
+            poll_fn(|cx| self.usb.poll_write(endpoint_number, cx)).await;
+            let packet_length = self
+                .usb
+                .write_packet(endpoint_number, iter.by_ref().take(packet_size));
+            bytes_written += packet_length;
+            if packet_length < packet_size || requested_length == Some(bytes_written) {
+                break;
+            }
+        }
+        poll_fn(|cx| self.usb.poll_write(endpoint_number, cx)).await;
+
+        bytes_written
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::device::Speed;
+    use crate::testing::{block_on, Operation, SimulatedDriver};
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_write() {
+        let usb = SimulatedDriver::new(Speed::Full);
+        let usb_async = AsyncUsb::new(&usb);
+
+        // transfers are split into packets and end with a short packet
+        let data: Vec<u8> = (0..100).collect();
+        let bytes_written = block_on(usb_async.write(1, data.iter().copied()));
+        assert_eq!(bytes_written, 100);
+        assert_eq!(
+            usb.take_operations(),
+            [
+                Operation::Write(1, data[..64].to_vec()),
+                Operation::Write(1, data[64..].to_vec()),
+            ]
+        );
+
+        // a transfer ending on a full packet is terminated with a zlp
+        let bytes_written = block_on(usb_async.write(1, [0xaa; 128].into_iter()));
+        assert_eq!(bytes_written, 128);
+        assert_eq!(
+            usb.packets_written(1),
+            [vec![0xaa; 64], vec![0xaa; 64], vec![]]
+        );
+        usb.take_operations();
+
+        // ...unless it completes the requested length
+        let bytes_written = block_on(usb_async.write_requested(0, 64, [0x55; 64].into_iter()));
+        assert_eq!(bytes_written, 64);
+        assert_eq!(usb.packets_written(0), [vec![0x55; 64]]);
+    }
+
+    #[test]
+    fn test_read_and_wait_setup() {
+        let usb = SimulatedDriver::new(Speed::Full);
+        let usb_async = AsyncUsb::new(&usb);
+
+        let setup_packet = SetupPacket::set_configuration(1);
+        usb.host_setup(0, setup_packet);
+        assert_eq!(block_on(usb_async.wait_setup(0)), setup_packet);
+
+        usb.host_out(2, b"smol");
+        let mut buffer = [0; 64];
+        let bytes_read = block_on(usb_async.read(2, &mut buffer));
+        assert_eq!(&buffer[..bytes_read], b"smol");
+        assert_eq!(
+            usb.take_operations(),
+            [Operation::Prime(2), Operation::Read(2, b"smol".to_vec())]
+        );
+    }
+}
@@ -1,3 +1,156 @@
 //! USB device and interface classes
+//!
+//! Class implementations implement [`UsbClass`] so they can be
+//! registered with a [`UsbDevice`](crate::device::UsbDevice), which
+//! routes control requests and endpoint events to them. Composite
+//! devices are assembled from tuples of classes.
 
 pub mod acm;
+pub mod dfu;
+pub mod hid;
+pub mod msc;
+pub mod uac;
+
+use crate::event::UsbEvent;
+use crate::setup::{Recipient, SetupPacket};
+use crate::traits::UsbDriver;
+
+// - UsbClass -----------------------------------------------------------------
+
+/// A USB function made up of one or more interfaces and their endpoints.
+pub trait UsbClass<D>
+where
+    D: UsbDriver,
+{
+    /// Returns `true` if the interface number belongs to the function.
+    fn has_interface(&self, interface_number: u8) -> bool;
+
+    /// Returns `true` if the endpoint address belongs to the function.
+    fn has_endpoint(&self, endpoint_address: u8) -> bool;
+
+    /// Handles a request not handled by [`Control`](crate::control::Control).
+    ///
+    /// Only called for requests addressed to one of the function's
+    /// interfaces or endpoints, or to the device. `endpoint_number` is
+    /// the control endpoint the request was received on and `data`
+    /// holds the data stage of host-to-device requests, which has
+    /// already been acknowledged.
+    ///
+    /// Returns `false` if the request was not handled.
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool;
+
+    /// Called after the host has selected a configuration, or `0` if
+    /// the device was deconfigured.
+    fn set_configuration(&mut self, _usb: &D, _configuration: u8) {}
+
+    /// Called after the host has selected an alternate setting for one
+    /// of the function's interfaces.
+    fn set_alternate_setting(&mut self, _usb: &D, _interface_number: u8, _alternate_setting: u8) {}
+
+    /// Dispatches a bus event or an event for one of the function's
+    /// endpoints.
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent);
+}
+
+/// Returns `true` if the request should be offered to the function.
+///
+/// Requests addressed to the device or to other recipients are offered
+/// to every function.
+pub fn is_recipient<C, D>(class: &C, setup_packet: SetupPacket) -> bool
+where
+    C: UsbClass<D> + ?Sized,
+    D: UsbDriver,
+{
+    match setup_packet.recipient() {
+        Recipient::Interface => class.has_interface(setup_packet.interface_number()),
+        Recipient::Endpoint => class.has_endpoint(setup_packet.endpoint_address()),
+        _ => true,
+    }
+}
+
+/// Returns `true` if the event should be dispatched to the function.
+///
+/// Events not associated with an endpoint are dispatched to every
+/// function.
+pub fn is_event_target<C, D>(class: &C, event: UsbEvent) -> bool
+where
+    C: UsbClass<D> + ?Sized,
+    D: UsbDriver,
+{
+    match event {
+        UsbEvent::ReceiveControl(endpoint_number)
+        | UsbEvent::ReceiveSetupPacket(endpoint_number, _)
+        | UsbEvent::ReceivePacket(endpoint_number) => class.has_endpoint(endpoint_number),
+        UsbEvent::SendComplete(endpoint_number) => class.has_endpoint(endpoint_number | 0x80),
+        _ => true,
+    }
+}
+
+// - composite functions ------------------------------------------------------
+
+macro_rules! impl_usb_class_for_tuple {
+    ($($class:ident $index:tt),+) => {
+        impl<D, $($class),+> UsbClass<D> for ($($class,)+)
+        where
+            D: UsbDriver,
+            $($class: UsbClass<D>,)+
+        {
+            fn has_interface(&self, interface_number: u8) -> bool {
+                $(self.$index.has_interface(interface_number))||+
+            }
+
+            fn has_endpoint(&self, endpoint_address: u8) -> bool {
+                $(self.$index.has_endpoint(endpoint_address))||+
+            }
+
+            fn handle_setup_packet(
+                &mut self,
+                usb: &D,
+                endpoint_number: u8,
+                setup_packet: SetupPacket,
+                data: &[u8],
+            ) -> bool {
+                $(
+                    if is_recipient(&self.$index, setup_packet)
+                        && self.$index.handle_setup_packet(usb, endpoint_number, setup_packet, data)
+                    {
+                        return true;
+                    }
+                )+
+                false
+            }
+
+            fn set_configuration(&mut self, usb: &D, configuration: u8) {
+                $(self.$index.set_configuration(usb, configuration);)+
+            }
+
+            fn set_alternate_setting(&mut self, usb: &D, interface_number: u8, alternate_setting: u8) {
+                $(
+                    if self.$index.has_interface(interface_number) {
+                        self.$index.set_alternate_setting(usb, interface_number, alternate_setting);
+                    }
+                )+
+            }
+
+            fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+                $(
+                    if is_event_target(&self.$index, event) {
+                        self.$index.dispatch_event(usb, event);
+                    }
+                )+
+            }
+        }
+    };
+}
+
+impl_usb_class_for_tuple!(A 0, B 1);
+impl_usb_class_for_tuple!(A 0, B 1, C 2);
+impl_usb_class_for_tuple!(A 0, B 1, C 2, E 3);
+impl_usb_class_for_tuple!(A 0, B 1, C 2, E 3, F 4);
+impl_usb_class_for_tuple!(A 0, B 1, C 2, E 3, F 4, G 5);
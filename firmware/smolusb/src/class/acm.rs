@@ -1,39 +1,17 @@
 use crate::descriptor::{
-    ClassSpecificDescriptor, ConfigurationDescriptor, ConfigurationDescriptorHeader,
+    string_index, ClassSpecificDescriptor, ConfigurationDescriptor, ConfigurationDescriptorHeader,
     DescriptorType, DeviceDescriptor, DeviceQualifierDescriptor, EndpointDescriptor,
     InterfaceDescriptor, InterfaceDescriptorHeader, LanguageId, StringDescriptor,
     StringDescriptorZero,
 };
+use crate::device::Speed;
 
 pub const VENDOR_ID: u16 = 0x1209; // https://pid.codes/1209/
 pub const PRODUCT_ID: u16 = 0x0001; // pid.codes Test PID 1
 
-pub mod serial {
-    /// ACM Serial Class Requests
-    ///
-    /// Just enough of the requests to be usable on the major operating systems.
-    #[derive(Debug, PartialEq)]
-    #[repr(u8)]
-    pub enum ClassRequest {
-        SetLineCoding = 0x20,       //  32
-        GetLineCoding = 0x21,       //  33
-        SetControlLineState = 0x22, //  34
-        SendBreak = 0x23,           //  35
-        Unknown(u8),
-    }
+pub mod serial;
 
-    impl From<u8> for ClassRequest {
-        fn from(value: u8) -> Self {
-            match value {
-                0x20 => ClassRequest::SetLineCoding,
-                0x21 => ClassRequest::GetLineCoding,
-                0x22 => ClassRequest::SetControlLineState,
-                0x23 => ClassRequest::SendBreak,
-                _ => ClassRequest::Unknown(value),
-            }
-        }
-    }
-}
+pub use serial::{AcmSerial, LineCoding, SerialState};
 
 pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     bcdUSB: 0x0200,
@@ -44,9 +22,9 @@ pub const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
     idVendor: VENDOR_ID,
     idProduct: PRODUCT_ID,
     bcdDevice: 0x0001,
-    iManufacturer: 1,
-    iProduct: 2,
-    iSerialNumber: 3,
+    iManufacturer: string_index(STRING_DESCRIPTORS, MANUFACTURER),
+    iProduct: string_index(STRING_DESCRIPTORS, PRODUCT),
+    iSerialNumber: string_index(STRING_DESCRIPTORS, SERIAL_NUMBER),
     bNumConfigurations: 1,
     ..DeviceDescriptor::new()
 };
@@ -66,7 +44,7 @@ pub const CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDes
     ConfigurationDescriptorHeader {
         bDescriptorType: DescriptorType::Configuration as u8,
         bConfigurationValue: 1,
-        iConfiguration: 4,
+        iConfiguration: string_index(STRING_DESCRIPTORS, CONFIGURATION_0),
         bmAttributes: 0x80, // 0b1000_0000 = bus-powered
         bMaxPower: 50,      // 50 * 2 mA = 100 mA
         ..ConfigurationDescriptorHeader::new()
@@ -80,7 +58,7 @@ pub const CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDes
                 bInterfaceClass: 0x02, // Communications-Control
                 bInterfaceSubClass: 0x02,
                 bInterfaceProtocol: 0x01,
-                iInterface: 5,
+                iInterface: string_index(STRING_DESCRIPTORS, INTERFACE_0),
                 ..InterfaceDescriptorHeader::new()
             },
             &[
@@ -103,13 +81,7 @@ pub const CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDes
                     ..ClassSpecificDescriptor::new()
                 },
             ],
-            &[EndpointDescriptor {
-                bEndpointAddress: 0x83, // IN
-                bmAttributes: 0x03,     // Interrupt
-                wMaxPacketSize: 64,
-                bInterval: 11,
-                ..EndpointDescriptor::new()
-            }],
+            &[EndpointDescriptor::interrupt(0x83, 64, 11)],
         ),
         // Interface #1 - Communications-Data/Unknown Comm Class Model
         InterfaceDescriptor::new(
@@ -119,35 +91,24 @@ pub const CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor = ConfigurationDes
                 bInterfaceClass: 0x0a,    // Communications-Data
                 bInterfaceSubClass: 0x00, // Unknown Comm Class Model
                 bInterfaceProtocol: 0x00,
-                iInterface: 6,
+                iInterface: string_index(STRING_DESCRIPTORS, INTERFACE_1),
                 ..InterfaceDescriptorHeader::new()
             },
             &[
-                EndpointDescriptor {
-                    bEndpointAddress: 0x84, // IN
-                    bmAttributes: 0x02,     // Bulk
-                    wMaxPacketSize: 64,
-                    bInterval: 255,
-                    ..EndpointDescriptor::new()
-                },
-                EndpointDescriptor {
-                    bEndpointAddress: 0x04, // OUT
-                    bmAttributes: 0x02,     // Bulk
-                    wMaxPacketSize: 512,    // 512 ?
-                    bInterval: 255,
-                    ..EndpointDescriptor::new()
-                },
+                EndpointDescriptor::bulk(0x84, 512), // IN
+                EndpointDescriptor::bulk(0x04, 512), // OUT
             ],
         ),
     ],
-);
+)
+.validate(Speed::High);
 
 pub const OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
     ConfigurationDescriptor::new(
         ConfigurationDescriptorHeader {
             bDescriptorType: DescriptorType::Configuration as u8,
             bConfigurationValue: 1,
-            iConfiguration: 4,
+            iConfiguration: string_index(STRING_DESCRIPTORS, CONFIGURATION_0),
             bmAttributes: 0x80, // 0b1000_0000 = bus-powered
             bMaxPower: 50,      // 50 * 2 mA = 100 mA
             ..ConfigurationDescriptorHeader::new()
@@ -161,7 +122,7 @@ pub const OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
                     bInterfaceClass: 0x02, // Communications-Control
                     bInterfaceSubClass: 0x02,
                     bInterfaceProtocol: 0x01,
-                    iInterface: 5,
+                    iInterface: string_index(STRING_DESCRIPTORS, INTERFACE_0),
                     ..InterfaceDescriptorHeader::new()
                 },
                 &[
@@ -184,13 +145,7 @@ pub const OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
                         ..ClassSpecificDescriptor::new()
                     },
                 ],
-                &[EndpointDescriptor {
-                    bEndpointAddress: 0x83, // IN
-                    bmAttributes: 0x02,     // Interrupt
-                    wMaxPacketSize: 64,
-                    bInterval: 11,
-                    ..EndpointDescriptor::new()
-                }],
+                &[EndpointDescriptor::interrupt(0x83, 64, 11)],
             ),
             // Interface #1 - Communications-Data/Unknown Comm Class Model
             InterfaceDescriptor::new(
@@ -200,38 +155,38 @@ pub const OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
                     bInterfaceClass: 0x0a,    // Communications-Data
                     bInterfaceSubClass: 0x00, // Unknown Comm Class Model
                     bInterfaceProtocol: 0x00,
-                    iInterface: 6,
+                    iInterface: string_index(STRING_DESCRIPTORS, INTERFACE_1),
                     ..InterfaceDescriptorHeader::new()
                 },
                 &[
-                    EndpointDescriptor {
-                        bEndpointAddress: 0x84, // IN
-                        bmAttributes: 0x02,     // Bulk
-                        wMaxPacketSize: 64,
-                        bInterval: 255,
-                        ..EndpointDescriptor::new()
-                    },
-                    EndpointDescriptor {
-                        bEndpointAddress: 0x04, // OUT
-                        bmAttributes: 0x02,     // Bulk
-                        wMaxPacketSize: 64,
-                        bInterval: 255,
-                        ..EndpointDescriptor::new()
-                    },
+                    EndpointDescriptor::bulk(0x84, 64), // IN
+                    EndpointDescriptor::bulk(0x04, 64), // OUT
                 ],
             ),
         ],
-    );
+    )
+    .validate(Speed::Full);
+
+pub const CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] = &[CONFIGURATION_DESCRIPTOR_0];
+pub const OTHER_SPEED_CONFIGURATION_DESCRIPTORS: &[ConfigurationDescriptor] =
+    &[OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0];
 
 pub const STRING_DESCRIPTOR_0: StringDescriptorZero =
     StringDescriptorZero::new(&[LanguageId::EnglishUnitedStates]);
 
-pub const STRING_DESCRIPTOR_1: StringDescriptor = StringDescriptor::new("Cynthion Project");
-pub const STRING_DESCRIPTOR_2: StringDescriptor = StringDescriptor::new("USB-to-serial");
-pub const STRING_DESCRIPTOR_3: StringDescriptor = StringDescriptor::new("100");
-pub const STRING_DESCRIPTOR_4: StringDescriptor = StringDescriptor::new("iConfiguration 0"); // iConfiguration #0
-pub const STRING_DESCRIPTOR_5: StringDescriptor = StringDescriptor::new("iInterface 0"); // iInterface #0
-pub const STRING_DESCRIPTOR_6: StringDescriptor = StringDescriptor::new("iInterface 1"); // iInterface #1
+pub const MANUFACTURER: &str = "Cynthion Project";
+pub const PRODUCT: &str = "USB-to-serial";
+pub const SERIAL_NUMBER: &str = "100";
+pub const CONFIGURATION_0: &str = "iConfiguration 0";
+pub const INTERFACE_0: &str = "iInterface 0";
+pub const INTERFACE_1: &str = "iInterface 1";
+
+pub const STRING_DESCRIPTOR_1: StringDescriptor = StringDescriptor::new(MANUFACTURER);
+pub const STRING_DESCRIPTOR_2: StringDescriptor = StringDescriptor::new(PRODUCT);
+pub const STRING_DESCRIPTOR_3: StringDescriptor = StringDescriptor::new(SERIAL_NUMBER);
+pub const STRING_DESCRIPTOR_4: StringDescriptor = StringDescriptor::new(CONFIGURATION_0);
+pub const STRING_DESCRIPTOR_5: StringDescriptor = StringDescriptor::new(INTERFACE_0);
+pub const STRING_DESCRIPTOR_6: StringDescriptor = StringDescriptor::new(INTERFACE_1);
 
 pub const STRING_DESCRIPTORS: &[&StringDescriptor] = &[
     &STRING_DESCRIPTOR_1,
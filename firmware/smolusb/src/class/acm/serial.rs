@@ -0,0 +1,809 @@
+//! CDC-ACM virtual serial port
+
+#![allow(non_snake_case)]
+
+use log::{trace, warn};
+use zerocopy::byteorder::{LittleEndian, U32};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::class::UsbClass;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, Recipient, Request, RequestType, SetupPacket};
+use crate::traits::UsbDriver;
+
+// - ClassRequest -------------------------------------------------------------
+
+/// ACM Serial Class Requests
+///
+/// Just enough of the requests to be usable on the major operating systems.
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum ClassRequest {
+    SetLineCoding = 0x20,       //  32
+    GetLineCoding = 0x21,       //  33
+    SetControlLineState = 0x22, //  34
+    SendBreak = 0x23,           //  35
+    Unknown(u8),
+}
+
+impl From<u8> for ClassRequest {
+    fn from(value: u8) -> Self {
+        match value {
+            0x20 => ClassRequest::SetLineCoding,
+            0x21 => ClassRequest::GetLineCoding,
+            0x22 => ClassRequest::SetControlLineState,
+            0x23 => ClassRequest::SendBreak,
+            _ => ClassRequest::Unknown(value),
+        }
+    }
+}
+
+impl From<ClassRequest> for u8 {
+    fn from(class_request: ClassRequest) -> Self {
+        match class_request {
+            ClassRequest::SetLineCoding => 0x20,
+            ClassRequest::GetLineCoding => 0x21,
+            ClassRequest::SetControlLineState => 0x22,
+            ClassRequest::SendBreak => 0x23,
+            ClassRequest::Unknown(value) => value,
+        }
+    }
+}
+
+/// ACM Serial Class Notifications
+const NOTIFICATION_SERIAL_STATE: u8 = 0x20;
+
+// - LineCoding ---------------------------------------------------------------
+
+/// Line coding structure exchanged by `GET_LINE_CODING` and `SET_LINE_CODING`.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(C)]
+pub struct LineCoding {
+    /// Data terminal rate in bits per second
+    pub dwDTERate: U32<LittleEndian>,
+    /// 0 = 1 stop bit, 1 = 1.5 stop bits, 2 = 2 stop bits
+    pub bCharFormat: u8,
+    /// 0 = None, 1 = Odd, 2 = Even, 3 = Mark, 4 = Space
+    pub bParityType: u8,
+    /// 5, 6, 7, 8 or 16
+    pub bDataBits: u8,
+}
+
+impl Default for LineCoding {
+    /// 115200 baud, 8N1
+    fn default() -> Self {
+        Self::new(115_200, 0, 0, 8)
+    }
+}
+
+impl LineCoding {
+    #[must_use]
+    pub const fn new(data_rate: u32, char_format: u8, parity_type: u8, data_bits: u8) -> Self {
+        Self {
+            dwDTERate: U32::from_bytes(data_rate.to_le_bytes()),
+            bCharFormat: char_format,
+            bParityType: parity_type,
+            bDataBits: data_bits,
+        }
+    }
+
+    /// Returns the data terminal rate in bits per second.
+    #[must_use]
+    pub fn data_rate(&self) -> u32 {
+        self.dwDTERate.get()
+    }
+}
+
+// - SerialState --------------------------------------------------------------
+
+/// `SERIAL_STATE` notification bitmap.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SerialState(pub u16);
+
+impl SerialState {
+    /// `bRxCarrier`: state of the receiver carrier detection mechanism (DCD)
+    pub const RX_CARRIER: Self = Self(1 << 0);
+    /// `bTxCarrier`: state of the transmission carrier (DSR)
+    pub const TX_CARRIER: Self = Self(1 << 1);
+    /// `bBreak`: a break has been detected
+    pub const BREAK: Self = Self(1 << 2);
+    /// `bRingSignal`: a ring signal has been detected
+    pub const RING_SIGNAL: Self = Self(1 << 3);
+    /// `bFraming`: a framing error has occurred
+    pub const FRAMING: Self = Self(1 << 4);
+    /// `bParity`: a parity error has occurred
+    pub const PARITY: Self = Self(1 << 5);
+    /// `bOverRun`: received data has been discarded due to an overrun
+    pub const OVERRUN: Self = Self(1 << 6);
+
+    /// Returns `true` if all bits set in `other` are also set in `self`.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for SerialState {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+// - RingBuffer ---------------------------------------------------------------
+
+struct RingBuffer<const N: usize> {
+    buffer: [u8; N],
+    head: usize,
+    len: usize,
+}
+
+impl<const N: usize> RingBuffer<N> {
+    const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn available(&self) -> usize {
+        N - self.len
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    fn push(&mut self, byte: u8) -> bool {
+        if self.len == N {
+            return false;
+        }
+        self.buffer[(self.head + self.len) % N] = byte;
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let byte = self.buffer[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+// - AcmSerial ----------------------------------------------------------------
+
+/// Transmit state of the bulk IN endpoint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TxState {
+    /// No packet in flight
+    Idle,
+    /// A packet is in flight
+    Busy,
+    /// A full packet ending the queued data is in flight and must be
+    /// followed by a zlp unless more data is queued in the meantime
+    BusyFull,
+}
+
+/// A CDC-ACM virtual serial port.
+///
+/// `AcmSerial` answers the ACM class requests sent to its
+/// communications interface, buffers the data received on its bulk
+/// OUT endpoint and transmits buffered data on its bulk IN endpoint.
+///
+/// The serial port can be registered with a
+/// [`UsbDevice`](crate::device::UsbDevice), otherwise the application
+/// is responsible for routing events to it: class requests returned by
+/// [`Control::dispatch_event()`](crate::control::Control::dispatch_event)
+/// are passed to [`handle_setup_packet()`](Self::handle_setup_packet)
+/// and all other events to [`dispatch_event()`](Self::dispatch_event).
+///
+/// Received data is read with [`read()`](Self::read), data to be sent
+/// is queued with [`write()`](Self::write). `RX_BUFFER_SIZE` should be
+/// at least as large as the endpoint's maximum packet size as the OUT
+/// endpoint will only be primed while a full packet can be buffered.
+pub struct AcmSerial<const RX_BUFFER_SIZE: usize, const TX_BUFFER_SIZE: usize> {
+    interface_number: u8,
+    notification_endpoint_number: u8,
+    data_endpoint_number: u8,
+    max_packet_size: usize,
+
+    line_coding: LineCoding,
+    control_line_state: u16,
+    break_active: bool,
+
+    serial_state: SerialState,
+    serial_state_in_flight: Option<SerialState>,
+
+    rx_buffer: RingBuffer<RX_BUFFER_SIZE>,
+    rx_primed: bool,
+    tx_buffer: RingBuffer<TX_BUFFER_SIZE>,
+    tx_state: TxState,
+}
+
+impl<const RX_BUFFER_SIZE: usize, const TX_BUFFER_SIZE: usize>
+    AcmSerial<RX_BUFFER_SIZE, TX_BUFFER_SIZE>
+{
+    /// Creates a new serial port for the given communications
+    /// interface, interrupt IN notification endpoint number and bulk
+    /// data endpoint number.
+    #[must_use]
+    pub const fn new(
+        interface_number: u8,
+        notification_endpoint_number: u8,
+        data_endpoint_number: u8,
+    ) -> Self {
+        Self {
+            interface_number,
+            notification_endpoint_number,
+            data_endpoint_number,
+            max_packet_size: crate::EP_MAX_PACKET_SIZE,
+            line_coding: LineCoding::new(115_200, 0, 0, 8),
+            control_line_state: 0,
+            break_active: false,
+            serial_state: SerialState(0),
+            serial_state_in_flight: None,
+            rx_buffer: RingBuffer::new(),
+            rx_primed: false,
+            tx_buffer: RingBuffer::new(),
+            tx_state: TxState::Idle,
+        }
+    }
+
+    /// Sets the maximum packet size of the bulk data endpoints.
+    ///
+    /// Defaults to [`EP_MAX_PACKET_SIZE`](crate::EP_MAX_PACKET_SIZE).
+    #[must_use]
+    pub const fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Returns the line coding last set by the host.
+    #[must_use]
+    pub fn line_coding(&self) -> LineCoding {
+        self.line_coding
+    }
+
+    /// Returns the state of the Data Terminal Ready signal.
+    ///
+    /// Most hosts assert DTR while the port is open.
+    #[must_use]
+    pub fn dtr(&self) -> bool {
+        self.control_line_state & 0b01 != 0
+    }
+
+    /// Returns the state of the Request To Send signal.
+    #[must_use]
+    pub fn rts(&self) -> bool {
+        self.control_line_state & 0b10 != 0
+    }
+
+    /// Returns `true` while the host is sending a break.
+    #[must_use]
+    pub fn break_active(&self) -> bool {
+        self.break_active
+    }
+
+    /// Returns the last serial state reported to the host.
+    #[must_use]
+    pub fn serial_state(&self) -> SerialState {
+        self.serial_state
+    }
+
+    /// Returns the number of received bytes waiting to be read.
+    #[must_use]
+    pub fn rx_len(&self) -> usize {
+        self.rx_buffer.len()
+    }
+
+    /// Returns the number of bytes waiting to be sent to the host.
+    #[must_use]
+    pub fn tx_len(&self) -> usize {
+        self.tx_buffer.len()
+    }
+}
+
+impl<const RX_BUFFER_SIZE: usize, const TX_BUFFER_SIZE: usize>
+    AcmSerial<RX_BUFFER_SIZE, TX_BUFFER_SIZE>
+{
+    /// Resets the serial port and primes the data OUT endpoint.
+    ///
+    /// Called on bus reset and should also be called once the host
+    /// has selected the configuration containing the serial port.
+    pub fn reset<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        self.control_line_state = 0;
+        self.break_active = false;
+        self.serial_state_in_flight = None;
+        self.rx_buffer.clear();
+        self.tx_buffer.clear();
+        self.tx_state = TxState::Idle;
+
+        self.rx_primed = false;
+        self.prime_receive(usb);
+    }
+
+    /// Handles a class request addressed to the serial port's
+    /// communications interface.
+    ///
+    /// `endpoint_number` is the control endpoint the request was
+    /// received on, as returned by
+    /// [`Control::endpoint_number()`](crate::control::Control::endpoint_number).
+    /// `data` contains the data stage of the request, if any, as
+    /// returned by [`Control::data()`](crate::control::Control::data).
+    ///
+    /// Returns `false` if the request was not addressed to the serial port.
+    pub fn handle_setup_packet<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool
+    where
+        D: UsbDriver,
+    {
+        let Request::ClassOrVendor(request) = setup_packet.request() else {
+            return false;
+        };
+        if setup_packet.request_type() != RequestType::Class
+            || setup_packet.recipient() != Recipient::Interface
+            || setup_packet.interface_number() != self.interface_number
+        {
+            return false;
+        }
+
+        let class_request = ClassRequest::from(request);
+        trace!("AcmSerial {:?} {:?}", class_request, setup_packet);
+
+        match (setup_packet.direction(), class_request) {
+            (Direction::HostToDevice, ClassRequest::SetLineCoding) => {
+                // the data stage has already been acknowledged by Control
+                match LineCoding::read_from_prefix(data) {
+                    Some(line_coding) => self.line_coding = line_coding,
+                    None => warn!("AcmSerial SET_LINE_CODING short data stage: {:?}", data),
+                }
+            }
+            (Direction::DeviceToHost, ClassRequest::GetLineCoding) => {
+                // prime to receive host zlp
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    usize::from(setup_packet.length()),
+                    self.line_coding.as_bytes().iter().copied(),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::SetControlLineState) => {
+                self.control_line_state = setup_packet.value();
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (Direction::HostToDevice, ClassRequest::SendBreak) => {
+                self.break_active = setup_packet.value() != 0;
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (direction, class_request) => {
+                warn!(
+                    "AcmSerial stall: unhandled class request {:?} {:?}",
+                    direction, class_request
+                );
+                usb.stall_endpoint_in(endpoint_number);
+            }
+        }
+
+        true
+    }
+
+    /// Dispatches an event to the serial port.
+    ///
+    /// Events for endpoints not belonging to the serial port are ignored.
+    pub fn dispatch_event<D>(&mut self, usb: &D, event: UsbEvent)
+    where
+        D: UsbDriver,
+    {
+        match event {
+            UsbEvent::BusReset => self.reset(usb),
+
+            UsbEvent::ReceivePacket(endpoint_number)
+                if endpoint_number == self.data_endpoint_number =>
+            {
+                let mut packet_buffer = [0; crate::EP_MAX_PACKET_SIZE];
+                let bytes_read = usb.read(endpoint_number, &mut packet_buffer);
+                self.receive(&packet_buffer[..bytes_read]);
+                self.rx_primed = false;
+                self.prime_receive(usb);
+            }
+
+            #[cfg(feature = "chonky_events")]
+            UsbEvent::ReceiveBuffer(endpoint_number, bytes_read, packet_buffer)
+                if endpoint_number == self.data_endpoint_number =>
+            {
+                self.receive(&packet_buffer[..bytes_read]);
+                self.rx_primed = false;
+                self.prime_receive(usb);
+            }
+
+            UsbEvent::SendComplete(endpoint_number)
+                if endpoint_number == self.data_endpoint_number =>
+            {
+                let zlp = self.tx_state == TxState::BusyFull;
+                self.tx_state = TxState::Idle;
+                self.transmit(usb, zlp);
+            }
+
+            UsbEvent::SendComplete(endpoint_number)
+                if endpoint_number == self.notification_endpoint_number =>
+            {
+                if let Some(in_flight) = self.serial_state_in_flight.take() {
+                    if in_flight != self.serial_state {
+                        self.send_serial_state(usb);
+                    }
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Reads received data into `buffer`.
+    ///
+    /// Returns the number of bytes read.
+    pub fn read<D>(&mut self, usb: &D, buffer: &mut [u8]) -> usize
+    where
+        D: UsbDriver,
+    {
+        let mut bytes_read = 0;
+        for (slot, byte) in buffer
+            .iter_mut()
+            .zip(core::iter::from_fn(|| self.rx_buffer.pop()))
+        {
+            *slot = byte;
+            bytes_read += 1;
+        }
+        self.prime_receive(usb);
+        bytes_read
+    }
+
+    /// Queues `data` for transmission to the host.
+    ///
+    /// Returns the number of bytes queued, which will be less than
+    /// `data.len()` if the transmit buffer is full.
+    pub fn write<D>(&mut self, usb: &D, data: &[u8]) -> usize
+    where
+        D: UsbDriver,
+    {
+        let bytes_queued = data
+            .iter()
+            .take_while(|byte| self.tx_buffer.push(**byte))
+            .count();
+        self.flush(usb);
+        bytes_queued
+    }
+
+    /// Starts transmitting queued data if the bulk IN endpoint is idle.
+    ///
+    /// Transfers ending on a packet boundary are terminated with a
+    /// zero-length packet.
+    pub fn flush<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        if self.tx_state == TxState::Idle {
+            self.transmit(usb, false);
+        }
+    }
+
+    /// Reports a new serial state to the host with a `SERIAL_STATE` notification.
+    ///
+    /// Notifications are only sent when the state changes. If a
+    /// notification is still in flight the latest state will be sent
+    /// once it has completed.
+    pub fn set_serial_state<D>(&mut self, usb: &D, serial_state: SerialState)
+    where
+        D: UsbDriver,
+    {
+        if serial_state == self.serial_state {
+            return;
+        }
+        self.serial_state = serial_state;
+        if self.serial_state_in_flight.is_none() {
+            self.send_serial_state(usb);
+        }
+    }
+}
+
+impl<const RX_BUFFER_SIZE: usize, const TX_BUFFER_SIZE: usize>
+    AcmSerial<RX_BUFFER_SIZE, TX_BUFFER_SIZE>
+{
+    fn receive(&mut self, data: &[u8]) {
+        let bytes_received = data
+            .iter()
+            .take_while(|byte| self.rx_buffer.push(**byte))
+            .count();
+        if bytes_received < data.len() {
+            warn!(
+                "AcmSerial receive buffer overflow, dropped {} bytes",
+                data.len() - bytes_received
+            );
+        }
+    }
+
+    /// Writes the next packet of queued data, or a zlp if `zlp` is set
+    /// and no data is queued.
+    fn transmit<D>(&mut self, usb: &D, zlp: bool)
+    where
+        D: UsbDriver,
+    {
+        let packet_length = self.tx_buffer.len().min(self.max_packet_size);
+        if packet_length == 0 && !zlp {
+            return;
+        }
+
+        // write exactly one packet, write() would append its own zlp
+        let tx_buffer = &mut self.tx_buffer;
+        usb.write_requested(
+            self.data_endpoint_number,
+            packet_length,
+            core::iter::from_fn(|| tx_buffer.pop()).take(packet_length),
+        );
+        self.tx_state = if packet_length == self.max_packet_size && self.tx_buffer.len() == 0 {
+            TxState::BusyFull
+        } else {
+            TxState::Busy
+        };
+    }
+
+    /// Primes the data OUT endpoint if a full packet can be buffered.
+    fn prime_receive<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        if !self.rx_primed && self.rx_buffer.available() >= self.max_packet_size {
+            usb.ep_out_prime_receive(self.data_endpoint_number);
+            self.rx_primed = true;
+        }
+    }
+
+    fn send_serial_state<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        let header = SetupPacket::new(
+            SetupPacket::request_type_bits(
+                Direction::DeviceToHost,
+                RequestType::Class,
+                Recipient::Interface,
+            ),
+            NOTIFICATION_SERIAL_STATE,
+            0,
+            u16::from(self.interface_number),
+            2,
+        );
+        let notification = SetupPacket::as_bytes(header)
+            .into_iter()
+            .chain(self.serial_state.0.to_le_bytes());
+        usb.write(self.notification_endpoint_number, notification);
+        self.serial_state_in_flight = Some(self.serial_state);
+    }
+}
+
+// - UsbClass -----------------------------------------------------------------
+
+/// The data interface is expected to follow the communications interface.
+impl<D, const RX_BUFFER_SIZE: usize, const TX_BUFFER_SIZE: usize> UsbClass<D>
+    for AcmSerial<RX_BUFFER_SIZE, TX_BUFFER_SIZE>
+where
+    D: UsbDriver,
+{
+    fn has_interface(&self, interface_number: u8) -> bool {
+        interface_number == self.interface_number
+            || Some(interface_number) == self.interface_number.checked_add(1)
+    }
+
+    fn has_endpoint(&self, endpoint_address: u8) -> bool {
+        endpoint_address == self.notification_endpoint_number | 0x80
+            || endpoint_address & 0x7f == self.data_endpoint_number
+    }
+
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool {
+        Self::handle_setup_packet(self, usb, endpoint_number, setup_packet, data)
+    }
+
+    fn set_configuration(&mut self, usb: &D, configuration: u8) {
+        if configuration != 0 {
+            self.reset(usb);
+        }
+    }
+
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+        Self::dispatch_event(self, usb, event);
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::class::acm;
+    use crate::device::Descriptors;
+    use crate::testing::{class_request, descriptors, ClassHarness};
+
+    // - fixtures -------------------------------------------------------------
+
+    type Serial = AcmSerial<128, 128>;
+
+    fn setup() -> ClassHarness<Serial> {
+        ClassHarness::new(
+            Descriptors {
+                string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
+                string_descriptors: acm::STRING_DESCRIPTORS,
+                ..descriptors(
+                    acm::DEVICE_DESCRIPTOR,
+                    acm::OTHER_SPEED_CONFIGURATION_DESCRIPTORS,
+                )
+            },
+            Serial::new(0, 3, 4).with_max_packet_size(64),
+        )
+    }
+
+    fn acm_request(
+        direction: Direction,
+        request: ClassRequest,
+        value: u16,
+        length: u16,
+    ) -> SetupPacket {
+        class_request(direction, Recipient::Interface, request, value, 0, length)
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_line_coding_and_control_line_state() {
+        let mut harness = setup();
+        assert_eq!(harness.classes().line_coding(), LineCoding::default());
+
+        // SET_LINE_CODING: 9600 7E1
+        let line_coding = LineCoding::new(9600, 0, 2, 7);
+        let setup_packet = acm_request(Direction::HostToDevice, ClassRequest::SetLineCoding, 0, 7);
+        harness.request(setup_packet, line_coding.as_bytes());
+        assert_eq!(harness.classes().line_coding(), line_coding);
+        assert_eq!(harness.classes().line_coding().data_rate(), 9600);
+        assert_eq!(harness.usb.packets_written(0), [Vec::<u8>::new()]);
+
+        // GET_LINE_CODING
+        let setup_packet = acm_request(Direction::DeviceToHost, ClassRequest::GetLineCoding, 0, 7);
+        harness.request(setup_packet, &[]);
+        assert_eq!(
+            harness.usb.packets_written(0),
+            [line_coding.as_bytes().to_vec()]
+        );
+        assert!(harness.usb.is_primed(0));
+        harness.usb.host_out(0, &[]);
+        harness.dispatch();
+
+        // SET_CONTROL_LINE_STATE: DTR
+        let setup_packet = acm_request(
+            Direction::HostToDevice,
+            ClassRequest::SetControlLineState,
+            0b01,
+            0,
+        );
+        harness.request(setup_packet, &[]);
+        assert!(harness.classes().dtr());
+        assert!(!harness.classes().rts());
+        assert_eq!(harness.usb.packets_written(0), [Vec::<u8>::new()]);
+
+        // SEND_BREAK
+        let setup_packet = acm_request(Direction::HostToDevice, ClassRequest::SendBreak, 0xffff, 0);
+        harness.request(setup_packet, &[]);
+        assert!(harness.classes().break_active());
+
+        // unsupported requests are stalled
+        let setup_packet = acm_request(Direction::DeviceToHost, ClassRequest::Unknown(0x42), 0, 2);
+        harness.request(setup_packet, &[]);
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+
+        // requests for other interfaces are left to the application
+        let setup_packet = SetupPacket::new(0x21, ClassRequest::SendBreak.into(), 0, 1, 0);
+        harness.usb.host_setup(0, setup_packet);
+        assert_eq!(harness.dispatch(), [setup_packet]);
+
+        // bus reset clears the line state
+        harness.usb.host_bus_reset();
+        harness.dispatch();
+        assert!(!harness.classes().dtr());
+        assert!(!harness.classes().break_active());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut harness = setup();
+        assert!(harness.usb.is_primed(4));
+
+        // receive
+        harness.usb.host_out(4, b"hello");
+        harness.dispatch();
+        let mut buffer = [0; 16];
+        let (usb, serial) = harness.split();
+        assert_eq!(serial.read(usb, &mut buffer), 5);
+        assert_eq!(&buffer[..5], b"hello");
+
+        // OUT endpoint is not primed while a full packet can't be buffered
+        harness.usb.host_out(4, &[1; 64]);
+        harness.usb.host_out(4, &[2; 64]);
+        harness.dispatch();
+        let (usb, serial) = harness.split();
+        assert_eq!(serial.rx_len(), 128);
+        assert!(!usb.is_primed(4));
+        assert_eq!(serial.read(usb, &mut buffer), 16);
+        assert!(!usb.is_primed(4));
+        while serial.read(usb, &mut buffer) > 0 {}
+        assert!(usb.is_primed(4));
+        usb.take_operations();
+
+        // transfers are split into packets and terminated with a zlp
+        assert_eq!(serial.write(usb, &[0xaa; 64]), 64);
+        harness.dispatch();
+        assert_eq!(harness.usb.packets_written(4), [vec![0xaa; 64], vec![]]);
+        harness.usb.take_operations();
+
+        let (usb, serial) = harness.split();
+        assert_eq!(serial.write(usb, &[0x55; 200]), 128);
+        assert_eq!(serial.tx_len(), 64);
+        harness.dispatch();
+        assert_eq!(harness.classes().tx_len(), 0);
+        assert_eq!(
+            harness.usb.packets_written(4),
+            [vec![0x55; 64], vec![0x55; 64], vec![]]
+        );
+        harness.usb.take_operations();
+
+        let (usb, serial) = harness.split();
+        assert_eq!(serial.write(usb, b"abc"), 3);
+        harness.dispatch();
+        assert_eq!(harness.usb.packets_written(4), [b"abc".to_vec()]);
+    }
+
+    #[test]
+    fn test_serial_state_notification() {
+        let mut harness = setup();
+
+        let state = SerialState::RX_CARRIER | SerialState::TX_CARRIER;
+        let (usb, serial) = harness.split();
+        serial.set_serial_state(usb, state);
+        serial.set_serial_state(usb, state);
+        serial.set_serial_state(usb, SerialState::RX_CARRIER);
+        assert_eq!(
+            usb.packets_written(3),
+            [[0xa1, 0x20, 0x00, 0x00, 0x00, 0x00, 0x02, 0x00, 0x03, 0x00]]
+        );
+
+        // the latest state is sent once the previous notification completes
+        harness.dispatch();
+        assert_eq!(harness.usb.packets_written(3).len(), 2);
+        assert_eq!(harness.usb.packets_written(3)[1][8..], [0x01, 0x00]);
+        let serial_state = harness.classes().serial_state();
+        assert!(serial_state.contains(SerialState::RX_CARRIER));
+        assert!(!serial_state.contains(SerialState::TX_CARRIER));
+    }
+}
@@ -0,0 +1,1098 @@
+//! USB Device Firmware Upgrade class
+
+#![allow(non_snake_case)]
+
+use log::{trace, warn};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::class::UsbClass;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, Recipient, RequestType, SetupPacket};
+use crate::traits::{AsByteSliceIterator, UsbDriver};
+
+/// `bInterfaceClass` for DFU interfaces
+pub const INTERFACE_CLASS: u8 = 0xfe;
+/// `bInterfaceSubClass` for DFU interfaces
+pub const INTERFACE_SUBCLASS: u8 = 0x01;
+/// `bInterfaceProtocol` for the run-time DFU interface of an application
+pub const INTERFACE_PROTOCOL_RUNTIME: u8 = 0x01;
+/// `bInterfaceProtocol` for the DFU interface of a device in DFU mode
+pub const INTERFACE_PROTOCOL_DFU_MODE: u8 = 0x02;
+/// `bDescriptorType` of the DFU functional descriptor
+pub const DESCRIPTOR_TYPE_DFU_FUNCTIONAL: u8 = 0x21;
+
+// - ClassRequest -------------------------------------------------------------
+
+/// DFU Class Requests
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum ClassRequest {
+    Detach = 0x00,      // 0
+    Download = 0x01,    // 1
+    Upload = 0x02,      // 2
+    GetStatus = 0x03,   // 3
+    ClearStatus = 0x04, // 4
+    GetState = 0x05,    // 5
+    Abort = 0x06,       // 6
+    Unknown(u8),
+}
+
+impl From<u8> for ClassRequest {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => ClassRequest::Detach,
+            0x01 => ClassRequest::Download,
+            0x02 => ClassRequest::Upload,
+            0x03 => ClassRequest::GetStatus,
+            0x04 => ClassRequest::ClearStatus,
+            0x05 => ClassRequest::GetState,
+            0x06 => ClassRequest::Abort,
+            _ => ClassRequest::Unknown(value),
+        }
+    }
+}
+
+impl From<ClassRequest> for u8 {
+    fn from(class_request: ClassRequest) -> Self {
+        match class_request {
+            ClassRequest::Detach => 0x00,
+            ClassRequest::Download => 0x01,
+            ClassRequest::Upload => 0x02,
+            ClassRequest::GetStatus => 0x03,
+            ClassRequest::ClearStatus => 0x04,
+            ClassRequest::GetState => 0x05,
+            ClassRequest::Abort => 0x06,
+            ClassRequest::Unknown(value) => value,
+        }
+    }
+}
+
+// - State / Status -----------------------------------------------------------
+
+/// DFU device state reported by `DFU_GETSTATUS` and `DFU_GETSTATE`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum State {
+    AppIdle = 0,
+    AppDetach = 1,
+    DfuIdle = 2,
+    DownloadSync = 3,
+    DownloadBusy = 4,
+    DownloadIdle = 5,
+    ManifestSync = 6,
+    Manifest = 7,
+    ManifestWaitReset = 8,
+    UploadIdle = 9,
+    Error = 10,
+}
+
+impl State {
+    /// Returns `true` if the device is operating in run-time mode.
+    #[must_use]
+    pub const fn is_runtime(self) -> bool {
+        matches!(self, State::AppIdle | State::AppDetach)
+    }
+}
+
+/// DFU status reported by `DFU_GETSTATUS`.
+///
+/// Also used by [`DfuTarget`] implementations to report errors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Status {
+    Ok = 0x00,
+    /// File is not targeted for use by this device
+    ErrTarget = 0x01,
+    /// File is for this device but fails some vendor-specific verification test
+    ErrFile = 0x02,
+    /// Device is unable to write memory
+    ErrWrite = 0x03,
+    /// Memory erase function failed
+    ErrErase = 0x04,
+    /// Memory erase check failed
+    ErrCheckErased = 0x05,
+    /// Program memory function failed
+    ErrProg = 0x06,
+    /// Programmed memory failed verification
+    ErrVerify = 0x07,
+    /// Cannot program memory due to received address that is out of range
+    ErrAddress = 0x08,
+    /// Received `DFU_DNLOAD` with `wLength` = 0, but device does not
+    /// think it has all of the data yet
+    ErrNotDone = 0x09,
+    /// Device's firmware is corrupt
+    ErrFirmware = 0x0a,
+    /// Vendor-specific error
+    ErrVendor = 0x0b,
+    /// Device detected unexpected USB reset signaling
+    ErrUsbReset = 0x0c,
+    /// Device detected unexpected power on reset
+    ErrPowerOnReset = 0x0d,
+    /// Something went wrong, but the device does not know what it was
+    ErrUnknown = 0x0e,
+    /// Device stalled an unexpected request
+    ErrStalledPacket = 0x0f,
+}
+
+// - Attributes ---------------------------------------------------------------
+
+/// `bmAttributes` of the DFU functional descriptor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Attributes(pub u8);
+
+impl Attributes {
+    /// `bitCanDnload`: the device supports download
+    pub const CAN_DOWNLOAD: Self = Self(1 << 0);
+    /// `bitCanUpload`: the device supports upload
+    pub const CAN_UPLOAD: Self = Self(1 << 1);
+    /// `bitManifestationTolerant`: the device can communicate after manifestation
+    pub const MANIFESTATION_TOLERANT: Self = Self(1 << 2);
+    /// `bitWillDetach`: the device detaches itself on `DFU_DETACH`
+    pub const WILL_DETACH: Self = Self(1 << 3);
+
+    /// Returns the union of `self` and `other`.
+    #[must_use]
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Returns `true` if all bits set in `other` are also set in `self`.
+    #[must_use]
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for Attributes {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        self.union(other)
+    }
+}
+
+// - DfuFunctionalDescriptor --------------------------------------------------
+
+/// DFU functional descriptor
+///
+/// Follows the DFU interface descriptor in both run-time and DFU mode.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct DfuFunctionalDescriptor {
+    pub bLength: u8,         // 9
+    pub bDescriptorType: u8, // 33 = DFU functional
+    pub bmAttributes: u8,
+    pub wDetachTimeOut: u16,
+    pub wTransferSize: u16,
+    pub bcdDFUVersion: u16,
+}
+
+impl AsByteSliceIterator for DfuFunctionalDescriptor {}
+
+impl DfuFunctionalDescriptor {
+    /// Returns a DFU 1.1 functional descriptor.
+    ///
+    /// `detach_timeout` is the time in milliseconds the device waits
+    /// for a bus reset after `DFU_DETACH` and `transfer_size` the
+    /// maximum number of bytes per `DFU_DNLOAD` and `DFU_UPLOAD` request.
+    #[must_use]
+    pub const fn new(attributes: Attributes, detach_timeout: u16, transfer_size: u16) -> Self {
+        Self {
+            bLength: 9,
+            bDescriptorType: DESCRIPTOR_TYPE_DFU_FUNCTIONAL,
+            bmAttributes: attributes.0,
+            wDetachTimeOut: detach_timeout,
+            wTransferSize: transfer_size,
+            bcdDFUVersion: 0x0110,
+        }
+    }
+
+    /// Returns the descriptor without its header for use as the tail
+    /// of a [`ClassDescriptor`](crate::descriptor::ClassDescriptor).
+    ///
+    /// ```
+    /// use smolusb::class::dfu::{self, Attributes, DfuFunctionalDescriptor};
+    /// use smolusb::descriptor::ClassDescriptor;
+    ///
+    /// const DFU_FUNCTIONAL_DESCRIPTOR: [u8; 7] =
+    ///     DfuFunctionalDescriptor::new(Attributes::CAN_DOWNLOAD, 1000, 256).tail();
+    /// const CLASS_DESCRIPTORS: &[ClassDescriptor] = &[ClassDescriptor::new(
+    ///     dfu::DESCRIPTOR_TYPE_DFU_FUNCTIONAL,
+    ///     &DFU_FUNCTIONAL_DESCRIPTOR,
+    /// )];
+    /// assert_eq!(CLASS_DESCRIPTORS[0].length(), 9);
+    /// ```
+    #[must_use]
+    pub const fn tail(self) -> [u8; 7] {
+        let detach_timeout = self.wDetachTimeOut.to_le_bytes();
+        let transfer_size = self.wTransferSize.to_le_bytes();
+        let bcd_dfu_version = self.bcdDFUVersion.to_le_bytes();
+        [
+            self.bmAttributes,
+            detach_timeout[0],
+            detach_timeout[1],
+            transfer_size[0],
+            transfer_size[1],
+            bcd_dfu_version[0],
+            bcd_dfu_version[1],
+        ]
+    }
+}
+
+// - DfuTarget ----------------------------------------------------------------
+
+/// Memory updated by a [`Dfu`] interface, typically a flash backend.
+///
+/// Offsets are computed from the block number of each request as
+/// `block_number * TRANSFER_SIZE`.
+pub trait DfuTarget {
+    /// Writes a block received with `DFU_DNLOAD` to `offset`.
+    ///
+    /// Targets backed by flash are responsible for erasing pages
+    /// before they are first written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the status reported to the host if the block could not
+    /// be written.
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Status>;
+
+    /// Reads a block requested with `DFU_UPLOAD` from `offset`.
+    ///
+    /// Returns the number of bytes read, a short read ends the upload.
+    ///
+    /// # Errors
+    ///
+    /// Returns the status reported to the host if the block could not
+    /// be read.
+    fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<usize, Status>;
+
+    /// Completes a download once all blocks have been written.
+    ///
+    /// # Errors
+    ///
+    /// Returns the status reported to the host if the new firmware
+    /// could not be verified or committed.
+    fn manifest(&mut self) -> Result<(), Status>;
+
+    /// Called when the host requests a detach from run-time mode.
+    ///
+    /// Devices with [`Attributes::WILL_DETACH`] should reset into DFU
+    /// mode, other devices wait for the host to issue a bus reset.
+    fn detach(&mut self) {}
+
+    /// Called on the bus reset ending manifestation on devices without
+    /// [`Attributes::MANIFESTATION_TOLERANT`].
+    ///
+    /// Targets should reset into the new firmware, otherwise the
+    /// interface returns to `dfuIDLE`.
+    fn reset(&mut self) {}
+}
+
+// - Dfu ----------------------------------------------------------------------
+
+/// A DFU 1.1 interface.
+///
+/// The application provides the memory being updated by implementing
+/// [`DfuTarget`] and is responsible for passing setup packets returned
+/// by [`Control::dispatch_event()`](crate::control::Control::dispatch_event)
+/// to [`handle_setup_packet()`](Self::handle_setup_packet) along with
+/// [`Control::data()`](crate::control::Control::data) and bus resets
+/// to [`dispatch_event()`](Self::dispatch_event).
+///
+/// `TRANSFER_SIZE` must match `wTransferSize` of the functional
+/// descriptor and may not exceed the receive buffer size of
+/// [`Control`](crate::control::Control).
+///
+/// Blocks are written to the target while answering the
+/// `DFU_GETSTATUS` request following each `DFU_DNLOAD` and
+/// manifestation happens while answering the `DFU_GETSTATUS` request
+/// following the final zero-length `DFU_DNLOAD`, so neither
+/// `dfuDNBUSY` nor `dfuMANIFEST` are ever reported to the host.
+pub struct Dfu<T, const TRANSFER_SIZE: usize> {
+    interface_number: u8,
+    attributes: Attributes,
+
+    target: T,
+
+    state: State,
+    status: Status,
+    block_number: u16,
+    block_length: usize,
+    buffer: [u8; TRANSFER_SIZE],
+}
+
+impl<T, const TRANSFER_SIZE: usize> Dfu<T, TRANSFER_SIZE>
+where
+    T: DfuTarget,
+{
+    /// Creates a new DFU interface in DFU mode for the given interface
+    /// number and target.
+    #[must_use]
+    pub fn new(interface_number: u8, target: T) -> Self {
+        Self {
+            interface_number,
+            attributes: Attributes::CAN_DOWNLOAD
+                .union(Attributes::CAN_UPLOAD)
+                .union(Attributes::MANIFESTATION_TOLERANT),
+            target,
+            state: State::DfuIdle,
+            status: Status::Ok,
+            block_number: 0,
+            block_length: 0,
+            buffer: [0; TRANSFER_SIZE],
+        }
+    }
+
+    /// Creates a new DFU interface in run-time mode.
+    ///
+    /// The interface switches to DFU mode on the first bus reset
+    /// following a `DFU_DETACH` request.
+    #[must_use]
+    pub fn new_runtime(interface_number: u8, target: T) -> Self {
+        Self {
+            state: State::AppIdle,
+            ..Self::new(interface_number, target)
+        }
+    }
+
+    /// Sets the attributes of the interface.
+    ///
+    /// Must match `bmAttributes` of the functional descriptor, defaults to
+    /// [`CAN_DOWNLOAD`](Attributes::CAN_DOWNLOAD) |
+    /// [`CAN_UPLOAD`](Attributes::CAN_UPLOAD) |
+    /// [`MANIFESTATION_TOLERANT`](Attributes::MANIFESTATION_TOLERANT).
+    #[must_use]
+    pub fn with_attributes(mut self, attributes: Attributes) -> Self {
+        self.attributes = attributes;
+        self
+    }
+
+    /// Returns the current DFU state.
+    #[must_use]
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Returns the current DFU status.
+    #[must_use]
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns a reference to the target.
+    #[must_use]
+    pub fn target(&self) -> &T {
+        &self.target
+    }
+
+    /// Returns a mutable reference to the target.
+    pub fn target_mut(&mut self) -> &mut T {
+        &mut self.target
+    }
+}
+
+impl<T, const TRANSFER_SIZE: usize> Dfu<T, TRANSFER_SIZE>
+where
+    T: DfuTarget,
+{
+    /// Handles a class request addressed to the interface.
+    ///
+    /// `endpoint_number` is the control endpoint the request was
+    /// received on, as returned by
+    /// [`Control::endpoint_number()`](crate::control::Control::endpoint_number).
+    /// `data` holds the data stage of `DFU_DNLOAD` requests, which has
+    /// already been acknowledged by [`Control`](crate::control::Control).
+    ///
+    /// Returns `false` if the request was not addressed to the interface.
+    pub fn handle_setup_packet<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool
+    where
+        D: UsbDriver,
+    {
+        if setup_packet.request_type() != RequestType::Class
+            || setup_packet.recipient() != Recipient::Interface
+            || setup_packet.interface_number() != self.interface_number
+        {
+            return false;
+        }
+
+        // dfu class request codes overlap the standard request codes
+        let class_request = ClassRequest::from(u8::from(setup_packet.request()));
+        trace!(
+            "Dfu {:?} {:?} {:?}",
+            class_request,
+            self.state,
+            setup_packet
+        );
+
+        let requested_length = usize::from(setup_packet.length());
+
+        match (setup_packet.direction(), class_request) {
+            (Direction::HostToDevice, ClassRequest::Detach) if self.state == State::AppIdle => {
+                self.state = State::AppDetach;
+                usb.write(endpoint_number, [].into_iter());
+                self.target.detach();
+            }
+            (Direction::HostToDevice, ClassRequest::Download) => {
+                self.handle_download(usb, endpoint_number, setup_packet, data);
+            }
+            (Direction::DeviceToHost, ClassRequest::Upload) => {
+                self.handle_upload(usb, endpoint_number, setup_packet);
+            }
+            (Direction::DeviceToHost, ClassRequest::GetStatus) => {
+                self.poll();
+                let [timeout_0, timeout_1, timeout_2, _] = 0_u32.to_le_bytes();
+                let status = [
+                    self.status as u8,
+                    timeout_0,
+                    timeout_1,
+                    timeout_2,
+                    self.state as u8,
+                    0, // iString
+                ];
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    status.into_iter().take(requested_length),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::ClearStatus) if self.state == State::Error => {
+                self.state = State::DfuIdle;
+                self.status = Status::Ok;
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (Direction::DeviceToHost, ClassRequest::GetState) => {
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    [self.state as u8].into_iter(),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::Abort)
+                if matches!(
+                    self.state,
+                    State::DfuIdle
+                        | State::DownloadSync
+                        | State::DownloadIdle
+                        | State::ManifestSync
+                        | State::UploadIdle
+                ) =>
+            {
+                self.state = State::DfuIdle;
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (direction, class_request) => {
+                warn!(
+                    "Dfu stall: unexpected class request {:?} {:?} in state {:?}",
+                    direction, class_request, self.state
+                );
+                self.fail(usb, endpoint_number, setup_packet, Status::ErrStalledPacket);
+            }
+        }
+
+        true
+    }
+
+    /// Dispatches an event to the interface.
+    pub fn dispatch_event<D>(&mut self, _usb: &D, event: UsbEvent)
+    where
+        D: UsbDriver,
+    {
+        if !matches!(event, UsbEvent::BusReset) {
+            return;
+        }
+
+        match self.state {
+            State::AppDetach => {
+                self.state = State::DfuIdle;
+                self.status = Status::Ok;
+            }
+            State::ManifestWaitReset => {
+                self.target.reset();
+                self.state = State::DfuIdle;
+                self.status = Status::Ok;
+            }
+            _ => (),
+        }
+    }
+}
+
+impl<T, const TRANSFER_SIZE: usize> Dfu<T, TRANSFER_SIZE>
+where
+    T: DfuTarget,
+{
+    fn handle_download<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) where
+        D: UsbDriver,
+    {
+        let length = usize::from(setup_packet.length());
+
+        if !self.attributes.contains(Attributes::CAN_DOWNLOAD)
+            || !matches!(self.state, State::DfuIdle | State::DownloadIdle)
+        {
+            warn!("Dfu: unexpected download in state {:?}", self.state);
+            self.fail(usb, endpoint_number, setup_packet, Status::ErrStalledPacket);
+        } else if length == 0 {
+            // the final zero-length download starts manifestation
+            if self.state == State::DownloadIdle {
+                self.state = State::ManifestSync;
+                usb.write(endpoint_number, [].into_iter());
+            } else {
+                self.fail(usb, endpoint_number, setup_packet, Status::ErrNotDone);
+            }
+        } else if length > TRANSFER_SIZE || data.len() != length {
+            warn!("Dfu: invalid download of {} bytes", data.len());
+            self.fail(usb, endpoint_number, setup_packet, Status::ErrStalledPacket);
+        } else {
+            self.buffer[..length].copy_from_slice(data);
+            self.block_number = setup_packet.value();
+            self.block_length = length;
+            self.state = State::DownloadSync;
+        }
+    }
+
+    fn handle_upload<D>(&mut self, usb: &D, endpoint_number: u8, setup_packet: SetupPacket)
+    where
+        D: UsbDriver,
+    {
+        if !self.attributes.contains(Attributes::CAN_UPLOAD)
+            || !matches!(self.state, State::DfuIdle | State::UploadIdle)
+        {
+            warn!("Dfu: unexpected upload in state {:?}", self.state);
+            self.fail(usb, endpoint_number, setup_packet, Status::ErrStalledPacket);
+            return;
+        }
+
+        let requested_length = usize::from(setup_packet.length()).min(TRANSFER_SIZE);
+        let offset = Self::offset(setup_packet.value());
+        match self
+            .target
+            .read(offset, &mut self.buffer[..requested_length])
+        {
+            Ok(bytes_read) => {
+                let bytes_read = bytes_read.min(requested_length);
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    usize::from(setup_packet.length()),
+                    self.buffer[..bytes_read].iter().copied(),
+                );
+                // a short block ends the upload
+                self.state = if bytes_read < requested_length {
+                    State::DfuIdle
+                } else {
+                    State::UploadIdle
+                };
+            }
+            Err(status) => {
+                warn!("Dfu: upload failed at offset {}: {:?}", offset, status);
+                self.fail(usb, endpoint_number, setup_packet, status);
+            }
+        }
+    }
+
+    /// Completes pending work before reporting status to the host.
+    fn poll(&mut self) {
+        match self.state {
+            State::DownloadSync => {
+                let offset = Self::offset(self.block_number);
+                match self.target.write(offset, &self.buffer[..self.block_length]) {
+                    Ok(()) => self.state = State::DownloadIdle,
+                    Err(status) => {
+                        warn!("Dfu: download failed at offset {}: {:?}", offset, status);
+                        self.error(status);
+                    }
+                }
+            }
+            State::ManifestSync => match self.target.manifest() {
+                Ok(()) if self.attributes.contains(Attributes::MANIFESTATION_TOLERANT) => {
+                    self.state = State::DfuIdle;
+                }
+                Ok(()) => self.state = State::ManifestWaitReset,
+                Err(status) => {
+                    warn!("Dfu: manifestation failed: {:?}", status);
+                    self.error(status);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    /// Stalls the request and enters the error state.
+    ///
+    /// Requests with a data stage from the host have already been
+    /// acknowledged and can only report the error on the next
+    /// `DFU_GETSTATUS`.
+    fn fail<D>(&mut self, usb: &D, endpoint_number: u8, setup_packet: SetupPacket, status: Status)
+    where
+        D: UsbDriver,
+    {
+        if setup_packet.direction() == Direction::DeviceToHost || setup_packet.length() == 0 {
+            usb.stall_endpoint_in(endpoint_number);
+        }
+        self.error(status);
+    }
+
+    fn error(&mut self, status: Status) {
+        // run-time mode has no error state
+        if !self.state.is_runtime() {
+            self.state = State::Error;
+            self.status = status;
+        }
+    }
+
+    fn offset(block_number: u16) -> u32 {
+        u32::from(block_number) * TRANSFER_SIZE as u32
+    }
+}
+
+// - UsbClass -----------------------------------------------------------------
+
+impl<D, T, const TRANSFER_SIZE: usize> UsbClass<D> for Dfu<T, TRANSFER_SIZE>
+where
+    D: UsbDriver,
+    T: DfuTarget,
+{
+    fn has_interface(&self, interface_number: u8) -> bool {
+        interface_number == self.interface_number
+    }
+
+    fn has_endpoint(&self, _endpoint_address: u8) -> bool {
+        false
+    }
+
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool {
+        Self::handle_setup_packet(self, usb, endpoint_number, setup_packet, data)
+    }
+
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+        Self::dispatch_event(self, usb, event);
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::descriptor::{
+        ClassDescriptor, ConfigurationDescriptor, ConfigurationDescriptorHeader, DeviceDescriptor,
+        InterfaceDescriptor, InterfaceDescriptorHeader,
+    };
+    use crate::device::Speed;
+    use crate::testing::{class_request, descriptors, ClassHarness};
+
+    // - fixtures -------------------------------------------------------------
+
+    const TRANSFER_SIZE: usize = 64;
+
+    /// RAM-backed target
+    struct RamTarget {
+        memory: Vec<u8>,
+        manifested: bool,
+        detached: bool,
+        reset: bool,
+    }
+
+    impl DfuTarget for RamTarget {
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), Status> {
+            let offset = offset as usize;
+            self.memory
+                .get_mut(offset..offset + data.len())
+                .ok_or(Status::ErrAddress)?
+                .copy_from_slice(data);
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u32, buffer: &mut [u8]) -> Result<usize, Status> {
+            let memory = self.memory.get(offset as usize..).unwrap_or(&[]);
+            let length = buffer.len().min(memory.len());
+            buffer[..length].copy_from_slice(&memory[..length]);
+            Ok(length)
+        }
+
+        fn manifest(&mut self) -> Result<(), Status> {
+            self.manifested = true;
+            Ok(())
+        }
+
+        fn detach(&mut self) {
+            self.detached = true;
+        }
+
+        fn reset(&mut self) {
+            self.reset = true;
+        }
+    }
+
+    type Firmware = Dfu<RamTarget, TRANSFER_SIZE>;
+
+    const ATTRIBUTES: Attributes = Attributes::CAN_DOWNLOAD
+        .union(Attributes::CAN_UPLOAD)
+        .union(Attributes::MANIFESTATION_TOLERANT);
+
+    const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
+        bDeviceClass: 0x00, // defined by the interface
+        bMaxPacketSize: 64,
+        idVendor: 0x1209,
+        idProduct: 0x0001,
+        bcdDevice: 0x0100,
+        bNumConfigurations: 1,
+        ..DeviceDescriptor::new()
+    };
+
+    const DFU_FUNCTIONAL_DESCRIPTOR: [u8; 7] =
+        DfuFunctionalDescriptor::new(ATTRIBUTES, 1000, TRANSFER_SIZE as u16).tail();
+
+    static CONFIGURATION_DESCRIPTORS: [ConfigurationDescriptor; 1] =
+        [ConfigurationDescriptor::new(
+            ConfigurationDescriptorHeader {
+                bConfigurationValue: 1,
+                bmAttributes: 0x80,
+                bMaxPower: 50,
+                ..ConfigurationDescriptorHeader::new()
+            },
+            &[InterfaceDescriptor::new(
+                InterfaceDescriptorHeader {
+                    iInterfaceNumber: 0,
+                    bInterfaceClass: INTERFACE_CLASS,
+                    bInterfaceSubClass: INTERFACE_SUBCLASS,
+                    bInterfaceProtocol: INTERFACE_PROTOCOL_DFU_MODE,
+                    ..InterfaceDescriptorHeader::new()
+                },
+                &[],
+            )
+            .with_class_descriptors(&[ClassDescriptor::new(
+                DESCRIPTOR_TYPE_DFU_FUNCTIONAL,
+                &DFU_FUNCTIONAL_DESCRIPTOR,
+            )])],
+        )
+        .validate(Speed::Full)];
+
+    fn ram_target(size: usize) -> RamTarget {
+        RamTarget {
+            memory: vec![0; size],
+            manifested: false,
+            detached: false,
+            reset: false,
+        }
+    }
+
+    fn setup(firmware: Firmware) -> ClassHarness<Firmware> {
+        ClassHarness::new(
+            descriptors(DEVICE_DESCRIPTOR, &CONFIGURATION_DESCRIPTORS),
+            firmware,
+        )
+    }
+
+    /// Runs a class request and returns the data written by the device.
+    fn dfu_request(
+        harness: &mut ClassHarness<Firmware>,
+        direction: Direction,
+        request: ClassRequest,
+        value: u16,
+        length: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let setup_packet =
+            class_request(direction, Recipient::Interface, request, value, 0, length);
+        harness.request(setup_packet, data)
+    }
+
+    fn get_status(harness: &mut ClassHarness<Firmware>) -> (Status, State) {
+        let status = dfu_request(
+            harness,
+            Direction::DeviceToHost,
+            ClassRequest::GetStatus,
+            0,
+            6,
+            &[],
+        );
+        let firmware = harness.classes();
+        assert_eq!(status.len(), 6);
+        assert_eq!(status[4], firmware.state() as u8);
+        assert_eq!(status[0], firmware.status() as u8);
+        (firmware.status(), firmware.state())
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_dfu_descriptors() {
+        let descriptor = DfuFunctionalDescriptor::new(ATTRIBUTES, 1000, TRANSFER_SIZE as u16);
+        assert_eq!(
+            descriptor.as_bytes(),
+            [9, 0x21, 0b0111, 0xe8, 0x03, 64, 0, 0x10, 0x01]
+        );
+        assert_eq!(descriptor.as_bytes()[2..], DFU_FUNCTIONAL_DESCRIPTOR);
+
+        let configuration: Vec<u8> = CONFIGURATION_DESCRIPTORS[0].iter().copied().collect();
+        assert_eq!(configuration.len(), 9 + 9 + 9);
+        assert_eq!(&configuration[18..], descriptor.as_bytes());
+    }
+
+    #[test]
+    fn test_download_upload() {
+        let mut harness = setup(Firmware::new(0, ram_target(256)));
+        let image: Vec<u8> = (0..150).map(|n| n as u8).collect();
+
+        // DFU_DNLOAD: one block at a time, written on DFU_GETSTATUS
+        for (block_number, block) in image.chunks(TRANSFER_SIZE).enumerate() {
+            let data = dfu_request(
+                &mut harness,
+                Direction::HostToDevice,
+                ClassRequest::Download,
+                block_number as u16,
+                block.len() as u16,
+                block,
+            );
+            assert!(data.is_empty());
+            assert_eq!(harness.classes().state(), State::DownloadSync);
+            assert_eq!(get_status(&mut harness), (Status::Ok, State::DownloadIdle));
+        }
+        assert_eq!(harness.classes().target().memory[..image.len()], image);
+        assert!(!harness.classes().target().manifested);
+
+        // final zero-length DFU_DNLOAD starts manifestation
+        let data = dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            3,
+            0,
+            &[],
+        );
+        assert!(data.is_empty());
+        assert_eq!(harness.classes().state(), State::ManifestSync);
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::DfuIdle));
+        assert!(harness.classes().target().manifested);
+
+        // DFU_UPLOAD: until a short block
+        let mut upload = Vec::new();
+        for block_number in 0.. {
+            let data = dfu_request(
+                &mut harness,
+                Direction::DeviceToHost,
+                ClassRequest::Upload,
+                block_number,
+                TRANSFER_SIZE as u16,
+                &[],
+            );
+            upload.extend_from_slice(&data);
+            if data.len() < TRANSFER_SIZE {
+                break;
+            }
+            assert_eq!(harness.classes().state(), State::UploadIdle);
+        }
+        assert_eq!(harness.classes().state(), State::DfuIdle);
+        assert_eq!(upload.len(), 256);
+        assert_eq!(upload[..image.len()], image);
+
+        // DFU_GETSTATE
+        let state = dfu_request(
+            &mut harness,
+            Direction::DeviceToHost,
+            ClassRequest::GetState,
+            0,
+            1,
+            &[],
+        );
+        assert_eq!(state, [State::DfuIdle as u8]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut harness = setup(Firmware::new(0, ram_target(64)));
+
+        // zero-length DFU_DNLOAD without any data is stalled
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            0,
+            0,
+            &[],
+        );
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+        assert_eq!(get_status(&mut harness), (Status::ErrNotDone, State::Error));
+
+        // requests other than DFU_CLRSTATUS are stalled in the error state
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Abort,
+            0,
+            0,
+            &[],
+        );
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+        assert_eq!(harness.classes().state(), State::Error);
+
+        // DFU_CLRSTATUS
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::ClearStatus,
+            0,
+            0,
+            &[],
+        );
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::DfuIdle));
+
+        // target errors are reported by DFU_GETSTATUS
+        let block = [0xff; TRANSFER_SIZE];
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            1,
+            64,
+            &block,
+        );
+        assert_eq!(get_status(&mut harness), (Status::ErrAddress, State::Error));
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::ClearStatus,
+            0,
+            0,
+            &[],
+        );
+
+        // DFU_ABORT returns to idle
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            0,
+            64,
+            &block,
+        );
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::DownloadIdle));
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Abort,
+            0,
+            0,
+            &[],
+        );
+        assert_eq!(harness.classes().state(), State::DfuIdle);
+
+        // DFU_DETACH is only valid in run-time mode
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Detach,
+            1000,
+            0,
+            &[],
+        );
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+        assert!(!harness.classes().target().detached);
+    }
+
+    #[test]
+    fn test_runtime_detach() {
+        let mut harness = setup(Firmware::new_runtime(0, ram_target(64)));
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::AppIdle));
+
+        // downloads are stalled in run-time mode without entering the error state
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            0,
+            0,
+            &[],
+        );
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+        assert_eq!(harness.classes().state(), State::AppIdle);
+
+        // DFU_DETACH
+        let data = dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Detach,
+            1000,
+            0,
+            &[],
+        );
+        assert!(data.is_empty());
+        assert_eq!(harness.classes().state(), State::AppDetach);
+        assert!(harness.classes().target().detached);
+
+        // the following bus reset enters DFU mode
+        harness.usb.host_bus_reset();
+        dfu_request(
+            &mut harness,
+            Direction::DeviceToHost,
+            ClassRequest::GetState,
+            0,
+            1,
+            &[],
+        );
+        assert_eq!(harness.classes().state(), State::DfuIdle);
+    }
+
+    #[test]
+    fn test_manifest_wait_reset() {
+        let mut harness = setup(
+            Firmware::new(0, ram_target(64))
+                .with_attributes(Attributes::CAN_DOWNLOAD | Attributes::CAN_UPLOAD),
+        );
+
+        let block = [0x55; TRANSFER_SIZE];
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            0,
+            64,
+            &block,
+        );
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::DownloadIdle));
+        dfu_request(
+            &mut harness,
+            Direction::HostToDevice,
+            ClassRequest::Download,
+            1,
+            0,
+            &[],
+        );
+
+        // devices that are not manifestation tolerant wait for a bus reset
+        assert_eq!(
+            get_status(&mut harness),
+            (Status::Ok, State::ManifestWaitReset)
+        );
+        assert!(harness.classes().target().manifested);
+        assert!(!harness.classes().target().reset);
+
+        // the bus reset notifies the target and returns to idle
+        harness.usb.host_bus_reset();
+        harness.dispatch();
+        assert!(harness.classes().target().reset);
+        assert_eq!(get_status(&mut harness), (Status::Ok, State::DfuIdle));
+    }
+}
@@ -0,0 +1,814 @@
+//! USB Human Interface Device class
+
+#![allow(non_snake_case)]
+
+use log::{trace, warn};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::class::UsbClass;
+use crate::descriptor::DescriptorType;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, Recipient, Request, RequestType, SetupPacket};
+use crate::traits::{AsByteSliceIterator, UsbDriver};
+
+/// `bInterfaceClass` for HID interfaces
+pub const INTERFACE_CLASS: u8 = 0x03;
+/// `bInterfaceSubClass` for interfaces supporting the boot protocol
+pub const INTERFACE_SUBCLASS_BOOT: u8 = 0x01;
+/// `bInterfaceProtocol` for boot keyboards
+pub const INTERFACE_PROTOCOL_KEYBOARD: u8 = 0x01;
+/// `bInterfaceProtocol` for boot mice
+pub const INTERFACE_PROTOCOL_MOUSE: u8 = 0x02;
+
+// - ClassRequest -------------------------------------------------------------
+
+/// HID Class Requests
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum ClassRequest {
+    GetReport = 0x01,   //  1
+    GetIdle = 0x02,     //  2
+    GetProtocol = 0x03, //  3
+    SetReport = 0x09,   //  9
+    SetIdle = 0x0a,     // 10
+    SetProtocol = 0x0b, // 11
+    Unknown(u8),
+}
+
+impl From<u8> for ClassRequest {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => ClassRequest::GetReport,
+            0x02 => ClassRequest::GetIdle,
+            0x03 => ClassRequest::GetProtocol,
+            0x09 => ClassRequest::SetReport,
+            0x0a => ClassRequest::SetIdle,
+            0x0b => ClassRequest::SetProtocol,
+            _ => ClassRequest::Unknown(value),
+        }
+    }
+}
+
+impl From<ClassRequest> for u8 {
+    fn from(class_request: ClassRequest) -> Self {
+        match class_request {
+            ClassRequest::GetReport => 0x01,
+            ClassRequest::GetIdle => 0x02,
+            ClassRequest::GetProtocol => 0x03,
+            ClassRequest::SetReport => 0x09,
+            ClassRequest::SetIdle => 0x0a,
+            ClassRequest::SetProtocol => 0x0b,
+            ClassRequest::Unknown(value) => value,
+        }
+    }
+}
+
+/// Report type carried in the high byte of `wValue` by `GET_REPORT`
+/// and `SET_REPORT`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum ReportType {
+    Input = 0x01,
+    Output = 0x02,
+    Feature = 0x03,
+    Unknown(u8),
+}
+
+impl From<u8> for ReportType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => ReportType::Input,
+            0x02 => ReportType::Output,
+            0x03 => ReportType::Feature,
+            _ => ReportType::Unknown(value),
+        }
+    }
+}
+
+/// Protocol selected by `SET_PROTOCOL`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum Protocol {
+    Boot = 0,
+    Report = 1,
+}
+
+// - HidDescriptor ------------------------------------------------------------
+
+/// HID descriptor
+///
+/// Describes a HID interface with a single report descriptor.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct HidDescriptor {
+    pub bLength: u8,         // 9
+    pub bDescriptorType: u8, // 33 = Hid
+    pub bcdHID: u16,
+    pub bCountryCode: u8,
+    pub bNumDescriptors: u8,
+    pub bReportDescriptorType: u8, // 34 = Report
+    pub wReportDescriptorLength: u16,
+}
+
+impl AsByteSliceIterator for HidDescriptor {}
+
+impl HidDescriptor {
+    /// Returns a HID 1.11 descriptor for a report descriptor of the given length.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the report descriptor is longer than 65535 bytes.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new(report_descriptor_length: usize) -> Self {
+        assert!(
+            report_descriptor_length <= u16::MAX as usize,
+            "report descriptor is too long"
+        );
+        Self {
+            bLength: 9,
+            bDescriptorType: DescriptorType::Hid as u8,
+            bcdHID: 0x0111,
+            bCountryCode: 0,
+            bNumDescriptors: 1,
+            bReportDescriptorType: DescriptorType::Report as u8,
+            wReportDescriptorLength: report_descriptor_length as u16,
+        }
+    }
+
+    /// Returns the descriptor without its header for use as the tail
+    /// of a [`ClassDescriptor`](crate::descriptor::ClassDescriptor).
+    ///
+    /// ```
+    /// use smolusb::class::hid::{self, HidDescriptor};
+    /// use smolusb::descriptor::{ClassDescriptor, DescriptorType};
+    ///
+    /// const HID_DESCRIPTOR: [u8; 7] =
+    ///     HidDescriptor::new(hid::BOOT_KEYBOARD_REPORT_DESCRIPTOR.len()).tail();
+    /// const CLASS_DESCRIPTORS: &[ClassDescriptor] =
+    ///     &[ClassDescriptor::new(DescriptorType::Hid as u8, &HID_DESCRIPTOR)];
+    /// assert_eq!(CLASS_DESCRIPTORS[0].length(), 9);
+    /// ```
+    #[must_use]
+    pub const fn tail(self) -> [u8; 7] {
+        let bcd_hid = self.bcdHID.to_le_bytes();
+        let report_descriptor_length = self.wReportDescriptorLength.to_le_bytes();
+        [
+            bcd_hid[0],
+            bcd_hid[1],
+            self.bCountryCode,
+            self.bNumDescriptors,
+            self.bReportDescriptorType,
+            report_descriptor_length[0],
+            report_descriptor_length[1],
+        ]
+    }
+}
+
+// - reference report descriptors ---------------------------------------------
+
+/// Boot keyboard report descriptor
+///
+/// See: Device Class Definition for HID 1.11, Appendix B.1
+///
+/// Input report: modifiers, reserved, 6 key codes.
+/// Output report: 5 LEDs.
+pub const BOOT_KEYBOARD_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x06, // Usage (Keyboard)
+    0xa1, 0x01, // Collection (Application)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0xe0, //   Usage Minimum (224)
+    0x29, 0xe7, //   Usage Maximum (231)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x08, //   Report Count (8)
+    0x81, 0x02, //   Input (Data, Variable, Absolute) ; modifier byte
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x08, //   Report Size (8)
+    0x81, 0x01, //   Input (Constant) ; reserved byte
+    0x95, 0x05, //   Report Count (5)
+    0x75, 0x01, //   Report Size (1)
+    0x05, 0x08, //   Usage Page (LEDs)
+    0x19, 0x01, //   Usage Minimum (1)
+    0x29, 0x05, //   Usage Maximum (5)
+    0x91, 0x02, //   Output (Data, Variable, Absolute) ; LED report
+    0x95, 0x01, //   Report Count (1)
+    0x75, 0x03, //   Report Size (3)
+    0x91, 0x01, //   Output (Constant) ; LED report padding
+    0x95, 0x06, //   Report Count (6)
+    0x75, 0x08, //   Report Size (8)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x65, //   Logical Maximum (101)
+    0x05, 0x07, //   Usage Page (Key Codes)
+    0x19, 0x00, //   Usage Minimum (0)
+    0x29, 0x65, //   Usage Maximum (101)
+    0x81, 0x00, //   Input (Data, Array) ; key arrays (6 bytes)
+    0xc0, // End Collection
+];
+
+/// Boot mouse report descriptor
+///
+/// See: Device Class Definition for HID 1.11, Appendix B.2
+///
+/// Input report: 3 buttons, X and Y displacement.
+pub const BOOT_MOUSE_REPORT_DESCRIPTOR: &[u8] = &[
+    0x05, 0x01, // Usage Page (Generic Desktop)
+    0x09, 0x02, // Usage (Mouse)
+    0xa1, 0x01, // Collection (Application)
+    0x09, 0x01, //   Usage (Pointer)
+    0xa1, 0x00, //   Collection (Physical)
+    0x05, 0x09, //     Usage Page (Buttons)
+    0x19, 0x01, //     Usage Minimum (1)
+    0x29, 0x03, //     Usage Maximum (3)
+    0x15, 0x00, //     Logical Minimum (0)
+    0x25, 0x01, //     Logical Maximum (1)
+    0x95, 0x03, //     Report Count (3)
+    0x75, 0x01, //     Report Size (1)
+    0x81, 0x02, //     Input (Data, Variable, Absolute) ; 3 button bits
+    0x95, 0x01, //     Report Count (1)
+    0x75, 0x05, //     Report Size (5)
+    0x81, 0x01, //     Input (Constant) ; 5 bit padding
+    0x05, 0x01, //     Usage Page (Generic Desktop)
+    0x09, 0x30, //     Usage (X)
+    0x09, 0x31, //     Usage (Y)
+    0x15, 0x81, //     Logical Minimum (-127)
+    0x25, 0x7f, //     Logical Maximum (127)
+    0x75, 0x08, //     Report Size (8)
+    0x95, 0x02, //     Report Count (2)
+    0x81, 0x06, //     Input (Data, Variable, Relative) ; X & Y
+    0xc0, //   End Collection
+    0xc0, // End Collection
+];
+
+// - ReportQueue --------------------------------------------------------------
+
+struct ReportQueue<const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> {
+    reports: [[u8; REPORT_SIZE]; QUEUE_DEPTH],
+    lengths: [usize; QUEUE_DEPTH],
+    head: usize,
+    len: usize,
+}
+
+impl<const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> ReportQueue<REPORT_SIZE, QUEUE_DEPTH> {
+    const fn new() -> Self {
+        Self {
+            reports: [[0; REPORT_SIZE]; QUEUE_DEPTH],
+            lengths: [0; QUEUE_DEPTH],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    fn push(&mut self, report: &[u8]) -> bool {
+        if self.len == QUEUE_DEPTH {
+            return false;
+        }
+        let index = (self.head + self.len) % QUEUE_DEPTH;
+        self.reports[index][..report.len()].copy_from_slice(report);
+        self.lengths[index] = report.len();
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) -> Option<&[u8]> {
+        if self.len == 0 {
+            return None;
+        }
+        let index = self.head;
+        self.head = (self.head + 1) % QUEUE_DEPTH;
+        self.len -= 1;
+        Some(&self.reports[index][..self.lengths[index]])
+    }
+}
+
+// - Hid ----------------------------------------------------------------------
+
+/// A HID interface with a single interrupt IN endpoint.
+///
+/// `Hid` answers `GET_DESCRIPTOR` requests for its HID and report
+/// descriptors as well as the HID class requests sent to its
+/// interface and sends input reports on its interrupt IN endpoint.
+///
+/// The interface can be registered with a
+/// [`UsbDevice`](crate::device::UsbDevice), otherwise the application
+/// is responsible for routing events to it: setup packets returned by
+/// [`Control::dispatch_event()`](crate::control::Control::dispatch_event)
+/// are passed to [`handle_setup_packet()`](Self::handle_setup_packet)
+/// and all other events to [`dispatch_event()`](Self::dispatch_event).
+///
+/// Input reports are queued with [`send_report()`](Self::send_report),
+/// output and feature reports set by the host can be retrieved with
+/// [`take_report()`](Self::take_report).
+///
+/// Reports may be at most `REPORT_SIZE` bytes long and up to
+/// `QUEUE_DEPTH` input reports can be queued for transmission.
+pub struct Hid<'a, const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> {
+    interface_number: u8,
+    endpoint_number: u8,
+    descriptor: HidDescriptor,
+    report_descriptor: &'a [u8],
+
+    protocol: Protocol,
+    idle_rate: u8,
+
+    input_report: [u8; REPORT_SIZE],
+    input_report_length: usize,
+    report: Option<ReportType>,
+    report_buffer: [u8; REPORT_SIZE],
+    report_length: usize,
+
+    queue: ReportQueue<REPORT_SIZE, QUEUE_DEPTH>,
+    busy: bool,
+}
+
+impl<'a, const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> Hid<'a, REPORT_SIZE, QUEUE_DEPTH> {
+    /// Creates a new HID interface for the given interface number,
+    /// interrupt IN endpoint number and report descriptor.
+    #[must_use]
+    pub const fn new(
+        interface_number: u8,
+        endpoint_number: u8,
+        report_descriptor: &'a [u8],
+    ) -> Self {
+        Self {
+            interface_number,
+            endpoint_number,
+            descriptor: HidDescriptor::new(report_descriptor.len()),
+            report_descriptor,
+            protocol: Protocol::Report,
+            idle_rate: 0,
+            input_report: [0; REPORT_SIZE],
+            input_report_length: 0,
+            report: None,
+            report_buffer: [0; REPORT_SIZE],
+            report_length: 0,
+            queue: ReportQueue::new(),
+            busy: false,
+        }
+    }
+
+    /// Returns the protocol last selected by the host.
+    #[must_use]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Returns the idle rate last set by the host in units of 4 ms.
+    ///
+    /// An idle rate of `0` means reports should only be sent when
+    /// they change.
+    #[must_use]
+    pub fn idle_rate(&self) -> u8 {
+        self.idle_rate
+    }
+
+    /// Returns the number of input reports waiting to be sent.
+    #[must_use]
+    pub fn queued_reports(&self) -> usize {
+        self.queue.len()
+    }
+}
+
+impl<const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> Hid<'_, REPORT_SIZE, QUEUE_DEPTH> {
+    /// Resets the interface to its default state.
+    pub fn reset(&mut self) {
+        self.protocol = Protocol::Report;
+        self.idle_rate = 0;
+        self.report = None;
+        self.queue.clear();
+        self.busy = false;
+    }
+
+    /// Handles a standard or class request addressed to the interface.
+    ///
+    /// `endpoint_number` is the control endpoint the request was
+    /// received on, as returned by
+    /// [`Control::endpoint_number()`](crate::control::Control::endpoint_number).
+    /// `data` contains the data stage of the request, if any, as
+    /// returned by [`Control::data()`](crate::control::Control::data).
+    ///
+    /// Returns `false` if the request was not addressed to the interface.
+    pub fn handle_setup_packet<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool
+    where
+        D: UsbDriver,
+    {
+        if setup_packet.recipient() != Recipient::Interface
+            || setup_packet.interface_number() != self.interface_number
+        {
+            return false;
+        }
+
+        match (setup_packet.request_type(), setup_packet.request()) {
+            (RequestType::Standard, Request::GetDescriptor) => {
+                self.handle_get_descriptor(usb, endpoint_number, setup_packet);
+                true
+            }
+            (RequestType::Class, request) => {
+                // hid class request codes overlap the standard request codes
+                let class_request = ClassRequest::from(u8::from(request));
+                self.handle_class_request(usb, endpoint_number, setup_packet, class_request, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Dispatches an event to the interface.
+    ///
+    /// Events for endpoints not belonging to the interface are ignored.
+    pub fn dispatch_event<D>(&mut self, usb: &D, event: UsbEvent)
+    where
+        D: UsbDriver,
+    {
+        match event {
+            UsbEvent::BusReset => self.reset(),
+            UsbEvent::SendComplete(endpoint_number) if endpoint_number == self.endpoint_number => {
+                self.busy = false;
+                self.transmit(usb);
+            }
+            _ => (),
+        }
+    }
+
+    /// Queues an input report for transmission to the host.
+    ///
+    /// Returns `false` if the report is longer than `REPORT_SIZE` or
+    /// the queue is full.
+    pub fn send_report<D>(&mut self, usb: &D, report: &[u8]) -> bool
+    where
+        D: UsbDriver,
+    {
+        if report.len() > REPORT_SIZE || !self.queue.push(report) {
+            return false;
+        }
+        self.input_report[..report.len()].copy_from_slice(report);
+        self.input_report_length = report.len();
+        if !self.busy {
+            self.transmit(usb);
+        }
+        true
+    }
+
+    /// Returns the last output or feature report set by the host
+    /// since the previous call.
+    pub fn take_report(&mut self) -> Option<(ReportType, &[u8])> {
+        let report_type = self.report.take()?;
+        Some((report_type, &self.report_buffer[..self.report_length]))
+    }
+}
+
+impl<const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> Hid<'_, REPORT_SIZE, QUEUE_DEPTH> {
+    fn handle_get_descriptor<D>(&mut self, usb: &D, endpoint_number: u8, setup_packet: SetupPacket)
+    where
+        D: UsbDriver,
+    {
+        let requested_length = usize::from(setup_packet.length());
+        let descriptor_type = setup_packet.descriptor_request().descriptor_type;
+
+        // prime to receive host zlp
+        usb.ep_out_prime_receive(endpoint_number);
+        match descriptor_type {
+            DescriptorType::Hid => {
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    self.descriptor.as_iter().copied().take(requested_length),
+                );
+            }
+            DescriptorType::Report => {
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    self.report_descriptor
+                        .iter()
+                        .copied()
+                        .take(requested_length),
+                );
+            }
+            _ => {
+                warn!(
+                    "Hid stall: unhandled descriptor request {:?}",
+                    descriptor_type
+                );
+                usb.stall_endpoint_in(endpoint_number);
+            }
+        }
+    }
+
+    fn handle_class_request<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        class_request: ClassRequest,
+        data: &[u8],
+    ) where
+        D: UsbDriver,
+    {
+        trace!("Hid {:?} {:?}", class_request, setup_packet);
+
+        let [value_low, value_high] = setup_packet.value().to_le_bytes();
+        let requested_length = usize::from(setup_packet.length());
+
+        match (setup_packet.direction(), class_request) {
+            (Direction::DeviceToHost, ClassRequest::GetReport)
+                if ReportType::from(value_high) == ReportType::Input =>
+            {
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    self.input_report[..self.input_report_length]
+                        .iter()
+                        .copied()
+                        .take(requested_length),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::SetReport) => {
+                // the data stage has already been acknowledged by Control
+                let report_length = data.len().min(REPORT_SIZE);
+                self.report_buffer[..report_length].copy_from_slice(&data[..report_length]);
+                self.report_length = report_length;
+                self.report = Some(ReportType::from(value_high));
+                if setup_packet.length() == 0 {
+                    usb.write(endpoint_number, [].into_iter());
+                }
+            }
+            (Direction::DeviceToHost, ClassRequest::GetIdle) => {
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    [self.idle_rate].into_iter(),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::SetIdle) => {
+                // only a single idle rate is kept for all report ids
+                self.idle_rate = value_high;
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (Direction::DeviceToHost, ClassRequest::GetProtocol) => {
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    requested_length,
+                    [self.protocol as u8].into_iter(),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::SetProtocol) => {
+                self.protocol = if value_low == 0 {
+                    Protocol::Boot
+                } else {
+                    Protocol::Report
+                };
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (direction, class_request) => {
+                warn!(
+                    "Hid stall: unhandled class request {:?} {:?}",
+                    direction, class_request
+                );
+                usb.stall_endpoint_in(endpoint_number);
+            }
+        }
+    }
+
+    fn transmit<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        if let Some(report) = self.queue.pop() {
+            // write exactly one packet, write() would append a zlp to full packets
+            usb.write_requested(self.endpoint_number, report.len(), report.iter().copied());
+            self.busy = true;
+        }
+    }
+}
+
+// - UsbClass -----------------------------------------------------------------
+
+impl<D, const REPORT_SIZE: usize, const QUEUE_DEPTH: usize> UsbClass<D>
+    for Hid<'_, REPORT_SIZE, QUEUE_DEPTH>
+where
+    D: UsbDriver,
+{
+    fn has_interface(&self, interface_number: u8) -> bool {
+        interface_number == self.interface_number
+    }
+
+    fn has_endpoint(&self, endpoint_address: u8) -> bool {
+        endpoint_address == self.endpoint_number | 0x80
+    }
+
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool {
+        Self::handle_setup_packet(self, usb, endpoint_number, setup_packet, data)
+    }
+
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+        Self::dispatch_event(self, usb, event);
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::descriptor::{
+        ClassDescriptor, ConfigurationDescriptor, ConfigurationDescriptorHeader, DeviceDescriptor,
+        EndpointDescriptor, InterfaceDescriptor, InterfaceDescriptorHeader,
+    };
+    use crate::device::Speed;
+    use crate::testing::{class_request, descriptors, ClassHarness};
+
+    // - fixtures -------------------------------------------------------------
+
+    type Keyboard = Hid<'static, 8, 2>;
+
+    const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
+        bDeviceClass: 0x00, // defined by the interface
+        bMaxPacketSize: 64,
+        idVendor: 0x1209,
+        idProduct: 0x0001,
+        bcdDevice: 0x0100,
+        bNumConfigurations: 1,
+        ..DeviceDescriptor::new()
+    };
+
+    const HID_DESCRIPTOR: [u8; 7] =
+        HidDescriptor::new(BOOT_KEYBOARD_REPORT_DESCRIPTOR.len()).tail();
+
+    static CONFIGURATION_DESCRIPTORS: [ConfigurationDescriptor; 1] =
+        [ConfigurationDescriptor::new(
+            ConfigurationDescriptorHeader {
+                bConfigurationValue: 1,
+                bmAttributes: 0x80,
+                bMaxPower: 50,
+                ..ConfigurationDescriptorHeader::new()
+            },
+            &[InterfaceDescriptor::new(
+                InterfaceDescriptorHeader {
+                    iInterfaceNumber: 0,
+                    bInterfaceClass: INTERFACE_CLASS,
+                    bInterfaceSubClass: INTERFACE_SUBCLASS_BOOT,
+                    bInterfaceProtocol: INTERFACE_PROTOCOL_KEYBOARD,
+                    ..InterfaceDescriptorHeader::new()
+                },
+                &[EndpointDescriptor::interrupt(0x81, 8, 10)],
+            )
+            .with_class_descriptors(&[ClassDescriptor::new(
+                DescriptorType::Hid as u8,
+                &HID_DESCRIPTOR,
+            )])],
+        )
+        .validate(Speed::Full)];
+
+    fn setup() -> ClassHarness<Keyboard> {
+        ClassHarness::new(
+            descriptors(DEVICE_DESCRIPTOR, &CONFIGURATION_DESCRIPTORS),
+            Keyboard::new(0, 1, BOOT_KEYBOARD_REPORT_DESCRIPTOR),
+        )
+    }
+
+    fn hid_request(
+        direction: Direction,
+        request: ClassRequest,
+        value: u16,
+        length: u16,
+    ) -> SetupPacket {
+        class_request(direction, Recipient::Interface, request, value, 0, length)
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_hid_descriptors() {
+        let mut harness = setup();
+
+        assert_eq!(BOOT_KEYBOARD_REPORT_DESCRIPTOR.len(), 63);
+        assert_eq!(BOOT_MOUSE_REPORT_DESCRIPTOR.len(), 50);
+
+        // the hid descriptor follows the interface descriptor
+        let configuration: Vec<u8> = CONFIGURATION_DESCRIPTORS[0].iter().copied().collect();
+        assert_eq!(configuration.len(), 9 + 9 + 9 + 7);
+        let hid_descriptor = &configuration[18..27];
+        assert_eq!(hid_descriptor, [9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0]);
+        assert_eq!(hid_descriptor, harness.classes().descriptor.as_bytes());
+
+        // GET_DESCRIPTOR(Report)
+        let setup_packet = SetupPacket::new(0x81, Request::GetDescriptor.into(), 0x2200, 0, 0xff);
+        assert_eq!(
+            harness.request(setup_packet, &[]),
+            BOOT_KEYBOARD_REPORT_DESCRIPTOR
+        );
+        assert!(harness.usb.is_primed(0));
+
+        // GET_DESCRIPTOR(HID)
+        let setup_packet = SetupPacket::new(0x81, Request::GetDescriptor.into(), 0x2100, 0, 9);
+        assert_eq!(harness.request(setup_packet, &[]), hid_descriptor);
+
+        // descriptor requests for other interfaces are left to the application
+        let setup_packet = SetupPacket::new(0x81, Request::GetDescriptor.into(), 0x2200, 1, 0xff);
+        harness.usb.host_setup(0, setup_packet);
+        assert_eq!(harness.dispatch(), [setup_packet]);
+    }
+
+    #[test]
+    fn test_class_requests() {
+        let mut harness = setup();
+
+        // SET_IDLE / GET_IDLE: 500 ms
+        let setup_packet = hid_request(Direction::HostToDevice, ClassRequest::SetIdle, 0x7d00, 0);
+        harness.request(setup_packet, &[]);
+        assert_eq!(harness.classes().idle_rate(), 125);
+        assert_eq!(harness.usb.packets_written(0), [Vec::<u8>::new()]);
+
+        let setup_packet = hid_request(Direction::DeviceToHost, ClassRequest::GetIdle, 0, 1);
+        assert_eq!(harness.request(setup_packet, &[]), [125]);
+
+        // SET_PROTOCOL / GET_PROTOCOL: boot protocol
+        assert_eq!(harness.classes().protocol(), Protocol::Report);
+        let setup_packet = hid_request(Direction::HostToDevice, ClassRequest::SetProtocol, 0, 0);
+        harness.request(setup_packet, &[]);
+        assert_eq!(harness.classes().protocol(), Protocol::Boot);
+
+        let setup_packet = hid_request(Direction::DeviceToHost, ClassRequest::GetProtocol, 0, 1);
+        assert_eq!(harness.request(setup_packet, &[]), [0]);
+
+        // SET_REPORT: caps lock led
+        assert_eq!(harness.classes_mut().take_report(), None);
+        let setup_packet = hid_request(Direction::HostToDevice, ClassRequest::SetReport, 0x0200, 1);
+        harness.request(setup_packet, &[0b10]);
+        assert_eq!(
+            harness.classes_mut().take_report(),
+            Some((ReportType::Output, &[0b10][..]))
+        );
+        assert_eq!(harness.classes_mut().take_report(), None);
+
+        // GET_REPORT: last input report
+        let report = [0, 0, 0x04, 0, 0, 0, 0, 0];
+        let (usb, keyboard) = harness.split();
+        assert!(keyboard.send_report(usb, &report));
+        let setup_packet = hid_request(Direction::DeviceToHost, ClassRequest::GetReport, 0x0100, 8);
+        assert_eq!(harness.request(setup_packet, &[]), report);
+
+        // unsupported requests are stalled
+        let setup_packet = hid_request(Direction::DeviceToHost, ClassRequest::GetReport, 0x0300, 8);
+        harness.request(setup_packet, &[]);
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+
+        // bus reset restores the defaults
+        harness.usb.host_bus_reset();
+        harness.dispatch();
+        assert_eq!(harness.classes().protocol(), Protocol::Report);
+        assert_eq!(harness.classes().idle_rate(), 0);
+    }
+
+    #[test]
+    fn test_report_queue() {
+        let mut harness = setup();
+        let (usb, keyboard) = harness.split();
+
+        // the first report is sent right away, the next two are queued
+        for key in 0x04..0x07 {
+            assert!(keyboard.send_report(usb, &[0, 0, key, 0, 0, 0, 0, 0]));
+        }
+        assert_eq!(keyboard.queued_reports(), 2);
+        assert!(!keyboard.send_report(usb, &[0; 8]));
+        assert!(!keyboard.send_report(usb, &[0; 9]));
+        assert_eq!(usb.packets_written(1).len(), 1);
+
+        // each send complete event sends the next report
+        harness.dispatch();
+        assert_eq!(harness.classes().queued_reports(), 0);
+        let packets = harness.usb.packets_written(1);
+        assert_eq!(packets.len(), 3);
+        assert_eq!(packets[0][2], 0x04);
+        assert_eq!(packets[1][2], 0x05);
+        assert_eq!(packets[2][2], 0x06);
+    }
+}
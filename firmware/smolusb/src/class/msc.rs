@@ -0,0 +1,1302 @@
+//! USB Mass Storage class: Bulk-Only Transport with the SCSI command set
+
+#![allow(non_snake_case)]
+
+use log::{trace, warn};
+use zerocopy::byteorder::{LittleEndian, U32};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::class::UsbClass;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, Recipient, Request, RequestType, SetupPacket};
+use crate::traits::UsbDriver;
+
+pub mod scsi;
+
+use scsi::{OperationCode, Sense};
+
+/// `bInterfaceClass` for Mass Storage interfaces
+pub const INTERFACE_CLASS: u8 = 0x08;
+/// `bInterfaceSubClass` for the SCSI transparent command set
+pub const INTERFACE_SUBCLASS_SCSI: u8 = 0x06;
+/// `bInterfaceProtocol` for Bulk-Only Transport
+pub const INTERFACE_PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+// - ClassRequest -------------------------------------------------------------
+
+/// Mass Storage Bulk-Only Transport Class Requests
+#[derive(Debug, PartialEq)]
+pub enum ClassRequest {
+    GetMaxLun,                // 0xfe
+    BulkOnlyMassStorageReset, // 0xff
+    Unknown(u8),
+}
+
+impl From<u8> for ClassRequest {
+    fn from(value: u8) -> Self {
+        match value {
+            0xfe => ClassRequest::GetMaxLun,
+            0xff => ClassRequest::BulkOnlyMassStorageReset,
+            _ => ClassRequest::Unknown(value),
+        }
+    }
+}
+
+impl From<ClassRequest> for u8 {
+    fn from(class_request: ClassRequest) -> Self {
+        match class_request {
+            ClassRequest::GetMaxLun => 0xfe,
+            ClassRequest::BulkOnlyMassStorageReset => 0xff,
+            ClassRequest::Unknown(value) => value,
+        }
+    }
+}
+
+// - BlockDevice --------------------------------------------------------------
+
+/// Errors reported by a [`BlockDevice`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockDeviceError {
+    NotReady,
+    ReadFailed,
+    WriteFailed,
+    WriteProtected,
+}
+
+impl From<BlockDeviceError> for Sense {
+    fn from(error: BlockDeviceError) -> Self {
+        match error {
+            BlockDeviceError::NotReady => Sense::MEDIUM_NOT_PRESENT,
+            BlockDeviceError::ReadFailed => Sense::UNRECOVERED_READ_ERROR,
+            BlockDeviceError::WriteFailed => Sense::WRITE_ERROR,
+            BlockDeviceError::WriteProtected => Sense::WRITE_PROTECTED,
+        }
+    }
+}
+
+/// Storage medium exposed by a [`MassStorage`] interface.
+///
+/// Blocks are exchanged in slices of the block size the
+/// [`MassStorage`] interface was created with.
+pub trait BlockDevice {
+    /// Returns the number of blocks on the device.
+    fn block_count(&self) -> u32;
+
+    /// Reads the block at `lba` into `block`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block could not be read.
+    fn read_block(&mut self, lba: u32, block: &mut [u8]) -> Result<(), BlockDeviceError>;
+
+    /// Writes `block` to the block at `lba`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the block could not be written.
+    fn write_block(&mut self, lba: u32, block: &[u8]) -> Result<(), BlockDeviceError>;
+
+    /// Returns `true` if a medium is present and ready.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Returns `true` if the medium may not be written to.
+    fn is_write_protected(&self) -> bool {
+        false
+    }
+}
+
+// - CommandBlockWrapper ------------------------------------------------------
+
+/// Command Block Wrapper sent by the host to start a command.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CommandBlockWrapper {
+    pub dCBWSignature: U32<LittleEndian>,
+    pub dCBWTag: U32<LittleEndian>,
+    pub dCBWDataTransferLength: U32<LittleEndian>,
+    pub bmCBWFlags: u8,
+    pub bCBWLUN: u8,
+    pub bCBWCBLength: u8,
+    pub CBWCB: [u8; 16],
+}
+
+impl CommandBlockWrapper {
+    /// "USBC"
+    pub const SIGNATURE: u32 = 0x4342_5355;
+
+    /// Returns a command block wrapper for the given command block.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn new(tag: u32, data_transfer_length: u32, direction: Direction, command: &[u8]) -> Self {
+        let length = command.len().min(16);
+        let mut command_block = [0; 16];
+        command_block[..length].copy_from_slice(&command[..length]);
+        Self {
+            dCBWSignature: U32::new(Self::SIGNATURE),
+            dCBWTag: U32::new(tag),
+            dCBWDataTransferLength: U32::new(data_transfer_length),
+            bmCBWFlags: direction as u8,
+            bCBWLUN: 0,
+            bCBWCBLength: length as u8,
+            CBWCB: command_block,
+        }
+    }
+
+    /// Parses a command block wrapper.
+    ///
+    /// Returns `None` if the packet is not a valid and meaningful CBW.
+    #[must_use]
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() != core::mem::size_of::<Self>() {
+            return None;
+        }
+        let cbw = Self::read_from(packet)?;
+        let is_valid = cbw.dCBWSignature.get() == Self::SIGNATURE;
+        let is_meaningful = cbw.bCBWLUN == 0 && matches!(cbw.bCBWCBLength, 1..=16);
+        (is_valid && is_meaningful).then_some(cbw)
+    }
+
+    /// Returns the direction of the data stage.
+    #[must_use]
+    pub fn direction(&self) -> Direction {
+        Direction::from(self.bmCBWFlags)
+    }
+}
+
+// - CommandStatusWrapper -----------------------------------------------------
+
+/// Command status reported in the Command Status Wrapper.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum CommandStatus {
+    Passed = 0x00,
+    Failed = 0x01,
+    PhaseError = 0x02,
+}
+
+/// Command Status Wrapper sent by the device to complete a command.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CommandStatusWrapper {
+    pub dCSWSignature: U32<LittleEndian>,
+    pub dCSWTag: U32<LittleEndian>,
+    pub dCSWDataResidue: U32<LittleEndian>,
+    pub bCSWStatus: u8,
+}
+
+impl CommandStatusWrapper {
+    /// "USBS"
+    pub const SIGNATURE: u32 = 0x5342_5355;
+
+    #[must_use]
+    pub fn new(tag: u32, data_residue: u32, status: CommandStatus) -> Self {
+        Self {
+            dCSWSignature: U32::new(Self::SIGNATURE),
+            dCSWTag: U32::new(tag),
+            dCSWDataResidue: U32::new(data_residue),
+            bCSWStatus: status as u8,
+        }
+    }
+}
+
+// - MassStorage --------------------------------------------------------------
+
+/// Data stage requested by a SCSI command.
+enum Reply {
+    /// No data stage
+    None,
+    /// Send the given number of bytes from the buffer
+    Data(usize),
+    /// Send the given blocks
+    Read { lba: u32, blocks: u32 },
+    /// Receive the given blocks
+    Write { lba: u32, blocks: u32 },
+}
+
+/// Bulk-Only Transport state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    /// Waiting for a CBW
+    Command,
+    /// Sending data to the host, reading the next block from `lba`
+    /// once the buffer has been sent.
+    DataIn {
+        lba: Option<u32>,
+        remaining: u32,
+        position: usize,
+        zlp: bool,
+    },
+    /// Receiving data from the host, writing `writes` bytes to the
+    /// blocks starting at `lba` and discarding the rest.
+    DataOut {
+        lba: Option<u32>,
+        remaining: u32,
+        writes: u32,
+        position: usize,
+    },
+    /// A zlp terminating the data stage is in flight
+    Zlp,
+    /// The CSW is in flight
+    Status,
+    /// An invalid CBW was received, waiting for a Bulk-Only Mass Storage Reset
+    ResetRecovery,
+}
+
+/// A Mass Storage interface using the Bulk-Only Transport and the SCSI
+/// transparent command set with a single logical unit.
+///
+/// The application provides the storage medium by implementing
+/// [`BlockDevice`]. The interface can be registered with a
+/// [`UsbDevice`](crate::device::UsbDevice), otherwise the application
+/// is responsible for routing events to it: setup packets returned by
+/// [`Control::dispatch_event()`](crate::control::Control::dispatch_event)
+/// are passed to [`handle_setup_packet()`](Self::handle_setup_packet)
+/// and all other events to [`dispatch_event()`](Self::dispatch_event).
+///
+/// `BLOCK_SIZE` is the block size of the device in bytes and must be a
+/// multiple of the bulk endpoints' maximum packet size.
+pub struct MassStorage<B, const BLOCK_SIZE: usize> {
+    interface_number: u8,
+    endpoint_number: u8,
+    max_packet_size: usize,
+    vendor: [u8; 8],
+    product: [u8; 16],
+    revision: [u8; 4],
+
+    device: B,
+
+    state: State,
+    sense: Sense,
+    tag: u32,
+    residue: u32,
+    status: CommandStatus,
+    buffer: [u8; BLOCK_SIZE],
+}
+
+impl<B, const BLOCK_SIZE: usize> MassStorage<B, BLOCK_SIZE>
+where
+    B: BlockDevice,
+{
+    /// Creates a new Mass Storage interface for the given interface
+    /// number, bulk endpoint number and block device.
+    #[must_use]
+    pub fn new(interface_number: u8, endpoint_number: u8, device: B) -> Self {
+        Self {
+            interface_number,
+            endpoint_number,
+            max_packet_size: crate::EP_MAX_PACKET_SIZE,
+            vendor: *b"Cynthion",
+            product: *b"smolusb storage ",
+            revision: *b"0001",
+            device,
+            state: State::Command,
+            sense: Sense::NO_SENSE,
+            tag: 0,
+            residue: 0,
+            status: CommandStatus::Passed,
+            buffer: [0; BLOCK_SIZE],
+        }
+    }
+
+    /// Sets the maximum packet size of the bulk endpoints.
+    ///
+    /// Defaults to [`EP_MAX_PACKET_SIZE`](crate::EP_MAX_PACKET_SIZE).
+    #[must_use]
+    pub fn with_max_packet_size(mut self, max_packet_size: usize) -> Self {
+        self.max_packet_size = max_packet_size;
+        self
+    }
+
+    /// Sets the vendor, product and revision reported by `INQUIRY`.
+    #[must_use]
+    pub fn with_identification(
+        mut self,
+        vendor: &[u8; 8],
+        product: &[u8; 16],
+        revision: &[u8; 4],
+    ) -> Self {
+        self.vendor = *vendor;
+        self.product = *product;
+        self.revision = *revision;
+        self
+    }
+
+    /// Returns a reference to the block device.
+    #[must_use]
+    pub fn device(&self) -> &B {
+        &self.device
+    }
+
+    /// Returns a mutable reference to the block device.
+    pub fn device_mut(&mut self) -> &mut B {
+        &mut self.device
+    }
+
+    /// Returns the sense data of the last failed command.
+    #[must_use]
+    pub fn sense(&self) -> Sense {
+        self.sense
+    }
+}
+
+impl<B, const BLOCK_SIZE: usize> MassStorage<B, BLOCK_SIZE>
+where
+    B: BlockDevice,
+{
+    /// Resets the transport and primes the bulk OUT endpoint to
+    /// receive the next CBW.
+    pub fn reset<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        self.state = State::Command;
+        self.sense = Sense::NO_SENSE;
+        usb.ep_out_prime_receive(self.endpoint_number);
+    }
+
+    /// Handles a class request addressed to the interface.
+    ///
+    /// `endpoint_number` is the control endpoint the request was
+    /// received on, as returned by
+    /// [`Control::endpoint_number()`](crate::control::Control::endpoint_number).
+    ///
+    /// Returns `false` if the request was not addressed to the interface.
+    pub fn handle_setup_packet<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+    ) -> bool
+    where
+        D: UsbDriver,
+    {
+        let Request::ClassOrVendor(request) = setup_packet.request() else {
+            return false;
+        };
+        if setup_packet.request_type() != RequestType::Class
+            || setup_packet.recipient() != Recipient::Interface
+            || setup_packet.interface_number() != self.interface_number
+        {
+            return false;
+        }
+
+        let class_request = ClassRequest::from(request);
+        trace!("MassStorage {:?} {:?}", class_request, setup_packet);
+
+        match (setup_packet.direction(), class_request) {
+            (Direction::DeviceToHost, ClassRequest::GetMaxLun) => {
+                // prime to receive host zlp
+                usb.ep_out_prime_receive(endpoint_number);
+                usb.write_requested(
+                    endpoint_number,
+                    usize::from(setup_packet.length()),
+                    [0].into_iter(),
+                );
+            }
+            (Direction::HostToDevice, ClassRequest::BulkOnlyMassStorageReset) => {
+                self.reset(usb);
+                usb.write(endpoint_number, [].into_iter());
+            }
+            (direction, class_request) => {
+                warn!(
+                    "MassStorage stall: unhandled class request {:?} {:?}",
+                    direction, class_request
+                );
+                usb.stall_endpoint_in(endpoint_number);
+            }
+        }
+
+        true
+    }
+
+    /// Dispatches an event to the interface.
+    ///
+    /// Events for endpoints not belonging to the interface are ignored.
+    pub fn dispatch_event<D>(&mut self, usb: &D, event: UsbEvent)
+    where
+        D: UsbDriver,
+    {
+        match (event, self.state) {
+            (UsbEvent::BusReset, _) => self.reset(usb),
+
+            (UsbEvent::ReceivePacket(endpoint_number), State::Command)
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.receive_command(usb);
+            }
+            (UsbEvent::ReceivePacket(endpoint_number), State::DataOut { .. })
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.receive_data(usb);
+            }
+            (UsbEvent::ReceivePacket(endpoint_number), state)
+                if endpoint_number == self.endpoint_number =>
+            {
+                let bytes_read = usb.read(endpoint_number, &mut [0; crate::EP_MAX_PACKET_SIZE]);
+                warn!(
+                    "MassStorage discarded {} bytes received in state {:?}",
+                    bytes_read, state
+                );
+            }
+
+            (UsbEvent::SendComplete(endpoint_number), State::DataIn { .. })
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.send_data(usb);
+            }
+            (UsbEvent::SendComplete(endpoint_number), State::Zlp)
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.send_status(usb);
+            }
+            (UsbEvent::SendComplete(endpoint_number), State::Status)
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.state = State::Command;
+                usb.ep_out_prime_receive(self.endpoint_number);
+            }
+
+            _ => (),
+        }
+    }
+}
+
+// - Bulk-Only Transport ------------------------------------------------------
+
+impl<B, const BLOCK_SIZE: usize> MassStorage<B, BLOCK_SIZE>
+where
+    B: BlockDevice,
+{
+    fn receive_command<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        let mut packet = [0; crate::EP_MAX_PACKET_SIZE];
+        let bytes_read = usb.read(self.endpoint_number, &mut packet);
+
+        let Some(cbw) = CommandBlockWrapper::parse(&packet[..bytes_read.min(packet.len())]) else {
+            warn!(
+                "MassStorage received an invalid CBW, waiting for reset recovery: {:?}",
+                &packet[..bytes_read.min(packet.len())]
+            );
+            usb.stall_endpoint_in(self.endpoint_number);
+            usb.stall_endpoint_out(self.endpoint_number);
+            self.state = State::ResetRecovery;
+            return;
+        };
+
+        self.tag = cbw.dCBWTag.get();
+        self.status = CommandStatus::Passed;
+
+        let host_length = cbw.dCBWDataTransferLength.get();
+        let host_in = cbw.direction() == Direction::DeviceToHost;
+        trace!(
+            "MassStorage CBW {:?} {} bytes {:?}",
+            OperationCode::from(cbw.CBWCB[0]),
+            host_length,
+            cbw.direction()
+        );
+
+        let block_size = BLOCK_SIZE as u32;
+        match self.execute(&cbw.CBWCB) {
+            Ok(Reply::None) => self.start_no_data(usb, host_length, host_in),
+            Ok(Reply::Data(length)) => {
+                self.start_data_in(usb, host_length, host_in, None, length as u32);
+            }
+            Ok(Reply::Read { lba, blocks }) => {
+                self.start_data_in(usb, host_length, host_in, Some(lba), blocks * block_size);
+            }
+            Ok(Reply::Write { lba, blocks }) => {
+                self.start_data_out(usb, host_length, host_in, Some(lba), blocks * block_size);
+            }
+            Err(sense) => {
+                self.sense = sense;
+                self.status = CommandStatus::Failed;
+                self.start_no_data(usb, host_length, host_in);
+            }
+        }
+    }
+
+    /// Starts a command without a data stage on the device side.
+    fn start_no_data<D>(&mut self, usb: &D, host_length: u32, host_in: bool)
+    where
+        D: UsbDriver,
+    {
+        self.residue = host_length;
+        if host_length == 0 {
+            self.send_status(usb);
+        } else if host_in {
+            // end the data stage with a zlp
+            self.state = State::DataIn {
+                lba: None,
+                remaining: 0,
+                position: 0,
+                zlp: true,
+            };
+            self.send_data(usb);
+        } else {
+            // receive and discard the data sent by the host
+            self.state = State::DataOut {
+                lba: None,
+                remaining: host_length,
+                writes: 0,
+                position: 0,
+            };
+            usb.ep_out_prime_receive(self.endpoint_number);
+        }
+    }
+
+    /// Starts a command sending `device_length` bytes to the host.
+    fn start_data_in<D>(
+        &mut self,
+        usb: &D,
+        host_length: u32,
+        host_in: bool,
+        lba: Option<u32>,
+        device_length: u32,
+    ) where
+        D: UsbDriver,
+    {
+        if device_length == 0 {
+            self.start_no_data(usb, host_length, host_in);
+            return;
+        }
+
+        if !host_in || host_length == 0 {
+            warn!("MassStorage phase error: host does not expect any data");
+            if host_length > 0 {
+                usb.stall_endpoint_out(self.endpoint_number);
+            }
+            self.status = CommandStatus::PhaseError;
+            self.residue = host_length;
+            self.send_status(usb);
+            return;
+        }
+
+        let length = if device_length > host_length {
+            warn!("MassStorage phase error: host expects less data than the device sends");
+            self.status = CommandStatus::PhaseError;
+            host_length
+        } else {
+            device_length
+        };
+        self.residue = host_length - length;
+
+        self.state = State::DataIn {
+            lba,
+            remaining: length,
+            position: if lba.is_some() { BLOCK_SIZE } else { 0 },
+            zlp: self.residue > 0 && length as usize % self.max_packet_size == 0,
+        };
+        self.send_data(usb);
+    }
+
+    /// Starts a command receiving `device_length` bytes from the host.
+    fn start_data_out<D>(
+        &mut self,
+        usb: &D,
+        host_length: u32,
+        host_in: bool,
+        lba: Option<u32>,
+        device_length: u32,
+    ) where
+        D: UsbDriver,
+    {
+        if device_length == 0 {
+            self.start_no_data(usb, host_length, host_in);
+            return;
+        }
+
+        if host_in || device_length > host_length {
+            warn!("MassStorage phase error: host sends less data than the device expects");
+            if !host_in && host_length > 0 {
+                usb.stall_endpoint_out(self.endpoint_number);
+            }
+            self.status = CommandStatus::PhaseError;
+            self.residue = host_length;
+            self.send_status(usb);
+            return;
+        }
+
+        self.residue = host_length - device_length;
+        self.state = State::DataOut {
+            lba,
+            remaining: host_length,
+            writes: device_length,
+            position: 0,
+        };
+        usb.ep_out_prime_receive(self.endpoint_number);
+    }
+
+    /// Sends the next packet of the data stage.
+    fn send_data<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        let State::DataIn {
+            mut lba,
+            mut remaining,
+            mut position,
+            mut zlp,
+        } = self.state
+        else {
+            return;
+        };
+
+        // read the next block once the buffer has been sent
+        if let Some(block) = lba {
+            if remaining > 0 && position == BLOCK_SIZE {
+                match self.device.read_block(block, &mut self.buffer) {
+                    Ok(()) => {
+                        lba = Some(block + 1);
+                        position = 0;
+                    }
+                    Err(error) => {
+                        warn!("MassStorage read error at lba {}: {:?}", block, error);
+                        self.sense = error.into();
+                        self.status = CommandStatus::Failed;
+                        self.residue += remaining;
+                        remaining = 0;
+                        zlp = true;
+                    }
+                }
+            }
+        }
+
+        if remaining == 0 {
+            if zlp {
+                usb.write(self.endpoint_number, [].into_iter());
+                self.state = State::Zlp;
+            } else {
+                self.send_status(usb);
+            }
+            return;
+        }
+
+        let packet_length = (remaining as usize)
+            .min(self.max_packet_size)
+            .min(BLOCK_SIZE - position);
+        // write exactly one packet, write() would append its own zlp
+        usb.write_requested(
+            self.endpoint_number,
+            packet_length,
+            self.buffer[position..position + packet_length]
+                .iter()
+                .copied(),
+        );
+
+        self.state = State::DataIn {
+            lba,
+            remaining: remaining - packet_length as u32,
+            position: position + packet_length,
+            zlp,
+        };
+    }
+
+    /// Receives the next packet of the data stage.
+    fn receive_data<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        let State::DataOut {
+            mut lba,
+            remaining,
+            mut writes,
+            mut position,
+        } = self.state
+        else {
+            return;
+        };
+
+        let mut packet = [0; crate::EP_MAX_PACKET_SIZE];
+        let bytes_read = usb
+            .read(self.endpoint_number, &mut packet)
+            .min(packet.len())
+            .min(remaining as usize);
+
+        let mut offset = 0;
+        while offset < bytes_read && writes > 0 {
+            let length = (bytes_read - offset)
+                .min(BLOCK_SIZE - position)
+                .min(writes as usize);
+            self.buffer[position..position + length]
+                .copy_from_slice(&packet[offset..offset + length]);
+            offset += length;
+            position += length;
+            writes -= length as u32;
+
+            if position == BLOCK_SIZE {
+                if let Some(block) = lba {
+                    if self.status == CommandStatus::Passed {
+                        if let Err(error) = self.device.write_block(block, &self.buffer) {
+                            warn!("MassStorage write error at lba {}: {:?}", block, error);
+                            self.sense = error.into();
+                            self.status = CommandStatus::Failed;
+                        }
+                    }
+                    lba = Some(block + 1);
+                }
+                position = 0;
+            }
+        }
+
+        let remaining = remaining - bytes_read as u32;
+        if remaining == 0 || bytes_read < self.max_packet_size {
+            // the host may end the data stage early with a short packet
+            self.residue = self.residue.max(remaining);
+            self.send_status(usb);
+        } else {
+            self.state = State::DataOut {
+                lba,
+                remaining,
+                writes,
+                position,
+            };
+            usb.ep_out_prime_receive(self.endpoint_number);
+        }
+    }
+
+    fn send_status<D>(&mut self, usb: &D)
+    where
+        D: UsbDriver,
+    {
+        let csw = CommandStatusWrapper::new(self.tag, self.residue, self.status);
+        trace!("MassStorage CSW {:?}", csw);
+        usb.write(self.endpoint_number, csw.as_bytes().iter().copied());
+        self.state = State::Status;
+    }
+}
+
+// - SCSI ---------------------------------------------------------------------
+
+impl<B, const BLOCK_SIZE: usize> MassStorage<B, BLOCK_SIZE>
+where
+    B: BlockDevice,
+{
+    /// Executes a SCSI command and returns the data stage it requires.
+    fn execute(&mut self, command: &[u8; 16]) -> Result<Reply, Sense> {
+        let operation_code = OperationCode::from(command[0]);
+        if operation_code != OperationCode::RequestSense {
+            self.sense = Sense::NO_SENSE;
+        }
+
+        match operation_code {
+            OperationCode::TestUnitReady => {
+                self.check_ready()?;
+                Ok(Reply::None)
+            }
+            OperationCode::RequestSense => {
+                let data = self.sense.fixed_format();
+                self.sense = Sense::NO_SENSE;
+                Ok(self.respond(&data, usize::from(command[4])))
+            }
+            OperationCode::Inquiry => {
+                // vital product data pages are not supported
+                if command[1] & 0x01 != 0 {
+                    return Err(Sense::INVALID_FIELD_IN_CDB);
+                }
+                let data = scsi::inquiry(&self.vendor, &self.product, &self.revision);
+                let allocation_length = u16::from_be_bytes([command[3], command[4]]);
+                Ok(self.respond(&data, usize::from(allocation_length)))
+            }
+            OperationCode::ReadCapacity10 => {
+                self.check_ready()?;
+                let data = scsi::read_capacity_10(self.device.block_count(), BLOCK_SIZE as u32);
+                Ok(self.respond(&data, data.len()))
+            }
+            OperationCode::ModeSense6 => {
+                let data = scsi::mode_sense_6(self.device.is_write_protected());
+                Ok(self.respond(&data, usize::from(command[4])))
+            }
+            OperationCode::ModeSense10 => {
+                let data = scsi::mode_sense_10(self.device.is_write_protected());
+                let allocation_length = u16::from_be_bytes([command[7], command[8]]);
+                Ok(self.respond(&data, usize::from(allocation_length)))
+            }
+            OperationCode::Read10 => {
+                self.check_ready()?;
+                let (lba, blocks) = self.block_range(command)?;
+                Ok(Reply::Read { lba, blocks })
+            }
+            OperationCode::Write10 => {
+                self.check_ready()?;
+                if self.device.is_write_protected() {
+                    return Err(Sense::WRITE_PROTECTED);
+                }
+                let (lba, blocks) = self.block_range(command)?;
+                Ok(Reply::Write { lba, blocks })
+            }
+            OperationCode::StartStopUnit
+            | OperationCode::PreventAllowMediumRemoval
+            | OperationCode::Verify10 => Ok(Reply::None),
+            OperationCode::Unknown(operation_code) => {
+                warn!(
+                    "MassStorage unsupported SCSI command: {:#04x}",
+                    operation_code
+                );
+                Err(Sense::INVALID_COMMAND)
+            }
+        }
+    }
+
+    fn check_ready(&self) -> Result<(), Sense> {
+        if self.device.is_ready() {
+            Ok(())
+        } else {
+            Err(Sense::MEDIUM_NOT_PRESENT)
+        }
+    }
+
+    /// Returns the logical block address and transfer length of a
+    /// `READ (10)` or `WRITE (10)` command.
+    fn block_range(&self, command: &[u8; 16]) -> Result<(u32, u32), Sense> {
+        let lba = u32::from_be_bytes([command[2], command[3], command[4], command[5]]);
+        let blocks = u32::from(u16::from_be_bytes([command[7], command[8]]));
+        match lba.checked_add(blocks) {
+            Some(end) if end <= self.device.block_count() => Ok((lba, blocks)),
+            _ => Err(Sense::LBA_OUT_OF_RANGE),
+        }
+    }
+
+    /// Copies response data truncated to the allocation length into the buffer.
+    fn respond(&mut self, data: &[u8], allocation_length: usize) -> Reply {
+        let length = data.len().min(allocation_length).min(BLOCK_SIZE);
+        self.buffer[..length].copy_from_slice(&data[..length]);
+        Reply::Data(length)
+    }
+}
+
+// - UsbClass -----------------------------------------------------------------
+
+impl<D, B, const BLOCK_SIZE: usize> UsbClass<D> for MassStorage<B, BLOCK_SIZE>
+where
+    D: UsbDriver,
+    B: BlockDevice,
+{
+    fn has_interface(&self, interface_number: u8) -> bool {
+        interface_number == self.interface_number
+    }
+
+    fn has_endpoint(&self, endpoint_address: u8) -> bool {
+        endpoint_address & 0x7f == self.endpoint_number
+    }
+
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        _data: &[u8],
+    ) -> bool {
+        Self::handle_setup_packet(self, usb, endpoint_number, setup_packet)
+    }
+
+    fn set_configuration(&mut self, usb: &D, configuration: u8) {
+        if configuration != 0 {
+            self.reset(usb);
+        }
+    }
+
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+        Self::dispatch_event(self, usb, event);
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::descriptor::{
+        ConfigurationDescriptor, ConfigurationDescriptorHeader, DeviceDescriptor,
+        EndpointDescriptor, InterfaceDescriptor, InterfaceDescriptorHeader,
+    };
+    use crate::device::Speed;
+    use crate::setup::Recipient;
+    use crate::testing::{class_request, descriptors, ClassHarness};
+
+    // - fixtures -------------------------------------------------------------
+
+    const BLOCK_SIZE: usize = 512;
+
+    /// In-memory block device
+    struct RamDisk {
+        blocks: Vec<[u8; BLOCK_SIZE]>,
+        write_protected: bool,
+    }
+
+    impl RamDisk {
+        fn new(block_count: usize) -> Self {
+            Self {
+                blocks: vec![[0; BLOCK_SIZE]; block_count],
+                write_protected: false,
+            }
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        fn block_count(&self) -> u32 {
+            self.blocks.len() as u32
+        }
+
+        fn read_block(&mut self, lba: u32, block: &mut [u8]) -> Result<(), BlockDeviceError> {
+            let data = self
+                .blocks
+                .get(lba as usize)
+                .ok_or(BlockDeviceError::ReadFailed)?;
+            block.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn write_block(&mut self, lba: u32, block: &[u8]) -> Result<(), BlockDeviceError> {
+            let data = self
+                .blocks
+                .get_mut(lba as usize)
+                .ok_or(BlockDeviceError::WriteFailed)?;
+            data.copy_from_slice(block);
+            Ok(())
+        }
+
+        fn is_write_protected(&self) -> bool {
+            self.write_protected
+        }
+    }
+
+    type Storage = MassStorage<RamDisk, BLOCK_SIZE>;
+
+    const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
+        bDeviceClass: 0x00, // defined by the interface
+        bMaxPacketSize: 64,
+        idVendor: 0x1209,
+        idProduct: 0x0001,
+        bcdDevice: 0x0100,
+        bNumConfigurations: 1,
+        ..DeviceDescriptor::new()
+    };
+
+    static CONFIGURATION_DESCRIPTORS: [ConfigurationDescriptor; 1] =
+        [ConfigurationDescriptor::new(
+            ConfigurationDescriptorHeader {
+                bConfigurationValue: 1,
+                bmAttributes: 0x80,
+                bMaxPower: 50,
+                ..ConfigurationDescriptorHeader::new()
+            },
+            &[InterfaceDescriptor::new(
+                InterfaceDescriptorHeader {
+                    iInterfaceNumber: 0,
+                    bInterfaceClass: INTERFACE_CLASS,
+                    bInterfaceSubClass: INTERFACE_SUBCLASS_SCSI,
+                    bInterfaceProtocol: INTERFACE_PROTOCOL_BULK_ONLY,
+                    ..InterfaceDescriptorHeader::new()
+                },
+                &[
+                    EndpointDescriptor::bulk(0x81, 64),
+                    EndpointDescriptor::bulk(0x01, 64),
+                ],
+            )],
+        )
+        .validate(Speed::Full)];
+
+    fn setup() -> ClassHarness<Storage> {
+        let mut harness = ClassHarness::new(
+            descriptors(DEVICE_DESCRIPTOR, &CONFIGURATION_DESCRIPTORS),
+            Storage::new(0, 1, RamDisk::new(16)).with_max_packet_size(64),
+        );
+        harness.configure(1);
+        harness
+    }
+
+    fn storage_request(request: ClassRequest, index: u16, length: u16) -> SetupPacket {
+        let direction = if length == 0 {
+            Direction::HostToDevice
+        } else {
+            Direction::DeviceToHost
+        };
+        class_request(direction, Recipient::Interface, request, 0, index, length)
+    }
+
+    /// Runs a command and returns the data and CSW sent by the device.
+    fn command(
+        harness: &mut ClassHarness<Storage>,
+        tag: u32,
+        length: u32,
+        direction: Direction,
+        command: &[u8],
+        data_out: &[u8],
+    ) -> (Vec<Vec<u8>>, CommandStatusWrapper) {
+        let cbw = CommandBlockWrapper::new(tag, length, direction, command);
+        harness.usb.host_out(1, cbw.as_bytes());
+        assert!(harness.dispatch().is_empty());
+        for packet in data_out.chunks(64) {
+            assert!(harness.usb.is_primed(1));
+            harness.usb.host_out(1, packet);
+            assert!(harness.dispatch().is_empty());
+        }
+        let mut packets = harness.usb.packets_written(1);
+        harness.usb.take_operations();
+
+        let csw = packets.pop().expect("no CSW");
+        let csw = CommandStatusWrapper::read_from(&csw[..]).expect("invalid CSW");
+        assert_eq!(csw.dCSWSignature.get(), CommandStatusWrapper::SIGNATURE);
+        assert_eq!(csw.dCSWTag.get(), tag);
+
+        // ready for the next command
+        assert!(harness.usb.is_primed(1));
+
+        (packets, csw)
+    }
+
+    fn read10(lba: u32, blocks: u16) -> [u8; 10] {
+        let lba = lba.to_be_bytes();
+        let blocks = blocks.to_be_bytes();
+        [
+            0x28, 0, lba[0], lba[1], lba[2], lba[3], 0, blocks[0], blocks[1], 0,
+        ]
+    }
+
+    fn write10(lba: u32, blocks: u16) -> [u8; 10] {
+        let mut command = read10(lba, blocks);
+        command[0] = 0x2a;
+        command
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_scsi_queries() {
+        let mut harness = setup();
+        assert_eq!(core::mem::size_of::<CommandBlockWrapper>(), 31);
+        assert_eq!(core::mem::size_of::<CommandStatusWrapper>(), 13);
+
+        // INQUIRY
+        let inquiry = [0x12, 0, 0, 0, 36, 0];
+        let (data, csw) = command(&mut harness, 1, 36, Direction::DeviceToHost, &inquiry, &[]);
+        assert_eq!(data.len(), 1);
+        assert_eq!(data[0].len(), 36);
+        assert_eq!(&data[0][..5], [0x00, 0x80, 0x04, 0x02, 31]);
+        assert_eq!(&data[0][8..16], b"Cynthion");
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 0);
+
+        // TEST UNIT READY
+        let (data, csw) = command(&mut harness, 2, 0, Direction::HostToDevice, &[0; 6], &[]);
+        assert!(data.is_empty());
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+
+        // READ CAPACITY (10)
+        let read_capacity = [0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let (data, csw) = command(
+            &mut harness,
+            3,
+            8,
+            Direction::DeviceToHost,
+            &read_capacity,
+            &[],
+        );
+        assert_eq!(data, [vec![0, 0, 0, 15, 0, 0, 0x02, 0x00]]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+
+        // MODE SENSE (6) with a larger allocation length
+        harness.classes_mut().device_mut().write_protected = true;
+        let mode_sense = [0x1a, 0, 0x3f, 0, 192, 0];
+        let (data, csw) = command(
+            &mut harness,
+            4,
+            192,
+            Direction::DeviceToHost,
+            &mode_sense,
+            &[],
+        );
+        assert_eq!(data, [vec![3, 0, 0x80, 0]]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 188);
+    }
+
+    #[test]
+    fn test_read_write_blocks() {
+        let mut harness = setup();
+
+        // WRITE (10): two blocks
+        let data: Vec<u8> = (0..2 * BLOCK_SIZE).map(|n| (n % 251) as u8).collect();
+        let (packets, csw) = command(
+            &mut harness,
+            1,
+            data.len() as u32,
+            Direction::HostToDevice,
+            &write10(5, 2),
+            &data,
+        );
+        assert!(packets.is_empty());
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 0);
+        assert_eq!(harness.classes().device().blocks[5][..], data[..BLOCK_SIZE]);
+        assert_eq!(harness.classes().device().blocks[6][..], data[BLOCK_SIZE..]);
+
+        // READ (10): the same blocks, one packet at a time
+        let (packets, csw) = command(
+            &mut harness,
+            2,
+            data.len() as u32,
+            Direction::DeviceToHost,
+            &read10(5, 2),
+            &[],
+        );
+        assert_eq!(packets.len(), 2 * BLOCK_SIZE / 64);
+        assert!(packets.iter().all(|packet| packet.len() == 64));
+        assert_eq!(packets.concat(), data);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+
+        // READ (10): host expects less data than the device sends
+        let (packets, csw) = command(
+            &mut harness,
+            3,
+            256,
+            Direction::DeviceToHost,
+            &read10(5, 1),
+            &[],
+        );
+        assert_eq!(packets.concat(), data[..256]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::PhaseError as u8);
+    }
+
+    #[test]
+    fn test_zero_length_read_write() {
+        let mut harness = setup();
+
+        // READ (10) and WRITE (10) of zero blocks without a data stage
+        for (tag, command_block) in [(1, read10(5, 0)), (2, write10(5, 0))] {
+            let (packets, csw) = command(
+                &mut harness,
+                tag,
+                0,
+                Direction::HostToDevice,
+                &command_block,
+                &[],
+            );
+            assert!(packets.is_empty());
+            assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+            assert_eq!(csw.dCSWDataResidue.get(), 0);
+        }
+
+        // READ (10) of zero blocks when the host expects data
+        let (packets, csw) = command(
+            &mut harness,
+            3,
+            512,
+            Direction::DeviceToHost,
+            &read10(5, 0),
+            &[],
+        );
+        assert_eq!(packets, [Vec::<u8>::new()]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 512);
+
+        // WRITE (10) of zero blocks when the host sends data
+        let (packets, csw) = command(
+            &mut harness,
+            4,
+            64,
+            Direction::HostToDevice,
+            &write10(5, 0),
+            &[0xaa; 64],
+        );
+        assert!(packets.is_empty());
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 64);
+        assert!(harness.classes().device().blocks[5]
+            .iter()
+            .all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let mut harness = setup();
+
+        // READ (10) past the end of the device: data stage ends with a zlp
+        let (packets, csw) = command(
+            &mut harness,
+            1,
+            512,
+            Direction::DeviceToHost,
+            &read10(15, 2),
+            &[],
+        );
+        assert_eq!(packets, [Vec::<u8>::new()]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Failed as u8);
+        assert_eq!(csw.dCSWDataResidue.get(), 512);
+
+        // REQUEST SENSE reports the error once
+        let request_sense = [0x03, 0, 0, 0, 18, 0];
+        let (packets, _) = command(
+            &mut harness,
+            2,
+            18,
+            Direction::DeviceToHost,
+            &request_sense,
+            &[],
+        );
+        assert_eq!(packets[0][2], scsi::SenseKey::IllegalRequest as u8);
+        assert_eq!(packets[0][12], Sense::LBA_OUT_OF_RANGE.asc);
+        assert_eq!(harness.classes().sense(), Sense::NO_SENSE);
+
+        // WRITE (10) to a write protected device: data is discarded
+        harness.classes_mut().device_mut().write_protected = true;
+        let (_, csw) = command(
+            &mut harness,
+            3,
+            512,
+            Direction::HostToDevice,
+            &write10(0, 1),
+            &[0xff; 512],
+        );
+        assert_eq!(csw.bCSWStatus, CommandStatus::Failed as u8);
+        assert_eq!(harness.classes().sense(), Sense::WRITE_PROTECTED);
+        assert_eq!(harness.classes().device().blocks[0], [0; BLOCK_SIZE]);
+
+        // unsupported command
+        let (_, csw) = command(&mut harness, 4, 0, Direction::HostToDevice, &[0xc0; 6], &[]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Failed as u8);
+        assert_eq!(harness.classes().sense(), Sense::INVALID_COMMAND);
+    }
+
+    #[test]
+    fn test_reset_recovery() {
+        let mut harness = setup();
+
+        // GET_MAX_LUN
+        let setup_packet = storage_request(ClassRequest::GetMaxLun, 0, 1);
+        assert_eq!(harness.request(setup_packet, &[]), [0]);
+
+        // invalid CBW stalls both bulk endpoints
+        harness.usb.take_operations();
+        harness.usb.host_out(1, &[0; 31]);
+        harness.dispatch();
+        assert!(harness.usb.is_stalled(1, Direction::DeviceToHost));
+        assert!(harness.usb.is_stalled(1, Direction::HostToDevice));
+
+        // ...and further packets are ignored until reset recovery
+        let cbw = CommandBlockWrapper::new(1, 0, Direction::HostToDevice, &[0; 6]);
+        harness.usb.host_out(1, cbw.as_bytes());
+        harness.dispatch();
+        assert!(harness.usb.packets_written(1).is_empty());
+
+        // BULK-ONLY MASS STORAGE RESET
+        let setup_packet = storage_request(ClassRequest::BulkOnlyMassStorageReset, 0, 0);
+        harness.request(setup_packet, &[]);
+        assert_eq!(harness.usb.packets_written(0), [Vec::<u8>::new()]);
+        assert!(harness.usb.is_primed(1));
+        harness.usb.take_operations();
+
+        let (_, csw) = command(&mut harness, 2, 0, Direction::HostToDevice, &[0; 6], &[]);
+        assert_eq!(csw.bCSWStatus, CommandStatus::Passed as u8);
+
+        // requests for other interfaces are left to the application
+        let setup_packet = storage_request(ClassRequest::GetMaxLun, 1, 1);
+        harness.usb.host_setup(0, setup_packet);
+        assert_eq!(harness.dispatch(), [setup_packet]);
+    }
+}
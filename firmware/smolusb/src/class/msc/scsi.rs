@@ -0,0 +1,163 @@
+//! SCSI commands and response data used by the Mass Storage class
+
+// - OperationCode ------------------------------------------------------------
+
+/// SCSI operation codes
+///
+/// Just enough of the SCSI Block Commands to be usable on the major
+/// operating systems.
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum OperationCode {
+    TestUnitReady = 0x00,             //   0
+    RequestSense = 0x03,              //   3
+    Inquiry = 0x12,                   //  18
+    ModeSense6 = 0x1a,                //  26
+    StartStopUnit = 0x1b,             //  27
+    PreventAllowMediumRemoval = 0x1e, //  30
+    ReadCapacity10 = 0x25,            //  37
+    Read10 = 0x28,                    //  40
+    Write10 = 0x2a,                   //  42
+    Verify10 = 0x2f,                  //  47
+    ModeSense10 = 0x5a,               //  90
+    Unknown(u8),
+}
+
+impl From<u8> for OperationCode {
+    fn from(value: u8) -> Self {
+        match value {
+            0x00 => OperationCode::TestUnitReady,
+            0x03 => OperationCode::RequestSense,
+            0x12 => OperationCode::Inquiry,
+            0x1a => OperationCode::ModeSense6,
+            0x1b => OperationCode::StartStopUnit,
+            0x1e => OperationCode::PreventAllowMediumRemoval,
+            0x25 => OperationCode::ReadCapacity10,
+            0x28 => OperationCode::Read10,
+            0x2a => OperationCode::Write10,
+            0x2f => OperationCode::Verify10,
+            0x5a => OperationCode::ModeSense10,
+            _ => OperationCode::Unknown(value),
+        }
+    }
+}
+
+// - Sense --------------------------------------------------------------------
+
+/// SCSI sense key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SenseKey {
+    NoSense = 0x00,
+    NotReady = 0x02,
+    MediumError = 0x03,
+    IllegalRequest = 0x05,
+    UnitAttention = 0x06,
+    DataProtect = 0x07,
+}
+
+/// Sense data reported by `REQUEST SENSE` after a failed command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sense {
+    pub key: SenseKey,
+    /// Additional sense code
+    pub asc: u8,
+    /// Additional sense code qualifier
+    pub ascq: u8,
+}
+
+impl Sense {
+    pub const NO_SENSE: Self = Self::new(SenseKey::NoSense, 0x00, 0x00);
+    pub const MEDIUM_NOT_PRESENT: Self = Self::new(SenseKey::NotReady, 0x3a, 0x00);
+    pub const UNRECOVERED_READ_ERROR: Self = Self::new(SenseKey::MediumError, 0x11, 0x00);
+    pub const WRITE_ERROR: Self = Self::new(SenseKey::MediumError, 0x0c, 0x00);
+    pub const INVALID_COMMAND: Self = Self::new(SenseKey::IllegalRequest, 0x20, 0x00);
+    pub const LBA_OUT_OF_RANGE: Self = Self::new(SenseKey::IllegalRequest, 0x21, 0x00);
+    pub const INVALID_FIELD_IN_CDB: Self = Self::new(SenseKey::IllegalRequest, 0x24, 0x00);
+    pub const WRITE_PROTECTED: Self = Self::new(SenseKey::DataProtect, 0x27, 0x00);
+
+    #[must_use]
+    pub const fn new(key: SenseKey, asc: u8, ascq: u8) -> Self {
+        Self { key, asc, ascq }
+    }
+
+    /// Returns the sense data in fixed format.
+    #[must_use]
+    pub const fn fixed_format(&self) -> [u8; 18] {
+        [
+            0x70, // current errors, fixed format
+            0x00,
+            self.key as u8,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            10, // additional sense length
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+            self.asc,
+            self.ascq,
+            0x00,
+            0x00,
+            0x00,
+            0x00,
+        ]
+    }
+}
+
+// - response data ------------------------------------------------------------
+
+/// Returns standard `INQUIRY` data for a direct access block device.
+#[must_use]
+pub fn inquiry(vendor: &[u8; 8], product: &[u8; 16], revision: &[u8; 4]) -> [u8; 36] {
+    let mut data = [0; 36];
+    data[0] = 0x00; // direct access block device
+    data[1] = 0x80; // removable medium
+    data[2] = 0x04; // SPC-2
+    data[3] = 0x02; // response data format
+    data[4] = 36 - 5; // additional length
+    data[8..16].copy_from_slice(vendor);
+    data[16..32].copy_from_slice(product);
+    data[32..36].copy_from_slice(revision);
+    data
+}
+
+/// Returns `READ CAPACITY (10)` data.
+#[must_use]
+pub fn read_capacity_10(block_count: u32, block_size: u32) -> [u8; 8] {
+    let mut data = [0; 8];
+    data[..4].copy_from_slice(&block_count.saturating_sub(1).to_be_bytes());
+    data[4..].copy_from_slice(&block_size.to_be_bytes());
+    data
+}
+
+/// Returns a `MODE SENSE (6)` parameter header without any mode pages.
+#[must_use]
+pub fn mode_sense_6(write_protected: bool) -> [u8; 4] {
+    [3, 0x00, device_specific_parameter(write_protected), 0x00]
+}
+
+/// Returns a `MODE SENSE (10)` parameter header without any mode pages.
+#[must_use]
+pub fn mode_sense_10(write_protected: bool) -> [u8; 8] {
+    [
+        0x00,
+        6,
+        0x00,
+        device_specific_parameter(write_protected),
+        0x00,
+        0x00,
+        0x00,
+        0x00,
+    ]
+}
+
+fn device_specific_parameter(write_protected: bool) -> u8 {
+    if write_protected {
+        0x80
+    } else {
+        0x00
+    }
+}
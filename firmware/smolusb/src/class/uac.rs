@@ -0,0 +1,980 @@
+//! USB Audio Class 1.0
+
+use log::{trace, warn};
+
+use crate::class::UsbClass;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, Recipient, RequestType, SetupPacket};
+use crate::traits::{IsochronousEndpoint, UsbDriver};
+
+/// `bInterfaceClass` for audio interfaces
+pub const INTERFACE_CLASS: u8 = 0x01;
+/// `bInterfaceSubClass` for the audio control interface
+pub const INTERFACE_SUBCLASS_AUDIOCONTROL: u8 = 0x01;
+/// `bInterfaceSubClass` for audio streaming interfaces
+pub const INTERFACE_SUBCLASS_AUDIOSTREAMING: u8 = 0x02;
+
+/// `bDescriptorType` of class-specific interface descriptors
+pub const DESCRIPTOR_TYPE_CS_INTERFACE: u8 = 0x24;
+/// `bDescriptorType` of class-specific endpoint descriptors
+pub const DESCRIPTOR_TYPE_CS_ENDPOINT: u8 = 0x25;
+
+/// `wTerminalType` of a USB streaming terminal
+pub const TERMINAL_USB_STREAMING: u16 = 0x0101;
+/// `wTerminalType` of a microphone input terminal
+pub const TERMINAL_MICROPHONE: u16 = 0x0201;
+/// `wTerminalType` of a speaker output terminal
+pub const TERMINAL_SPEAKER: u16 = 0x0301;
+
+/// Feature unit control selector for mute
+pub const MUTE_CONTROL: u8 = 0x01;
+/// Feature unit control selector for volume
+pub const VOLUME_CONTROL: u8 = 0x02;
+/// Endpoint control selector for the sampling frequency
+pub const SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+/// Minimum volume reported by the feature unit in 1/256 dB: -60 dB
+pub const VOLUME_MIN: i16 = -60 * 256;
+/// Maximum volume reported by the feature unit in 1/256 dB: 0 dB
+pub const VOLUME_MAX: i16 = 0;
+/// Volume resolution reported by the feature unit in 1/256 dB: 1 dB
+pub const VOLUME_RESOLUTION: i16 = 256;
+
+// - ClassRequest -------------------------------------------------------------
+
+/// Audio Class Requests
+#[derive(Debug, PartialEq)]
+#[repr(u8)]
+pub enum ClassRequest {
+    SetCur = 0x01, //   1
+    SetMin = 0x02, //   2
+    SetMax = 0x03, //   3
+    SetRes = 0x04, //   4
+    GetCur = 0x81, // 129
+    GetMin = 0x82, // 130
+    GetMax = 0x83, // 131
+    GetRes = 0x84, // 132
+    Unknown(u8),
+}
+
+impl From<u8> for ClassRequest {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => ClassRequest::SetCur,
+            0x02 => ClassRequest::SetMin,
+            0x03 => ClassRequest::SetMax,
+            0x04 => ClassRequest::SetRes,
+            0x81 => ClassRequest::GetCur,
+            0x82 => ClassRequest::GetMin,
+            0x83 => ClassRequest::GetMax,
+            0x84 => ClassRequest::GetRes,
+            _ => ClassRequest::Unknown(value),
+        }
+    }
+}
+
+impl From<ClassRequest> for u8 {
+    fn from(class_request: ClassRequest) -> Self {
+        match class_request {
+            ClassRequest::SetCur => 0x01,
+            ClassRequest::SetMin => 0x02,
+            ClassRequest::SetMax => 0x03,
+            ClassRequest::SetRes => 0x04,
+            ClassRequest::GetCur => 0x81,
+            ClassRequest::GetMin => 0x82,
+            ClassRequest::GetMax => 0x83,
+            ClassRequest::GetRes => 0x84,
+            ClassRequest::Unknown(value) => value,
+        }
+    }
+}
+
+// - class-specific descriptors -----------------------------------------------
+//
+// The functions below return descriptors without their header for use
+// as the tail of a `ClassDescriptor` of type `DESCRIPTOR_TYPE_CS_INTERFACE`
+// or `DESCRIPTOR_TYPE_CS_ENDPOINT`.
+//
+// UAC1 specifies 9 byte standard endpoint descriptors for isochronous
+// data endpoints with two trailing fields that must be zero for them.
+// smolusb emits the standard 7 byte form which is accepted by Linux.
+
+/// Returns a class-specific audio control interface header for a
+/// function with a single audio streaming interface.
+///
+/// `total_length` is the combined length of all class-specific audio
+/// control interface descriptors, including this one.
+#[must_use]
+pub const fn ac_header(total_length: u16, streaming_interface: u8) -> [u8; 7] {
+    let total_length = total_length.to_le_bytes();
+    [
+        0x01, // HEADER
+        0x00, // bcdADC: 1.00
+        0x01,
+        total_length[0],
+        total_length[1],
+        1, // bInCollection
+        streaming_interface,
+    ]
+}
+
+/// Returns an input terminal descriptor.
+#[must_use]
+pub const fn input_terminal(
+    terminal_id: u8,
+    terminal_type: u16,
+    channels: u8,
+    channel_config: u16,
+) -> [u8; 10] {
+    let terminal_type = terminal_type.to_le_bytes();
+    let channel_config = channel_config.to_le_bytes();
+    [
+        0x02, // INPUT_TERMINAL
+        terminal_id,
+        terminal_type[0],
+        terminal_type[1],
+        0, // bAssocTerminal
+        channels,
+        channel_config[0],
+        channel_config[1],
+        0, // iChannelNames
+        0, // iTerminal
+    ]
+}
+
+/// Returns an output terminal descriptor.
+#[must_use]
+pub const fn output_terminal(terminal_id: u8, terminal_type: u16, source_id: u8) -> [u8; 7] {
+    let terminal_type = terminal_type.to_le_bytes();
+    [
+        0x03, // OUTPUT_TERMINAL
+        terminal_id,
+        terminal_type[0],
+        terminal_type[1],
+        0, // bAssocTerminal
+        source_id,
+        0, // iTerminal
+    ]
+}
+
+/// Returns a feature unit descriptor with mute and volume controls on
+/// the master channel.
+#[must_use]
+pub const fn feature_unit(unit_id: u8, source_id: u8) -> [u8; 6] {
+    [
+        0x06, // FEATURE_UNIT
+        unit_id,
+        source_id,
+        1,                                                       // bControlSize
+        (1 << (MUTE_CONTROL - 1)) | (1 << (VOLUME_CONTROL - 1)), // bmaControls(0)
+        0,                                                       // iFeature
+    ]
+}
+
+/// Returns a class-specific audio streaming interface descriptor for
+/// PCM data.
+#[must_use]
+pub const fn as_general(terminal_link: u8) -> [u8; 5] {
+    [
+        0x01, // AS_GENERAL
+        terminal_link,
+        1,    // bDelay
+        0x01, // wFormatTag: PCM
+        0x00,
+    ]
+}
+
+/// Returns a Type I format descriptor with a discrete set of sample rates.
+///
+/// `LENGTH` must be `6 + 3 * sample_rates.len()`:
+///
+/// ```
+/// use smolusb::class::uac;
+///
+/// const FORMAT: [u8; 12] = uac::format_type_i(2, 2, 16, &[44_100, 48_000]);
+/// assert_eq!(FORMAT[6..9], [0x44, 0xac, 0x00]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `LENGTH` does not match the number of sample rates.
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub const fn format_type_i<const LENGTH: usize>(
+    channels: u8,
+    subframe_size: u8,
+    bit_resolution: u8,
+    sample_rates: &[u32],
+) -> [u8; LENGTH] {
+    assert!(
+        LENGTH == 6 + 3 * sample_rates.len(),
+        "format descriptor length does not match the number of sample rates"
+    );
+    let mut descriptor = [0; LENGTH];
+    descriptor[0] = 0x02; // FORMAT_TYPE
+    descriptor[1] = 0x01; // FORMAT_TYPE_I
+    descriptor[2] = channels;
+    descriptor[3] = subframe_size;
+    descriptor[4] = bit_resolution;
+    descriptor[5] = sample_rates.len() as u8;
+    let mut i = 0;
+    while i < sample_rates.len() {
+        let sample_rate = sample_rates[i].to_le_bytes();
+        descriptor[6 + 3 * i] = sample_rate[0];
+        descriptor[7 + 3 * i] = sample_rate[1];
+        descriptor[8 + 3 * i] = sample_rate[2];
+        i += 1;
+    }
+    descriptor
+}
+
+/// Returns a class-specific isochronous audio data endpoint descriptor
+/// supporting the sampling frequency control.
+#[must_use]
+pub const fn as_endpoint_general() -> [u8; 5] {
+    [
+        0x01,                             // EP_GENERAL
+        1 << (SAMPLING_FREQ_CONTROL - 1), // bmAttributes
+        0,                                // bLockDelayUnits
+        0x00,                             // wLockDelay
+        0x00,
+    ]
+}
+
+// - AudioStream --------------------------------------------------------------
+
+/// An audio streaming interface of a UAC1 speaker or microphone.
+///
+/// The interface can be registered with a
+/// [`UsbDevice`](crate::device::UsbDevice), otherwise the application
+/// is responsible for routing events to it: setup packets returned by
+/// [`Control::dispatch_event()`](crate::control::Control::dispatch_event)
+/// are passed to [`handle_setup_packet()`](Self::handle_setup_packet)
+/// along with [`Control::data()`](crate::control::Control::data) and
+/// all other events to [`dispatch_event()`](Self::dispatch_event).
+/// Changes to the alternate setting of the streaming interface are
+/// passed to [`set_alternate_setting()`](Self::set_alternate_setting).
+///
+/// Alternate setting zero is the zero-bandwidth setting, any other
+/// alternate setting starts streaming.
+///
+/// `PACKET_SIZE` is the maximum packet size of the isochronous endpoint.
+pub struct AudioStream<'a, const PACKET_SIZE: usize> {
+    streaming_interface: u8,
+    endpoint_number: u8,
+    direction: Direction,
+    sample_rates: &'a [u32],
+    feature_unit: Option<(u8, u8)>,
+
+    alternate_setting: u8,
+    sample_rate: u32,
+    mute: bool,
+    volume: i16,
+    frame_number: Option<u16>,
+
+    packet: [u8; PACKET_SIZE],
+    packet_length: Option<usize>,
+}
+
+impl<'a, const PACKET_SIZE: usize> AudioStream<'a, PACKET_SIZE> {
+    /// Creates the streaming interface of a speaker receiving audio from
+    /// the host on an isochronous OUT endpoint.
+    ///
+    /// The first sample rate is the default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rates` is empty.
+    #[must_use]
+    pub fn speaker(streaming_interface: u8, endpoint_number: u8, sample_rates: &'a [u32]) -> Self {
+        Self::new(
+            streaming_interface,
+            endpoint_number,
+            Direction::HostToDevice,
+            sample_rates,
+        )
+    }
+
+    /// Creates the streaming interface of a microphone sending audio to
+    /// the host on an isochronous IN endpoint.
+    ///
+    /// The first sample rate is the default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sample_rates` is empty.
+    #[must_use]
+    pub fn microphone(
+        streaming_interface: u8,
+        endpoint_number: u8,
+        sample_rates: &'a [u32],
+    ) -> Self {
+        Self::new(
+            streaming_interface,
+            endpoint_number,
+            Direction::DeviceToHost,
+            sample_rates,
+        )
+    }
+
+    fn new(
+        streaming_interface: u8,
+        endpoint_number: u8,
+        direction: Direction,
+        sample_rates: &'a [u32],
+    ) -> Self {
+        assert!(!sample_rates.is_empty(), "no sample rates");
+        Self {
+            streaming_interface,
+            endpoint_number,
+            direction,
+            sample_rates,
+            feature_unit: None,
+            alternate_setting: 0,
+            sample_rate: sample_rates[0],
+            mute: false,
+            volume: VOLUME_MAX,
+            frame_number: None,
+            packet: [0; PACKET_SIZE],
+            packet_length: None,
+        }
+    }
+
+    /// Handles mute and volume requests for the feature unit with the
+    /// given id on the audio control interface.
+    #[must_use]
+    pub fn with_feature_unit(mut self, control_interface: u8, unit_id: u8) -> Self {
+        self.feature_unit = Some((control_interface, unit_id));
+        self
+    }
+
+    /// Returns the address of the isochronous endpoint.
+    #[must_use]
+    pub fn endpoint_address(&self) -> u8 {
+        self.endpoint_number | self.direction as u8
+    }
+
+    /// Returns the sample rate selected by the host.
+    #[must_use]
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Returns `true` if the host has muted the feature unit.
+    #[must_use]
+    pub fn is_muted(&self) -> bool {
+        self.mute
+    }
+
+    /// Returns the feature unit volume in 1/256 dB.
+    #[must_use]
+    pub fn volume(&self) -> i16 {
+        self.volume
+    }
+
+    /// Returns `true` if the host has selected a streaming alternate setting.
+    #[must_use]
+    pub fn is_streaming(&self) -> bool {
+        self.alternate_setting != 0
+    }
+
+    /// Returns the frame number of the last start-of-frame event.
+    ///
+    /// Returns `None` until the first [`UsbEvent::StartOfFrame`] has
+    /// been dispatched, which never happens with drivers that do not
+    /// detect start-of-frame packets.
+    #[must_use]
+    pub fn frame_number(&self) -> Option<u16> {
+        self.frame_number
+    }
+}
+
+impl<const PACKET_SIZE: usize> AudioStream<'_, PACKET_SIZE> {
+    /// Stops streaming and discards any received packet.
+    pub fn reset(&mut self) {
+        self.alternate_setting = 0;
+        self.packet_length = None;
+    }
+
+    /// Sets the alternate setting selected by the host for the
+    /// streaming interface.
+    pub fn set_alternate_setting<D>(&mut self, usb: &D, alternate_setting: u8)
+    where
+        D: UsbDriver + IsochronousEndpoint,
+    {
+        if alternate_setting == self.alternate_setting {
+            return;
+        }
+        trace!(
+            "AudioStream interface {} alternate setting {}",
+            self.streaming_interface,
+            alternate_setting
+        );
+
+        self.alternate_setting = alternate_setting;
+        self.packet_length = None;
+        if alternate_setting != 0 {
+            usb.configure_isochronous(self.endpoint_number, self.direction, PACKET_SIZE);
+            if self.direction == Direction::HostToDevice {
+                usb.ep_out_prime_receive(self.endpoint_number);
+            }
+        }
+    }
+
+    /// Handles a class request addressed to the isochronous endpoint or
+    /// the feature unit.
+    ///
+    /// `endpoint_number` is the control endpoint the request was
+    /// received on, as returned by
+    /// [`Control::endpoint_number()`](crate::control::Control::endpoint_number).
+    /// `data` holds the data stage of `SET_CUR` requests, which has
+    /// already been acknowledged by [`Control`](crate::control::Control).
+    ///
+    /// Returns `false` if the request was not addressed to the interface.
+    pub fn handle_setup_packet<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool
+    where
+        D: UsbDriver,
+    {
+        if setup_packet.request_type() != RequestType::Class {
+            return false;
+        }
+
+        // audio class request codes overlap the standard request codes
+        let class_request = ClassRequest::from(u8::from(setup_packet.request()));
+        let [index_low, index_high] = setup_packet.index().to_le_bytes();
+        let control_selector = setup_packet.value().to_le_bytes()[1];
+
+        match setup_packet.recipient() {
+            Recipient::Endpoint if index_low == self.endpoint_address() => {
+                trace!("AudioStream {:?} {:?}", class_request, setup_packet);
+                self.handle_endpoint_request(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    class_request,
+                    control_selector,
+                    data,
+                );
+                true
+            }
+            Recipient::Interface if matches!(self.feature_unit, Some(unit) if unit == (index_low, index_high)) =>
+            {
+                trace!("AudioStream {:?} {:?}", class_request, setup_packet);
+                self.handle_feature_unit_request(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    class_request,
+                    control_selector,
+                    data,
+                );
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Dispatches an event to the interface.
+    ///
+    /// Events for endpoints not belonging to the interface are ignored.
+    pub fn dispatch_event<D>(&mut self, usb: &D, event: UsbEvent)
+    where
+        D: UsbDriver,
+    {
+        match event {
+            UsbEvent::BusReset => self.reset(),
+            UsbEvent::StartOfFrame(frame_number) => self.frame_number = Some(frame_number),
+            UsbEvent::ReceivePacket(endpoint_number)
+                if endpoint_number == self.endpoint_number
+                    && self.direction == Direction::HostToDevice =>
+            {
+                let bytes_read = usb.read(endpoint_number, &mut self.packet);
+                if bytes_read > PACKET_SIZE {
+                    warn!(
+                        "AudioStream discarded {} bytes of an oversized packet",
+                        bytes_read - PACKET_SIZE
+                    );
+                }
+                self.packet_length = Some(bytes_read.min(PACKET_SIZE));
+                if self.is_streaming() {
+                    usb.ep_out_prime_receive(endpoint_number);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Returns the last packet of samples received from the host since
+    /// the previous call.
+    pub fn read(&mut self) -> Option<&[u8]> {
+        let packet_length = self.packet_length.take()?;
+        Some(&self.packet[..packet_length])
+    }
+
+    /// Writes a packet of samples to the host.
+    ///
+    /// Should be called once per (micro)frame, typically in response to
+    /// [`UsbEvent::StartOfFrame`] or from a timer if the driver does not
+    /// detect start-of-frame packets. Samples beyond `PACKET_SIZE` are
+    /// discarded.
+    ///
+    /// Returns the number of bytes written, which is zero if the host
+    /// is not streaming from a microphone or the driver dropped the
+    /// packet.
+    pub fn write<D>(&mut self, usb: &D, samples: &[u8]) -> usize
+    where
+        D: UsbDriver + IsochronousEndpoint,
+    {
+        if !self.is_streaming() || self.direction != Direction::DeviceToHost {
+            return 0;
+        }
+        usb.write_isochronous(
+            self.endpoint_number,
+            samples.iter().copied().take(PACKET_SIZE),
+        )
+    }
+}
+
+impl<const PACKET_SIZE: usize> AudioStream<'_, PACKET_SIZE> {
+    fn handle_endpoint_request<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        class_request: ClassRequest,
+        control_selector: u8,
+        data: &[u8],
+    ) where
+        D: UsbDriver,
+    {
+        match (class_request, control_selector) {
+            (ClassRequest::SetCur, SAMPLING_FREQ_CONTROL) if data.len() == 3 => {
+                // the data stage has already been acknowledged by Control
+                let sample_rate = u32::from_le_bytes([data[0], data[1], data[2], 0]);
+                if self.sample_rates.contains(&sample_rate) {
+                    self.sample_rate = sample_rate;
+                } else {
+                    warn!("AudioStream: unsupported sample rate {}", sample_rate);
+                }
+            }
+            (ClassRequest::GetCur, SAMPLING_FREQ_CONTROL) => {
+                let [rate_0, rate_1, rate_2, _] = self.sample_rate.to_le_bytes();
+                Self::respond(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    &[rate_0, rate_1, rate_2],
+                );
+            }
+            (class_request, control_selector) => {
+                warn!(
+                    "AudioStream stall: unhandled endpoint request {:?} {}",
+                    class_request, control_selector
+                );
+                Self::stall(usb, endpoint_number, setup_packet);
+            }
+        }
+    }
+
+    fn handle_feature_unit_request<D>(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        class_request: ClassRequest,
+        control_selector: u8,
+        data: &[u8],
+    ) where
+        D: UsbDriver,
+    {
+        match (class_request, control_selector, data) {
+            (ClassRequest::SetCur, MUTE_CONTROL, &[mute]) => {
+                self.mute = mute != 0;
+            }
+            (ClassRequest::GetCur, MUTE_CONTROL, _) => {
+                Self::respond(usb, endpoint_number, setup_packet, &[u8::from(self.mute)]);
+            }
+            (ClassRequest::SetCur, VOLUME_CONTROL, &[volume_low, volume_high]) => {
+                let volume = i16::from_le_bytes([volume_low, volume_high]);
+                self.volume = volume.clamp(VOLUME_MIN, VOLUME_MAX);
+            }
+            (ClassRequest::GetCur, VOLUME_CONTROL, _) => {
+                Self::respond(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    &self.volume.to_le_bytes(),
+                );
+            }
+            (ClassRequest::GetMin, VOLUME_CONTROL, _) => {
+                Self::respond(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    &VOLUME_MIN.to_le_bytes(),
+                );
+            }
+            (ClassRequest::GetMax, VOLUME_CONTROL, _) => {
+                Self::respond(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    &VOLUME_MAX.to_le_bytes(),
+                );
+            }
+            (ClassRequest::GetRes, VOLUME_CONTROL, _) => {
+                Self::respond(
+                    usb,
+                    endpoint_number,
+                    setup_packet,
+                    &VOLUME_RESOLUTION.to_le_bytes(),
+                );
+            }
+            (class_request, control_selector, _) => {
+                warn!(
+                    "AudioStream stall: unhandled feature unit request {:?} {}",
+                    class_request, control_selector
+                );
+                Self::stall(usb, endpoint_number, setup_packet);
+            }
+        }
+    }
+
+    fn respond<D>(usb: &D, endpoint_number: u8, setup_packet: SetupPacket, data: &[u8])
+    where
+        D: UsbDriver,
+    {
+        let requested_length = usize::from(setup_packet.length());
+        // prime to receive host zlp
+        usb.ep_out_prime_receive(endpoint_number);
+        usb.write_requested(
+            endpoint_number,
+            requested_length,
+            data.iter().copied().take(requested_length),
+        );
+    }
+
+    fn stall<D>(usb: &D, endpoint_number: u8, setup_packet: SetupPacket)
+    where
+        D: UsbDriver,
+    {
+        // requests with a data stage from the host have already been acknowledged
+        if setup_packet.direction() == Direction::DeviceToHost || setup_packet.length() == 0 {
+            usb.stall_endpoint_in(endpoint_number);
+        }
+    }
+}
+
+// - UsbClass -----------------------------------------------------------------
+
+impl<D, const PACKET_SIZE: usize> UsbClass<D> for AudioStream<'_, PACKET_SIZE>
+where
+    D: UsbDriver + IsochronousEndpoint,
+{
+    fn has_interface(&self, interface_number: u8) -> bool {
+        interface_number == self.streaming_interface
+            || matches!(self.feature_unit, Some((control_interface, _)) if control_interface == interface_number)
+    }
+
+    fn has_endpoint(&self, endpoint_address: u8) -> bool {
+        endpoint_address == self.endpoint_address()
+    }
+
+    fn handle_setup_packet(
+        &mut self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> bool {
+        Self::handle_setup_packet(self, usb, endpoint_number, setup_packet, data)
+    }
+
+    fn set_configuration(&mut self, _usb: &D, _configuration: u8) {
+        self.reset();
+    }
+
+    fn set_alternate_setting(&mut self, usb: &D, interface_number: u8, alternate_setting: u8) {
+        if interface_number == self.streaming_interface {
+            Self::set_alternate_setting(self, usb, alternate_setting);
+        }
+    }
+
+    fn dispatch_event(&mut self, usb: &D, event: UsbEvent) {
+        Self::dispatch_event(self, usb, event);
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::descriptor::{
+        ClassDescriptor, ConfigurationDescriptor, ConfigurationDescriptorHeader, DeviceDescriptor,
+        EndpointDescriptor, InterfaceDescriptor, InterfaceDescriptorHeader, SynchronizationType,
+    };
+    use crate::device::Speed;
+    use crate::testing::{class_request, descriptors, ClassHarness, Operation};
+
+    // - fixtures -------------------------------------------------------------
+
+    const DEVICE_DESCRIPTOR: DeviceDescriptor = DeviceDescriptor {
+        bDeviceClass: 0x00, // defined by the interface
+        bMaxPacketSize: 64,
+        idVendor: 0x1209,
+        idProduct: 0x0001,
+        bcdDevice: 0x0100,
+        bNumConfigurations: 1,
+        ..DeviceDescriptor::new()
+    };
+
+    // 48 kHz, 16 bit stereo: 192 bytes per frame
+    const PACKET_SIZE: usize = 192;
+    const SAMPLE_RATES: &[u32] = &[48_000, 44_100];
+
+    const AC_HEADER: [u8; 7] = ac_header(9 + 12 + 8 + 9, 1);
+    const INPUT_TERMINAL: [u8; 10] = input_terminal(1, TERMINAL_USB_STREAMING, 2, 0x0003);
+    const FEATURE_UNIT: [u8; 6] = feature_unit(2, 1);
+    const OUTPUT_TERMINAL: [u8; 7] = output_terminal(3, TERMINAL_SPEAKER, 2);
+    const AS_GENERAL: [u8; 5] = as_general(1);
+    const FORMAT_TYPE_I: [u8; 12] = format_type_i(2, 2, 16, SAMPLE_RATES);
+    const AS_ENDPOINT_GENERAL: [u8; 5] = as_endpoint_general();
+
+    static CONFIGURATION_DESCRIPTORS: [ConfigurationDescriptor; 1] =
+        [ConfigurationDescriptor::new(
+            ConfigurationDescriptorHeader {
+                bConfigurationValue: 1,
+                bmAttributes: 0x80,
+                bMaxPower: 50,
+                ..ConfigurationDescriptorHeader::new()
+            },
+            &[
+                InterfaceDescriptor::new(
+                    InterfaceDescriptorHeader {
+                        iInterfaceNumber: 0,
+                        bInterfaceClass: INTERFACE_CLASS,
+                        bInterfaceSubClass: INTERFACE_SUBCLASS_AUDIOCONTROL,
+                        ..InterfaceDescriptorHeader::new()
+                    },
+                    &[],
+                )
+                .with_class_descriptors(&[
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &AC_HEADER),
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &INPUT_TERMINAL),
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &FEATURE_UNIT),
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &OUTPUT_TERMINAL),
+                ]),
+                // zero-bandwidth alternate setting
+                InterfaceDescriptor::new(
+                    InterfaceDescriptorHeader {
+                        iInterfaceNumber: 1,
+                        bInterfaceClass: INTERFACE_CLASS,
+                        bInterfaceSubClass: INTERFACE_SUBCLASS_AUDIOSTREAMING,
+                        ..InterfaceDescriptorHeader::new()
+                    },
+                    &[],
+                ),
+                InterfaceDescriptor::new(
+                    InterfaceDescriptorHeader {
+                        iInterfaceNumber: 1,
+                        bAlternateSetting: 1,
+                        bInterfaceClass: INTERFACE_CLASS,
+                        bInterfaceSubClass: INTERFACE_SUBCLASS_AUDIOSTREAMING,
+                        ..InterfaceDescriptorHeader::new()
+                    },
+                    &[EndpointDescriptor::isochronous(
+                        0x01,
+                        PACKET_SIZE as u16,
+                        1,
+                        SynchronizationType::Adaptive,
+                    )],
+                )
+                .with_class_descriptors(&[
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &AS_GENERAL),
+                    ClassDescriptor::new(DESCRIPTOR_TYPE_CS_INTERFACE, &FORMAT_TYPE_I),
+                ])
+                .with_endpoint_class_descriptors(&[&[ClassDescriptor::new(
+                    DESCRIPTOR_TYPE_CS_ENDPOINT,
+                    &AS_ENDPOINT_GENERAL,
+                )]]),
+            ],
+        )
+        .validate(Speed::Full)];
+
+    type Stream = AudioStream<'static, PACKET_SIZE>;
+
+    fn setup(stream: Stream) -> ClassHarness<Stream> {
+        let mut harness = ClassHarness::new(
+            descriptors(DEVICE_DESCRIPTOR, &CONFIGURATION_DESCRIPTORS),
+            stream,
+        );
+        harness.configure(1);
+        harness
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_uac_descriptors() {
+        let configuration: Vec<u8> = CONFIGURATION_DESCRIPTORS[0].iter().copied().collect();
+
+        // audio control interface and its class-specific descriptors
+        assert_eq!(configuration[9..18], [9, 4, 0, 0, 0, 1, 1, 0, 0]);
+        assert_eq!(
+            configuration[18..27],
+            [9, 0x24, 0x01, 0x00, 0x01, 38, 0, 1, 1]
+        );
+        let total_length = u16::from_le_bytes([configuration[23], configuration[24]]);
+        assert_eq!(
+            usize::from(total_length),
+            9 + INPUT_TERMINAL.len() + 2 + FEATURE_UNIT.len() + 2 + OUTPUT_TERMINAL.len() + 2
+        );
+
+        // isochronous endpoint
+        let endpoint = configuration
+            .windows(7)
+            .find(|descriptor| descriptor[..3] == [7, 5, 0x01])
+            .unwrap();
+        assert_eq!(endpoint, [7, 5, 0x01, 0b1001, 192, 0, 1]);
+
+        // format type i with both sample rates
+        assert_eq!(
+            FORMAT_TYPE_I,
+            [0x02, 0x01, 2, 2, 16, 2, 0x80, 0xbb, 0x00, 0x44, 0xac, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_speaker() {
+        let mut harness = setup(Stream::speaker(1, 1, SAMPLE_RATES).with_feature_unit(0, 2));
+        assert!(!harness.classes().is_streaming());
+
+        // sampling frequency
+        assert_eq!(harness.classes().sample_rate(), 48_000);
+        let set_cur = class_request(
+            Direction::HostToDevice,
+            Recipient::Endpoint,
+            ClassRequest::SetCur,
+            0x0100,
+            0x01,
+            3,
+        );
+        harness.request(set_cur, &[0x44, 0xac, 0x00]);
+        assert_eq!(harness.classes().sample_rate(), 44_100);
+        harness.request(set_cur, &[0x00, 0x7d, 0x00]); // 32 kHz is not supported
+        assert_eq!(harness.classes().sample_rate(), 44_100);
+        let get_cur = class_request(
+            Direction::DeviceToHost,
+            Recipient::Endpoint,
+            ClassRequest::GetCur,
+            0x0100,
+            0x01,
+            3,
+        );
+        assert_eq!(harness.request(get_cur, &[]), [0x44, 0xac, 0x00]);
+
+        // feature unit: mute and volume
+        let index = u16::from_le_bytes([0, 2]); // interface 0, unit 2
+        let set_mute = class_request(
+            Direction::HostToDevice,
+            Recipient::Interface,
+            ClassRequest::SetCur,
+            0x0100,
+            index,
+            1,
+        );
+        harness.request(set_mute, &[1]);
+        assert!(harness.classes().is_muted());
+        let set_volume = class_request(
+            Direction::HostToDevice,
+            Recipient::Interface,
+            ClassRequest::SetCur,
+            0x0200,
+            index,
+            2,
+        );
+        harness.request(set_volume, &(-10_i16 * 256).to_le_bytes());
+        assert_eq!(harness.classes().volume(), -2560);
+        let get_min = class_request(
+            Direction::DeviceToHost,
+            Recipient::Interface,
+            ClassRequest::GetMin,
+            0x0200,
+            index,
+            2,
+        );
+        assert_eq!(harness.request(get_min, &[]), VOLUME_MIN.to_le_bytes());
+
+        // unsupported controls are stalled
+        let get_cur = class_request(
+            Direction::DeviceToHost,
+            Recipient::Interface,
+            ClassRequest::GetCur,
+            0x0300,
+            index,
+            1,
+        );
+        harness.request(get_cur, &[]);
+        assert!(harness.usb.is_stalled(0, Direction::DeviceToHost));
+
+        // SET_INTERFACE starts streaming
+        harness.request(SetupPacket::set_interface(1, 1), &[]);
+        assert!(harness.classes().is_streaming());
+        assert!(harness
+            .usb
+            .operations()
+            .contains(&Operation::ConfigureIsochronous(
+                1,
+                Direction::HostToDevice,
+                PACKET_SIZE
+            )));
+        assert!(harness.usb.is_primed(1));
+
+        let samples: Vec<u8> = (0..PACKET_SIZE).map(|n| n as u8).collect();
+        harness.usb.host_out(1, &samples);
+        harness.dispatch();
+        assert_eq!(harness.classes_mut().read(), Some(&samples[..]));
+        assert_eq!(harness.classes_mut().read(), None);
+        assert!(harness.usb.is_primed(1));
+
+        // zero-bandwidth alternate setting stops streaming
+        harness.request(SetupPacket::set_interface(1, 0), &[]);
+        assert!(!harness.classes().is_streaming());
+    }
+
+    #[test]
+    fn test_microphone() {
+        let mut harness = setup(Stream::microphone(1, 1, SAMPLE_RATES));
+        assert_eq!(harness.classes().endpoint_address(), 0x81);
+
+        // nothing is sent before the host starts streaming
+        let samples = [0x55; PACKET_SIZE];
+        let (usb, stream) = harness.split();
+        assert_eq!(stream.write(usb, &samples), 0);
+        assert_eq!(harness.classes().frame_number(), None);
+        assert_eq!(harness.usb.frame_number(), None);
+
+        harness.request(SetupPacket::set_interface(1, 1), &[]);
+        harness.usb.take_operations();
+
+        // one packet per frame without waiting for the host
+        for frame_number in 1..=3 {
+            harness.usb.host_start_of_frame();
+            harness.dispatch();
+            assert_eq!(harness.classes().frame_number(), Some(frame_number));
+            assert_eq!(harness.usb.frame_number(), Some(frame_number));
+            let (usb, stream) = harness.split();
+            assert_eq!(stream.write(usb, &samples), PACKET_SIZE);
+        }
+        assert_eq!(harness.usb.packets_written(1).len(), 3);
+        assert!(harness.usb.next_event().is_none());
+    }
+}
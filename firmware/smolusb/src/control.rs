@@ -2,10 +2,11 @@ use core::marker::PhantomData;
 
 use log::{error, info, trace, warn};
 
-use crate::descriptor::microsoft10;
-use crate::device::Descriptors;
+use crate::descriptor::{microsoft10, microsoft20, InterfaceDescriptor};
+use crate::device::{DescriptorResponse, Descriptors};
+use crate::error::ErrorKind;
 use crate::event::UsbEvent;
-use crate::setup::{Direction, Feature, Recipient, Request, RequestType, SetupPacket};
+use crate::setup::{Direction, Feature, Recipient, Request, RequestType, SetupPacket, TestMode};
 use crate::traits::{AsByteSliceIterator, UsbDriver};
 
 // - State --------------------------------------------------------------------
@@ -17,12 +18,104 @@ pub enum State {
     Send,
     WaitForZlp,
     SetAddress(u8),
+    SetTestMode(TestMode),
     ReceiveHostData(SetupPacket),
     FinishHostData(SetupPacket),
     Complete,
     Stall,
 }
 
+// - DataSink / DataSource ---------------------------------------------------
+
+/// Receives the data stage of host-to-device control requests.
+///
+/// Implemented by the application and registered with
+/// [`Control::with_data_sink()`].
+pub trait DataSink<D> {
+    /// Receives part of the data stage of the request.
+    ///
+    /// `offset` is the position of `data` within the data stage.
+    ///
+    /// Returns `false` to reject the data, which stalls the request.
+    fn receive(&mut self, usb: &D, setup_packet: SetupPacket, offset: usize, data: &[u8]) -> bool;
+}
+
+/// Supplies the data stage of device-to-host control requests.
+///
+/// Implemented by the application and registered with
+/// [`Control::with_data_source()`].
+pub trait DataSource<D> {
+    /// Fills `buffer` with the part of the data stage starting at `offset`.
+    ///
+    /// Returns the number of bytes written to the buffer with `Some(0)`
+    /// ending the data stage early, or `None` if the source does not
+    /// handle the request.
+    fn supply(
+        &mut self,
+        usb: &D,
+        setup_packet: SetupPacket,
+        offset: usize,
+        buffer: &mut [u8],
+    ) -> Option<usize>;
+}
+
+/// Iterates over the data stage supplied by a [`DataSource`] one chunk at a time.
+struct DataSourceIterator<'a, D> {
+    usb: &'a D,
+    source: &'a mut dyn DataSource<D>,
+    setup_packet: SetupPacket,
+    buffer: [u8; crate::EP_MAX_PACKET_SIZE],
+    offset: usize,
+    length: usize,
+    position: usize,
+}
+
+impl<'a, D> DataSourceIterator<'a, D> {
+    /// Returns `None` if the source does not handle the request.
+    fn new(
+        usb: &'a D,
+        source: &'a mut dyn DataSource<D>,
+        setup_packet: SetupPacket,
+    ) -> Option<Self> {
+        let mut buffer = [0; crate::EP_MAX_PACKET_SIZE];
+        let length = source.supply(usb, setup_packet, 0, &mut buffer)?;
+        Some(Self {
+            usb,
+            source,
+            setup_packet,
+            buffer,
+            offset: 0,
+            length: length.min(crate::EP_MAX_PACKET_SIZE),
+            position: 0,
+        })
+    }
+}
+
+impl<D> Iterator for DataSourceIterator<'_, D> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position == self.length {
+            if self.length == 0 {
+                return None;
+            }
+            self.offset += self.length;
+            self.position = 0;
+            self.length = self
+                .source
+                .supply(self.usb, self.setup_packet, self.offset, &mut self.buffer)
+                .unwrap_or(0)
+                .min(crate::EP_MAX_PACKET_SIZE);
+            if self.length == 0 {
+                return None;
+            }
+        }
+        let byte = self.buffer[self.position];
+        self.position += 1;
+        Some(byte)
+    }
+}
+
 // - Control ------------------------------------------------------------------
 
 /// Implements a USB Control endpoint.
@@ -32,26 +125,156 @@ pub struct Control<'a, D, const RX_BUFFER_SIZE: usize> {
 
     next: State,
     configuration: Option<u8>,
+    alternate_settings: [u8; crate::MAX_INTERFACES],
+    endpoint_halt_in: [bool; crate::EP_MAX_ENDPOINTS],
+    endpoint_halt_out: [bool; crate::EP_MAX_ENDPOINTS],
     feature_remote_wakeup: bool,
+    suspended: bool,
+
+    configuration_callback: Option<fn(&D, u8)>,
+    interface_callback: Option<fn(&D, u8, u8)>,
+    data_sink: Option<&'a mut dyn DataSink<D>>,
+    data_source: Option<&'a mut dyn DataSource<D>>,
 
     rx_buffer: [u8; RX_BUFFER_SIZE],
     rx_buffer_position: usize,
+    rx_data_length: usize,
 
     _marker: PhantomData<&'a D>,
 }
 
-impl<'a, D, const RX_BUFFER_SIZE: usize> Control<'a, D, RX_BUFFER_SIZE>
+impl<D, const RX_BUFFER_SIZE: usize> Control<'_, D, RX_BUFFER_SIZE>
 where
     D: UsbDriver,
 {
     /// Returns the last received control data from the host.
     #[must_use]
-    pub fn data(&'a self) -> &'a [u8] {
+    pub fn data(&self) -> &[u8] {
         &self.rx_buffer[..self.rx_buffer_position]
     }
 
-    fn write_zlp(&self, usb: &D) {
-        usb.write(self.endpoint_number, [].into_iter());
+    /// Returns the endpoint number of the control endpoint.
+    #[must_use]
+    pub fn endpoint_number(&self) -> u8 {
+        self.endpoint_number
+    }
+
+    /// Returns the `bConfigurationValue` of the active configuration.
+    #[must_use]
+    pub fn configuration(&self) -> Option<u8> {
+        self.configuration
+    }
+
+    /// Returns the active alternate setting for the given interface.
+    ///
+    /// Returns `None` if the device is not configured or the interface
+    /// is not part of the active configuration.
+    #[must_use]
+    pub fn alternate_setting(&self, interface_number: u8) -> Option<u8> {
+        let configuration = self.descriptors.configuration(self.configuration?)?;
+        configuration.interface(interface_number, 0)?;
+        self.alternate_settings
+            .get(usize::from(interface_number))
+            .copied()
+    }
+
+    /// Returns `true` if the given endpoint address has been halted by the host.
+    #[must_use]
+    pub fn is_endpoint_halted(&self, endpoint_address: u8) -> bool {
+        let index = usize::from(endpoint_address & 0x0f);
+        match Direction::from(endpoint_address) {
+            Direction::HostToDevice => self.endpoint_halt_out[index],
+            Direction::DeviceToHost => self.endpoint_halt_in[index],
+        }
+    }
+
+    /// Returns `true` if the host has enabled remote wakeup.
+    #[must_use]
+    pub fn is_remote_wakeup_enabled(&self) -> bool {
+        self.feature_remote_wakeup
+    }
+
+    /// Returns `true` if the bus is suspended.
+    #[must_use]
+    pub fn is_suspended(&self) -> bool {
+        self.suspended
+    }
+
+    /// Returns `true` if the given endpoint address is the control
+    /// endpoint or is used by the active interface settings.
+    fn has_endpoint(&self, endpoint_address: u8) -> bool {
+        if endpoint_address & 0x7f == self.endpoint_number {
+            return true;
+        }
+        let Some(configuration) = self
+            .configuration
+            .and_then(|value| self.descriptors.configuration(value))
+        else {
+            return false;
+        };
+        configuration
+            .tail
+            .iter()
+            .filter(|interface| {
+                self.alternate_settings
+                    .get(usize::from(interface.interface_number()))
+                    .map_or(interface.alternate_setting() == 0, |alternate_setting| {
+                        interface.alternate_setting() == *alternate_setting
+                    })
+            })
+            .flat_map(InterfaceDescriptor::endpoints)
+            .any(|endpoint| endpoint.bEndpointAddress == endpoint_address)
+    }
+
+    /// Updates the halt condition for the given endpoint address.
+    fn set_endpoint_halt(&mut self, endpoint_address: u8, halt: bool) {
+        let index = usize::from(endpoint_address & 0x0f);
+        match Direction::from(endpoint_address) {
+            Direction::HostToDevice => self.endpoint_halt_out[index] = halt,
+            Direction::DeviceToHost => self.endpoint_halt_in[index] = halt,
+        }
+    }
+
+    /// Updates the active configuration and notifies the application if it changed.
+    fn set_configuration(&mut self, usb: &D, configuration: Option<u8>) {
+        // selecting a configuration always resets all interfaces to
+        // their default setting and clears any endpoint halts
+        self.alternate_settings = [0; crate::MAX_INTERFACES];
+        for endpoint_number in 0..self.endpoint_halt_in.len() {
+            if self.endpoint_halt_in[endpoint_number] {
+                usb.clear_feature_endpoint_halt(endpoint_number as u8, Direction::DeviceToHost);
+            }
+            if self.endpoint_halt_out[endpoint_number] {
+                usb.clear_feature_endpoint_halt(endpoint_number as u8, Direction::HostToDevice);
+            }
+        }
+        self.endpoint_halt_in = [false; crate::EP_MAX_ENDPOINTS];
+        self.endpoint_halt_out = [false; crate::EP_MAX_ENDPOINTS];
+        if self.configuration == configuration {
+            return;
+        }
+        self.configuration = configuration;
+        if let Some(callback) = self.configuration_callback {
+            callback(usb, configuration.unwrap_or(0));
+        }
+    }
+
+    fn try_write_zlp(&self, usb: &D) -> Result<(), ErrorKind> {
+        usb.try_write(self.endpoint_number, [].into_iter())?;
+        Ok(())
+    }
+
+    /// Stalls the current request until the host sends the next SETUP.
+    ///
+    /// Only the stage the host is about to start is stalled: the OUT
+    /// data stage while host data is still expected and the IN data
+    /// or status stage otherwise.
+    fn stall(&mut self, usb: &D, direction: Direction) {
+        self.next = State::Stall;
+        match direction {
+            Direction::HostToDevice => usb.stall_endpoint_out(self.endpoint_number),
+            Direction::DeviceToHost => usb.stall_endpoint_in(self.endpoint_number),
+        }
     }
 
     fn read_zlp(&self, usb: &D) -> bool {
@@ -67,33 +290,157 @@ where
     pub fn new(endpoint_number: u8, descriptors: Descriptors<'a>) -> Self {
         Self {
             endpoint_number,
-            descriptors: descriptors.set_total_lengths(), // TODO figure out a better solution
+            descriptors,
             next: State::Idle,
             configuration: None,
+            alternate_settings: [0; crate::MAX_INTERFACES],
+            endpoint_halt_in: [false; crate::EP_MAX_ENDPOINTS],
+            endpoint_halt_out: [false; crate::EP_MAX_ENDPOINTS],
             feature_remote_wakeup: false,
+            suspended: false,
+            configuration_callback: None,
+            interface_callback: None,
+            data_sink: None,
+            data_source: None,
             rx_buffer: [0; RX_BUFFER_SIZE],
             rx_buffer_position: 0,
+            rx_data_length: 0,
             _marker: PhantomData,
         }
     }
 
+    /// Registers a function to be called whenever the host changes the
+    /// active configuration.
+    ///
+    /// The callback receives the new `bConfigurationValue`, or `0` if
+    /// the device was deconfigured, and can be used to (re-)configure
+    /// the endpoints used by the configuration.
+    #[must_use]
+    pub fn with_configuration_callback(mut self, callback: fn(&D, u8)) -> Self {
+        self.configuration_callback = Some(callback);
+        self
+    }
+
+    /// Registers a function to be called whenever the host selects an
+    /// alternate setting for an interface.
+    ///
+    /// The callback receives the interface number and the new
+    /// `bAlternateSetting` and can be used to (re-)configure the
+    /// endpoints used by the alternate setting.
+    #[must_use]
+    pub fn with_interface_callback(mut self, callback: fn(&D, u8, u8)) -> Self {
+        self.interface_callback = Some(callback);
+        self
+    }
+
+    /// Streams the data stage of host-to-device requests not handled by
+    /// [`Control`] to `sink` one packet at a time.
+    ///
+    /// Data stages of up to 64 KiB can then be received without buffering
+    /// them, in which case [`Control::data()`] is empty. The request is
+    /// stalled if the sink rejects the data.
+    #[must_use]
+    pub fn with_data_sink(mut self, sink: &'a mut dyn DataSink<D>) -> Self {
+        self.data_sink = Some(sink);
+        self
+    }
+
+    /// Answers device-to-host requests not handled by [`Control`] with
+    /// the data stage supplied by `source`.
+    ///
+    /// Requests the source does not handle are returned by
+    /// [`Control::dispatch_event()`] as before.
+    #[must_use]
+    pub fn with_data_source(mut self, source: &'a mut dyn DataSource<D>) -> Self {
+        self.data_source = Some(source);
+        self
+    }
+
+    /// Signals remote wakeup to a suspended host.
+    ///
+    /// Returns `false` without signalling if the bus is not suspended
+    /// or if the host has not enabled remote wakeup with
+    /// `SET_FEATURE(DEVICE_REMOTE_WAKEUP)`, and `false` if the driver
+    /// could not drive resume signalling.
+    pub fn remote_wakeup(&self, usb: &D) -> bool {
+        if !self.suspended {
+            warn!("Control remote wakeup requested while the bus is not suspended");
+            return false;
+        }
+        if !self.feature_remote_wakeup {
+            warn!("Control remote wakeup requested but not enabled by the host");
+            return false;
+        }
+        usb.remote_wakeup()
+    }
+
     /// Dispatches an interrupt event generated by the USB peripheral
     /// for handling by the [`Control`] interface.
     ///
     /// Returns the last [`SetupPacket`] received if it could not be
     /// handled by the [`Control`] interface.  (e.g. if it was a
     /// [`RequestType::Class`] or [`RequestType::Vendor`] request)
-    #[allow(clippy::too_many_lines)] // sometimes you can't have too much of a good thing!
+    ///
+    /// Errors are logged, use [`Control::try_dispatch_event()`] to
+    /// handle them.
     pub fn dispatch_event(&mut self, usb: &D, event: UsbEvent) -> Option<SetupPacket> {
+        match self.try_dispatch_event(usb, event) {
+            Ok(unhandled) => unhandled,
+            Err(e) => {
+                error!("Control error. Failed to handle event '{:?}': {}", event, e);
+                None
+            }
+        }
+    }
+
+    /// Dispatches an interrupt event generated by the USB peripheral
+    /// for handling by the [`Control`] interface.
+    ///
+    /// Returns the last [`SetupPacket`] received if it could not be
+    /// handled by the [`Control`] interface.
+    ///
+    /// # Errors
+    ///
+    /// Returns an [`ErrorKind`] if a read or write on the control
+    /// endpoint failed, in which case the current transfer is
+    /// abandoned.
+    pub fn try_dispatch_event(
+        &mut self,
+        usb: &D,
+        event: UsbEvent,
+    ) -> Result<Option<SetupPacket>, ErrorKind> {
+        let result = self.handle_event(usb, event);
+        if result.is_err() {
+            self.next = State::Idle;
+        }
+        result
+    }
+
+    #[allow(clippy::too_many_lines)] // sometimes you can't have too much of a good thing!
+    fn handle_event(&mut self, usb: &D, event: UsbEvent) -> Result<Option<SetupPacket>, ErrorKind> {
         // The Control interface state machine operates on the latest
         // receive event and the current state of the interface.
         match (event, &self.next.clone()) {
             (UsbEvent::BusReset, _state) => {
                 // reset
                 self.next = State::Idle;
+                self.feature_remote_wakeup = false;
+                self.suspended = false;
+                self.set_configuration(usb, None);
                 // self.bus_reset(); - irq handler is doing the reset for us
             }
 
+            // - bus state ----------------------------------------------------
+            (UsbEvent::Suspend, _state) => {
+                self.suspended = true;
+            }
+            (UsbEvent::Resume, _state) => {
+                self.suspended = false;
+            }
+            (UsbEvent::StartOfFrame(_frame_number), _state) => {
+                // nothing to do
+            }
+
             (
                 UsbEvent::ReceiveSetupPacket(endpoint_number, setup_packet),
                 State::Idle | State::Stall,
@@ -103,13 +450,55 @@ where
                     self.next = State::Idle;
                 }
 
-                let requested_length = setup_packet.length as usize;
+                let requested_length = setup_packet.length() as usize;
 
                 match (
                     setup_packet.direction(),
                     setup_packet.request_type(),
                     setup_packet.request(),
                 ) {
+                    // handle microsoft os 2.0 descriptor set requests
+                    (
+                        Direction::DeviceToHost,
+                        RequestType::Vendor,
+                        Request::ClassOrVendor(vendor_code),
+                    ) if matches!(
+                        &self.descriptors.microsoft20,
+                        Some(descriptors) if descriptors.vendor_code == vendor_code
+                    ) =>
+                    {
+                        let vendor_index = microsoft20::VendorIndex::from(setup_packet.index());
+                        match (
+                            &setup_packet.recipient(),
+                            &vendor_index,
+                            &self.descriptors.microsoft20,
+                        ) {
+                            (
+                                Recipient::Device,
+                                microsoft20::VendorIndex::DescriptorSet,
+                                Some(descriptors),
+                            ) => {
+                                self.next = State::Send;
+                                usb.try_write_requested(
+                                    self.endpoint_number,
+                                    requested_length,
+                                    descriptors
+                                        .descriptor_set
+                                        .iter()
+                                        .copied()
+                                        .take(requested_length),
+                                )?;
+                            }
+                            _ => {
+                                error!(
+                                    "Control error. Could not handle Microsoft OS 2.0 Request: '{:?}'.",
+                                    setup_packet
+                                );
+                                self.stall(usb, Direction::DeviceToHost);
+                            }
+                        }
+                    }
+
                     // handle microsoft os 1.0 descriptor requests
                     //
                     // reg entries are:
@@ -127,7 +516,7 @@ where
                         Request::ClassOrVendor(microsoft10::VendorRequest::Microsoft),
                     ) => {
                         let recipient = setup_packet.recipient();
-                        let vendor_index = microsoft10::VendorIndex::from(setup_packet.index);
+                        let vendor_index = microsoft10::VendorIndex::from(setup_packet.index());
 
                         match (&recipient, &vendor_index, &self.descriptors.microsoft10) {
                             (
@@ -136,7 +525,7 @@ where
                                 Some(descriptors),
                             ) => {
                                 self.next = State::Send;
-                                usb.write_requested(
+                                usb.try_write_requested(
                                     self.endpoint_number,
                                     requested_length,
                                     descriptors
@@ -144,7 +533,7 @@ where
                                         .iter()
                                         .copied()
                                         .take(requested_length),
-                                );
+                                )?;
                             }
                             (
                                 Recipient::Interface,
@@ -152,7 +541,7 @@ where
                                 Some(descriptors),
                             ) => {
                                 self.next = State::Send;
-                                usb.write_requested(
+                                usb.try_write_requested(
                                     self.endpoint_number,
                                     requested_length,
                                     descriptors
@@ -160,15 +549,14 @@ where
                                         .as_iter()
                                         .copied()
                                         .take(requested_length),
-                                );
+                                )?;
                             }
                             _ => {
-                                self.next = State::Stall;
                                 error!(
                                     "Control error. Could not handle Microsoft OS 1.0 Request: '{:?}'.",
                                     setup_packet
                                 );
-                                usb.stall_endpoint_in(self.endpoint_number);
+                                self.stall(usb, Direction::DeviceToHost);
                             }
                         }
                     }
@@ -176,100 +564,249 @@ where
                     // - standard requests
                     (Direction::DeviceToHost, RequestType::Standard, Request::GetDescriptor) => {
                         self.next = State::Send;
-                        return self
-                            .descriptors
-                            .write(usb, self.endpoint_number, setup_packet);
+                        match self.descriptors.try_respond(
+                            usb,
+                            self.endpoint_number,
+                            setup_packet,
+                        )? {
+                            DescriptorResponse::Written => (),
+                            DescriptorResponse::Stalled => {
+                                self.next = State::Stall;
+                            }
+                            DescriptorResponse::Unhandled(setup_packet) => {
+                                // nothing was sent, don't wait for a status stage
+                                self.next = State::Idle;
+                                return Ok(Some(setup_packet));
+                            }
+                        }
                     }
                     (Direction::HostToDevice, RequestType::Standard, Request::SetAddress) => {
-                        let address: u8 = (setup_packet.value & 0x7f) as u8;
+                        let address: u8 = (setup_packet.value() & 0x7f) as u8;
                         self.next = State::SetAddress(address);
-                        self.write_zlp(usb);
+                        self.try_write_zlp(usb)?;
                     }
                     (Direction::HostToDevice, RequestType::Standard, Request::SetConfiguration) => {
-                        let configuration: u8 = (setup_packet.value & 0xff) as u8;
+                        let configuration: u8 = setup_packet.value().to_le_bytes()[0];
                         // check whether this is a valid configuration
-                        // TODO support multiple configurations
-                        if configuration > 1 {
+                        if configuration == 0 {
+                            self.set_configuration(usb, None);
+                        } else if self.descriptors.configuration(configuration).is_some() {
+                            self.set_configuration(usb, Some(configuration));
+                        } else {
                             warn!("Control stall - unknown configuration {}", configuration);
-                            self.configuration = None;
-                            self.next = State::Stall;
-                            usb.stall_endpoint_out(self.endpoint_number);
-                            return None;
+                            self.stall(usb, Direction::DeviceToHost);
+                            return Ok(None);
                         }
-                        self.configuration = Some(configuration);
                         self.next = State::Complete;
-                        self.write_zlp(usb);
+                        self.try_write_zlp(usb)?;
                     }
                     (Direction::DeviceToHost, RequestType::Standard, Request::GetConfiguration) => {
                         self.next = State::Send;
                         if let Some(configuration) = self.configuration {
-                            usb.write(self.endpoint_number, [configuration].into_iter());
+                            usb.try_write(self.endpoint_number, [configuration].into_iter())?;
+                        } else {
+                            usb.try_write(self.endpoint_number, [0].into_iter())?;
+                        }
+                    }
+                    (Direction::DeviceToHost, RequestType::Standard, Request::GetInterface) => {
+                        let interface_number = setup_packet.interface_number();
+                        if let Some(alternate_setting) = self.alternate_setting(interface_number) {
+                            self.next = State::Send;
+                            usb.try_write(self.endpoint_number, [alternate_setting].into_iter())?;
                         } else {
-                            usb.write(self.endpoint_number, [0].into_iter());
+                            warn!("Control stall - unknown interface {}", interface_number);
+                            self.stall(usb, Direction::DeviceToHost);
                         }
                     }
+                    (Direction::HostToDevice, RequestType::Standard, Request::SetInterface) => {
+                        let interface_number = setup_packet.interface_number();
+                        let alternate_setting = setup_packet.value().to_le_bytes()[0];
+                        // check whether this is a valid alternate setting
+                        let is_valid = usize::from(interface_number) < crate::MAX_INTERFACES
+                            && self
+                                .configuration
+                                .and_then(|value| self.descriptors.configuration(value))
+                                .and_then(|configuration| {
+                                    configuration.interface(interface_number, alternate_setting)
+                                })
+                                .is_some();
+                        if !is_valid {
+                            warn!(
+                                "Control stall - unknown interface {} alternate setting {}",
+                                interface_number, alternate_setting
+                            );
+                            self.stall(usb, Direction::DeviceToHost);
+                            return Ok(None);
+                        }
+                        self.alternate_settings[usize::from(interface_number)] = alternate_setting;
+                        // selecting an alternate setting clears any halts on its endpoints
+                        let endpoints = self
+                            .configuration
+                            .and_then(|value| self.descriptors.configuration(value))
+                            .and_then(|configuration| {
+                                configuration.interface(interface_number, alternate_setting)
+                            })
+                            .map_or(&[][..], |interface| interface.endpoints());
+                        for endpoint in endpoints {
+                            self.set_endpoint_halt(endpoint.bEndpointAddress, false);
+                        }
+                        if let Some(callback) = self.interface_callback {
+                            callback(usb, interface_number, alternate_setting);
+                        }
+                        self.next = State::Complete;
+                        self.try_write_zlp(usb)?;
+                    }
                     (Direction::DeviceToHost, RequestType::Standard, Request::GetStatus) => {
-                        let status: u16 = 0b01; // bit 1:remote-wakeup bit 0:self-powered
-                        let status = status | u16::from(self.feature_remote_wakeup) << 1;
-                        self.next = State::Send;
-                        usb.write(0, status.to_le_bytes().into_iter());
+                        let recipient = setup_packet.recipient();
+                        let status: Option<u16> = match recipient {
+                            Recipient::Device => {
+                                let status: u16 = 0b01; // bit 1:remote-wakeup bit 0:self-powered
+                                Some(status | u16::from(self.feature_remote_wakeup) << 1)
+                            }
+                            Recipient::Interface => {
+                                let interface_number = setup_packet.interface_number();
+                                self.alternate_setting(interface_number).map(|_| 0)
+                            }
+                            Recipient::Endpoint => {
+                                let endpoint_address = setup_packet.endpoint_address();
+                                if self.has_endpoint(endpoint_address) {
+                                    // bit 0:halt
+                                    Some(u16::from(self.is_endpoint_halted(endpoint_address)))
+                                } else {
+                                    None
+                                }
+                            }
+                            _ => None,
+                        };
+                        if let Some(status) = status {
+                            self.next = State::Send;
+                            usb.try_write(self.endpoint_number, status.to_le_bytes().into_iter())?;
+                        } else {
+                            warn!(
+                                "SETUP stall: unhandled get status {:?} {}",
+                                recipient,
+                                setup_packet.index()
+                            );
+                            self.stall(usb, Direction::DeviceToHost);
+                        }
                     }
-                    (direction, RequestType::Standard, Request::ClearFeature) => {
-                        info!("  TODO Request::ClearFeature {:?}", direction);
+                    (Direction::HostToDevice, RequestType::Standard, Request::ClearFeature) => {
                         let recipient = setup_packet.recipient();
-                        let feature = Feature::from(setup_packet.value);
+                        let feature = setup_packet.feature();
+                        let endpoint_address = setup_packet.endpoint_address();
                         match (&recipient, &feature) {
-                            (Recipient::Endpoint, Feature::EndpointHalt) => {
-                                let endpoint_address = (setup_packet.index & 0xff) as u8;
+                            (Recipient::Endpoint, Feature::EndpointHalt)
+                                if self.has_endpoint(endpoint_address) =>
+                            {
                                 let endpoint_number = endpoint_address & 0x7f;
                                 let direction = Direction::from(endpoint_address);
+                                self.set_endpoint_halt(endpoint_address, false);
                                 usb.clear_feature_endpoint_halt(endpoint_number, direction);
                                 self.next = State::Complete;
-                                self.write_zlp(usb);
+                                self.try_write_zlp(usb)?;
                             }
                             (Recipient::Device, Feature::DeviceRemoteWakeup) => {
                                 self.feature_remote_wakeup = false;
                                 self.next = State::Complete;
-                                self.write_zlp(usb);
+                                self.try_write_zlp(usb)?;
                             }
                             _ => {
                                 warn!(
                                     "SETUP stall: unhandled clear feature {:?}, {:?}",
                                     recipient, feature
                                 );
-                                self.next = State::Stall;
-                                usb.stall_endpoint_in(self.endpoint_number);
+                                self.stall(usb, Direction::DeviceToHost);
                             }
                         }
                     }
-                    (direction, RequestType::Standard, Request::SetFeature) => {
-                        info!("  TODO Request::SetFeature {:?}", direction);
+                    (Direction::HostToDevice, RequestType::Standard, Request::SetFeature) => {
                         let recipient = setup_packet.recipient();
-                        let feature = Feature::from(setup_packet.value);
-                        self.next = State::Complete;
+                        let feature = setup_packet.feature();
+                        let endpoint_address = setup_packet.endpoint_address();
                         match (&recipient, &feature) {
+                            (Recipient::Endpoint, Feature::EndpointHalt)
+                                if self.has_endpoint(endpoint_address) =>
+                            {
+                                let endpoint_number = endpoint_address & 0x7f;
+                                let direction = Direction::from(endpoint_address);
+                                // halting the control endpoint is neither required nor recommended
+                                if endpoint_number != self.endpoint_number {
+                                    self.set_endpoint_halt(endpoint_address, true);
+                                    usb.set_feature_endpoint_halt(endpoint_number, direction);
+                                }
+                                self.next = State::Complete;
+                                self.try_write_zlp(usb)?;
+                            }
                             (Recipient::Device, Feature::DeviceRemoteWakeup) => {
                                 self.feature_remote_wakeup = true;
-                                self.write_zlp(usb);
+                                self.next = State::Complete;
+                                self.try_write_zlp(usb)?;
+                            }
+                            // the lower byte of wIndex must be zero
+                            (Recipient::Device, Feature::DeviceTestMode)
+                                if setup_packet.index().to_le_bytes()[0] == 0 =>
+                            {
+                                let test_mode =
+                                    TestMode::from(setup_packet.index().to_le_bytes()[1]);
+                                if let TestMode::Reserved(selector) = test_mode {
+                                    warn!("SETUP stall: unknown test selector {}", selector);
+                                    self.stall(usb, Direction::DeviceToHost);
+                                } else if !usb.supports_test_mode(test_mode) {
+                                    warn!("SETUP stall: unsupported test mode {:?}", test_mode);
+                                    self.stall(usb, Direction::DeviceToHost);
+                                } else {
+                                    // the transition happens after the status stage
+                                    self.next = State::SetTestMode(test_mode);
+                                    self.try_write_zlp(usb)?;
+                                }
                             }
                             _ => {
                                 warn!(
                                     "SETUP stall: unhandled set feature {:?}, {:?}",
                                     recipient, feature
                                 );
-                                usb.stall_endpoint_in(self.endpoint_number);
-                                self.next = State::Stall;
+                                self.stall(usb, Direction::DeviceToHost);
                             }
                         }
                     }
 
                     // - unsupported requests with host data we need to read
-                    (Direction::HostToDevice, _, _) if setup_packet.length > 0 => {
+                    (Direction::HostToDevice, _, _) if setup_packet.length() > 0 => {
+                        if self.data_sink.is_none() && requested_length > RX_BUFFER_SIZE {
+                            error!(
+                                "Control stall - {} bytes of host data exceed receive buffer size of {} bytes",
+                                requested_length, RX_BUFFER_SIZE
+                            );
+                            self.stall(usb, Direction::HostToDevice);
+                            return Ok(None);
+                        }
                         self.rx_buffer_position = 0;
+                        self.rx_data_length = 0;
                         self.next = State::ReceiveHostData(setup_packet);
                         usb.ep_out_prime_receive(self.endpoint_number); // prime to receive data from host
                     }
 
+                    // - unsupported requests with device data supplied by the data source
+                    (Direction::DeviceToHost, _, _)
+                        if setup_packet.length() > 0 && self.data_source.is_some() =>
+                    {
+                        let iter = self
+                            .data_source
+                            .as_deref_mut()
+                            .and_then(|source| DataSourceIterator::new(usb, source, setup_packet));
+                        if let Some(iter) = iter {
+                            self.next = State::Send;
+                            usb.try_write_requested(
+                                self.endpoint_number,
+                                requested_length,
+                                iter.take(requested_length),
+                            )?;
+                        } else {
+                            self.next = State::Idle;
+                            return Ok(Some(setup_packet));
+                        }
+                    }
+
                     // - unsupported requests
                     (direction, request_type, request) => {
                         trace!(
@@ -279,7 +816,7 @@ where
                             request
                         );
                         self.next = State::Idle;
-                        return Some(setup_packet);
+                        return Ok(Some(setup_packet));
                     }
                 }
             }
@@ -318,6 +855,14 @@ where
                 usb.set_address(address); // set address
             }
 
+            (UsbEvent::SendComplete(endpoint_number), &State::SetTestMode(test_mode))
+                if endpoint_number == self.endpoint_number =>
+            {
+                self.next = State::Idle;
+                info!("Control entering test mode: {:?}", test_mode);
+                usb.set_test_mode(test_mode);
+            }
+
             (UsbEvent::SendComplete(endpoint_number), State::Complete)
                 if endpoint_number == self.endpoint_number =>
             {
@@ -329,37 +874,63 @@ where
             {
                 let mut packet_buffer: [u8; crate::EP_MAX_PACKET_SIZE] =
                     [0; crate::EP_MAX_PACKET_SIZE];
-                let bytes_read = usb.read(self.endpoint_number, &mut packet_buffer);
+                let bytes_read = usb.try_read(self.endpoint_number, &mut packet_buffer)?;
 
                 // handle early abort
                 if bytes_read == 0 {
                     warn!("Control receive early abort");
                     // we're done
                     self.next = State::FinishHostData(setup_packet);
-                    self.write_zlp(usb);
-                    return None;
+                    self.try_write_zlp(usb)?;
+                    return Ok(None);
                 }
 
-                // handle buffer overflow
-                if self.rx_buffer_position + bytes_read > RX_BUFFER_SIZE {
-                    error!("Control receive buffer overflow, truncating.");
-                    // keep reading until the host has no more data to send
-                    self.next = State::ReceiveHostData(setup_packet);
-                    usb.ep_out_prime_receive(self.endpoint_number);
-                    return None;
+                // stall the data stage while more host data is expected,
+                // otherwise the status stage
+                let offset = self.rx_data_length;
+                let requested_length = usize::from(setup_packet.length());
+                let stall_direction = if offset + bytes_read < requested_length {
+                    Direction::HostToDevice
+                } else {
+                    Direction::DeviceToHost
+                };
+
+                // handle host data in excess of the requested length
+                if offset + bytes_read > requested_length {
+                    error!(
+                        "Control stall - received {} bytes of host data for a request of {} bytes",
+                        offset + bytes_read,
+                        requested_length
+                    );
+                    self.stall(usb, stall_direction);
+                    return Ok(None);
                 }
 
-                // append packet to rx_buffer
-                let offset = self.rx_buffer_position;
-                self.rx_buffer[offset..offset + bytes_read]
-                    .copy_from_slice(&packet_buffer[..bytes_read]);
-                self.rx_buffer_position += bytes_read;
+                if let Some(sink) = self.data_sink.as_deref_mut() {
+                    // stream packet to the data sink
+                    if !sink.receive(usb, setup_packet, offset, &packet_buffer[..bytes_read]) {
+                        warn!("Control stall - data sink rejected host data");
+                        self.stall(usb, stall_direction);
+                        return Ok(None);
+                    }
+                } else if offset + bytes_read > RX_BUFFER_SIZE {
+                    // handle buffer overflow
+                    error!("Control receive buffer overflow, stalling.");
+                    self.stall(usb, stall_direction);
+                    return Ok(None);
+                } else {
+                    // append packet to rx_buffer
+                    self.rx_buffer[offset..offset + bytes_read]
+                        .copy_from_slice(&packet_buffer[..bytes_read]);
+                    self.rx_buffer_position += bytes_read;
+                }
+                self.rx_data_length += bytes_read;
 
                 // are we done yet?
-                if self.rx_buffer_position >= usize::from(setup_packet.length) {
+                if self.rx_data_length == requested_length {
                     // we're done
                     self.next = State::FinishHostData(setup_packet);
-                    self.write_zlp(usb);
+                    self.try_write_zlp(usb)?;
                 } else {
                     // get ready to receive more data
                     self.next = State::ReceiveHostData(setup_packet);
@@ -375,14 +946,15 @@ where
                 self.next = State::Idle;
 
                 // check for length mismatch
-                if self.rx_buffer_position != usize::from(setup_packet.length) {
+                if self.rx_data_length != usize::from(setup_packet.length()) {
                     warn!(
                         "Control expected {} bytes of data from the host, but received {} bytes.",
-                        setup_packet.length, self.rx_buffer_position,
+                        setup_packet.length(),
+                        self.rx_data_length,
                     );
                 }
 
-                return Some(setup_packet);
+                return Ok(Some(setup_packet));
             }
 
             // we'll get these if someone is writing directly to usb1 outside control
@@ -408,6 +980,676 @@ where
             }
         }
 
-        None
+        Ok(None)
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU16, AtomicU8, Ordering};
+
+    use super::*;
+
+    use crate::class::acm;
+    use crate::descriptor::{
+        ConfigurationDescriptor, ConfigurationDescriptorHeader, DescriptorType, EndpointDescriptor,
+        InterfaceDescriptor, InterfaceDescriptorHeader, StringTable,
+    };
+    use crate::device::Speed;
+    use crate::testing::{HostSimulator, Operation, Response, SimulatedDriver};
+
+    // - fixtures -------------------------------------------------------------
+
+    static CONFIGURATION_DESCRIPTORS: [ConfigurationDescriptor; 2] = [
+        acm::CONFIGURATION_DESCRIPTOR_0,
+        ConfigurationDescriptor::new(
+            ConfigurationDescriptorHeader {
+                bConfigurationValue: 2,
+                bmAttributes: 0x80,
+                bMaxPower: 50,
+                ..ConfigurationDescriptorHeader::new()
+            },
+            &[
+                InterfaceDescriptor::new(
+                    InterfaceDescriptorHeader {
+                        iInterfaceNumber: 0,
+                        bInterfaceClass: 0xff,
+                        ..InterfaceDescriptorHeader::new()
+                    },
+                    &[EndpointDescriptor {
+                        bEndpointAddress: 0x81,
+                        bmAttributes: 0x02,
+                        wMaxPacketSize: 512,
+                        ..EndpointDescriptor::new()
+                    }],
+                ),
+                InterfaceDescriptor::new(
+                    InterfaceDescriptorHeader {
+                        iInterfaceNumber: 0,
+                        bAlternateSetting: 1,
+                        bInterfaceClass: 0xff,
+                        ..InterfaceDescriptorHeader::new()
+                    },
+                    &[
+                        EndpointDescriptor {
+                            bEndpointAddress: 0x81,
+                            bmAttributes: 0x02,
+                            wMaxPacketSize: 512,
+                            ..EndpointDescriptor::new()
+                        },
+                        EndpointDescriptor {
+                            bEndpointAddress: 0x02,
+                            bmAttributes: 0x02,
+                            wMaxPacketSize: 512,
+                            ..EndpointDescriptor::new()
+                        },
+                    ],
+                ),
+            ],
+        ),
+    ];
+
+    fn descriptors() -> Descriptors<'static> {
+        Descriptors {
+            device_speed: Speed::High,
+            device_descriptor: crate::descriptor::DeviceDescriptor {
+                bNumConfigurations: 2,
+                ..acm::DEVICE_DESCRIPTOR
+            },
+            configuration_descriptors: &CONFIGURATION_DESCRIPTORS,
+            string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
+            string_descriptors: acm::STRING_DESCRIPTORS,
+            string_table: StringTable::new(),
+            device_qualifier_descriptor: Some(acm::DEVICE_QUALIFIER_DESCRIPTOR),
+            other_speed_configuration_descriptors: None,
+            bos_descriptor: None,
+            microsoft10: None,
+            microsoft20: None,
+        }
+    }
+
+    fn get_configuration(
+        host: &mut HostSimulator,
+        control: &mut Control<'_, SimulatedDriver, 64>,
+    ) -> Response {
+        host.control_in(control, SetupPacket::get_configuration())
+    }
+
+    fn get_status(
+        host: &mut HostSimulator,
+        control: &mut Control<'_, SimulatedDriver, 64>,
+        recipient: u8,
+        index: u16,
+    ) -> Response {
+        let setup_packet = SetupPacket::get_status(Recipient::from(recipient), index);
+        host.control_in(control, setup_packet)
+    }
+
+    fn endpoint_halt(
+        host: &mut HostSimulator,
+        control: &mut Control<'_, SimulatedDriver, 64>,
+        endpoint_address: u16,
+        halt: bool,
+    ) -> Response {
+        let setup_packet = if halt {
+            SetupPacket::set_feature(Recipient::Endpoint, Feature::EndpointHalt, endpoint_address)
+        } else {
+            SetupPacket::clear_feature(Recipient::Endpoint, Feature::EndpointHalt, endpoint_address)
+        };
+        host.control_out(control, setup_packet, &[])
+    }
+
+    fn get_interface(
+        host: &mut HostSimulator,
+        control: &mut Control<'_, SimulatedDriver, 64>,
+        interface_number: u8,
+    ) -> Response {
+        host.control_in(control, SetupPacket::get_interface(interface_number))
+    }
+
+    fn set_interface(
+        host: &mut HostSimulator,
+        control: &mut Control<'_, SimulatedDriver, 64>,
+        interface_number: u8,
+        alternate_setting: u8,
+    ) -> Response {
+        let setup_packet = SetupPacket::set_interface(interface_number, alternate_setting);
+        host.control_out(control, setup_packet, &[])
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_get_configuration_descriptor_by_index() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let data = host
+            .get_descriptor(
+                &mut control,
+                DescriptorType::Configuration,
+                1,
+                0,
+                0xff,
+                false,
+            )
+            .unwrap();
+        assert_eq!(data.len(), 9 + 9 + 7 + 9 + 7 + 7);
+        assert_eq!(data[2], 48);
+        assert_eq!(data[4], 1); // alternate settings are not counted
+        assert_eq!(data[5], 2);
+
+        // out of range
+        let data = host.get_descriptor(
+            &mut control,
+            DescriptorType::Configuration,
+            2,
+            0,
+            0xff,
+            true,
+        );
+        assert!(data.is_none());
+
+        // stalled requests are not returned to the application
+        usb.host_setup(
+            0,
+            SetupPacket::get_descriptor(DescriptorType::Configuration, 2, 0, 0xff),
+        );
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert!(usb.is_stalled(0, Direction::DeviceToHost));
+
+        // the stall is cleared by the next request
+        let data = host.get_descriptor(
+            &mut control,
+            DescriptorType::Configuration,
+            1,
+            0,
+            0xff,
+            false,
+        );
+        assert_eq!(data.map(|data| data.len()), Some(9 + 9 + 7 + 9 + 7 + 7));
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_set_configuration() {
+        static LAST_CONFIGURATION: AtomicU8 = AtomicU8::new(0xff);
+        fn on_configuration(_usb: &SimulatedDriver, configuration: u8) {
+            LAST_CONFIGURATION.store(configuration, Ordering::Relaxed);
+        }
+
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> =
+            Control::new(0, descriptors()).with_configuration_callback(on_configuration);
+        let mut host = HostSimulator::new(&usb, 0);
+
+        assert_eq!(
+            get_configuration(&mut host, &mut control),
+            Response::Data(vec![0])
+        );
+
+        host.set_configuration(&mut control, 2);
+        assert_eq!(control.configuration(), Some(2));
+        assert_eq!(LAST_CONFIGURATION.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            get_configuration(&mut host, &mut control),
+            Response::Data(vec![2])
+        );
+
+        // unknown configuration values are stalled and leave the configuration unchanged
+        let setup_packet = SetupPacket::set_configuration(3);
+        assert_eq!(
+            host.control_out(&mut control, setup_packet, &[]),
+            Response::Stall
+        );
+        assert_eq!(control.configuration(), Some(2));
+
+        host.set_configuration(&mut control, 0);
+        assert_eq!(control.configuration(), None);
+        assert_eq!(LAST_CONFIGURATION.load(Ordering::Relaxed), 0);
+
+        // a bus reset deconfigures the device
+        host.set_configuration(&mut control, 1);
+        assert_eq!(LAST_CONFIGURATION.load(Ordering::Relaxed), 1);
+        host.bus_reset(&mut control);
+        assert_eq!(control.configuration(), None);
+        assert_eq!(LAST_CONFIGURATION.load(Ordering::Relaxed), 0);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_set_interface() {
+        static LAST_INTERFACE: AtomicU16 = AtomicU16::new(0xffff);
+        fn on_interface(_usb: &SimulatedDriver, interface_number: u8, alternate_setting: u8) {
+            LAST_INTERFACE.store(
+                u16::from_le_bytes([alternate_setting, interface_number]),
+                Ordering::Relaxed,
+            );
+        }
+
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> =
+            Control::new(0, descriptors()).with_interface_callback(on_interface);
+        let mut host = HostSimulator::new(&usb, 0);
+
+        // interfaces are not available until the device is configured
+        assert_eq!(get_interface(&mut host, &mut control, 0), Response::Stall);
+        assert_eq!(
+            set_interface(&mut host, &mut control, 0, 0),
+            Response::Stall
+        );
+
+        host.set_configuration(&mut control, 2);
+        assert_eq!(
+            get_interface(&mut host, &mut control, 0),
+            Response::Data(vec![0])
+        );
+
+        assert_eq!(set_interface(&mut host, &mut control, 0, 1), Response::Ack);
+        assert_eq!(control.alternate_setting(0), Some(1));
+        assert_eq!(LAST_INTERFACE.load(Ordering::Relaxed), 0x0001);
+        assert_eq!(
+            get_interface(&mut host, &mut control, 0),
+            Response::Data(vec![1])
+        );
+
+        // unknown interfaces and alternate settings are stalled
+        assert_eq!(
+            set_interface(&mut host, &mut control, 0, 2),
+            Response::Stall
+        );
+        assert_eq!(
+            set_interface(&mut host, &mut control, 1, 0),
+            Response::Stall
+        );
+        assert_eq!(get_interface(&mut host, &mut control, 1), Response::Stall);
+        assert_eq!(control.alternate_setting(0), Some(1));
+
+        // selecting a configuration resets all interfaces to their default setting
+        host.set_configuration(&mut control, 2);
+        assert_eq!(control.alternate_setting(0), Some(0));
+        assert_eq!(
+            get_interface(&mut host, &mut control, 0),
+            Response::Data(vec![0])
+        );
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_get_status() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+
+        // device
+        assert_eq!(
+            get_status(&mut host, &mut control, 0, 0),
+            Response::Data(vec![0b01, 0])
+        );
+
+        // interfaces and endpoints other than the control endpoint need a configuration
+        assert_eq!(get_status(&mut host, &mut control, 1, 0), Response::Stall);
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x81),
+            Response::Stall
+        );
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x80),
+            Response::Data(vec![0, 0])
+        );
+
+        host.set_configuration(&mut control, 2);
+        assert_eq!(
+            get_status(&mut host, &mut control, 1, 0),
+            Response::Data(vec![0, 0])
+        );
+        assert_eq!(get_status(&mut host, &mut control, 1, 1), Response::Stall);
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x81),
+            Response::Data(vec![0, 0])
+        );
+
+        // endpoints are only available in the alternate settings that use them
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x02),
+            Response::Stall
+        );
+        set_interface(&mut host, &mut control, 0, 1);
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x02),
+            Response::Data(vec![0, 0])
+        );
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_remote_wakeup() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+        let enable = SetupPacket::set_feature(Recipient::Device, Feature::DeviceRemoteWakeup, 0);
+
+        // only while suspended
+        assert_eq!(host.control_out(&mut control, enable, &[]), Response::Ack);
+        assert!(control.is_remote_wakeup_enabled());
+        assert!(!control.remote_wakeup(&usb));
+
+        usb.host_suspend();
+        usb.dispatch(&mut control);
+        assert!(control.is_suspended());
+        usb.take_operations();
+        assert!(control.remote_wakeup(&usb));
+        assert_eq!(usb.take_operations(), vec![Operation::RemoteWakeup]);
+        usb.dispatch(&mut control);
+        assert!(!control.is_suspended());
+
+        // only when supported by the driver
+        usb.host_suspend();
+        usb.dispatch(&mut control);
+        usb.set_remote_wakeup_supported(false);
+        usb.take_operations();
+        assert!(!control.remote_wakeup(&usb));
+        assert!(usb.operations().is_empty());
+        assert!(control.is_suspended());
+        usb.set_remote_wakeup_supported(true);
+        usb.host_resume();
+        usb.dispatch(&mut control);
+
+        // only when enabled by the host
+        let disable = SetupPacket::clear_feature(Recipient::Device, Feature::DeviceRemoteWakeup, 0);
+        assert_eq!(host.control_out(&mut control, disable, &[]), Response::Ack);
+        usb.host_suspend();
+        usb.dispatch(&mut control);
+        usb.take_operations();
+        assert!(!control.remote_wakeup(&usb));
+        assert!(usb.operations().is_empty());
+
+        // a bus reset disables remote wakeup
+        usb.host_resume();
+        usb.dispatch(&mut control);
+        assert_eq!(host.control_out(&mut control, enable, &[]), Response::Ack);
+        usb.host_bus_reset();
+        usb.dispatch(&mut control);
+        assert!(!control.is_remote_wakeup_enabled());
+        assert_eq!(
+            get_status(&mut host, &mut control, 0, 0),
+            Response::Data(vec![0b01, 0])
+        );
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_endpoint_halt() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+
+        host.set_configuration(&mut control, 2);
+
+        assert_eq!(
+            endpoint_halt(&mut host, &mut control, 0x81, true),
+            Response::Ack
+        );
+        assert!(control.is_endpoint_halted(0x81));
+        assert!(usb.is_stalled(1, Direction::DeviceToHost));
+        assert!(!usb.is_stalled(1, Direction::HostToDevice));
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x81),
+            Response::Data(vec![1, 0])
+        );
+
+        assert_eq!(
+            endpoint_halt(&mut host, &mut control, 0x81, false),
+            Response::Ack
+        );
+        assert!(!control.is_endpoint_halted(0x81));
+        assert!(!usb.is_stalled(1, Direction::DeviceToHost));
+        assert_eq!(
+            get_status(&mut host, &mut control, 2, 0x81),
+            Response::Data(vec![0, 0])
+        );
+
+        // unknown endpoints are stalled
+        assert_eq!(
+            endpoint_halt(&mut host, &mut control, 0x83, true),
+            Response::Stall
+        );
+        assert_eq!(
+            endpoint_halt(&mut host, &mut control, 0x02, true),
+            Response::Stall
+        );
+
+        // selecting a configuration clears all halts
+        endpoint_halt(&mut host, &mut control, 0x81, true);
+        host.set_configuration(&mut control, 2);
+        assert!(!control.is_endpoint_halted(0x81));
+        assert!(!usb.is_stalled(1, Direction::DeviceToHost));
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_set_test_mode() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+
+        // reserved test selectors are stalled
+        let setup_packet =
+            SetupPacket::set_feature(Recipient::Device, Feature::DeviceTestMode, 0x0600);
+        assert_eq!(
+            host.control_out(&mut control, setup_packet, &[]),
+            Response::Stall
+        );
+
+        // as are test modes the device doesn't support
+        usb.set_supported_test_modes(&[TestMode::TestSe0Nak, TestMode::TestPacket]);
+        let setup_packet =
+            SetupPacket::set_feature(Recipient::Device, Feature::DeviceTestMode, 0x0100); // Test_J
+        usb.take_operations();
+        assert_eq!(
+            host.control_out(&mut control, setup_packet, &[]),
+            Response::Stall
+        );
+        assert!(!usb
+            .take_operations()
+            .iter()
+            .any(|operation| matches!(operation, Operation::TestMode(_))));
+
+        // the device enters test mode after the status stage
+        let setup_packet =
+            SetupPacket::set_feature(Recipient::Device, Feature::DeviceTestMode, 0x0400); // Test_Packet
+        usb.take_operations();
+        usb.host_setup(0, setup_packet);
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert_eq!(
+            usb.take_operations(),
+            vec![
+                Operation::Write(0, vec![]),
+                Operation::TestMode(TestMode::TestPacket),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_receive_host_data_overflow() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 8> = Control::new(0, descriptors());
+        let mut host = HostSimulator::new(&usb, 0);
+
+        // requests with more host data than fits the receive buffer
+        // stall the data stage
+        let setup_packet = SetupPacket::new(0x40, 0x01, 0, 0, 16);
+        assert_eq!(
+            host.control_out(&mut control, setup_packet, &[0xaa; 16]),
+            Response::Stall
+        );
+        assert!(usb.is_stalled(0, Direction::HostToDevice));
+        assert!(!usb.is_stalled(0, Direction::DeviceToHost));
+
+        // host data in excess of the requested length stalls the status stage
+        usb.host_setup(0, SetupPacket::new(0x40, 0x01, 0, 0, 8));
+        usb.host_out(0, &[0xaa; 12]);
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert!(!usb.is_stalled(0, Direction::HostToDevice));
+        assert!(usb.is_stalled(0, Direction::DeviceToHost));
+        assert!(control.data().is_empty());
+
+        // ...even when it fits the receive buffer
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        usb.host_setup(0, SetupPacket::new(0x40, 0x01, 0, 0, 4));
+        usb.host_out(0, &[0xaa; 12]);
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert!(!usb.is_stalled(0, Direction::HostToDevice));
+        assert!(usb.is_stalled(0, Direction::DeviceToHost));
+        assert!(control.data().is_empty());
+
+        // the next request clears the stall
+        let setup_packet = SetupPacket::new(0x40, 0x01, 0, 0, 8);
+        assert_eq!(
+            host.control_out(&mut control, setup_packet, &[0x55; 8]),
+            Response::Unhandled
+        );
+        assert_eq!(control.data(), &[0x55; 8]);
+    }
+
+    #[test]
+    fn test_data_sink() {
+        /// Records the data it receives and rejects requests with a wValue
+        #[derive(Default)]
+        struct Recorder {
+            received: Vec<u8>,
+        }
+
+        impl DataSink<SimulatedDriver> for Recorder {
+            fn receive(
+                &mut self,
+                _usb: &SimulatedDriver,
+                setup_packet: SetupPacket,
+                offset: usize,
+                data: &[u8],
+            ) -> bool {
+                assert_eq!(offset, self.received.len());
+                self.received.extend_from_slice(data);
+                setup_packet.value() == 0
+            }
+        }
+
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut host = HostSimulator::new(&usb, 0);
+        let mut sink = Recorder::default();
+
+        // data stages are streamed without a receive buffer
+        let data: Vec<u8> = (0..4096).map(|n| (n % 251) as u8).collect();
+        {
+            let mut control: Control<'_, _, 0> =
+                Control::new(0, descriptors()).with_data_sink(&mut sink);
+            let setup_packet = SetupPacket::new(0x40, 0x01, 0, 0, 4096);
+            assert_eq!(
+                host.control_out(&mut control, setup_packet, &data),
+                Response::Unhandled
+            );
+            assert!(control.data().is_empty());
+        }
+        assert_eq!(sink.received, data);
+
+        // data rejected by the sink stalls the request
+        sink.received.clear();
+        {
+            let mut control: Control<'_, _, 0> =
+                Control::new(0, descriptors()).with_data_sink(&mut sink);
+            let setup_packet = SetupPacket::new(0x40, 0x01, 1, 0, 128);
+            assert_eq!(
+                host.control_out(&mut control, setup_packet, &data[..128]),
+                Response::Stall
+            );
+            // with more host data to come
+            assert!(usb.is_stalled(0, Direction::HostToDevice));
+            assert!(!usb.is_stalled(0, Direction::DeviceToHost));
+        }
+        assert_eq!(sink.received.len(), 64);
+
+        // host data in excess of the requested length is stalled before reaching the sink
+        sink.received.clear();
+        {
+            let mut control: Control<'_, _, 0> =
+                Control::new(0, descriptors()).with_data_sink(&mut sink);
+            usb.host_setup(0, SetupPacket::new(0x40, 0x01, 0, 0, 100));
+            usb.host_out(0, &data[..64]);
+            usb.host_out(0, &data[64..128]);
+            assert!(usb.dispatch(&mut control).is_empty());
+            assert!(!usb.is_stalled(0, Direction::HostToDevice));
+            assert!(usb.is_stalled(0, Direction::DeviceToHost));
+        }
+        assert_eq!(sink.received, data[..64]);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+    }
+
+    #[test]
+    fn test_data_source() {
+        /// Supplies `length` bytes for request 0x02 in chunks that don't line up with packets
+        struct Counter {
+            length: usize,
+        }
+
+        impl DataSource<SimulatedDriver> for Counter {
+            fn supply(
+                &mut self,
+                _usb: &SimulatedDriver,
+                setup_packet: SetupPacket,
+                offset: usize,
+                buffer: &mut [u8],
+            ) -> Option<usize> {
+                if setup_packet.request != 0x02 {
+                    return None;
+                }
+                let length = self
+                    .length
+                    .saturating_sub(offset)
+                    .min(buffer.len())
+                    .min(100);
+                for (index, byte) in buffer[..length].iter_mut().enumerate() {
+                    *byte = ((offset + index) % 251) as u8;
+                }
+                Some(length)
+            }
+        }
+
+        let mut source = Counter { length: 1000 };
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 0> =
+            Control::new(0, descriptors()).with_data_source(&mut source);
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let data: Vec<u8> = (0..1000).map(|n| (n % 251) as u8).collect();
+        let setup_packet = SetupPacket::new(0xc0, 0x02, 0, 0, 4096);
+        assert_eq!(
+            host.control_in(&mut control, setup_packet),
+            Response::Data(data.clone())
+        );
+
+        // the data stage is limited to the requested length
+        let setup_packet = SetupPacket::new(0xc0, 0x02, 0, 0, 600);
+        assert_eq!(
+            host.control_in(&mut control, setup_packet),
+            Response::Data(data[..600].to_vec())
+        );
+
+        // requests the source doesn't handle are returned to the application
+        let setup_packet = SetupPacket::new(0xc0, 0x03, 0, 0, 8);
+        assert_eq!(
+            host.control_in(&mut control, setup_packet),
+            Response::Unhandled
+        );
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
     }
 }
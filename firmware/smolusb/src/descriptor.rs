@@ -9,9 +9,13 @@ use core::slice;
 
 use zerocopy::{AsBytes, FromBytes, FromZeroes};
 
+use crate::device::Speed;
+use crate::error::ErrorKind;
 use crate::traits::AsByteSliceIterator;
 
 pub mod microsoft10;
+pub mod microsoft20;
+pub mod parser;
 
 /// USB descriptor type.
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -34,7 +38,10 @@ pub enum DescriptorType {
     BinaryDeviceObjectStore = 0x0f,     // 15
     DeviceCapability = 0x10,            // 16
     WirelessEndpointCompanion = 0x11,   // 17
+    Hid = 0x21,                         // 33
+    Report = 0x22,                      // 34
     ClassSpecific = 0x24,               // 36
+    ClassSpecificEndpoint = 0x25,       // 37
     SuperSpeedEndpointCompanion = 0x30, // 48
     Unknown = 0xff,
 }
@@ -59,7 +66,10 @@ impl From<u8> for DescriptorType {
             0x0f => DescriptorType::BinaryDeviceObjectStore,
             0x10 => DescriptorType::DeviceCapability,
             0x11 => DescriptorType::WirelessEndpointCompanion,
+            0x21 => DescriptorType::Hid,
+            0x22 => DescriptorType::Report,
             0x24 => DescriptorType::ClassSpecific,
+            0x25 => DescriptorType::ClassSpecificEndpoint,
             0x30 => DescriptorType::SuperSpeedEndpointCompanion,
             _ => DescriptorType::Unknown,
         }
@@ -221,11 +231,159 @@ impl<'a> ConfigurationDescriptor<'a> {
         tail: &'a [InterfaceDescriptor],
     ) -> Self {
         head.bLength = size_of::<ConfigurationDescriptorHeader>() as u8;
-        head.bNumInterfaces = tail.len() as u8;
+
+        // alternate settings don't count towards the number of interfaces
+        let mut num_interfaces = 0;
+        let mut total_length = size_of::<ConfigurationDescriptorHeader>();
+        let mut index = 0;
+        while index < tail.len() {
+            if tail[index].head.bAlternateSetting == 0 {
+                num_interfaces += 1;
+            }
+            total_length += tail[index].length();
+            index += 1;
+        }
+        assert!(
+            total_length <= u16::MAX as usize,
+            "configuration descriptor is too long"
+        );
+        head.bNumInterfaces = num_interfaces;
+        head.wTotalLength = total_length as u16;
 
         Self { head, tail }
     }
 
+    /// Checks the configuration for consistency with the given device speed.
+    ///
+    /// Panics if an interface alternate setting is declared twice, if an
+    /// interface association refers to missing interfaces, if an endpoint
+    /// address is used by more than one interface or alternate setting, if
+    /// an endpoint's `wMaxPacketSize` is not valid for its transfer type
+    /// at `device_speed` or if Super Speed endpoint companions are missing,
+    /// invalid or present at other speeds.
+    ///
+    /// When used to initialize a `const` or `static` the checks are
+    /// performed at compile time:
+    ///
+    /// ```compile_fail
+    /// use smolusb::descriptor::*;
+    /// use smolusb::device::Speed;
+    ///
+    /// const CONFIGURATION: ConfigurationDescriptor = ConfigurationDescriptor::new(
+    ///     ConfigurationDescriptorHeader::new(),
+    ///     &[InterfaceDescriptor::new(
+    ///         InterfaceDescriptorHeader::new(),
+    ///         &[EndpointDescriptor::bulk(0x81, 64)], // high-speed bulk endpoints must be 512 bytes
+    ///     )],
+    /// )
+    /// .validate(Speed::High);
+    /// ```
+    #[must_use]
+    pub const fn validate(self, device_speed: Speed) -> Self {
+        let interfaces = self.tail;
+        let mut i = 0;
+        while i < interfaces.len() {
+            let interface = &interfaces[i];
+
+            let mut j = i + 1;
+            while j < interfaces.len() {
+                assert!(
+                    interfaces[j].head.iInterfaceNumber != interface.head.iInterfaceNumber
+                        || interfaces[j].head.bAlternateSetting != interface.head.bAlternateSetting,
+                    "duplicate interface alternate setting"
+                );
+                j += 1;
+            }
+
+            if let Some(association) = &interface.association {
+                assert!(
+                    interface.head.bAlternateSetting == 0,
+                    "interface association must precede alternate setting 0"
+                );
+                assert!(
+                    association.bInterfaceCount > 0,
+                    "interface association must contain at least one interface"
+                );
+                let mut n = 0;
+                while n < association.bInterfaceCount {
+                    let interface_number = association.bFirstInterface as usize + n as usize;
+                    let mut found = false;
+                    let mut j = 0;
+                    while j < interfaces.len() {
+                        found |= interfaces[j].head.iInterfaceNumber as usize == interface_number;
+                        j += 1;
+                    }
+                    assert!(found, "interface association contains unknown interface");
+                    n += 1;
+                }
+            }
+
+            let mut e = 0;
+            while e < interface.tail2.len() {
+                let endpoint = &interface.tail2[e];
+                assert!(
+                    endpoint.bEndpointAddress & 0x0f != 0,
+                    "interfaces can not use endpoint zero"
+                );
+                assert!(
+                    endpoint.is_valid_max_packet_size(device_speed),
+                    "invalid endpoint wMaxPacketSize for device speed"
+                );
+                if matches!(device_speed, Speed::Super | Speed::SuperPlus) {
+                    assert!(
+                        interface.endpoint_companions.len() == interface.tail2.len(),
+                        "super speed endpoints need an endpoint companion"
+                    );
+                    assert!(
+                        interface.endpoint_companions[e].is_valid(endpoint),
+                        "invalid endpoint companion"
+                    );
+                } else {
+                    assert!(
+                        interface.endpoint_companions.is_empty(),
+                        "endpoint companions are only allowed for super speed endpoints"
+                    );
+                }
+
+                // alternate settings of the same interface may reuse endpoint addresses
+                let mut j = i;
+                while j < interfaces.len() {
+                    let other = &interfaces[j];
+                    if j == i || other.head.iInterfaceNumber != interface.head.iInterfaceNumber {
+                        let mut f = if j == i { e + 1 } else { 0 };
+                        while f < other.tail2.len() {
+                            assert!(
+                                other.tail2[f].bEndpointAddress != endpoint.bEndpointAddress,
+                                "duplicate endpoint address"
+                            );
+                            f += 1;
+                        }
+                    }
+                    j += 1;
+                }
+
+                e += 1;
+            }
+
+            i += 1;
+        }
+
+        self
+    }
+
+    /// Returns the interface descriptor for the given interface number and alternate setting.
+    #[must_use]
+    pub fn interface(
+        &self,
+        interface_number: u8,
+        alternate_setting: u8,
+    ) -> Option<&InterfaceDescriptor<'a>> {
+        self.tail.iter().find(|interface| {
+            interface.interface_number() == interface_number
+                && interface.alternate_setting() == alternate_setting
+        })
+    }
+
     /// Calculate and update the descriptor total length field
     pub fn set_total_length(&mut self) -> usize {
         let total_length = self.iter().count();
@@ -276,11 +434,53 @@ impl<'a> Iterator for ConfigurationDescriptorIterator<'a> {
     }
 }
 
+// - InterfaceAssociationDescriptor -------------------------------------------
+
+/// USB interface association descriptor
+///
+/// Groups consecutive interfaces into a single function, see:
+/// <https://www.usb.org/sites/default/files/iadclasscode_r10.pdf>
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct InterfaceAssociationDescriptor {
+    pub bLength: u8,         // 8
+    pub bDescriptorType: u8, // 11 = InterfaceAssociation
+    pub bFirstInterface: u8,
+    pub bInterfaceCount: u8,
+    pub bFunctionClass: u8,
+    pub bFunctionSubClass: u8,
+    pub bFunctionProtocol: u8,
+    pub iFunction: u8,
+}
+
+impl AsByteSliceIterator for InterfaceAssociationDescriptor {}
+
+impl InterfaceAssociationDescriptor {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::InterfaceAssociation as u8,
+            bFirstInterface: 0,
+            bInterfaceCount: 0,
+            bFunctionClass: 0,
+            bFunctionSubClass: 0,
+            bFunctionProtocol: 0,
+            iFunction: 0,
+        }
+    }
+}
+
+impl Default for InterfaceAssociationDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // - InterfaceDescriptor ------------------------------------------------------
 
 // type aliases for sanity
-pub type InterfaceDescriptorIterator<'a> =
-    CompositeIterator3<'a, InterfaceDescriptorHeader, ClassSpecificDescriptor, EndpointDescriptor>;
 pub type ConfigurationDescriptorTailIterator<'a> = iter::FlatMap<
     slice::Iter<'a, InterfaceDescriptor<'a>>,
     InterfaceDescriptorIterator<'a>,
@@ -323,56 +523,255 @@ impl InterfaceDescriptorHeader {
 }
 
 /// USB interface descriptor
+///
+/// Descriptors are written in the order:
+///
+/// 1. interface association descriptor, if any
+/// 2. interface descriptor header
+/// 3. fixed-length class-specific descriptors
+/// 4. variable-length class descriptors
+/// 5. each endpoint descriptor, followed by its endpoint companion
+///    and class descriptors
 pub struct InterfaceDescriptor<'a> {
+    association: Option<InterfaceAssociationDescriptor>,
     head: InterfaceDescriptorHeader,
     tail1: &'a [ClassSpecificDescriptor],
+    class_descriptors: &'a [ClassDescriptor<'a>],
     tail2: &'a [EndpointDescriptor],
+    endpoint_companions: &'a [SuperSpeedEndpointCompanionDescriptor],
+    endpoint_class_descriptors: &'a [&'a [ClassDescriptor<'a>]],
 }
 
 impl<'a> InterfaceDescriptor<'a> {
+    #[must_use]
+    pub const fn new(head: InterfaceDescriptorHeader, tail2: &'a [EndpointDescriptor]) -> Self {
+        Self::new_cs(head, &[], tail2)
+    }
+
     #[must_use]
     #[allow(clippy::cast_possible_truncation)]
-    pub const fn new(mut head: InterfaceDescriptorHeader, tail2: &'a [EndpointDescriptor]) -> Self {
+    pub const fn new_cs(
+        mut head: InterfaceDescriptorHeader,
+        tail1: &'a [ClassSpecificDescriptor],
+        tail2: &'a [EndpointDescriptor],
+    ) -> Self {
         head.bLength = size_of::<InterfaceDescriptorHeader>() as u8;
         head.bNumEndpoints = tail2.len() as u8;
         Self {
+            association: None,
             head,
-            tail1: &[],
+            tail1,
+            class_descriptors: &[],
             tail2,
+            endpoint_companions: &[],
+            endpoint_class_descriptors: &[],
         }
     }
 
+    /// Precedes the interface with an interface association descriptor.
+    ///
+    /// `bFirstInterface` is set to the number of this interface.
     #[must_use]
     #[allow(clippy::cast_possible_truncation)]
-    pub const fn new_cs(
-        mut head: InterfaceDescriptorHeader,
-        tail1: &'a [ClassSpecificDescriptor],
-        tail2: &'a [EndpointDescriptor],
+    pub const fn with_association(
+        mut self,
+        mut association: InterfaceAssociationDescriptor,
     ) -> Self {
-        head.bLength = size_of::<InterfaceDescriptorHeader>() as u8;
-        head.bNumEndpoints = tail2.len() as u8;
-        Self { head, tail1, tail2 }
+        association.bLength = size_of::<InterfaceAssociationDescriptor>() as u8;
+        association.bFirstInterface = self.head.iInterfaceNumber;
+        self.association = Some(association);
+        self
+    }
+
+    /// Follows the interface header with variable-length class descriptors.
+    #[must_use]
+    pub const fn with_class_descriptors(
+        mut self,
+        class_descriptors: &'a [ClassDescriptor<'a>],
+    ) -> Self {
+        self.class_descriptors = class_descriptors;
+        self
+    }
+
+    /// Follows each endpoint descriptor with a Super Speed endpoint companion descriptor.
+    ///
+    /// `endpoint_companions[n]` is written after the `n`th endpoint.
+    #[must_use]
+    pub const fn with_endpoint_companions(
+        mut self,
+        endpoint_companions: &'a [SuperSpeedEndpointCompanionDescriptor],
+    ) -> Self {
+        assert!(
+            endpoint_companions.len() == self.tail2.len(),
+            "every endpoint needs an endpoint companion"
+        );
+        self.endpoint_companions = endpoint_companions;
+        self
+    }
+
+    /// Follows each endpoint descriptor with variable-length class descriptors.
+    ///
+    /// `endpoint_class_descriptors[n]` is written after the `n`th endpoint.
+    #[must_use]
+    pub const fn with_endpoint_class_descriptors(
+        mut self,
+        endpoint_class_descriptors: &'a [&'a [ClassDescriptor<'a>]],
+    ) -> Self {
+        assert!(
+            endpoint_class_descriptors.len() <= self.tail2.len(),
+            "more endpoint class descriptors than endpoints"
+        );
+        self.endpoint_class_descriptors = endpoint_class_descriptors;
+        self
+    }
+
+    /// Returns the interface number.
+    #[must_use]
+    pub const fn interface_number(&self) -> u8 {
+        self.head.iInterfaceNumber
+    }
+
+    /// Returns the alternate setting of the interface.
+    #[must_use]
+    pub const fn alternate_setting(&self) -> u8 {
+        self.head.bAlternateSetting
+    }
+
+    /// Returns the interface association descriptor preceding the interface.
+    #[must_use]
+    pub const fn association(&self) -> Option<&InterfaceAssociationDescriptor> {
+        self.association.as_ref()
+    }
+
+    /// Returns the endpoints used by the interface.
+    #[must_use]
+    pub const fn endpoints(&self) -> &'a [EndpointDescriptor] {
+        self.tail2
+    }
+
+    /// Returns the Super Speed endpoint companions of the interface's endpoints.
+    #[must_use]
+    pub const fn endpoint_companions(&self) -> &'a [SuperSpeedEndpointCompanionDescriptor] {
+        self.endpoint_companions
+    }
+
+    /// Returns the length in bytes of the interface and its
+    /// association, class-specific, endpoint and endpoint companion descriptors.
+    #[must_use]
+    pub const fn length(&self) -> usize {
+        let mut length = size_of::<InterfaceDescriptorHeader>()
+            + self.tail1.len() * size_of::<ClassSpecificDescriptor>()
+            + ClassDescriptor::total_length(self.class_descriptors)
+            + self.tail2.len() * size_of::<EndpointDescriptor>()
+            + self.endpoint_companions.len() * size_of::<SuperSpeedEndpointCompanionDescriptor>();
+        if self.association.is_some() {
+            length += size_of::<InterfaceAssociationDescriptor>();
+        }
+        let mut index = 0;
+        while index < self.endpoint_class_descriptors.len() {
+            length += ClassDescriptor::total_length(self.endpoint_class_descriptors[index]);
+            index += 1;
+        }
+        length
     }
 
     #[must_use]
     #[allow(clippy::iter_without_into_iter)]
-    pub fn iter(
-        &'a self,
-    ) -> CompositeIterator3<
-        'a,
-        InterfaceDescriptorHeader,
-        ClassSpecificDescriptor,
-        EndpointDescriptor,
-    > {
-        let iter = CompositeIterator3::new(&self.head, self.tail1, self.tail2);
-        iter
+    pub fn iter(&'a self) -> InterfaceDescriptorIterator<'a> {
+        InterfaceDescriptorIterator::new(self)
+    }
+}
+
+/// USB interface descriptor iterator
+pub struct InterfaceDescriptorIterator<'a> {
+    descriptor: &'a InterfaceDescriptor<'a>,
+    chunk: usize,
+    bytes: slice::Iter<'a, u8>,
+}
+
+impl<'a> InterfaceDescriptorIterator<'a> {
+    #[must_use]
+    pub fn new(descriptor: &'a InterfaceDescriptor<'a>) -> Self {
+        Self {
+            descriptor,
+            chunk: 0,
+            bytes: [].iter(),
+        }
+    }
+
+    /// Returns the `n`th byte slice making up the interface descriptor.
+    fn chunk(&self, mut n: usize) -> Option<&'a [u8]> {
+        let descriptor = self.descriptor;
+
+        if let Some(association) = &descriptor.association {
+            if n == 0 {
+                return Some(association.as_bytes());
+            }
+            n -= 1;
+        }
+
+        if n == 0 {
+            return Some(descriptor.head.as_bytes());
+        }
+        n -= 1;
+
+        if let Some(class_specific) = descriptor.tail1.get(n) {
+            return Some(class_specific.as_bytes());
+        }
+        n -= descriptor.tail1.len();
+
+        if let Some(chunk) = ClassDescriptor::chunk(descriptor.class_descriptors, n) {
+            return Some(chunk);
+        }
+        n -= descriptor.class_descriptors.len() * 2;
+
+        for (index, endpoint) in descriptor.tail2.iter().enumerate() {
+            if n == 0 {
+                return Some(endpoint.as_bytes());
+            }
+            n -= 1;
+
+            if let Some(endpoint_companion) = descriptor.endpoint_companions.get(index) {
+                if n == 0 {
+                    return Some(endpoint_companion.as_bytes());
+                }
+                n -= 1;
+            }
+
+            let class_descriptors = descriptor
+                .endpoint_class_descriptors
+                .get(index)
+                .copied()
+                .unwrap_or(&[]);
+            if let Some(chunk) = ClassDescriptor::chunk(class_descriptors, n) {
+                return Some(chunk);
+            }
+            n -= class_descriptors.len() * 2;
+        }
+
+        None
+    }
+}
+
+impl<'a> Iterator for InterfaceDescriptorIterator<'a> {
+    type Item = &'a u8;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(byte) = self.bytes.next() {
+                return Some(byte);
+            }
+            self.bytes = self.chunk(self.chunk)?.iter();
+            self.chunk += 1;
+        }
     }
 }
 
 // - ClassSpecificDescriptor --------------------------------------------------
 
 /// USB Class-specific Descriptor
-/// FIXME this is only the beginning of being able to handle class-specific descriptors
+///
+/// Fixed-length descriptor with a two byte payload, see [`ClassDescriptor`]
+/// for descriptors of any other length.
 #[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
 #[repr(C, packed)]
 pub struct ClassSpecificDescriptor {
@@ -397,8 +796,113 @@ impl ClassSpecificDescriptor {
     }
 }
 
+// - ClassDescriptor ----------------------------------------------------------
+
+/// USB class descriptor header
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct ClassDescriptorHeader {
+    pub bLength: u8,
+    pub bDescriptorType: u8,
+}
+
+impl AsByteSliceIterator for ClassDescriptorHeader {}
+
+/// Variable-length USB class descriptor
+///
+/// Used for class-specific interface and endpoint descriptors such as CDC
+/// functional descriptors, UAC and UVC descriptors or the HID descriptor.
+#[derive(Clone, Copy)]
+pub struct ClassDescriptor<'a> {
+    pub head: ClassDescriptorHeader,
+    pub tail: &'a [u8],
+}
+
+impl<'a> ClassDescriptor<'a> {
+    /// Returns a descriptor of the given type followed by `tail`.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new(descriptor_type: u8, tail: &'a [u8]) -> Self {
+        let length = size_of::<ClassDescriptorHeader>() + tail.len();
+        assert!(length <= u8::MAX as usize, "class descriptor is too long");
+        Self {
+            head: ClassDescriptorHeader {
+                bLength: length as u8,
+                bDescriptorType: descriptor_type,
+            },
+            tail,
+        }
+    }
+
+    /// Returns a class-specific interface descriptor (`CS_INTERFACE`).
+    #[must_use]
+    pub const fn interface(tail: &'a [u8]) -> Self {
+        Self::new(DescriptorType::ClassSpecific as u8, tail)
+    }
+
+    /// Returns a class-specific endpoint descriptor (`CS_ENDPOINT`).
+    #[must_use]
+    pub const fn endpoint(tail: &'a [u8]) -> Self {
+        Self::new(DescriptorType::ClassSpecificEndpoint as u8, tail)
+    }
+
+    /// Returns the length in bytes of the descriptor.
+    #[must_use]
+    pub const fn length(&self) -> usize {
+        self.head.bLength as usize
+    }
+
+    /// Returns the combined length in bytes of `descriptors`.
+    #[must_use]
+    pub const fn total_length(descriptors: &[ClassDescriptor]) -> usize {
+        let mut length = 0;
+        let mut index = 0;
+        while index < descriptors.len() {
+            length += descriptors[index].length();
+            index += 1;
+        }
+        length
+    }
+
+    /// Returns the `n`th byte slice of `descriptors` where each
+    /// descriptor is made up of a header and a tail slice.
+    fn chunk(descriptors: &'a [ClassDescriptor<'a>], n: usize) -> Option<&'a [u8]> {
+        let descriptor = descriptors.get(n / 2)?;
+        if n % 2 == 0 {
+            Some(descriptor.head.as_bytes())
+        } else {
+            Some(descriptor.tail)
+        }
+    }
+
+    #[allow(clippy::iter_without_into_iter)]
+    pub fn iter(&'a self) -> iter::Chain<slice::Iter<'a, u8>, slice::Iter<'a, u8>> {
+        self.head.as_iter().chain(self.tail.iter())
+    }
+}
+
 // - EndpointDescriptor -------------------------------------------------------
 
+/// USB endpoint transfer type
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum TransferType {
+    Control = 0,
+    Isochronous = 1,
+    Bulk = 2,
+    Interrupt = 3,
+}
+
+/// USB isochronous endpoint synchronization type
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum SynchronizationType {
+    NoSynchronization = 0,
+    Asynchronous = 1,
+    Adaptive = 2,
+    Synchronous = 3,
+}
+
 /// USB endpoint descriptor
 #[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
 #[repr(C, packed)]
@@ -426,6 +930,140 @@ impl EndpointDescriptor {
             bInterval: 0,
         }
     }
+
+    /// Returns a bulk endpoint descriptor.
+    #[must_use]
+    pub const fn bulk(endpoint_address: u8, max_packet_size: u16) -> Self {
+        Self {
+            bEndpointAddress: endpoint_address,
+            bmAttributes: TransferType::Bulk as u8,
+            wMaxPacketSize: max_packet_size,
+            bInterval: 0,
+            ..Self::new()
+        }
+    }
+
+    /// Returns an interrupt endpoint descriptor.
+    #[must_use]
+    pub const fn interrupt(endpoint_address: u8, max_packet_size: u16, interval: u8) -> Self {
+        Self {
+            bEndpointAddress: endpoint_address,
+            bmAttributes: TransferType::Interrupt as u8,
+            wMaxPacketSize: max_packet_size,
+            bInterval: interval,
+            ..Self::new()
+        }
+    }
+
+    /// Returns an isochronous data endpoint descriptor.
+    ///
+    /// `interval` is the polling interval exponent: the endpoint is
+    /// serviced every 2^(`interval` - 1) (micro)frames.
+    #[must_use]
+    pub const fn isochronous(
+        endpoint_address: u8,
+        max_packet_size: u16,
+        interval: u8,
+        synchronization_type: SynchronizationType,
+    ) -> Self {
+        Self {
+            bEndpointAddress: endpoint_address,
+            bmAttributes: TransferType::Isochronous as u8 | ((synchronization_type as u8) << 2),
+            wMaxPacketSize: max_packet_size,
+            bInterval: interval,
+            ..Self::new()
+        }
+    }
+
+    /// Sets the number of additional transactions per microframe for
+    /// high-bandwidth high-speed isochronous and interrupt endpoints.
+    #[must_use]
+    pub const fn with_additional_transactions(mut self, additional_transactions: u8) -> Self {
+        assert!(
+            additional_transactions <= 2,
+            "at most two additional transactions are allowed"
+        );
+        self.wMaxPacketSize =
+            (self.wMaxPacketSize & 0x07ff) | ((additional_transactions as u16) << 11);
+        self
+    }
+
+    /// Returns the maximum packet size, excluding any additional transaction bits.
+    #[must_use]
+    pub const fn max_packet_size(&self) -> u16 {
+        self.wMaxPacketSize & 0x07ff
+    }
+
+    /// Returns the number of additional transactions per microframe.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn additional_transactions(&self) -> u8 {
+        ((self.wMaxPacketSize >> 11) & 0b11) as u8
+    }
+
+    /// Returns the endpoint's transfer type.
+    #[must_use]
+    pub const fn transfer_type(&self) -> TransferType {
+        match self.bmAttributes & 0b11 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Returns the synchronization type of an isochronous endpoint.
+    #[must_use]
+    pub const fn synchronization_type(&self) -> SynchronizationType {
+        match (self.bmAttributes >> 2) & 0b11 {
+            0 => SynchronizationType::NoSynchronization,
+            1 => SynchronizationType::Asynchronous,
+            2 => SynchronizationType::Adaptive,
+            _ => SynchronizationType::Synchronous,
+        }
+    }
+
+    /// Returns `true` if `wMaxPacketSize` is allowed for the endpoint's
+    /// transfer type at the given device speed.
+    #[must_use]
+    pub const fn is_valid_max_packet_size(&self, device_speed: Speed) -> bool {
+        let max_packet_size = self.max_packet_size();
+        let transactions = self.wMaxPacketSize >> 11;
+        if max_packet_size == 0 {
+            return false;
+        }
+
+        match (device_speed, self.transfer_type()) {
+            (Speed::Low, TransferType::Control) => transactions == 0 && max_packet_size == 8,
+            (Speed::Low, TransferType::Interrupt) => transactions == 0 && max_packet_size <= 8,
+            (Speed::Low, _) => false,
+            (Speed::Full, TransferType::Control | TransferType::Bulk) => {
+                transactions == 0 && matches!(max_packet_size, 8 | 16 | 32 | 64)
+            }
+            (Speed::Full, TransferType::Interrupt) => transactions == 0 && max_packet_size <= 64,
+            (Speed::Full, TransferType::Isochronous) => {
+                transactions == 0 && max_packet_size <= 1023
+            }
+            (Speed::High, TransferType::Control) => transactions == 0 && max_packet_size == 64,
+            (Speed::High, TransferType::Bulk) => transactions == 0 && max_packet_size == 512,
+            (Speed::High, TransferType::Interrupt | TransferType::Isochronous) => {
+                match transactions {
+                    0 => max_packet_size <= 1024,
+                    1 => max_packet_size >= 513 && max_packet_size <= 1024,
+                    2 => max_packet_size >= 683 && max_packet_size <= 1024,
+                    _ => false,
+                }
+            }
+            (Speed::Super | Speed::SuperPlus, TransferType::Control) => {
+                transactions == 0 && max_packet_size == 512
+            }
+            (Speed::Super | Speed::SuperPlus, TransferType::Bulk) => {
+                transactions == 0 && max_packet_size == 1024
+            }
+            (Speed::Super | Speed::SuperPlus, _) => transactions == 0 && max_packet_size <= 1024,
+            (Speed::Unknown, _) => true,
+        }
+    }
 }
 
 impl Default for EndpointDescriptor {
@@ -434,16 +1072,373 @@ impl Default for EndpointDescriptor {
     }
 }
 
+/// USB Super Speed endpoint companion descriptor
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SuperSpeedEndpointCompanionDescriptor {
+    pub bLength: u8,         // 6
+    pub bDescriptorType: u8, // 48 = SuperSpeedEndpointCompanion
+    pub bMaxBurst: u8,
+    pub bmAttributes: u8,
+    pub wBytesPerInterval: u16,
+}
+
+impl AsByteSliceIterator for SuperSpeedEndpointCompanionDescriptor {}
+
+impl SuperSpeedEndpointCompanionDescriptor {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::SuperSpeedEndpointCompanion as u8,
+            bMaxBurst: 0,
+            bmAttributes: 0,
+            wBytesPerInterval: 0,
+        }
+    }
+}
+
+impl SuperSpeedEndpointCompanionDescriptor {
+    /// Returns `true` if the companion's attributes are valid for `endpoint`.
+    #[must_use]
+    pub const fn is_valid(&self, endpoint: &EndpointDescriptor) -> bool {
+        if self.bMaxBurst > 15 {
+            return false;
+        }
+        match endpoint.transfer_type() {
+            TransferType::Control => self.bMaxBurst == 0 && self.bmAttributes == 0,
+            // MaxStreams
+            TransferType::Bulk => self.bmAttributes <= 16,
+            // Mult
+            TransferType::Isochronous => self.bmAttributes <= 2,
+            TransferType::Interrupt => self.bMaxBurst <= 2 && self.bmAttributes == 0,
+        }
+    }
+}
+
+impl Default for SuperSpeedEndpointCompanionDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// - BinaryObjectStoreDescriptor ----------------------------------------------
+
+/// USB device capability type
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[repr(u8)]
+pub enum DeviceCapabilityType {
+    WirelessUsb = 0x01,
+    Usb20Extension = 0x02,
+    SuperSpeedUsb = 0x03,
+    ContainerId = 0x04,
+    Platform = 0x05,
+    Unknown = 0xff,
+}
+
+impl From<u8> for DeviceCapabilityType {
+    fn from(value: u8) -> Self {
+        match value {
+            0x01 => DeviceCapabilityType::WirelessUsb,
+            0x02 => DeviceCapabilityType::Usb20Extension,
+            0x03 => DeviceCapabilityType::SuperSpeedUsb,
+            0x04 => DeviceCapabilityType::ContainerId,
+            0x05 => DeviceCapabilityType::Platform,
+            _ => DeviceCapabilityType::Unknown,
+        }
+    }
+}
+
+/// USB binary object store descriptor header
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct BinaryObjectStoreDescriptorHeader {
+    pub bLength: u8,         // 5
+    pub bDescriptorType: u8, // 15 = BinaryDeviceObjectStore
+    pub wTotalLength: u16,
+    pub bNumDeviceCaps: u8,
+}
+
+impl AsByteSliceIterator for BinaryObjectStoreDescriptorHeader {}
+
+impl BinaryObjectStoreDescriptorHeader {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::BinaryDeviceObjectStore as u8,
+            wTotalLength: 0,
+            bNumDeviceCaps: 0,
+        }
+    }
+}
+
+impl Default for BinaryObjectStoreDescriptorHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// USB binary object store descriptor
+#[derive(Clone, Copy)]
+pub struct BinaryObjectStoreDescriptor<'a> {
+    pub head: BinaryObjectStoreDescriptorHeader,
+    pub tail: &'a [DeviceCapability<'a>],
+}
+
+impl<'a> BinaryObjectStoreDescriptor<'a> {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new(
+        mut head: BinaryObjectStoreDescriptorHeader,
+        tail: &'a [DeviceCapability<'a>],
+    ) -> Self {
+        let mut total_length = size_of::<BinaryObjectStoreDescriptorHeader>();
+        let mut index = 0;
+        while index < tail.len() {
+            total_length += tail[index].length();
+            index += 1;
+        }
+        assert!(
+            total_length <= u16::MAX as usize,
+            "binary object store descriptor is too long"
+        );
+        assert!(
+            tail.len() <= u8::MAX as usize,
+            "binary object store descriptor has too many device capabilities"
+        );
+
+        head.bLength = size_of::<BinaryObjectStoreDescriptorHeader>() as u8;
+        head.wTotalLength = total_length as u16;
+        head.bNumDeviceCaps = tail.len() as u8;
+
+        Self { head, tail }
+    }
+
+    #[allow(clippy::iter_without_into_iter)]
+    pub fn iter(&'a self) -> BinaryObjectStoreDescriptorIterator<'a> {
+        let head_iter: slice::Iter<'a, u8> = self.head.as_iter();
+        let tail_iter: BinaryObjectStoreDescriptorTailIterator = self
+            .tail
+            .iter()
+            .flat_map(&|x: &'a DeviceCapability| x.iter());
+        head_iter.chain(tail_iter)
+    }
+}
+
+// type aliases for sanity
+pub type DeviceCapabilityIterator<'a> = iter::Chain<slice::Iter<'a, u8>, slice::Iter<'a, u8>>;
+pub type BinaryObjectStoreDescriptorTailIterator<'a> = iter::FlatMap<
+    slice::Iter<'a, DeviceCapability<'a>>,
+    DeviceCapabilityIterator<'a>,
+    &'a dyn Fn(&'a DeviceCapability<'a>) -> DeviceCapabilityIterator<'a>,
+>;
+pub type BinaryObjectStoreDescriptorIterator<'a> =
+    iter::Chain<slice::Iter<'a, u8>, BinaryObjectStoreDescriptorTailIterator<'a>>;
+
+/// USB device capability descriptor
+#[derive(Clone, Copy)]
+pub enum DeviceCapability<'a> {
+    Usb20Extension(Usb20ExtensionDescriptor),
+    SuperSpeedUsb(SuperSpeedUsbDescriptor),
+    Platform(PlatformDescriptor<'a>),
+}
+
+impl<'a> DeviceCapability<'a> {
+    /// Returns the length of the capability descriptor in bytes.
+    #[must_use]
+    pub const fn length(&self) -> usize {
+        match self {
+            DeviceCapability::Usb20Extension(descriptor) => descriptor.bLength as usize,
+            DeviceCapability::SuperSpeedUsb(descriptor) => descriptor.bLength as usize,
+            DeviceCapability::Platform(descriptor) => descriptor.head.bLength as usize,
+        }
+    }
+
+    #[allow(clippy::iter_without_into_iter)]
+    pub fn iter(&'a self) -> DeviceCapabilityIterator<'a> {
+        match self {
+            DeviceCapability::Usb20Extension(descriptor) => descriptor.as_iter().chain([].iter()),
+            DeviceCapability::SuperSpeedUsb(descriptor) => descriptor.as_iter().chain([].iter()),
+            DeviceCapability::Platform(descriptor) => {
+                descriptor.head.as_iter().chain(descriptor.tail.iter())
+            }
+        }
+    }
+}
+
+/// USB 2.0 extension device capability descriptor
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct Usb20ExtensionDescriptor {
+    pub bLength: u8,            // 7
+    pub bDescriptorType: u8,    // 16 = DeviceCapability
+    pub bDevCapabilityType: u8, // 2 = Usb20Extension
+    pub bmAttributes: u32,
+}
+
+impl AsByteSliceIterator for Usb20ExtensionDescriptor {}
+
+impl Usb20ExtensionDescriptor {
+    /// Link Power Management is supported
+    pub const LPM: u32 = 1 << 1;
+    /// BESL and alternate HIRD definitions are supported
+    pub const BESL: u32 = 1 << 2;
+    /// The recommended baseline BESL value is valid
+    pub const BASELINE_BESL_VALID: u32 = 1 << 3;
+    /// The recommended deep BESL value is valid
+    pub const DEEP_BESL_VALID: u32 = 1 << 4;
+
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::DeviceCapability as u8,
+            bDevCapabilityType: DeviceCapabilityType::Usb20Extension as u8,
+            bmAttributes: 0,
+        }
+    }
+
+    /// Returns `bmAttributes` bits for the given recommended baseline
+    /// and deep BESL values.
+    #[must_use]
+    pub const fn besl(baseline: u8, deep: u8) -> u32 {
+        Self::BASELINE_BESL_VALID
+            | Self::DEEP_BESL_VALID
+            | ((baseline as u32 & 0xf) << 8)
+            | ((deep as u32 & 0xf) << 12)
+    }
+}
+
+impl Default for Usb20ExtensionDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// USB Super Speed device capability descriptor
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct SuperSpeedUsbDescriptor {
+    pub bLength: u8,            // 10
+    pub bDescriptorType: u8,    // 16 = DeviceCapability
+    pub bDevCapabilityType: u8, // 3 = SuperSpeedUsb
+    pub bmAttributes: u8,
+    pub wSpeedsSupported: u16,
+    pub bFunctionalitySupport: u8,
+    pub bU1DevExitLat: u8,
+    pub wU2DevExitLat: u16,
+}
+
+impl AsByteSliceIterator for SuperSpeedUsbDescriptor {}
+
+impl SuperSpeedUsbDescriptor {
+    /// Latency Tolerance Messages are supported
+    pub const LTM: u8 = 1 << 1;
+    /// Low speed (1.5 Mbps) is supported
+    pub const SPEED_LOW: u16 = 1 << 0;
+    /// Full speed (12 Mbps) is supported
+    pub const SPEED_FULL: u16 = 1 << 1;
+    /// High speed (480 Mbps) is supported
+    pub const SPEED_HIGH: u16 = 1 << 2;
+    /// Gen 1 Super Speed (5 Gbps) is supported
+    pub const SPEED_SUPER: u16 = 1 << 3;
+
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::DeviceCapability as u8,
+            bDevCapabilityType: DeviceCapabilityType::SuperSpeedUsb as u8,
+            bmAttributes: 0,
+            wSpeedsSupported: 0,
+            bFunctionalitySupport: 0,
+            bU1DevExitLat: 0,
+            wU2DevExitLat: 0,
+        }
+    }
+}
+
+impl Default for SuperSpeedUsbDescriptor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// USB platform device capability descriptor header
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy)]
+#[repr(C, packed)]
+pub struct PlatformDescriptorHeader {
+    pub bLength: u8,            // 20 + capability data
+    pub bDescriptorType: u8,    // 16 = DeviceCapability
+    pub bDevCapabilityType: u8, // 5 = Platform
+    pub bReserved: u8,
+    pub PlatformCapabilityUUID: [u8; 16],
+}
+
+impl AsByteSliceIterator for PlatformDescriptorHeader {}
+
+impl PlatformDescriptorHeader {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new() -> Self {
+        Self {
+            bLength: size_of::<Self>() as u8,
+            bDescriptorType: DescriptorType::DeviceCapability as u8,
+            bDevCapabilityType: DeviceCapabilityType::Platform as u8,
+            bReserved: 0,
+            PlatformCapabilityUUID: [0; 16],
+        }
+    }
+}
+
+impl Default for PlatformDescriptorHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// USB platform device capability descriptor
+#[derive(Clone, Copy)]
+pub struct PlatformDescriptor<'a> {
+    pub head: PlatformDescriptorHeader,
+    /// Platform specific capability data
+    pub tail: &'a [u8],
+}
+
+impl<'a> PlatformDescriptor<'a> {
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn new(mut head: PlatformDescriptorHeader, tail: &'a [u8]) -> Self {
+        let length = size_of::<PlatformDescriptorHeader>() + tail.len();
+        assert!(
+            length <= u8::MAX as usize,
+            "platform descriptor is too long"
+        );
+        head.bLength = length as u8;
+        Self { head, tail }
+    }
+}
+
 // - StringDescriptorZero -----------------------------------------------------
 
 /// USB string descriptor language id
-#[derive(AsBytes, Copy, Clone, Debug)]
+#[derive(AsBytes, Copy, Clone, Debug, PartialEq)]
 #[repr(u16)]
 pub enum LanguageId {
     EnglishUnitedStates = 0x0409,
     EnglishUnitedKingdom = 0x0809,
     EnglishCanadian = 0x1009,
     EnglishSouthAfrica = 0x1c09,
+    German = 0x0407,
+    French = 0x040c,
+    Spanish = 0x0c0a,
+    Japanese = 0x0411,
+    ChineseSimplified = 0x0804,
 }
 
 impl AsByteSliceIterator for LanguageId {}
@@ -476,6 +1471,26 @@ impl<'a> StringDescriptorZero<'a> {
         let iter = CompositeIterator::new(&self.head, self.tail);
         iter
     }
+
+    /// Returns the language ids supported by the device.
+    #[must_use]
+    pub fn language_ids(&self) -> &'a [LanguageId] {
+        self.tail
+    }
+
+    /// Returns the supported language matching the `wIndex` of a
+    /// string descriptor request.
+    ///
+    /// Falls back to the first language if the language is not
+    /// supported.
+    #[must_use]
+    pub fn language_id(&self, language_id: u16) -> Option<LanguageId> {
+        self.tail
+            .iter()
+            .find(|candidate| **candidate as u16 == language_id)
+            .or(self.tail.first())
+            .copied()
+    }
 }
 
 // - StringDescriptor ---------------------------------------------------------
@@ -524,6 +1539,54 @@ impl<'a> StringDescriptor<'a> {
     }
 }
 
+/// Returns the string descriptor index of `string` in `string_descriptors`.
+///
+/// String descriptor indices start at 1 as index 0 is reserved for
+/// the language id descriptor. Panics if the string can not be found
+/// which, when used to initialize a `const`, is a compile time error:
+///
+/// ```
+/// use smolusb::descriptor::{string_index, StringDescriptor};
+///
+/// const MANUFACTURER: &str = "Cynthion Project";
+/// const PRODUCT: &str = "USB-to-serial";
+/// const STRING_DESCRIPTORS: &[&StringDescriptor] = &[
+///     &StringDescriptor::new(MANUFACTURER),
+///     &StringDescriptor::new(PRODUCT),
+/// ];
+///
+/// const I_PRODUCT: u8 = string_index(STRING_DESCRIPTORS, PRODUCT);
+/// assert_eq!(I_PRODUCT, 2);
+/// ```
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub const fn string_index(string_descriptors: &[&StringDescriptor], string: &str) -> u8 {
+    assert!(
+        string_descriptors.len() < u8::MAX as usize,
+        "too many string descriptors"
+    );
+
+    let needle = string.as_bytes();
+    let mut index = 0;
+    'descriptors: while index < string_descriptors.len() {
+        let candidate = string_descriptors[index].tail.as_bytes();
+        index += 1;
+        if candidate.len() != needle.len() {
+            continue;
+        }
+        let mut n = 0;
+        while n < needle.len() {
+            if candidate[n] != needle[n] {
+                continue 'descriptors;
+            }
+            n += 1;
+        }
+        return index as u8;
+    }
+
+    panic!("string not found in string descriptors");
+}
+
 impl<'a> StringDescriptor<'a> {
     /// Returns an iterator to the descriptor
     #[allow(clippy::cloned_instead_of_copied)]
@@ -541,6 +1604,139 @@ impl<'a> StringDescriptor<'a> {
 pub type StringDescriptorIterator<'a> =
     iter::Chain<iter::Cloned<slice::Iter<'a, u8>>, Utf16ByteIterator<'a>>;
 
+// - StringTable --------------------------------------------------------------
+
+/// Maximum number of strings held by a [`StringTable`].
+pub const STRING_TABLE_CAPACITY: usize = 8;
+
+/// Maximum length, in bytes, of a string held by a [`StringTable`].
+pub const STRING_TABLE_MAX_STRING_LENGTH: usize = 32;
+
+#[derive(Clone, Copy)]
+struct StringTableEntry {
+    index: u8,
+    language_id: Option<LanguageId>,
+    length: usize,
+    bytes: [u8; STRING_TABLE_MAX_STRING_LENGTH],
+}
+
+impl StringTableEntry {
+    fn as_str(&self) -> &str {
+        // entries are only ever created from a valid `&str`
+        core::str::from_utf8(&self.bytes[..self.length]).unwrap_or_default()
+    }
+}
+
+/// A fixed-capacity table of strings generated at runtime.
+///
+/// Holds strings, such as serial numbers or firmware versions, that
+/// are not known at compile time. Strings can be stored for a single
+/// language or for all of the languages in [`StringDescriptorZero`],
+/// with strings for a single language taking precedence.
+///
+/// Strings in the table take precedence over the static
+/// `string_descriptors` of a [`Descriptors`](crate::device::Descriptors)
+/// instance with the same index. Requests for an index in the table
+/// without a string for the requested language are stalled.
+#[derive(Clone, Copy)]
+pub struct StringTable {
+    entries: [Option<StringTableEntry>; STRING_TABLE_CAPACITY],
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StringTable {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; STRING_TABLE_CAPACITY],
+        }
+    }
+
+    /// Stores a copy of `string` with the given string descriptor
+    /// index for the given language, or for all languages if
+    /// `language_id` is `None`.
+    ///
+    /// Replaces any string previously stored for the same index and
+    /// language.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::Overflow`] with the length of the string
+    /// if it is longer than [`STRING_TABLE_MAX_STRING_LENGTH`] or if
+    /// the table is full.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is 0 as it is reserved for the language id
+    /// descriptor.
+    pub fn insert(
+        &mut self,
+        index: u8,
+        language_id: Option<LanguageId>,
+        string: &str,
+    ) -> Result<(), ErrorKind> {
+        assert!(
+            index != StringDescriptorNumber::Zero,
+            "string index 0 is reserved"
+        );
+
+        if string.len() > STRING_TABLE_MAX_STRING_LENGTH {
+            return Err(ErrorKind::Overflow(string.len()));
+        }
+
+        let mut entry = StringTableEntry {
+            index,
+            language_id,
+            length: string.len(),
+            bytes: [0; STRING_TABLE_MAX_STRING_LENGTH],
+        };
+        entry.bytes[..string.len()].copy_from_slice(string.as_bytes());
+
+        let slot = self
+            .entries
+            .iter()
+            .position(|candidate| {
+                candidate.as_ref().map_or(false, |candidate| {
+                    candidate.index == index && candidate.language_id == language_id
+                })
+            })
+            .or_else(|| self.entries.iter().position(Option::is_none))
+            .ok_or(ErrorKind::Overflow(string.len()))?;
+        self.entries[slot] = Some(entry);
+
+        Ok(())
+    }
+
+    /// Returns the string with the given index for the given language.
+    #[must_use]
+    pub fn get(&self, index: u8, language_id: LanguageId) -> Option<&str> {
+        let find = |language_id| {
+            self.entries
+                .iter()
+                .flatten()
+                .find(|entry| entry.index == index && entry.language_id == language_id)
+        };
+        find(Some(language_id))
+            .or_else(|| find(None))
+            .map(StringTableEntry::as_str)
+    }
+
+    /// Returns `true` if the table holds a string with the given
+    /// index for any language.
+    #[must_use]
+    pub fn contains(&self, index: u8) -> bool {
+        self.entries
+            .iter()
+            .flatten()
+            .any(|entry| entry.index == index)
+    }
+}
+
 // - Utf16ByteIterator --------------------------------------------------------
 
 #[derive(Clone)]
@@ -645,3 +1841,334 @@ impl<'a, H, T1, T2> Iterator for CompositeIterator3<'a, H, T1, T2> {
         self.chain.next()
     }
 }
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class::acm;
+
+    // - fixtures -------------------------------------------------------------
+
+    const ENDPOINTS: &[EndpointDescriptor] = &[
+        EndpointDescriptor::bulk(0x81, 512),
+        EndpointDescriptor::bulk(0x01, 512),
+    ];
+
+    fn configuration<'a>(interfaces: &'a [InterfaceDescriptor<'a>]) -> ConfigurationDescriptor<'a> {
+        ConfigurationDescriptor::new(ConfigurationDescriptorHeader::new(), interfaces)
+    }
+
+    fn interface(
+        interface_number: u8,
+        alternate_setting: u8,
+        endpoints: &[EndpointDescriptor],
+    ) -> InterfaceDescriptor<'_> {
+        InterfaceDescriptor::new(
+            InterfaceDescriptorHeader {
+                iInterfaceNumber: interface_number,
+                bAlternateSetting: alternate_setting,
+                ..InterfaceDescriptorHeader::new()
+            },
+            endpoints,
+        )
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_configuration_descriptor_lengths() {
+        for descriptor in acm::CONFIGURATION_DESCRIPTORS
+            .iter()
+            .chain(acm::OTHER_SPEED_CONFIGURATION_DESCRIPTORS)
+        {
+            let bytes: Vec<u8> = descriptor.iter().copied().collect();
+            assert_eq!(usize::from(descriptor.head.wTotalLength), bytes.len());
+            assert_eq!(bytes[2..4], descriptor.head.wTotalLength.to_le_bytes());
+            assert_eq!(descriptor.head.bNumInterfaces, 2);
+            assert_eq!(bytes[9 + 4], 1); // interface #0 bNumEndpoints
+        }
+
+        // alternate settings add to the total length but not the interface count
+        let interfaces = [interface(0, 0, &[]), interface(0, 1, ENDPOINTS)];
+        let descriptor = configuration(&interfaces).validate(Speed::High);
+        assert_eq!({ descriptor.head.wTotalLength }, 9 + 9 + 9 + 7 + 7);
+        assert_eq!(descriptor.head.bNumInterfaces, 1);
+    }
+
+    #[test]
+    fn test_validate_alternate_settings_share_endpoints() {
+        let interfaces = [interface(0, 0, ENDPOINTS), interface(0, 1, ENDPOINTS)];
+        let _ = configuration(&interfaces).validate(Speed::High);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate endpoint address")]
+    fn test_validate_duplicate_endpoint_address() {
+        let interfaces = [interface(0, 0, ENDPOINTS), interface(1, 0, &ENDPOINTS[..1])];
+        let _ = configuration(&interfaces).validate(Speed::High);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate interface alternate setting")]
+    fn test_validate_duplicate_interface() {
+        let interfaces = [interface(0, 0, &[]), interface(0, 0, &[])];
+        let _ = configuration(&interfaces).validate(Speed::High);
+    }
+
+    #[test]
+    fn test_max_packet_size() {
+        let bulk = |size| EndpointDescriptor::bulk(0x81, size);
+        let interrupt = |size| EndpointDescriptor::interrupt(0x81, size, 1);
+
+        assert!(bulk(64).is_valid_max_packet_size(Speed::Full));
+        assert!(!bulk(512).is_valid_max_packet_size(Speed::Full));
+        assert!(!bulk(64).is_valid_max_packet_size(Speed::High));
+        assert!(!bulk(8).is_valid_max_packet_size(Speed::Low));
+        assert!(interrupt(8).is_valid_max_packet_size(Speed::Low));
+        assert!(!interrupt(1024).is_valid_max_packet_size(Speed::Full));
+        assert!(interrupt(1024 | (2 << 11)).is_valid_max_packet_size(Speed::High));
+        assert!(!interrupt(1024 | (3 << 11)).is_valid_max_packet_size(Speed::High));
+
+        let isochronous = |size| {
+            EndpointDescriptor::isochronous(0x81, size, 1, SynchronizationType::Asynchronous)
+        };
+        assert_eq!(isochronous(1023).transfer_type(), TransferType::Isochronous);
+        assert_eq!(
+            isochronous(1023).synchronization_type(),
+            SynchronizationType::Asynchronous
+        );
+        assert_eq!(isochronous(1023).bmAttributes, 0b0101);
+        assert!(isochronous(1023).is_valid_max_packet_size(Speed::Full));
+        assert!(!isochronous(1024).is_valid_max_packet_size(Speed::Full));
+        assert!(!isochronous(8).is_valid_max_packet_size(Speed::Low));
+        assert!(isochronous(1024 | (2 << 11)).is_valid_max_packet_size(Speed::High));
+
+        let interfaces = [interface(0, 0, ENDPOINTS)];
+        let result = std::panic::catch_unwind(|| configuration(&interfaces).validate(Speed::Full));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_interface_association_and_class_descriptors() {
+        const CDC_CLASS_DESCRIPTORS: &[ClassDescriptor] = &[
+            ClassDescriptor::interface(&[0x00, 0x10, 0x01]), // header
+            ClassDescriptor::interface(&[0x02, 0x02]),       // abstract control management
+            ClassDescriptor::interface(&[0x06, 0x00, 0x01]), // union
+        ];
+        const ENDPOINT_CLASS_DESCRIPTORS: &[&[ClassDescriptor]] = &[
+            &[],
+            &[ClassDescriptor::endpoint(&[0x01, 0x00, 0x00, 0x00, 0x00])],
+        ];
+        const INTERFACES: &[InterfaceDescriptor] = &[
+            InterfaceDescriptor::new(
+                InterfaceDescriptorHeader {
+                    iInterfaceNumber: 0,
+                    ..InterfaceDescriptorHeader::new()
+                },
+                &[EndpointDescriptor::interrupt(0x83, 8, 11)],
+            )
+            .with_association(InterfaceAssociationDescriptor {
+                bInterfaceCount: 2,
+                bFunctionClass: 0x02,
+                bFunctionSubClass: 0x02,
+                iFunction: 4,
+                ..InterfaceAssociationDescriptor::new()
+            })
+            .with_class_descriptors(CDC_CLASS_DESCRIPTORS),
+            InterfaceDescriptor::new(
+                InterfaceDescriptorHeader {
+                    iInterfaceNumber: 1,
+                    ..InterfaceDescriptorHeader::new()
+                },
+                ENDPOINTS,
+            )
+            .with_endpoint_class_descriptors(ENDPOINT_CLASS_DESCRIPTORS),
+        ];
+        const CONFIGURATION: ConfigurationDescriptor =
+            ConfigurationDescriptor::new(ConfigurationDescriptorHeader::new(), INTERFACES)
+                .validate(Speed::High);
+
+        let bytes: Vec<u8> = CONFIGURATION.iter().copied().collect();
+        assert_eq!(usize::from(CONFIGURATION.head.wTotalLength), bytes.len());
+        assert_eq!(CONFIGURATION.head.bNumInterfaces, 2);
+
+        // walk the descriptors
+        let mut descriptors = Vec::new();
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let length = usize::from(bytes[offset]);
+            descriptors.push(&bytes[offset..offset + length]);
+            offset += length;
+        }
+        assert_eq!(offset, bytes.len());
+
+        let types: Vec<u8> = descriptors.iter().map(|d| d[1]).collect();
+        assert_eq!(
+            types,
+            [0x02, 0x0b, 0x04, 0x24, 0x24, 0x24, 0x05, 0x04, 0x05, 0x05, 0x25]
+        );
+        assert_eq!(descriptors[1], [8, 0x0b, 0, 2, 0x02, 0x02, 0x00, 4]);
+        assert_eq!(descriptors[4], [4, 0x24, 0x02, 0x02]);
+        assert_eq!(descriptors[10], [7, 0x25, 0x01, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "interface association contains unknown interface")]
+    fn test_validate_interface_association() {
+        let interfaces = [
+            interface(0, 0, &[]).with_association(InterfaceAssociationDescriptor {
+                bInterfaceCount: 2,
+                ..InterfaceAssociationDescriptor::new()
+            }),
+        ];
+        let _ = configuration(&interfaces).validate(Speed::High);
+    }
+
+    #[test]
+    fn test_super_speed_descriptors() {
+        const ENDPOINTS: &[EndpointDescriptor] = &[
+            EndpointDescriptor::bulk(0x81, 1024),
+            EndpointDescriptor::bulk(0x01, 1024),
+        ];
+        const ENDPOINT_COMPANIONS: &[SuperSpeedEndpointCompanionDescriptor] = &[
+            SuperSpeedEndpointCompanionDescriptor {
+                bMaxBurst: 15,
+                ..SuperSpeedEndpointCompanionDescriptor::new()
+            },
+            SuperSpeedEndpointCompanionDescriptor::new(),
+        ];
+        const INTERFACES: &[InterfaceDescriptor] =
+            &[
+                InterfaceDescriptor::new(InterfaceDescriptorHeader::new(), ENDPOINTS)
+                    .with_endpoint_companions(ENDPOINT_COMPANIONS),
+            ];
+        const CONFIGURATION: ConfigurationDescriptor =
+            ConfigurationDescriptor::new(ConfigurationDescriptorHeader::new(), INTERFACES)
+                .validate(Speed::Super);
+
+        let bytes: Vec<u8> = CONFIGURATION.iter().copied().collect();
+        assert_eq!(usize::from(CONFIGURATION.head.wTotalLength), bytes.len());
+        assert_eq!(bytes.len(), 9 + 9 + 2 * (7 + 6));
+        assert_eq!(bytes[18..25], [7, 0x05, 0x81, 0x02, 0x00, 0x04, 0]);
+        assert_eq!(bytes[25..31], [6, 0x30, 15, 0, 0, 0]);
+        assert_eq!(bytes[31..38], [7, 0x05, 0x01, 0x02, 0x00, 0x04, 0]);
+
+        // companions are required at super speed and not allowed otherwise
+        let interfaces = [interface(0, 0, ENDPOINTS)];
+        let result = std::panic::catch_unwind(|| configuration(&interfaces).validate(Speed::Super));
+        assert!(result.is_err());
+        let interfaces = [interface(0, 0, ENDPOINTS).with_endpoint_companions(ENDPOINT_COMPANIONS)];
+        let result = std::panic::catch_unwind(|| configuration(&interfaces).validate(Speed::High));
+        assert!(result.is_err());
+
+        const BOS: BinaryObjectStoreDescriptor = BinaryObjectStoreDescriptor::new(
+            BinaryObjectStoreDescriptorHeader::new(),
+            &[DeviceCapability::SuperSpeedUsb(SuperSpeedUsbDescriptor {
+                wSpeedsSupported: SuperSpeedUsbDescriptor::SPEED_HIGH
+                    | SuperSpeedUsbDescriptor::SPEED_SUPER,
+                bFunctionalitySupport: 2,
+                ..SuperSpeedUsbDescriptor::new()
+            })],
+        );
+        let bytes: Vec<u8> = BOS.iter().copied().collect();
+        assert_eq!(
+            bytes,
+            [5, 0x0f, 15, 0, 1, 10, 0x10, 0x03, 0, 0x0c, 0, 2, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "platform descriptor is too long")]
+    fn test_platform_descriptor_too_long() {
+        let tail = [0; 236];
+        let _ = PlatformDescriptor::new(PlatformDescriptorHeader::new(), &tail);
+    }
+
+    #[test]
+    #[should_panic(expected = "binary object store descriptor has too many device capabilities")]
+    fn test_binary_object_store_too_many_capabilities() {
+        let capabilities = [DeviceCapability::Usb20Extension(Usb20ExtensionDescriptor::new()); 256];
+        let _ = BinaryObjectStoreDescriptor::new(
+            BinaryObjectStoreDescriptorHeader::new(),
+            &capabilities,
+        );
+    }
+
+    #[test]
+    fn test_additional_transactions() {
+        let endpoint = EndpointDescriptor::interrupt(0x81, 1024, 1).with_additional_transactions(2);
+        assert_eq!({ endpoint.wMaxPacketSize }, 1024 | (2 << 11));
+        assert_eq!(endpoint.max_packet_size(), 1024);
+        assert_eq!(endpoint.additional_transactions(), 2);
+        assert!(endpoint.is_valid_max_packet_size(Speed::High));
+        assert!(!endpoint.is_valid_max_packet_size(Speed::Full));
+        assert!(!endpoint.is_valid_max_packet_size(Speed::Super));
+
+        // high-bandwidth endpoints need large enough packets
+        let endpoint = EndpointDescriptor::interrupt(0x81, 512, 1).with_additional_transactions(1);
+        assert!(!endpoint.is_valid_max_packet_size(Speed::High));
+        let endpoint = EndpointDescriptor::interrupt(0x81, 600, 1).with_additional_transactions(2);
+        assert!(!endpoint.is_valid_max_packet_size(Speed::High));
+    }
+
+    #[test]
+    fn test_string_index() {
+        assert_eq!(string_index(acm::STRING_DESCRIPTORS, acm::MANUFACTURER), 1);
+        assert_eq!(string_index(acm::STRING_DESCRIPTORS, acm::INTERFACE_1), 6);
+        assert_eq!(acm::DEVICE_DESCRIPTOR.iSerialNumber, 3);
+
+        let result =
+            std::panic::catch_unwind(|| string_index(acm::STRING_DESCRIPTORS, "iInterface"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_string_table() {
+        let mut table = StringTable::new();
+        table.insert(3, None, "0123456789abcdef").unwrap();
+        table.insert(4, None, "config").unwrap();
+        table
+            .insert(4, Some(LanguageId::German), "Konfiguration")
+            .unwrap();
+
+        // strings for a single language take precedence
+        assert_eq!(table.get(3, LanguageId::German), Some("0123456789abcdef"));
+        assert_eq!(table.get(4, LanguageId::German), Some("Konfiguration"));
+        assert_eq!(
+            table.get(4, LanguageId::EnglishUnitedStates),
+            Some("config")
+        );
+        assert_eq!(table.get(5, LanguageId::EnglishUnitedStates), None);
+        assert!(table.contains(4));
+        assert!(!table.contains(5));
+
+        // replace
+        table.insert(3, None, "fedcba9876543210").unwrap();
+        assert_eq!(table.get(3, LanguageId::German), Some("fedcba9876543210"));
+
+        // overflow
+        let string = "a string that is longer than the maximum length";
+        assert_eq!(
+            table.insert(5, None, string),
+            Err(ErrorKind::Overflow(string.len()))
+        );
+        for index in 5..10 {
+            table.insert(index, None, "").unwrap();
+        }
+        assert_eq!(table.insert(10, None, "full"), Err(ErrorKind::Overflow(4)));
+    }
+
+    #[test]
+    fn test_string_descriptor_zero_language_id() {
+        let descriptor =
+            StringDescriptorZero::new(&[LanguageId::EnglishUnitedStates, LanguageId::German]);
+        assert_eq!(descriptor.language_id(0x0407), Some(LanguageId::German));
+        assert_eq!(
+            descriptor.language_id(0x040c),
+            Some(LanguageId::EnglishUnitedStates)
+        );
+        assert_eq!(StringDescriptorZero::new(&[]).language_id(0x0409), None);
+    }
+}
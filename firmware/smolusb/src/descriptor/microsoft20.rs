@@ -0,0 +1,527 @@
+//! Microsoft OS 2.0 USB Descriptors
+//!
+//! Microsoft OS 2.0 descriptors are advertised through a platform
+//! capability in the device's Binary Object Store descriptor and then
+//! retrieved by Windows with a vendor request.
+//!
+//! The descriptor set is assembled at compile time by a
+//! [`DescriptorSetBuilder`]:
+//!
+//! ```
+//! use smolusb::descriptor::microsoft20::{DescriptorSet, DescriptorSetBuilder, PropertyDataType};
+//!
+//! const DESCRIPTOR_SET: DescriptorSet<256> = DescriptorSetBuilder::new()
+//!     .configuration(0)
+//!     .function(0)
+//!     .compatible_id(b"WINUSB\0\0", &[0; 8])
+//!     .registry_property_str(
+//!         PropertyDataType::RegMultiSz,
+//!         "DeviceInterfaceGUIDs",
+//!         "{88bae032-5a81-49f0-bc3d-a4ff138216d6}\0",
+//!     )
+//!     .build();
+//! ```
+
+use crate::descriptor::{DeviceCapability, PlatformDescriptor, PlatformDescriptorHeader};
+
+// - Constants ----------------------------------------------------------------
+
+/// Platform capability UUID `{D8DD60DF-4589-4CC7-9CD2-659D9E648A9F}`
+pub const PLATFORM_CAPABILITY_UUID: [u8; 16] = [
+    0xdf, 0x60, 0xdd, 0xd8, 0x89, 0x45, 0xc7, 0x4c, 0x9c, 0xd2, 0x65, 0x9d, 0x9e, 0x64, 0x8a, 0x9f,
+];
+
+/// Minimum Windows version supporting Microsoft OS 2.0 descriptors (Windows 8.1)
+pub const WINDOWS_VERSION_8_1: u32 = 0x0603_0000;
+
+/// Vendor Indices
+#[repr(u16)]
+#[derive(Debug, PartialEq)]
+pub enum VendorIndex {
+    DescriptorSet = 0x0007,
+    SetAlternateEnumeration = 0x0008,
+    Unknown(u16),
+}
+
+impl From<u16> for VendorIndex {
+    fn from(value: u16) -> Self {
+        match value {
+            0x0007 => VendorIndex::DescriptorSet,
+            0x0008 => VendorIndex::SetAlternateEnumeration,
+            _ => VendorIndex::Unknown(value),
+        }
+    }
+}
+
+/// Microsoft OS 2.0 descriptor types
+#[repr(u16)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DescriptorType {
+    SetHeader = 0x00,
+    SubsetHeaderConfiguration = 0x01,
+    SubsetHeaderFunction = 0x02,
+    FeatureCompatibleId = 0x03,
+    FeatureRegistryProperty = 0x04,
+    FeatureMinResumeTime = 0x05,
+    FeatureModelId = 0x06,
+    FeatureCcgpDevice = 0x07,
+    FeatureVendorRevision = 0x08,
+}
+
+/// Registry property data types
+#[repr(u16)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PropertyDataType {
+    RegSz = 1,
+    RegExpandSz = 2,
+    RegBinary = 3,
+    RegDwordLittleEndian = 4,
+    RegDwordBigEndian = 5,
+    RegLink = 6,
+    RegMultiSz = 7,
+}
+
+// - Descriptors --------------------------------------------------------------
+
+/// Microsoft OS 2.0 Descriptors
+pub struct Descriptors<'a> {
+    /// Vendor request used by the host to retrieve the descriptor set
+    pub vendor_code: u8,
+    pub descriptor_set: &'a [u8],
+}
+
+/// Returns the platform capability advertising a Microsoft OS 2.0
+/// descriptor set.
+///
+/// `capability_data` is usually obtained from [`DescriptorSet::capability_data`].
+#[must_use]
+pub const fn platform_capability(capability_data: &[u8; 8]) -> DeviceCapability<'_> {
+    DeviceCapability::Platform(PlatformDescriptor::new(
+        PlatformDescriptorHeader {
+            PlatformCapabilityUUID: PLATFORM_CAPABILITY_UUID,
+            ..PlatformDescriptorHeader::new()
+        },
+        capability_data,
+    ))
+}
+
+// - DescriptorSet ------------------------------------------------------------
+
+/// A Microsoft OS 2.0 descriptor set stored in a buffer of `N` bytes.
+#[derive(Clone, Copy)]
+pub struct DescriptorSet<const N: usize> {
+    buffer: [u8; N],
+    length: usize,
+    windows_version: u32,
+}
+
+impl<const N: usize> DescriptorSet<N> {
+    /// Returns the serialized descriptor set.
+    #[must_use]
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    /// Returns the total length of the descriptor set.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub const fn total_length(&self) -> u16 {
+        self.length as u16
+    }
+
+    /// Returns the platform capability data for the descriptor set.
+    ///
+    /// The host retrieves the descriptor set with the given vendor
+    /// request code.
+    #[must_use]
+    pub const fn capability_data(&self, vendor_code: u8) -> [u8; 8] {
+        let windows_version = self.windows_version.to_le_bytes();
+        let total_length = self.total_length().to_le_bytes();
+        [
+            windows_version[0],
+            windows_version[1],
+            windows_version[2],
+            windows_version[3],
+            total_length[0],
+            total_length[1],
+            vendor_code,
+            0, // bAltEnumCode
+        ]
+    }
+}
+
+// - DescriptorSetBuilder -----------------------------------------------------
+
+/// Compile-time builder for a Microsoft OS 2.0 descriptor set.
+///
+/// Subset and set lengths are filled in as subsets are closed so the
+/// features only need to be listed in order. Exceeding the buffer size
+/// `N` is a compile-time error when the builder is used in a `const`.
+#[derive(Clone, Copy)]
+pub struct DescriptorSetBuilder<const N: usize> {
+    set: DescriptorSet<N>,
+    configuration_offset: Option<usize>,
+    function_offset: Option<usize>,
+}
+
+impl<const N: usize> DescriptorSetBuilder<N> {
+    const SET_HEADER_LENGTH: u16 = 10;
+    const SUBSET_HEADER_LENGTH: u16 = 8;
+    const COMPATIBLE_ID_LENGTH: u16 = 20;
+
+    /// Start a new descriptor set targeting Windows 8.1 or later.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self::with_windows_version(WINDOWS_VERSION_8_1)
+    }
+
+    /// Start a new descriptor set targeting the given Windows version.
+    #[must_use]
+    pub const fn with_windows_version(windows_version: u32) -> Self {
+        let builder = Self {
+            set: DescriptorSet {
+                buffer: [0; N],
+                length: 0,
+                windows_version,
+            },
+            configuration_offset: None,
+            function_offset: None,
+        };
+        builder
+            .push_u16(Self::SET_HEADER_LENGTH)
+            .push_u16(DescriptorType::SetHeader as u16)
+            .push_u32(windows_version)
+            .push_u16(0) // wTotalLength
+    }
+
+    /// Start a configuration subset.
+    ///
+    /// Note that, despite its name, the `bConfigurationValue` field
+    /// of the subset is the zero-based configuration index.
+    #[must_use]
+    pub const fn configuration(self, configuration_index: u8) -> Self {
+        let builder = self.close_function().close_configuration();
+        let offset = builder.set.length;
+        let mut builder = builder
+            .push_u16(Self::SUBSET_HEADER_LENGTH)
+            .push_u16(DescriptorType::SubsetHeaderConfiguration as u16)
+            .push_u8(configuration_index)
+            .push_u8(0) // bReserved
+            .push_u16(0); // wTotalLength
+        builder.configuration_offset = Some(offset);
+        builder
+    }
+
+    /// Start a function subset for the given first interface.
+    #[must_use]
+    pub const fn function(self, first_interface: u8) -> Self {
+        let builder = self.close_function();
+        let offset = builder.set.length;
+        let mut builder = builder
+            .push_u16(Self::SUBSET_HEADER_LENGTH)
+            .push_u16(DescriptorType::SubsetHeaderFunction as u16)
+            .push_u8(first_interface)
+            .push_u8(0) // bReserved
+            .push_u16(0); // wSubsetLength
+        builder.function_offset = Some(offset);
+        builder
+    }
+
+    /// Add a compatible ID feature descriptor.
+    #[must_use]
+    pub const fn compatible_id(self, compatible_id: &[u8; 8], sub_compatible_id: &[u8; 8]) -> Self {
+        self.push_u16(Self::COMPATIBLE_ID_LENGTH)
+            .push_u16(DescriptorType::FeatureCompatibleId as u16)
+            .push_bytes(compatible_id)
+            .push_bytes(sub_compatible_id)
+    }
+
+    /// Add a registry property feature descriptor with raw property data.
+    #[must_use]
+    pub const fn registry_property(
+        self,
+        data_type: PropertyDataType,
+        name: &str,
+        data: &[u8],
+    ) -> Self {
+        let offset = self.set.length;
+        let builder = self
+            .push_u16(0) // wLength
+            .push_u16(DescriptorType::FeatureRegistryProperty as u16)
+            .push_u16(data_type as u16)
+            .push_u16(0) // wPropertyNameLength
+            .push_utf16(name)
+            .push_u16(0); // null terminator
+        let name_length = builder.set.length - offset - 8;
+        let builder = builder
+            .set_u16(offset + 6, name_length)
+            .push_u16(0) // wPropertyDataLength
+            .push_bytes(data);
+        builder
+            .set_u16(offset + 8 + name_length, data.len())
+            .close(offset)
+    }
+
+    /// Add a registry property feature descriptor with string property data.
+    ///
+    /// The value is encoded as UTF-16LE followed by a null terminator.
+    /// `REG_MULTI_SZ` values should separate, and end, their strings
+    /// with `\0`.
+    #[must_use]
+    pub const fn registry_property_str(
+        self,
+        data_type: PropertyDataType,
+        name: &str,
+        value: &str,
+    ) -> Self {
+        let offset = self.set.length;
+        let builder = self
+            .registry_property(data_type, name, &[])
+            .push_utf16(value)
+            .push_u16(0); // null terminator
+
+        // update wPropertyDataLength
+        let data_offset = offset + 8 + builder.get_u16(offset + 6) as usize;
+        let data_length = builder.set.length - data_offset - 2;
+        builder.set_u16(data_offset, data_length).close(offset)
+    }
+
+    /// Finish the descriptor set.
+    #[must_use]
+    pub const fn build(self) -> DescriptorSet<N> {
+        let builder = self.close_function().close_configuration();
+        let length = builder.set.length;
+        builder.set_u16(8, length).set
+    }
+
+    // - helpers --
+
+    /// Update the length of the subset or feature starting at `offset`.
+    const fn close(self, offset: usize) -> Self {
+        let length = self.set.length - offset;
+        self.set_u16(offset, length)
+    }
+
+    const fn close_function(self) -> Self {
+        if let Some(offset) = self.function_offset {
+            let mut builder = self.set_u16(offset + 6, self.set.length - offset);
+            builder.function_offset = None;
+            builder
+        } else {
+            self
+        }
+    }
+
+    const fn close_configuration(self) -> Self {
+        if let Some(offset) = self.configuration_offset {
+            let mut builder = self.set_u16(offset + 6, self.set.length - offset);
+            builder.configuration_offset = None;
+            builder
+        } else {
+            self
+        }
+    }
+
+    const fn push_u8(mut self, value: u8) -> Self {
+        assert!(
+            self.set.length < N,
+            "Microsoft OS 2.0 descriptor set buffer is too small"
+        );
+        self.set.buffer[self.set.length] = value;
+        self.set.length += 1;
+        self
+    }
+
+    const fn push_u16(self, value: u16) -> Self {
+        let bytes = value.to_le_bytes();
+        self.push_u8(bytes[0]).push_u8(bytes[1])
+    }
+
+    const fn push_u32(self, value: u32) -> Self {
+        let bytes = value.to_le_bytes();
+        self.push_u8(bytes[0])
+            .push_u8(bytes[1])
+            .push_u8(bytes[2])
+            .push_u8(bytes[3])
+    }
+
+    const fn push_bytes(self, bytes: &[u8]) -> Self {
+        let mut builder = self;
+        let mut index = 0;
+        while index < bytes.len() {
+            builder = builder.push_u8(bytes[index]);
+            index += 1;
+        }
+        builder
+    }
+
+    /// Append the string encoded as UTF-16LE.
+    const fn push_utf16(self, string: &str) -> Self {
+        let bytes = string.as_bytes();
+        let mut builder = self;
+        let mut index = 0;
+        while index < bytes.len() {
+            // decode utf-8
+            let (code_point, length) = match bytes[index] {
+                byte @ 0x00..=0x7f => (byte as u32, 1),
+                byte @ 0xc0..=0xdf => (
+                    ((byte as u32 & 0x1f) << 6) | (bytes[index + 1] as u32 & 0x3f),
+                    2,
+                ),
+                byte @ 0xe0..=0xef => (
+                    ((byte as u32 & 0x0f) << 12)
+                        | ((bytes[index + 1] as u32 & 0x3f) << 6)
+                        | (bytes[index + 2] as u32 & 0x3f),
+                    3,
+                ),
+                byte => (
+                    ((byte as u32 & 0x07) << 18)
+                        | ((bytes[index + 1] as u32 & 0x3f) << 12)
+                        | ((bytes[index + 2] as u32 & 0x3f) << 6)
+                        | (bytes[index + 3] as u32 & 0x3f),
+                    4,
+                ),
+            };
+
+            // encode utf-16
+            if code_point >= 0x1_0000 {
+                let code_point = code_point - 0x1_0000;
+                builder = builder
+                    .push_u16(0xd800 | (code_point >> 10) as u16)
+                    .push_u16(0xdc00 | (code_point & 0x3ff) as u16);
+            } else {
+                builder = builder.push_u16(code_point as u16);
+            }
+            index += length;
+        }
+        builder
+    }
+
+    const fn get_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes([self.set.buffer[offset], self.set.buffer[offset + 1]])
+    }
+
+    const fn set_u16(mut self, offset: usize, value: usize) -> Self {
+        assert!(
+            value <= u16::MAX as usize,
+            "Microsoft OS 2.0 descriptor is too long"
+        );
+        let bytes = (value as u16).to_le_bytes();
+        self.set.buffer[offset] = bytes[0];
+        self.set.buffer[offset + 1] = bytes[1];
+        self
+    }
+}
+
+impl<const N: usize> Default for DescriptorSetBuilder<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // - fixtures -------------------------------------------------------------
+
+    const DESCRIPTOR_SET: DescriptorSet<256> = DescriptorSetBuilder::new()
+        .configuration(0)
+        .function(1)
+        .compatible_id(b"WINUSB\0\0", &[0; 8])
+        .registry_property_str(
+            PropertyDataType::RegMultiSz,
+            "DeviceInterfaceGUIDs",
+            "{88bae032-5a81-49f0-bc3d-a4ff138216d6}\0",
+        )
+        .build();
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_descriptor_set() {
+        let bytes = DESCRIPTOR_SET.as_bytes();
+
+        let registry_property_length = 8 + 42 + 2 + 80;
+        let function_length = 8 + 20 + registry_property_length;
+        let configuration_length = 8 + function_length;
+        let total_length = 10 + configuration_length;
+        assert_eq!(bytes.len(), total_length);
+        assert_eq!(usize::from(DESCRIPTOR_SET.total_length()), total_length);
+
+        // set header
+        assert_eq!(bytes[..8], [10, 0, 0x00, 0, 0x00, 0x00, 0x03, 0x06]);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([bytes[8], bytes[9]])),
+            total_length
+        );
+
+        // configuration subset header
+        let configuration = &bytes[10..];
+        assert_eq!(configuration[..6], [8, 0, 0x01, 0, 0, 0]);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([configuration[6], configuration[7]])),
+            configuration_length
+        );
+
+        // function subset header
+        let function = &configuration[8..];
+        assert_eq!(function[..6], [8, 0, 0x02, 0, 1, 0]);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([function[6], function[7]])),
+            function_length
+        );
+
+        // compatible id
+        let compatible_id = &function[8..28];
+        assert_eq!(compatible_id[..4], [20, 0, 0x03, 0]);
+        assert_eq!(&compatible_id[4..12], b"WINUSB\0\0");
+
+        // registry property
+        let property = &function[28..];
+        assert_eq!(property.len(), registry_property_length);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([property[0], property[1]])),
+            registry_property_length
+        );
+        assert_eq!(property[2..8], [0x04, 0, 7, 0, 42, 0]);
+        assert_eq!(property[8..12], [b'D', 0, b'e', 0]);
+        assert_eq!(property[46..50], [b's', 0, 0, 0]);
+        assert_eq!(property[50..52], [80, 0]);
+        assert_eq!(property[52..56], [b'{', 0, b'8', 0]);
+        assert_eq!(property[registry_property_length - 4..], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_utf16_encoding() {
+        const SET: DescriptorSet<64> = DescriptorSetBuilder::new()
+            .registry_property_str(PropertyDataType::RegSz, "é", "€𝄞")
+            .build();
+        let property = &SET.as_bytes()[10..];
+        assert_eq!(property[6..8], [4, 0]);
+        assert_eq!(property[8..12], [0xe9, 0x00, 0, 0]);
+        assert_eq!(property[12..14], [8, 0]);
+        assert_eq!(property[14..], [0xac, 0x20, 0x34, 0xd8, 0x1e, 0xdd, 0, 0]);
+    }
+
+    #[test]
+    fn test_capability_data() {
+        let capability_data = DESCRIPTOR_SET.capability_data(0x42);
+        let total_length = DESCRIPTOR_SET.total_length().to_le_bytes();
+        assert_eq!(
+            capability_data,
+            [
+                0x00,
+                0x00,
+                0x03,
+                0x06,
+                total_length[0],
+                total_length[1],
+                0x42,
+                0
+            ]
+        );
+    }
+}
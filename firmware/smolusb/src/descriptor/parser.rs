@@ -0,0 +1,912 @@
+//! USB configuration descriptor parser
+//!
+//! Parses a raw configuration descriptor, such as one read back from a
+//! device, into typed views over the original bytes without allocating:
+//!
+//! ```
+//! use smolusb::class::acm;
+//! use smolusb::descriptor::parser::{self, ConfigurationView};
+//! use smolusb::device::Speed;
+//!
+//! let bytes: Vec<u8> = acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0
+//!     .iter()
+//!     .copied()
+//!     .collect();
+//!
+//! let configuration = ConfigurationView::parse(&bytes).unwrap();
+//! for interface in configuration.interfaces() {
+//!     for endpoint in interface.endpoints() {
+//!         let _ = endpoint.head.bEndpointAddress;
+//!     }
+//! }
+//!
+//! assert_eq!(parser::validate(&bytes, Speed::Full, |_| {}), 0);
+//! ```
+//!
+//! [`validate`] checks a configuration descriptor against the
+//! specification and reports every violation it finds.
+
+use core::mem::size_of;
+
+use zerocopy::FromBytes;
+
+use crate::descriptor::{
+    ClassDescriptor, ClassDescriptorHeader, ConfigurationDescriptorHeader, DescriptorType,
+    EndpointDescriptor, InterfaceAssociationDescriptor, InterfaceDescriptorHeader,
+    SuperSpeedEndpointCompanionDescriptor,
+};
+use crate::device::Speed;
+
+// - ParseError ---------------------------------------------------------------
+
+/// Errors returned when parsing a configuration descriptor.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    /// The descriptor at the given offset has a `bLength` less than two or
+    /// extends past the end of the data.
+    Truncated(usize),
+    /// The descriptor at the given offset has a `bLength` that is too short
+    /// for its `bDescriptorType`.
+    InvalidLength(usize),
+    /// The data does not start with a configuration descriptor.
+    NotAConfiguration,
+}
+
+// - Descriptor ---------------------------------------------------------------
+
+/// A single descriptor parsed from a configuration descriptor.
+#[derive(Clone, Copy)]
+pub enum Descriptor<'a> {
+    /// Configuration or other speed configuration descriptor
+    Configuration(ConfigurationDescriptorHeader),
+    InterfaceAssociation(InterfaceAssociationDescriptor),
+    Interface(InterfaceDescriptorHeader),
+    Endpoint(EndpointDescriptor),
+    SuperSpeedEndpointCompanion(SuperSpeedEndpointCompanionDescriptor),
+    /// Class-specific, vendor-specific or unrecognized descriptor
+    Class(ClassDescriptor<'a>),
+}
+
+impl<'a> Descriptor<'a> {
+    /// Parses the descriptor at `offset`, returning it along with its length.
+    fn parse_at(bytes: &'a [u8], offset: usize) -> Result<(Self, usize), ParseError> {
+        let remaining = &bytes[offset..];
+        let length = usize::from(remaining[0]);
+        if length < size_of::<ClassDescriptorHeader>() || length > remaining.len() {
+            return Err(ParseError::Truncated(offset));
+        }
+        let data = &remaining[..length];
+
+        let invalid = ParseError::InvalidLength(offset);
+        let descriptor = match DescriptorType::from(data[1]) {
+            DescriptorType::Configuration | DescriptorType::OtherSpeedConfiguration => {
+                Descriptor::Configuration(read_from_prefix(data).ok_or(invalid)?)
+            }
+            DescriptorType::InterfaceAssociation => {
+                Descriptor::InterfaceAssociation(read_from_prefix(data).ok_or(invalid)?)
+            }
+            DescriptorType::Interface => {
+                Descriptor::Interface(read_from_prefix(data).ok_or(invalid)?)
+            }
+            DescriptorType::Endpoint => {
+                Descriptor::Endpoint(read_from_prefix(data).ok_or(invalid)?)
+            }
+            DescriptorType::SuperSpeedEndpointCompanion => {
+                Descriptor::SuperSpeedEndpointCompanion(read_from_prefix(data).ok_or(invalid)?)
+            }
+            _ => Descriptor::Class(ClassDescriptor {
+                head: ClassDescriptorHeader {
+                    bLength: data[0],
+                    bDescriptorType: data[1],
+                },
+                tail: &data[size_of::<ClassDescriptorHeader>()..],
+            }),
+        };
+
+        Ok((descriptor, length))
+    }
+}
+
+fn read_from_prefix<T: FromBytes>(data: &[u8]) -> Option<T> {
+    T::read_from_prefix(data)
+}
+
+/// Iterator over the descriptors contained in a configuration descriptor.
+///
+/// Iteration ends after the first [`ParseError`].
+#[derive(Clone)]
+pub struct DescriptorIterator<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> DescriptorIterator<'a> {
+    #[must_use]
+    pub const fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, offset: 0 }
+    }
+
+    /// Returns the offset of the next descriptor.
+    #[must_use]
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Advances past all descriptors preceding the next one matching
+    /// `stop` and returns their bytes.
+    fn take_until(&mut self, stop: fn(&Descriptor) -> bool) -> &'a [u8] {
+        let start = self.offset;
+        loop {
+            let mut peek = self.clone();
+            match peek.next() {
+                Some(Ok(descriptor)) if !stop(&descriptor) => *self = peek,
+                _ => break,
+            }
+        }
+        &self.bytes[start..self.offset]
+    }
+}
+
+impl<'a> Iterator for DescriptorIterator<'a> {
+    type Item = Result<Descriptor<'a>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.bytes.len() {
+            return None;
+        }
+        match Descriptor::parse_at(self.bytes, self.offset) {
+            Ok((descriptor, length)) => {
+                self.offset += length;
+                Some(Ok(descriptor))
+            }
+            Err(e) => {
+                self.offset = self.bytes.len();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// - ConfigurationView --------------------------------------------------------
+
+/// Typed view of a raw configuration descriptor.
+#[derive(Clone, Copy)]
+pub struct ConfigurationView<'a> {
+    pub head: ConfigurationDescriptorHeader,
+    tail: &'a [u8],
+}
+
+impl<'a> ConfigurationView<'a> {
+    /// Parses `bytes` as a configuration descriptor followed by its
+    /// interface, endpoint and class descriptors.
+    ///
+    /// Any data past `wTotalLength` is ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`ParseError`] if `bytes` does not start with a
+    /// configuration descriptor, is shorter than `wTotalLength` or
+    /// contains a descriptor that can not be parsed.
+    pub fn parse(bytes: &'a [u8]) -> Result<Self, ParseError> {
+        let head = match DescriptorIterator::new(bytes).next() {
+            Some(Ok(Descriptor::Configuration(head))) => head,
+            Some(Err(e)) => return Err(e),
+            _ => return Err(ParseError::NotAConfiguration),
+        };
+
+        let head_length = usize::from(head.bLength);
+        let total_length = usize::from(head.wTotalLength);
+        if total_length > bytes.len() {
+            return Err(ParseError::Truncated(bytes.len()));
+        } else if total_length < head_length {
+            return Err(ParseError::InvalidLength(0));
+        }
+
+        let bytes = &bytes[..total_length];
+        for descriptor in DescriptorIterator::new(bytes) {
+            descriptor?;
+        }
+
+        Ok(Self {
+            head,
+            tail: &bytes[head_length..],
+        })
+    }
+
+    /// Returns an iterator over all descriptors following the configuration descriptor.
+    #[must_use]
+    pub const fn descriptors(&self) -> DescriptorIterator<'a> {
+        DescriptorIterator::new(self.tail)
+    }
+
+    /// Returns an iterator over the configuration's interfaces and their alternate settings.
+    #[must_use]
+    pub const fn interfaces(&self) -> InterfaceViewIterator<'a> {
+        InterfaceViewIterator {
+            descriptors: self.descriptors(),
+        }
+    }
+
+    /// Returns the interface for the given interface number and alternate setting.
+    #[must_use]
+    pub fn interface(
+        &self,
+        interface_number: u8,
+        alternate_setting: u8,
+    ) -> Option<InterfaceView<'a>> {
+        self.interfaces().find(|interface| {
+            interface.interface_number() == interface_number
+                && interface.alternate_setting() == alternate_setting
+        })
+    }
+}
+
+// - InterfaceView ------------------------------------------------------------
+
+/// Typed view of an interface descriptor and the descriptors following it.
+#[derive(Clone, Copy)]
+pub struct InterfaceView<'a> {
+    pub head: InterfaceDescriptorHeader,
+    association: Option<InterfaceAssociationDescriptor>,
+    tail: &'a [u8],
+}
+
+impl<'a> InterfaceView<'a> {
+    #[must_use]
+    pub const fn interface_number(&self) -> u8 {
+        self.head.iInterfaceNumber
+    }
+
+    #[must_use]
+    pub const fn alternate_setting(&self) -> u8 {
+        self.head.bAlternateSetting
+    }
+
+    /// Returns the interface association descriptor preceding the interface, if any.
+    #[must_use]
+    pub const fn association(&self) -> Option<&InterfaceAssociationDescriptor> {
+        self.association.as_ref()
+    }
+
+    /// Returns an iterator over all descriptors following the interface descriptor.
+    #[must_use]
+    pub const fn descriptors(&self) -> DescriptorIterator<'a> {
+        DescriptorIterator::new(self.tail)
+    }
+
+    /// Returns an iterator over the class-specific interface descriptors.
+    pub fn class_descriptors(&self) -> impl Iterator<Item = ClassDescriptor<'a>> {
+        self.descriptors()
+            .map_while(|descriptor| match descriptor {
+                Ok(Descriptor::Endpoint(_)) | Err(_) => None,
+                Ok(descriptor) => Some(descriptor),
+            })
+            .filter_map(|descriptor| match descriptor {
+                Descriptor::Class(descriptor) => Some(descriptor),
+                _ => None,
+            })
+    }
+
+    /// Returns an iterator over the interface's endpoints.
+    #[must_use]
+    pub const fn endpoints(&self) -> EndpointViewIterator<'a> {
+        EndpointViewIterator {
+            descriptors: self.descriptors(),
+        }
+    }
+}
+
+/// Iterator over the interfaces of a [`ConfigurationView`].
+#[derive(Clone)]
+pub struct InterfaceViewIterator<'a> {
+    descriptors: DescriptorIterator<'a>,
+}
+
+impl<'a> Iterator for InterfaceViewIterator<'a> {
+    type Item = InterfaceView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut association = None;
+        loop {
+            match self.descriptors.next()? {
+                Ok(Descriptor::InterfaceAssociation(descriptor)) => association = Some(descriptor),
+                Ok(Descriptor::Interface(head)) => {
+                    let tail = self.descriptors.take_until(|descriptor| {
+                        matches!(
+                            descriptor,
+                            Descriptor::Interface(_) | Descriptor::InterfaceAssociation(_)
+                        )
+                    });
+                    return Some(InterfaceView {
+                        head,
+                        association,
+                        tail,
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// - EndpointView -------------------------------------------------------------
+
+/// Typed view of an endpoint descriptor and the descriptors following it.
+#[derive(Clone, Copy)]
+pub struct EndpointView<'a> {
+    pub head: EndpointDescriptor,
+    companion: Option<SuperSpeedEndpointCompanionDescriptor>,
+    tail: &'a [u8],
+}
+
+impl<'a> EndpointView<'a> {
+    /// Returns the Super Speed endpoint companion descriptor, if any.
+    #[must_use]
+    pub const fn companion(&self) -> Option<&SuperSpeedEndpointCompanionDescriptor> {
+        self.companion.as_ref()
+    }
+
+    /// Returns an iterator over the class-specific endpoint descriptors.
+    pub fn class_descriptors(&self) -> impl Iterator<Item = ClassDescriptor<'a>> {
+        DescriptorIterator::new(self.tail).filter_map(|descriptor| match descriptor {
+            Ok(Descriptor::Class(descriptor)) => Some(descriptor),
+            _ => None,
+        })
+    }
+}
+
+/// Iterator over the endpoints of an [`InterfaceView`].
+#[derive(Clone)]
+pub struct EndpointViewIterator<'a> {
+    descriptors: DescriptorIterator<'a>,
+}
+
+impl<'a> Iterator for EndpointViewIterator<'a> {
+    type Item = EndpointView<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Ok(Descriptor::Endpoint(head)) = self.descriptors.next()? {
+                let mut companion = None;
+                let mut peek = self.descriptors.clone();
+                if let Some(Ok(Descriptor::SuperSpeedEndpointCompanion(descriptor))) = peek.next() {
+                    companion = Some(descriptor);
+                    self.descriptors = peek;
+                }
+                let tail = self
+                    .descriptors
+                    .take_until(|descriptor| matches!(descriptor, Descriptor::Endpoint(_)));
+                return Some(EndpointView {
+                    head,
+                    companion,
+                    tail,
+                });
+            }
+        }
+    }
+}
+
+// - validate -----------------------------------------------------------------
+
+/// USB specification violations reported by [`validate`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    /// The descriptor at `offset` has a `bLength` less than two or extends
+    /// past the end of the data. Validation stops here.
+    Truncated { offset: usize },
+    /// The data does not start with a configuration descriptor.
+    NotAConfiguration,
+    /// The descriptor at `offset` has a `bLength` that is not valid for its
+    /// `bDescriptorType`.
+    InvalidLength {
+        offset: usize,
+        descriptor_type: u8,
+        length: u8,
+    },
+    /// `wTotalLength` does not match the length of the data.
+    TotalLengthMismatch { total_length: u16, length: usize },
+    /// `bNumInterfaces` does not match the number of distinct interface numbers.
+    InterfaceCountMismatch { num_interfaces: u8, count: u8 },
+    /// `bNumEndpoints` of the interface at `offset` does not match the
+    /// number of endpoint descriptors following it.
+    EndpointCountMismatch {
+        offset: usize,
+        num_endpoints: u8,
+        count: u8,
+    },
+    /// The endpoint at `offset` has a `wMaxPacketSize` that is not valid for
+    /// its transfer type at the device speed.
+    InvalidMaxPacketSize {
+        offset: usize,
+        endpoint_address: u8,
+        max_packet_size: u16,
+    },
+}
+
+/// Checks a raw configuration descriptor against the USB specification for
+/// the given device speed, calling `report` for every [`Violation`] found.
+///
+/// Returns the number of violations.
+#[allow(clippy::cast_possible_truncation)]
+pub fn validate(bytes: &[u8], device_speed: Speed, mut report: impl FnMut(Violation)) -> usize {
+    let mut violations = 0;
+    let mut report = |violation| {
+        violations += 1;
+        report(violation);
+    };
+
+    // bitmap of interface numbers
+    let mut interface_numbers = [0_u32; 8];
+    let mut configuration: Option<ConfigurationDescriptorHeader> = None;
+    // offset, header and endpoint count of the current interface
+    let mut interface: Option<(usize, InterfaceDescriptorHeader, u8)> = None;
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let remaining = &bytes[offset..];
+        let length = usize::from(remaining[0]);
+        if length < size_of::<ClassDescriptorHeader>() || length > remaining.len() {
+            report(Violation::Truncated { offset });
+            break;
+        }
+        let data = &remaining[..length];
+        let descriptor_type = DescriptorType::from(data[1]);
+
+        let is_configuration = matches!(
+            descriptor_type,
+            DescriptorType::Configuration | DescriptorType::OtherSpeedConfiguration
+        );
+        if offset == 0 && !is_configuration {
+            report(Violation::NotAConfiguration);
+        }
+
+        let valid_lengths = valid_lengths(descriptor_type);
+        if !valid_lengths.is_empty() && !valid_lengths.contains(&length) {
+            report(Violation::InvalidLength {
+                offset,
+                descriptor_type: data[1],
+                length: data[0],
+            });
+            offset += length;
+            continue;
+        }
+
+        match descriptor_type {
+            _ if is_configuration && offset == 0 => {
+                configuration = read_from_prefix(data);
+            }
+            DescriptorType::Interface => {
+                check_endpoint_count(interface.take(), &mut report);
+                if let Some(head) = read_from_prefix::<InterfaceDescriptorHeader>(data) {
+                    let number = usize::from(head.iInterfaceNumber);
+                    interface_numbers[number / 32] |= 1 << (number % 32);
+                    interface = Some((offset, head, 0));
+                }
+            }
+            DescriptorType::Endpoint => {
+                if let Some((_, _, count)) = interface.as_mut() {
+                    *count = count.saturating_add(1);
+                }
+                if let Some(endpoint) = read_from_prefix::<EndpointDescriptor>(data) {
+                    if !endpoint.is_valid_max_packet_size(device_speed) {
+                        report(Violation::InvalidMaxPacketSize {
+                            offset,
+                            endpoint_address: endpoint.bEndpointAddress,
+                            max_packet_size: endpoint.wMaxPacketSize,
+                        });
+                    }
+                }
+            }
+            _ => (),
+        }
+
+        offset += length;
+    }
+    check_endpoint_count(interface.take(), &mut report);
+
+    if let Some(head) = configuration {
+        if usize::from(head.wTotalLength) != bytes.len() {
+            report(Violation::TotalLengthMismatch {
+                total_length: head.wTotalLength,
+                length: bytes.len(),
+            });
+        }
+        let count = interface_numbers
+            .iter()
+            .map(|bits| bits.count_ones())
+            .sum::<u32>() as u8;
+        if head.bNumInterfaces != count {
+            report(Violation::InterfaceCountMismatch {
+                num_interfaces: head.bNumInterfaces,
+                count,
+            });
+        }
+    }
+
+    violations
+}
+
+/// Returns the valid `bLength` values for a standard descriptor type or an
+/// empty slice for all other descriptor types.
+const fn valid_lengths(descriptor_type: DescriptorType) -> &'static [usize] {
+    match descriptor_type {
+        DescriptorType::Configuration
+        | DescriptorType::OtherSpeedConfiguration
+        | DescriptorType::Interface => &[9],
+        DescriptorType::InterfaceAssociation => &[8],
+        // audio class 1.0 endpoints append bRefresh and bSynchAddress
+        DescriptorType::Endpoint => &[7, 9],
+        DescriptorType::SuperSpeedEndpointCompanion => &[6],
+        _ => &[],
+    }
+}
+
+fn check_endpoint_count(
+    interface: Option<(usize, InterfaceDescriptorHeader, u8)>,
+    report: &mut impl FnMut(Violation),
+) {
+    if let Some((offset, head, count)) = interface {
+        if head.bNumEndpoints != count {
+            report(Violation::EndpointCountMismatch {
+                offset,
+                num_endpoints: head.bNumEndpoints,
+                count,
+            });
+        }
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use zerocopy::AsBytes;
+
+    use crate::class::acm;
+    use crate::descriptor::{
+        ClassSpecificDescriptor, ConfigurationDescriptor, InterfaceDescriptor,
+    };
+
+    // - fixtures -------------------------------------------------------------
+
+    fn serialize(configuration: &ConfigurationDescriptor) -> Vec<u8> {
+        configuration.iter().copied().collect()
+    }
+
+    fn violations(bytes: &[u8], device_speed: Speed) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        let count = validate(bytes, device_speed, |violation| violations.push(violation));
+        assert_eq!(count, violations.len());
+        violations
+    }
+
+    fn unparse(descriptor: &Descriptor) -> Vec<u8> {
+        match descriptor {
+            Descriptor::Configuration(d) => d.as_bytes().to_vec(),
+            Descriptor::InterfaceAssociation(d) => d.as_bytes().to_vec(),
+            Descriptor::Interface(d) => d.as_bytes().to_vec(),
+            Descriptor::Endpoint(d) => d.as_bytes().to_vec(),
+            Descriptor::SuperSpeedEndpointCompanion(d) => d.as_bytes().to_vec(),
+            Descriptor::Class(d) => d.iter().copied().collect(),
+        }
+    }
+
+    /// Checks that a configuration parses back into its descriptors.
+    fn round_trip(configuration: &ConfigurationDescriptor) {
+        let bytes = serialize(configuration);
+        let unparsed: Vec<u8> = DescriptorIterator::new(&bytes)
+            .flat_map(|descriptor| unparse(&descriptor.unwrap()))
+            .collect();
+        assert_eq!(unparsed, bytes);
+
+        let view = ConfigurationView::parse(&bytes).unwrap();
+        assert_eq!(view.head.as_bytes(), configuration.head.as_bytes());
+        assert_eq!(view.interfaces().count(), configuration.tail.len());
+
+        for (interface, expected) in view.interfaces().zip(configuration.tail) {
+            assert_eq!(interface.head.as_bytes(), expected.head.as_bytes());
+            assert!(interface.association().is_none());
+
+            let class_descriptors: Vec<u8> = interface
+                .class_descriptors()
+                .flat_map(|descriptor| descriptor.iter().copied().collect::<Vec<_>>())
+                .collect();
+            let expected_class_descriptors: Vec<u8> = expected
+                .tail1
+                .iter()
+                .flat_map(ClassSpecificDescriptor::as_bytes)
+                .copied()
+                .collect();
+            assert_eq!(class_descriptors, expected_class_descriptors);
+
+            assert_eq!(interface.endpoints().count(), expected.endpoints().len());
+            for (endpoint, expected) in interface.endpoints().zip(expected.endpoints()) {
+                assert_eq!(endpoint.head.as_bytes(), expected.as_bytes());
+                assert!(endpoint.companion().is_none());
+                assert_eq!(endpoint.class_descriptors().count(), 0);
+            }
+        }
+    }
+
+    /// Configuration descriptors of `moondancer::usb`, which only builds
+    /// for the riscv target, with the `cynthion::shared` constants
+    /// substituted.
+    mod moondancer {
+        use crate::descriptor::{
+            ConfigurationDescriptor, ConfigurationDescriptorHeader, DescriptorType,
+            EndpointDescriptor, InterfaceDescriptor, InterfaceDescriptorHeader,
+        };
+        use crate::device::Speed;
+
+        const BULK_IN_ADDRESS: u8 = 0x81;
+        const BULK_OUT_ADDRESS: u8 = 0x02;
+        const INTERFACE_SUBCLASS_MOONDANCER: u8 = 0x20;
+        const INTERFACE_PROTOCOL_MOONDANCER: u8 = 0x00;
+
+        pub static CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
+            ConfigurationDescriptor::new(
+                ConfigurationDescriptorHeader {
+                    bDescriptorType: DescriptorType::Configuration as u8,
+                    bConfigurationValue: 1,
+                    iConfiguration: 4,
+                    bmAttributes: 0x80, // 0b1000_0000 = bus-powered
+                    bMaxPower: 250,     // 250 * 2 mA = 500 mA ?
+                    ..ConfigurationDescriptorHeader::new()
+                },
+                &[
+                    InterfaceDescriptor::new(
+                        InterfaceDescriptorHeader {
+                            iInterfaceNumber: 0,
+                            bAlternateSetting: 0,
+                            bInterfaceClass: 0xff, // Vendor-specific
+                            bInterfaceSubClass: INTERFACE_SUBCLASS_MOONDANCER,
+                            bInterfaceProtocol: INTERFACE_PROTOCOL_MOONDANCER,
+                            iInterface: 5,
+                            ..InterfaceDescriptorHeader::new()
+                        },
+                        &[
+                            EndpointDescriptor::bulk(BULK_IN_ADDRESS, 512),
+                            EndpointDescriptor::bulk(BULK_OUT_ADDRESS, 512),
+                        ],
+                    ),
+                    // Apollo stub interface
+                    InterfaceDescriptor::new(
+                        InterfaceDescriptorHeader {
+                            iInterfaceNumber: 1,
+                            bAlternateSetting: 0,
+                            bInterfaceClass: 0xff, // Vendor-specific
+                            bInterfaceSubClass: 0,
+                            bInterfaceProtocol: 0,
+                            iInterface: 6,
+                            ..InterfaceDescriptorHeader::new()
+                        },
+                        &[],
+                    ),
+                ],
+            )
+            .validate(Speed::High);
+
+        pub static OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0: ConfigurationDescriptor =
+            ConfigurationDescriptor::new(
+                ConfigurationDescriptorHeader {
+                    bDescriptorType: DescriptorType::OtherSpeedConfiguration as u8,
+                    bConfigurationValue: 1,
+                    iConfiguration: 7,
+                    bmAttributes: 0x80, // 0b1000_0000 = bus-powered
+                    bMaxPower: 250,     // 250 * 2 mA = 500 mA ?
+                    ..ConfigurationDescriptorHeader::new()
+                },
+                &[
+                    // Moondancer control interface
+                    InterfaceDescriptor::new(
+                        InterfaceDescriptorHeader {
+                            iInterfaceNumber: 0,
+                            bAlternateSetting: 0,
+                            bInterfaceClass: 0xff, // Vendor-specific
+                            bInterfaceSubClass: INTERFACE_SUBCLASS_MOONDANCER,
+                            bInterfaceProtocol: INTERFACE_PROTOCOL_MOONDANCER,
+                            iInterface: 8,
+                            ..InterfaceDescriptorHeader::new()
+                        },
+                        &[
+                            EndpointDescriptor::bulk(BULK_IN_ADDRESS, 64),
+                            EndpointDescriptor::bulk(BULK_OUT_ADDRESS, 64),
+                        ],
+                    ),
+                    // Apollo stub interface
+                    InterfaceDescriptor::new(
+                        InterfaceDescriptorHeader {
+                            iInterfaceNumber: 1,
+                            bAlternateSetting: 0,
+                            bInterfaceClass: 0xff, // Vendor-specific
+                            bInterfaceSubClass: 0,
+                            bInterfaceProtocol: 0,
+                            iInterface: 9,
+                            ..InterfaceDescriptorHeader::new()
+                        },
+                        &[],
+                    ),
+                ],
+            )
+            .validate(Speed::Full);
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_round_trip() {
+        for configuration in [
+            acm::CONFIGURATION_DESCRIPTOR_0,
+            acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0,
+        ] {
+            let bytes = serialize(&configuration);
+            round_trip(&configuration);
+
+            let view = ConfigurationView::parse(&bytes).unwrap();
+            let interface = view.interface(1, 0).unwrap();
+            assert_eq!(interface.head.bInterfaceClass, 0x0a);
+            assert!(view.interface(2, 0).is_none());
+        }
+    }
+
+    #[test]
+    fn test_round_trip_moondancer() {
+        for (configuration, device_speed, max_packet_size) in [
+            (&moondancer::CONFIGURATION_DESCRIPTOR_0, Speed::High, 512),
+            (
+                &moondancer::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0,
+                Speed::Full,
+                64,
+            ),
+        ] {
+            let bytes = serialize(configuration);
+            round_trip(configuration);
+            assert_eq!(violations(&bytes, device_speed), []);
+
+            let view = ConfigurationView::parse(&bytes).unwrap();
+            let interface = view.interface(0, 0).unwrap();
+            assert_eq!(interface.head.bInterfaceClass, 0xff);
+            assert_eq!(interface.head.bInterfaceSubClass, 0x20);
+            let endpoints: Vec<(u8, u16)> = interface
+                .endpoints()
+                .map(|endpoint| (endpoint.head.bEndpointAddress, endpoint.head.wMaxPacketSize))
+                .collect();
+            assert_eq!(
+                endpoints,
+                [(0x81, max_packet_size), (0x02, max_packet_size)]
+            );
+
+            // Apollo stub interface
+            let interface = view.interface(1, 0).unwrap();
+            assert_eq!(interface.endpoints().count(), 0);
+            assert!(view.interface(2, 0).is_none());
+        }
+
+        // the high-speed bulk endpoints are invalid at full speed
+        let bytes = serialize(&moondancer::CONFIGURATION_DESCRIPTOR_0);
+        assert_eq!(violations(&bytes, Speed::Full).len(), 2);
+    }
+
+    #[test]
+    fn test_validate() {
+        let bytes = serialize(&acm::CONFIGURATION_DESCRIPTOR_0);
+        assert_eq!(violations(&bytes, Speed::High), []);
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [
+                Violation::InvalidMaxPacketSize {
+                    offset: 49,
+                    endpoint_address: 0x84,
+                    max_packet_size: 512,
+                },
+                Violation::InvalidMaxPacketSize {
+                    offset: 56,
+                    endpoint_address: 0x04,
+                    max_packet_size: 512,
+                },
+            ]
+        );
+
+        let bytes = serialize(&acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0);
+        assert_eq!(violations(&bytes, Speed::Full), []);
+    }
+
+    #[test]
+    fn test_validate_violations() {
+        let valid = serialize(&acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0);
+
+        // wTotalLength
+        let mut bytes = valid.clone();
+        bytes[2] += 1;
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [Violation::TotalLengthMismatch {
+                total_length: 64,
+                length: 63,
+            }]
+        );
+        assert_eq!(
+            ConfigurationView::parse(&bytes).err(),
+            Some(ParseError::Truncated(63))
+        );
+
+        // bNumInterfaces
+        let mut bytes = valid.clone();
+        bytes[4] = 3;
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [Violation::InterfaceCountMismatch {
+                num_interfaces: 3,
+                count: 2,
+            }]
+        );
+
+        // bNumEndpoints of interface #1
+        let mut bytes = valid.clone();
+        bytes[40 + 4] = 1;
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [Violation::EndpointCountMismatch {
+                offset: 40,
+                num_endpoints: 1,
+                count: 2,
+            }]
+        );
+
+        // bLength of the notification endpoint, which also swallows the data interface
+        let mut bytes = valid.clone();
+        bytes[33] = 16;
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [
+                Violation::InvalidLength {
+                    offset: 33,
+                    descriptor_type: DescriptorType::Endpoint as u8,
+                    length: 16,
+                },
+                Violation::EndpointCountMismatch {
+                    offset: 9,
+                    num_endpoints: 1,
+                    count: 2,
+                },
+                Violation::InterfaceCountMismatch {
+                    num_interfaces: 2,
+                    count: 1,
+                },
+            ]
+        );
+
+        // bLength past the end of the data
+        let mut bytes = valid.clone();
+        bytes[56] = 8;
+        assert_eq!(
+            violations(&bytes, Speed::Full),
+            [
+                Violation::Truncated { offset: 56 },
+                Violation::EndpointCountMismatch {
+                    offset: 40,
+                    num_endpoints: 2,
+                    count: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            ConfigurationView::parse(&bytes).err(),
+            Some(ParseError::Truncated(56))
+        );
+
+        // not a configuration
+        let interface = InterfaceDescriptor::new(InterfaceDescriptorHeader::new(), &[]);
+        let bytes: Vec<u8> = interface.iter().copied().collect();
+        assert_eq!(
+            violations(&bytes, Speed::High),
+            [Violation::NotAConfiguration]
+        );
+        assert_eq!(
+            ConfigurationView::parse(&bytes).err(),
+            Some(ParseError::NotAConfiguration)
+        );
+    }
+}
@@ -1,12 +1,17 @@
 //! `smolusb` device types
 //!
 
-use crate::descriptor::microsoft10;
+use crate::class::{is_event_target, is_recipient, UsbClass};
+use crate::control::Control;
+use crate::descriptor::{microsoft10, microsoft20};
 use crate::descriptor::{
-    ConfigurationDescriptor, DescriptorType, DeviceDescriptor, DeviceQualifierDescriptor,
-    StringDescriptor, StringDescriptorNumber, StringDescriptorZero,
+    BinaryObjectStoreDescriptor, ConfigurationDescriptor, DescriptorType, DeviceDescriptor,
+    DeviceQualifierDescriptor, StringDescriptor, StringDescriptorNumber, StringDescriptorZero,
+    StringTable,
 };
-use crate::setup::SetupPacket;
+use crate::error::ErrorKind;
+use crate::event::UsbEvent;
+use crate::setup::{DescriptorRequest, Direction, Request, RequestType, SetupPacket};
 use crate::traits::{AsByteSliceIterator, UsbDriver};
 use log::{debug, trace, warn};
 
@@ -15,53 +20,99 @@ pub struct Descriptors<'a> {
     // required
     pub device_speed: Speed,
     pub device_descriptor: DeviceDescriptor,
-    pub configuration_descriptor: ConfigurationDescriptor<'a>,
+    /// Device configurations, indexed by `GET_DESCRIPTOR(Configuration, index)`
+    pub configuration_descriptors: &'a [ConfigurationDescriptor<'a>],
     pub string_descriptor_zero: StringDescriptorZero<'a>,
     pub string_descriptors: &'a [&'a StringDescriptor<'a>],
     // optional
+    /// Strings generated at runtime, these take precedence over `string_descriptors`
+    pub string_table: StringTable,
     pub device_qualifier_descriptor: Option<DeviceQualifierDescriptor>,
-    pub other_speed_configuration_descriptor: Option<ConfigurationDescriptor<'a>>,
+    /// Other-speed configurations, indexed like `configuration_descriptors`
+    pub other_speed_configuration_descriptors: Option<&'a [ConfigurationDescriptor<'a>]>,
+    /// Required for devices with a `bcdUSB` of 0x0201 or greater
+    pub bos_descriptor: Option<BinaryObjectStoreDescriptor<'a>>,
     pub microsoft10: Option<microsoft10::Descriptors<'a>>,
+    pub microsoft20: Option<microsoft20::Descriptors<'a>>,
 }
 
 impl<'a> Descriptors<'a> {
-    /// Calculates the total length of the descriptor and returns an updated instance.
-    ///
-    /// TODO ugly hack because I haven't figured out how to do this at compile time yet
+    /// Returns the configuration descriptor with the given `bConfigurationValue`.
     #[must_use]
-    pub fn set_total_lengths(mut self) -> Self {
-        self.configuration_descriptor.set_total_length();
-        if let Some(other_speed_configuration_descriptor) =
-            self.other_speed_configuration_descriptor.as_mut()
-        {
-            other_speed_configuration_descriptor.set_total_length();
-        }
-        self
+    pub fn configuration(&self, configuration_value: u8) -> Option<&ConfigurationDescriptor<'a>> {
+        self.configuration_descriptors
+            .iter()
+            .find(|descriptor| descriptor.head.bConfigurationValue == configuration_value)
     }
 
     /// Writes the descriptor corresponding to the request.
     ///
     /// Returns the given [`SetupPacket`] if the descriptor request could not be handled.
-    #[allow(clippy::too_many_lines)] // ...and sometimes clippy has opinions it should keep to itself!
     pub fn write<D>(
         &self,
         usb: &D,
         endpoint_number: u8,
         setup_packet: SetupPacket,
     ) -> Option<SetupPacket>
+    where
+        D: UsbDriver,
+    {
+        match self.try_write(usb, endpoint_number, setup_packet) {
+            Ok(unhandled) => unhandled,
+            Err(e) => {
+                warn!("Descriptors::write_descriptor() - write failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Writes the descriptor corresponding to the request.
+    ///
+    /// Returns the given [`SetupPacket`] if the descriptor request could not be handled.
+    ///
+    /// Requests for descriptors that do not exist are stalled and consumed.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`ErrorKind::Timeout`] if the host did not collect the descriptor.
+    pub fn try_write<D>(
+        &self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+    ) -> Result<Option<SetupPacket>, ErrorKind>
+    where
+        D: UsbDriver,
+    {
+        match self.try_respond(usb, endpoint_number, setup_packet)? {
+            DescriptorResponse::Written | DescriptorResponse::Stalled => Ok(None),
+            DescriptorResponse::Unhandled(setup_packet) => Ok(Some(setup_packet)),
+        }
+    }
+
+    #[allow(clippy::too_many_lines)] // ...and sometimes clippy has opinions it should keep to itself!
+    pub(crate) fn try_respond<D>(
+        &self,
+        usb: &D,
+        endpoint_number: u8,
+        setup_packet: SetupPacket,
+    ) -> Result<DescriptorResponse, ErrorKind>
     where
         D: UsbDriver,
     {
         // extract the descriptor type and number from our SETUP request
-        let [descriptor_number, descriptor_type_bits] = setup_packet.value.to_le_bytes();
-        let descriptor_type = DescriptorType::from(descriptor_type_bits);
+        let DescriptorRequest {
+            descriptor_type,
+            descriptor_index: descriptor_number,
+            ..
+        } = setup_packet.descriptor_request();
 
         // if the host is requesting less than the maximum amount of data,
         // only respond with the amount requested
-        let requested_length = setup_packet.length as usize;
+        let requested_length = setup_packet.length() as usize;
 
         let bytes_written = match (&descriptor_type, descriptor_number) {
-            (DescriptorType::Device, 0) => usb.write_requested(
+            (DescriptorType::Device, 0) => usb.try_write_requested(
                 endpoint_number,
                 requested_length,
                 self.device_descriptor
@@ -69,18 +120,22 @@ impl<'a> Descriptors<'a> {
                     .copied()
                     .take(requested_length),
             ),
-            (DescriptorType::Configuration, _) => usb.write_requested(
-                endpoint_number,
-                requested_length,
-                self.configuration_descriptor
-                    .iter()
-                    .copied()
-                    .take(requested_length),
-            ),
+            (DescriptorType::Configuration, index) => {
+                if let Some(descriptor) = self.configuration_descriptors.get(usize::from(index)) {
+                    write_configuration(usb, endpoint_number, requested_length, *descriptor)
+                } else {
+                    warn!(
+                        "Descriptors::write_descriptor() - unknown configuration descriptor {}",
+                        index
+                    );
+                    usb.stall_endpoint_in(endpoint_number);
+                    return Ok(DescriptorResponse::Stalled);
+                }
+            }
             (DescriptorType::DeviceQualifier, _) => {
                 if self.device_speed == Speed::High {
                     if let Some(descriptor) = &self.device_qualifier_descriptor {
-                        usb.write_requested(
+                        usb.try_write_requested(
                             endpoint_number,
                             requested_length,
                             descriptor.as_iter().copied().take(requested_length),
@@ -88,7 +143,7 @@ impl<'a> Descriptors<'a> {
                     } else {
                         // no device qualifier configured, ack HostToDevice instead - TODO check check on mac/windows
                         debug!("  No device qualifier configured for high-speed device");
-                        usb.write(endpoint_number, [].into_iter())
+                        usb.try_write(endpoint_number, [].into_iter())
                     }
                 } else {
                     // for full/low speed devices, ack HostToDevice instead - TODO check on mac/windows
@@ -96,24 +151,36 @@ impl<'a> Descriptors<'a> {
                         "  Device qualifier request is not supported for full/low-speed devices"
                     );
                     // FIXME we should stall instead
-                    usb.write(endpoint_number, [].into_iter())
+                    usb.try_write(endpoint_number, [].into_iter())
                 }
             }
-            (DescriptorType::OtherSpeedConfiguration, _) => {
-                if let Some(descriptor) = self.other_speed_configuration_descriptor {
-                    usb.write_requested(
+            (DescriptorType::OtherSpeedConfiguration, index) => {
+                if let Some(descriptor) = self
+                    .other_speed_configuration_descriptors
+                    .and_then(|descriptors| descriptors.get(usize::from(index)))
+                {
+                    write_configuration(usb, endpoint_number, requested_length, *descriptor)
+                } else {
+                    // no other speed configuration, ack HostToDevice instead - TODO check check on mac/windows
+                    debug!("  Descriptors::write_descriptor() - no other speed configuration descriptor configured");
+                    // FIXME we should stall instead
+                    usb.try_write(endpoint_number, [].into_iter())
+                }
+            }
+            (DescriptorType::BinaryDeviceObjectStore, 0) => {
+                if let Some(descriptor) = &self.bos_descriptor {
+                    usb.try_write_requested(
                         endpoint_number,
                         requested_length,
                         descriptor.iter().copied().take(requested_length),
                     )
                 } else {
-                    // no other speed configuration, ack HostToDevice instead - TODO check check on mac/windows
-                    debug!("  Descriptors::write_descriptor() - no other speed configuration descriptor configured");
-                    // FIXME we should stall instead
-                    usb.write(endpoint_number, [].into_iter())
+                    warn!("Descriptors::write_descriptor() - no bos descriptor defined");
+                    usb.stall_endpoint_in(endpoint_number);
+                    return Ok(DescriptorResponse::Stalled);
                 }
             }
-            (DescriptorType::String, StringDescriptorNumber::Zero) => usb.write_requested(
+            (DescriptorType::String, StringDescriptorNumber::Zero) => usb.try_write_requested(
                 endpoint_number,
                 requested_length,
                 self.string_descriptor_zero
@@ -123,7 +190,7 @@ impl<'a> Descriptors<'a> {
             ),
             (DescriptorType::String, StringDescriptorNumber::Microsoft) => {
                 match &self.microsoft10 {
-                    Some(descriptors) => usb.write_requested(
+                    Some(descriptors) => usb.try_write_requested(
                         endpoint_number,
                         requested_length,
                         descriptors.string_descriptor.iter(),
@@ -133,25 +200,43 @@ impl<'a> Descriptors<'a> {
                             "Descriptors::write_descriptor() - no ms os 1.0 string descriptor defined",
                         );
                         usb.stall_endpoint_in(endpoint_number);
-                        return Some(setup_packet);
+                        return Ok(DescriptorResponse::Stalled);
                     }
                 }
             }
             (DescriptorType::String, number) => {
+                let string = self
+                    .string_descriptor_zero
+                    .language_id(setup_packet.index())
+                    .and_then(|language_id| self.string_table.get(number, language_id));
                 let offset_index: usize = (number - 1).into();
-                if offset_index > self.string_descriptors.len() {
-                    warn!(
-                        "Descriptors::write_descriptor() - unknown string descriptor {}",
-                        number
-                    );
-                    return Some(setup_packet);
-                }
-                usb.write_requested(
+                let descriptor = match string {
+                    Some(string) => StringDescriptor::new(string),
+                    None if self.string_table.contains(number) => {
+                        warn!(
+                            "Descriptors::write_descriptor() - no string descriptor {} for language {:#06x}",
+                            number,
+                            setup_packet.index()
+                        );
+                        usb.stall_endpoint_in(endpoint_number);
+                        return Ok(DescriptorResponse::Stalled);
+                    }
+                    None if offset_index < self.string_descriptors.len() => {
+                        *self.string_descriptors[offset_index]
+                    }
+                    None => {
+                        warn!(
+                            "Descriptors::write_descriptor() - unknown string descriptor {}",
+                            number
+                        );
+                        usb.stall_endpoint_in(endpoint_number);
+                        return Ok(DescriptorResponse::Stalled);
+                    }
+                };
+                usb.try_write_requested(
                     endpoint_number,
                     requested_length,
-                    self.string_descriptors[offset_index]
-                        .iter()
-                        .take(requested_length),
+                    descriptor.iter().take(requested_length),
                 )
             }
             _ => {
@@ -159,14 +244,167 @@ impl<'a> Descriptors<'a> {
                     "  Descriptors::write_descriptor() - unhandled descriptor request {:?}, {}",
                     descriptor_type, descriptor_number
                 );
-                return Some(setup_packet);
+                return Ok(DescriptorResponse::Unhandled(setup_packet));
             }
-        };
+        }?;
 
         trace!("  wrote {} byte descriptor", bytes_written);
 
-        // consumed
-        None
+        Ok(DescriptorResponse::Written)
+    }
+}
+
+/// How [`Descriptors`] responded to a descriptor request.
+pub(crate) enum DescriptorResponse {
+    /// The descriptor was written.
+    Written,
+    /// The descriptor does not exist and the request was stalled.
+    Stalled,
+    /// The request was not handled.
+    Unhandled(SetupPacket),
+}
+
+/// Writes a configuration descriptor.
+fn write_configuration<D>(
+    usb: &D,
+    endpoint_number: u8,
+    requested_length: usize,
+    descriptor: ConfigurationDescriptor,
+) -> Result<usize, ErrorKind>
+where
+    D: UsbDriver,
+{
+    usb.try_write_requested(
+        endpoint_number,
+        requested_length,
+        descriptor.iter().copied().take(requested_length),
+    )
+}
+
+/// A USB device made up of a [`Control`] endpoint and the classes
+/// implementing its functions.
+///
+/// Standard requests are handled by [`Control`], all other requests
+/// are routed by recipient to the class owning the addressed interface
+/// or endpoint. Endpoint events are routed to the class owning the
+/// endpoint and bus events, such as a bus reset or suspend, are
+/// dispatched to every class.
+///
+/// Composite devices are assembled by passing a tuple of classes, e.g.
+/// `UsbDevice::new(control, (serial, keyboard))`.
+pub struct UsbDevice<'a, D, C, const RX_BUFFER_SIZE: usize> {
+    control: Control<'a, D, RX_BUFFER_SIZE>,
+    classes: C,
+}
+
+impl<'a, D, C, const RX_BUFFER_SIZE: usize> UsbDevice<'a, D, C, RX_BUFFER_SIZE>
+where
+    D: UsbDriver,
+    C: UsbClass<D>,
+{
+    #[must_use]
+    pub fn new(control: Control<'a, D, RX_BUFFER_SIZE>, classes: C) -> Self {
+        Self { control, classes }
+    }
+
+    /// Returns a reference to the control endpoint.
+    #[must_use]
+    pub fn control(&self) -> &Control<'a, D, RX_BUFFER_SIZE> {
+        &self.control
+    }
+
+    /// Returns a reference to the registered classes.
+    #[must_use]
+    pub fn classes(&self) -> &C {
+        &self.classes
+    }
+
+    /// Returns a mutable reference to the registered classes.
+    pub fn classes_mut(&mut self) -> &mut C {
+        &mut self.classes
+    }
+
+    /// Dispatches an interrupt event generated by the USB peripheral.
+    ///
+    /// Returns the last [`SetupPacket`] received if it could not be
+    /// handled by either the [`Control`] endpoint or any of the
+    /// classes. The application is responsible for responding to, or
+    /// stalling, the request.
+    pub fn dispatch_event(&mut self, usb: &D, event: UsbEvent) -> Option<SetupPacket> {
+        let control_endpoint_number = self.control.endpoint_number();
+
+        match event {
+            UsbEvent::BusReset | UsbEvent::Suspend | UsbEvent::Resume => {
+                self.control.dispatch_event(usb, event);
+                self.classes.dispatch_event(usb, event);
+                None
+            }
+            UsbEvent::ReceiveControl(endpoint_number)
+            | UsbEvent::ReceiveSetupPacket(endpoint_number, _)
+            | UsbEvent::ReceivePacket(endpoint_number)
+            | UsbEvent::SendComplete(endpoint_number)
+                if endpoint_number == control_endpoint_number =>
+            {
+                let unhandled = self.control.dispatch_event(usb, event);
+                if let UsbEvent::ReceiveSetupPacket(_, setup_packet) = event {
+                    self.notify_classes(usb, setup_packet);
+                }
+                let setup_packet = unhandled?;
+
+                // only pass on the data stage of requests that have one
+                let data = if setup_packet.direction() == Direction::HostToDevice
+                    && setup_packet.length() > 0
+                {
+                    self.control.data()
+                } else {
+                    &[]
+                };
+
+                if is_recipient(&self.classes, setup_packet)
+                    && self.classes.handle_setup_packet(
+                        usb,
+                        control_endpoint_number,
+                        setup_packet,
+                        data,
+                    )
+                {
+                    None
+                } else {
+                    Some(setup_packet)
+                }
+            }
+            event => {
+                if is_event_target(&self.classes, event) {
+                    self.classes.dispatch_event(usb, event);
+                }
+                None
+            }
+        }
+    }
+
+    /// Notifies the classes of configuration and alternate setting
+    /// changes accepted by the [`Control`] endpoint.
+    fn notify_classes(&mut self, usb: &D, setup_packet: SetupPacket) {
+        if setup_packet.request_type() != RequestType::Standard {
+            return;
+        }
+        let value = setup_packet.value().to_le_bytes()[0];
+
+        match setup_packet.request() {
+            Request::SetConfiguration if self.control.configuration().unwrap_or(0) == value => {
+                self.classes.set_configuration(usb, value);
+            }
+            Request::SetInterface => {
+                let interface_number = setup_packet.interface_number();
+                if self.classes.has_interface(interface_number)
+                    && self.control.alternate_setting(interface_number) == Some(value)
+                {
+                    self.classes
+                        .set_alternate_setting(usb, interface_number, value);
+                }
+            }
+            _ => (),
+        }
     }
 }
 
@@ -229,3 +467,144 @@ impl Speed {
         }
     }
 }
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::class::acm::{self, AcmSerial, LineCoding};
+    use crate::class::hid::{Hid, BOOT_KEYBOARD_REPORT_DESCRIPTOR};
+    use crate::setup::Recipient;
+    use crate::testing::{Operation, SimulatedDriver};
+
+    // - fixtures -------------------------------------------------------------
+
+    type Serial = AcmSerial<64, 64>;
+    type Keyboard = Hid<'static, 8, 4>;
+    type Device = UsbDevice<'static, SimulatedDriver, (Serial, Keyboard), 64>;
+
+    fn setup() -> (SimulatedDriver, Device) {
+        let usb = SimulatedDriver::new(Speed::Full);
+        let control = Control::new(
+            0,
+            Descriptors {
+                device_speed: Speed::Full,
+                device_descriptor: acm::DEVICE_DESCRIPTOR,
+                configuration_descriptors: acm::OTHER_SPEED_CONFIGURATION_DESCRIPTORS,
+                other_speed_configuration_descriptors: None,
+                device_qualifier_descriptor: None,
+                string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
+                string_descriptors: acm::STRING_DESCRIPTORS,
+                string_table: StringTable::new(),
+                bos_descriptor: None,
+                microsoft10: None,
+                microsoft20: None,
+            },
+        );
+        let serial = Serial::new(0, 3, 4).with_max_packet_size(64);
+        let keyboard = Keyboard::new(2, 1, BOOT_KEYBOARD_REPORT_DESCRIPTOR);
+        let mut device = UsbDevice::new(control, (serial, keyboard));
+        usb.host_bus_reset();
+        dispatch(&usb, &mut device);
+        usb.take_operations();
+        (usb, device)
+    }
+
+    /// Dispatches all pending events and returns the unhandled setup packets.
+    fn dispatch(usb: &SimulatedDriver, device: &mut Device) -> Vec<SetupPacket> {
+        let mut unhandled = Vec::new();
+        while let Some(event) = usb.next_event() {
+            if let Some(setup_packet) = device.dispatch_event(usb, event) {
+                unhandled.push(setup_packet);
+            }
+        }
+        unhandled
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_usb_device_routes_requests() {
+        let (usb, mut device) = setup();
+
+        // classes are notified of the configuration selected by the host
+        usb.host_setup(0, SetupPacket::set_configuration(1));
+        assert!(dispatch(&usb, &mut device).is_empty());
+        assert_eq!(device.control().configuration(), Some(1));
+        assert!(usb.take_operations().contains(&Operation::Prime(4)));
+
+        // class requests are routed by interface number
+        let line_coding = LineCoding::new(9600, 0, 2, 7);
+        usb.host_setup(
+            0,
+            SetupPacket::new(
+                SetupPacket::request_type_bits(
+                    Direction::HostToDevice,
+                    RequestType::Class,
+                    Recipient::Interface,
+                ),
+                acm::serial::ClassRequest::SetLineCoding.into(),
+                0,
+                0,
+                7,
+            ),
+        );
+        dispatch(&usb, &mut device);
+        usb.host_out(0, zerocopy::AsBytes::as_bytes(&line_coding));
+        assert!(dispatch(&usb, &mut device).is_empty());
+        assert_eq!(device.classes().0.line_coding(), line_coding);
+        usb.take_operations();
+
+        // standard requests not handled by control are routed as well
+        let setup_packet = SetupPacket::new(0x81, Request::GetDescriptor.into(), 0x2200, 2, 0xff);
+        usb.host_setup(0, setup_packet);
+        assert!(dispatch(&usb, &mut device).is_empty());
+        assert_eq!(usb.data_written(0), BOOT_KEYBOARD_REPORT_DESCRIPTOR);
+        usb.host_out(0, &[]);
+        dispatch(&usb, &mut device);
+        usb.take_operations();
+
+        // requests for unknown interfaces or handled by no class are returned
+        let unknown_interface =
+            SetupPacket::new(0x81, Request::GetDescriptor.into(), 0x2200, 5, 0xff);
+        let vendor_request = SetupPacket::new(0xc0, 0x42, 0, 0, 4);
+        usb.host_setup(0, unknown_interface);
+        usb.host_setup(0, vendor_request);
+        assert_eq!(
+            dispatch(&usb, &mut device),
+            [unknown_interface, vendor_request]
+        );
+        assert!(usb.packets_written(0).is_empty());
+    }
+
+    #[test]
+    fn test_usb_device_routes_events() {
+        let (usb, mut device) = setup();
+
+        // send completions are only dispatched to the class owning the endpoint
+        let keyboard = &mut device.classes_mut().1;
+        assert!(keyboard.send_report(&usb, &[0, 0, 4, 0, 0, 0, 0, 0]));
+        assert!(keyboard.send_report(&usb, &[0; 8]));
+        assert_eq!(keyboard.queued_reports(), 1);
+        dispatch(&usb, &mut device);
+        assert_eq!(device.classes().1.queued_reports(), 0);
+        assert_eq!(usb.packets_written(1).len(), 2);
+
+        // packets received on the data endpoint are dispatched to the serial port
+        usb.host_out(4, b"hello");
+        dispatch(&usb, &mut device);
+        let mut buffer = [0; 8];
+        let bytes_read = device.classes_mut().0.read(&usb, &mut buffer);
+        assert_eq!(&buffer[..bytes_read], b"hello");
+
+        // bus resets are dispatched to the control endpoint and every class
+        usb.host_setup(0, SetupPacket::set_configuration(1));
+        dispatch(&usb, &mut device);
+        usb.host_bus_reset();
+        dispatch(&usb, &mut device);
+        assert_eq!(device.control().configuration(), None);
+        assert_eq!(device.classes().1.queued_reports(), 0);
+    }
+}
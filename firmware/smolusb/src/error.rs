@@ -2,10 +2,24 @@
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
 pub enum ErrorKind {
+    /// A blocking operation timed out after transferring the given
+    /// number of bytes.
     Timeout(usize),
+    /// Data of the given length, such as a received packet, did not
+    /// fit in the available storage.
     Overflow(usize),
 }
 
+impl ErrorKind {
+    /// Returns the number of bytes associated with the error.
+    #[must_use]
+    pub const fn byte_count(self) -> usize {
+        match self {
+            ErrorKind::Timeout(count) | ErrorKind::Overflow(count) => count,
+        }
+    }
+}
+
 impl core::fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         core::fmt::Debug::fmt(&self, f)
@@ -22,6 +22,19 @@ pub enum UsbEvent {
     /// Contents is (`endpoint_number`)
     SendComplete(u8) = 13,
 
+    /// Received a start-of-frame packet
+    ///
+    /// Contents is (`frame_number`)
+    StartOfFrame(u16) = 14,
+
+    /// The bus has been idle for more than 3ms and the device must
+    /// enter the suspended state
+    Suspend = 15,
+
+    /// The host, or the device through remote wakeup, resumed bus
+    /// activity after a suspend
+    Resume = 16,
+
     /// Received a setup packet on `USBx_EP_CONTROL`
     ///
     /// An alternate version of `ReceiveControl` that can be used
@@ -57,6 +70,15 @@ impl core::fmt::Debug for UsbEvent {
             UsbEvent::SendComplete(endpoint) => {
                 write!(f, "SendComplete({endpoint})")
             }
+            UsbEvent::StartOfFrame(frame_number) => {
+                write!(f, "StartOfFrame({frame_number})")
+            }
+            UsbEvent::Suspend => {
+                write!(f, "Suspend")
+            }
+            UsbEvent::Resume => {
+                write!(f, "Resume")
+            }
             UsbEvent::ReceiveSetupPacket(endpoint, setup_packet) => {
                 write!(f, "ReceiveSetupPacket({endpoint}, {setup_packet:?})")
             }
@@ -75,6 +97,9 @@ impl From<UsbEvent> for u8 {
             UsbEvent::ReceiveControl(_) => 11,
             UsbEvent::ReceivePacket(_) => 12,
             UsbEvent::SendComplete(_) => 13,
+            UsbEvent::StartOfFrame(_) => 14,
+            UsbEvent::Suspend => 15,
+            UsbEvent::Resume => 16,
             UsbEvent::ReceiveSetupPacket(_, _) => 201,
             #[cfg(feature = "chonky_events")]
             UsbEvent::ReceiveBuffer(_, _, _) => 202,
@@ -93,6 +118,9 @@ impl core::convert::From<UsbEvent> for [u8; 2] {
             #[cfg(feature = "chonky_events")]
             ReceiveBuffer(endpoint_number, _, _) => [event.into(), endpoint_number],
             SendComplete(endpoint_number) => [event.into(), endpoint_number],
+            StartOfFrame(_frame_number) => [event.into(), 0],
+            Suspend => [event.into(), 0],
+            Resume => [event.into(), 0],
         }
     }
 }
@@ -1,9 +1,10 @@
 #![cfg_attr(feature = "nightly", feature(error_in_core))]
 #![cfg_attr(feature = "nightly", feature(panic_info_message))]
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 
 //! A simple peripheral-level USB stack designed for [`luna-soc`](https://github.com/greatscottgadgets/luna-soc/) USB peripherals.
 
+pub mod asynch;
 pub mod class;
 pub mod control;
 pub mod descriptor;
@@ -11,11 +12,16 @@ pub mod device;
 pub mod error;
 pub mod event;
 pub mod setup;
+#[cfg(any(test, feature = "std"))]
+pub mod testing;
 pub mod traits;
 
 /// USB devices can define up to 32 endpoints. 16 IN and 16 OUT.
 pub const EP_MAX_ENDPOINTS: usize = 16;
 
+/// Maximum number of interfaces per configuration that can have alternate settings.
+pub const MAX_INTERFACES: usize = 16;
+
 /// Maximum packet size for endpoints.
 pub const EP_MAX_PACKET_SIZE: usize = 512;
 
@@ -26,6 +32,8 @@ pub fn max_packet_size(device_speed: device::Speed, endpoint_number: u8) -> usiz
         (device::Speed::High, _) => EP_MAX_PACKET_SIZE,
         (device::Speed::Full, _) => 64,
         (device::Speed::Low, _) => 8,
+        // Super Speed devices are emulated over a high-speed link
+        (device::Speed::Super | device::Speed::SuperPlus, _) => EP_MAX_PACKET_SIZE,
         (_, _) => {
             log::warn!("Unsupported device speed: {:?}", device_speed);
             64
@@ -1,8 +1,13 @@
 //! Types for working with the SETUP packet.
 
+use zerocopy::byteorder::{LittleEndian, U16};
+use zerocopy::{AsBytes, FromBytes, FromZeroes};
+
+use crate::descriptor::DescriptorType;
+
 /// Represents a USB setup packet.
+#[derive(AsBytes, FromBytes, FromZeroes, Clone, Copy, Default, Debug, PartialEq, Eq)]
 #[repr(C)]
-#[derive(Clone, Copy, Default, Debug)]
 pub struct SetupPacket {
     // 0..4 Recipient: 0=Device, 1=Interface, 2=Endpoint, 3=Other, 4-31=Reserved
     // 5..6 Type: 0=Standard, 1=Class, 2=Vendor, 3=Reserved
@@ -10,28 +15,234 @@ pub struct SetupPacket {
     pub request_type: u8,
     // values 0..=9 are standard, others are class or vendor
     pub request: u8,
-    pub value: u16,
-    pub index: u16,
-    pub length: u16,
+    pub value: U16<LittleEndian>,
+    pub index: U16<LittleEndian>,
+    pub length: U16<LittleEndian>,
 }
 
 impl From<[u8; 8]> for SetupPacket {
     fn from(buffer: [u8; 8]) -> Self {
-        // Deserialize into a SetupRequest in the most cursed manner available to us
-        // TODO do this properly
-        unsafe { core::mem::transmute::<[u8; 8], SetupPacket>(buffer) }
+        zerocopy::transmute!(buffer)
+    }
+}
+
+impl From<SetupPacket> for [u8; 8] {
+    fn from(setup_packet: SetupPacket) -> Self {
+        zerocopy::transmute!(setup_packet)
     }
 }
 
 impl SetupPacket {
     #[must_use]
     pub fn as_bytes(setup_packet: SetupPacket) -> [u8; 8] {
-        // Serialize into bytes in the most cursed manner available to us
-        // TODO do this properly
-        unsafe { core::mem::transmute::<SetupPacket, [u8; 8]>(setup_packet) }
+        setup_packet.into()
+    }
+}
+
+// - constructors -------------------------------------------------------------
+
+impl SetupPacket {
+    /// Returns a setup packet with the given field values.
+    #[must_use]
+    pub fn new(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> Self {
+        Self {
+            request_type,
+            request,
+            value: U16::new(value),
+            index: U16::new(index),
+            length: U16::new(length),
+        }
+    }
+
+    /// Returns the `bmRequestType` bits for a request.
+    #[must_use]
+    pub fn request_type_bits(
+        direction: Direction,
+        request_type: RequestType,
+        recipient: Recipient,
+    ) -> u8 {
+        let request_type = match request_type {
+            RequestType::Standard => 0,
+            RequestType::Class => 1,
+            RequestType::Vendor => 2,
+            RequestType::Reserved(_) => 3,
+        };
+        direction as u8 | (request_type << 5) | recipient as u8
+    }
+
+    fn standard(
+        direction: Direction,
+        recipient: Recipient,
+        request: Request,
+        value: u16,
+        index: u16,
+        length: u16,
+    ) -> Self {
+        Self::new(
+            Self::request_type_bits(direction, RequestType::Standard, recipient),
+            request.into(),
+            value,
+            index,
+            length,
+        )
+    }
+
+    /// `GET_STATUS` for the device, an interface or an endpoint address.
+    #[must_use]
+    pub fn get_status(recipient: Recipient, index: u16) -> Self {
+        Self::standard(
+            Direction::DeviceToHost,
+            recipient,
+            Request::GetStatus,
+            0,
+            index,
+            2,
+        )
+    }
+
+    /// `CLEAR_FEATURE` for the device, an interface or an endpoint address.
+    #[must_use]
+    pub fn clear_feature(recipient: Recipient, feature: Feature, index: u16) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            recipient,
+            Request::ClearFeature,
+            feature.into(),
+            index,
+            0,
+        )
+    }
+
+    /// `SET_FEATURE` for the device, an interface or an endpoint address.
+    #[must_use]
+    pub fn set_feature(recipient: Recipient, feature: Feature, index: u16) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            recipient,
+            Request::SetFeature,
+            feature.into(),
+            index,
+            0,
+        )
+    }
+
+    /// `SET_ADDRESS`
+    #[must_use]
+    pub fn set_address(address: u8) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            Recipient::Device,
+            Request::SetAddress,
+            u16::from(address),
+            0,
+            0,
+        )
+    }
+
+    /// `GET_DESCRIPTOR`
+    #[must_use]
+    pub fn get_descriptor(
+        descriptor_type: DescriptorType,
+        descriptor_index: u8,
+        language_id: u16,
+        length: u16,
+    ) -> Self {
+        Self::standard(
+            Direction::DeviceToHost,
+            Recipient::Device,
+            Request::GetDescriptor,
+            u16::from_le_bytes([descriptor_index, descriptor_type as u8]),
+            language_id,
+            length,
+        )
+    }
+
+    /// `SET_DESCRIPTOR`
+    #[must_use]
+    pub fn set_descriptor(
+        descriptor_type: DescriptorType,
+        descriptor_index: u8,
+        language_id: u16,
+        length: u16,
+    ) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            Recipient::Device,
+            Request::SetDescriptor,
+            u16::from_le_bytes([descriptor_index, descriptor_type as u8]),
+            language_id,
+            length,
+        )
+    }
+
+    /// `GET_CONFIGURATION`
+    #[must_use]
+    pub fn get_configuration() -> Self {
+        Self::standard(
+            Direction::DeviceToHost,
+            Recipient::Device,
+            Request::GetConfiguration,
+            0,
+            0,
+            1,
+        )
+    }
+
+    /// `SET_CONFIGURATION`
+    #[must_use]
+    pub fn set_configuration(configuration: u8) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            Recipient::Device,
+            Request::SetConfiguration,
+            u16::from(configuration),
+            0,
+            0,
+        )
+    }
+
+    /// `GET_INTERFACE`
+    #[must_use]
+    pub fn get_interface(interface_number: u8) -> Self {
+        Self::standard(
+            Direction::DeviceToHost,
+            Recipient::Interface,
+            Request::GetInterface,
+            0,
+            u16::from(interface_number),
+            1,
+        )
+    }
+
+    /// `SET_INTERFACE`
+    #[must_use]
+    pub fn set_interface(interface_number: u8, alternate_setting: u8) -> Self {
+        Self::standard(
+            Direction::HostToDevice,
+            Recipient::Interface,
+            Request::SetInterface,
+            u16::from(alternate_setting),
+            u16::from(interface_number),
+            0,
+        )
+    }
+
+    /// `SYNCH_FRAME`
+    #[must_use]
+    pub fn synch_frame(endpoint_address: u8) -> Self {
+        Self::standard(
+            Direction::DeviceToHost,
+            Recipient::Endpoint,
+            Request::SynchronizeFrame,
+            0,
+            u16::from(endpoint_address),
+            2,
+        )
     }
 }
 
+// - accessors ----------------------------------------------------------------
+
 impl SetupPacket {
     #[must_use]
     pub fn request_type(&self) -> RequestType {
@@ -52,6 +263,62 @@ impl SetupPacket {
     pub fn request(&self) -> Request {
         Request::from(self.request)
     }
+
+    /// Returns the `wValue` field.
+    #[must_use]
+    pub fn value(&self) -> u16 {
+        self.value.get()
+    }
+
+    /// Returns the `wIndex` field.
+    #[must_use]
+    pub fn index(&self) -> u16 {
+        self.index.get()
+    }
+
+    /// Returns the `wLength` field.
+    #[must_use]
+    pub fn length(&self) -> u16 {
+        self.length.get()
+    }
+
+    /// Returns the descriptor type and index of a `GET_DESCRIPTOR` or `SET_DESCRIPTOR` request.
+    #[must_use]
+    pub fn descriptor_request(&self) -> DescriptorRequest {
+        let [descriptor_index, descriptor_type] = self.value.get().to_le_bytes();
+        DescriptorRequest {
+            descriptor_type: DescriptorType::from(descriptor_type),
+            descriptor_index,
+            language_id: self.index.get(),
+        }
+    }
+
+    /// Returns the feature selector of a `SET_FEATURE` or `CLEAR_FEATURE` request.
+    #[must_use]
+    pub fn feature(&self) -> Feature {
+        Feature::from(self.value.get())
+    }
+
+    /// Returns the endpoint address of an endpoint request.
+    #[must_use]
+    pub fn endpoint_address(&self) -> u8 {
+        self.index.get().to_le_bytes()[0]
+    }
+
+    /// Returns the interface number of an interface request.
+    #[must_use]
+    pub fn interface_number(&self) -> u8 {
+        self.index.get().to_le_bytes()[0]
+    }
+}
+
+/// The descriptor requested by a `GET_DESCRIPTOR` or `SET_DESCRIPTOR` request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DescriptorRequest {
+    pub descriptor_type: DescriptorType,
+    pub descriptor_index: u8,
+    /// Language id for string descriptors, zero otherwise
+    pub language_id: u16,
 }
 
 /// Represents bits 0..=4 of the `[SetupPacket]` `request_type` field.
@@ -163,6 +430,25 @@ impl From<u8> for Request {
     }
 }
 
+impl From<Request> for u8 {
+    fn from(request: Request) -> Self {
+        match request {
+            Request::GetStatus => 0,
+            Request::ClearFeature => 1,
+            Request::SetFeature => 3,
+            Request::SetAddress => 5,
+            Request::GetDescriptor => 6,
+            Request::SetDescriptor => 7,
+            Request::GetConfiguration => 8,
+            Request::SetConfiguration => 9,
+            Request::GetInterface => 10,
+            Request::SetInterface => 11,
+            Request::SynchronizeFrame => 12,
+            Request::ClassOrVendor(value) | Request::Reserved(value) => value,
+        }
+    }
+}
+
 /// Represents standard values for `Request::SetFeature` and `Request::ClearFeature`.
 #[derive(Debug, PartialEq)]
 #[repr(u8)]
@@ -189,3 +475,142 @@ impl From<u16> for Feature {
         }
     }
 }
+
+impl From<Feature> for u16 {
+    fn from(feature: Feature) -> Self {
+        match feature {
+            Feature::EndpointHalt => 0,
+            Feature::DeviceRemoteWakeup => 1,
+            Feature::DeviceTestMode => 2,
+            Feature::DeviceBHnpEnable => 3,
+            Feature::DeviceAHnpSupport => 4,
+            Feature::DeviceAAltHnpSupport => 5,
+            Feature::Unknown(value) => value,
+        }
+    }
+}
+
+/// Represents the test selector values for `Feature::DeviceTestMode`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[repr(u8)]
+pub enum TestMode {
+    TestJ = 1,
+    TestK = 2,
+    TestSe0Nak = 3,
+    TestPacket = 4,
+    TestForceEnable = 5,
+    Reserved(u8) = 255,
+}
+
+impl From<u8> for TestMode {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => TestMode::TestJ,
+            2 => TestMode::TestK,
+            3 => TestMode::TestSe0Nak,
+            4 => TestMode::TestPacket,
+            5 => TestMode::TestForceEnable,
+            _ => TestMode::Reserved(value),
+        }
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_setup_packet_bytes() {
+        // GET_DESCRIPTOR, String #2, English (US), 255 bytes
+        let bytes = [0x80, 0x06, 0x02, 0x03, 0x09, 0x04, 0xff, 0x00];
+        let setup_packet = SetupPacket::from(bytes);
+
+        assert_eq!(setup_packet.direction(), Direction::DeviceToHost);
+        assert_eq!(setup_packet.request_type(), RequestType::Standard);
+        assert_eq!(setup_packet.recipient(), Recipient::Device);
+        assert_eq!(setup_packet.request(), Request::GetDescriptor);
+        assert_eq!(setup_packet.value(), 0x0302);
+        assert_eq!(setup_packet.index(), 0x0409);
+        assert_eq!(setup_packet.length(), 0x00ff);
+        assert_eq!(
+            setup_packet.descriptor_request(),
+            DescriptorRequest {
+                descriptor_type: DescriptorType::String,
+                descriptor_index: 2,
+                language_id: 0x0409,
+            }
+        );
+
+        assert_eq!(<[u8; 8]>::from(setup_packet), bytes);
+        assert_eq!(SetupPacket::as_bytes(setup_packet), bytes);
+        assert_eq!(
+            SetupPacket::get_descriptor(DescriptorType::String, 2, 0x0409, 0xff),
+            setup_packet
+        );
+    }
+
+    #[test]
+    fn test_standard_requests() {
+        let cases: [(SetupPacket, [u8; 8]); 11] = [
+            (
+                SetupPacket::get_status(Recipient::Endpoint, 0x81),
+                [0x82, 0x00, 0x00, 0x00, 0x81, 0x00, 0x02, 0x00],
+            ),
+            (
+                SetupPacket::clear_feature(Recipient::Endpoint, Feature::EndpointHalt, 0x02),
+                [0x02, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00],
+            ),
+            (
+                SetupPacket::set_feature(Recipient::Device, Feature::DeviceRemoteWakeup, 0),
+                [0x00, 0x03, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                SetupPacket::set_address(42),
+                [0x00, 0x05, 0x2a, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                SetupPacket::get_descriptor(DescriptorType::Device, 0, 0, 18),
+                [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00],
+            ),
+            (
+                SetupPacket::set_descriptor(DescriptorType::Configuration, 1, 0, 9),
+                [0x00, 0x07, 0x01, 0x02, 0x00, 0x00, 0x09, 0x00],
+            ),
+            (
+                SetupPacket::get_configuration(),
+                [0x80, 0x08, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00],
+            ),
+            (
+                SetupPacket::set_configuration(1),
+                [0x00, 0x09, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00],
+            ),
+            (
+                SetupPacket::get_interface(1),
+                [0x81, 0x0a, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00],
+            ),
+            (
+                SetupPacket::set_interface(1, 2),
+                [0x01, 0x0b, 0x02, 0x00, 0x01, 0x00, 0x00, 0x00],
+            ),
+            (
+                SetupPacket::synch_frame(0x83),
+                [0x82, 0x0c, 0x00, 0x00, 0x83, 0x00, 0x02, 0x00],
+            ),
+        ];
+
+        for (setup_packet, bytes) in cases {
+            assert_eq!(<[u8; 8]>::from(setup_packet), bytes, "{setup_packet:?}");
+            assert_eq!(SetupPacket::from(bytes), setup_packet);
+        }
+
+        let setup_packet =
+            SetupPacket::clear_feature(Recipient::Endpoint, Feature::EndpointHalt, 0x81);
+        assert_eq!(setup_packet.feature(), Feature::EndpointHalt);
+        assert_eq!(setup_packet.endpoint_address(), 0x81);
+        assert_eq!(SetupPacket::set_interface(3, 1).interface_number(), 3);
+    }
+}
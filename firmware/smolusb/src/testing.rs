@@ -0,0 +1,17 @@
+//! Host-side test utilities for exercising `smolusb` without hardware.
+//!
+//! [`SimulatedDriver`] implements the `smolusb` driver traits on top of
+//! in-memory queues so that [`Control`](crate::control::Control) and
+//! [`Descriptors`](crate::device::Descriptors) can be driven from
+//! `cargo test`. [`HostSimulator`] uses it to play back the control
+//! transfers a real host performs during enumeration and
+//! [`ClassHarness`] to exercise class implementations through a
+//! [`UsbDevice`](crate::device::UsbDevice).
+
+pub mod driver;
+pub mod harness;
+pub mod host;
+
+pub use driver::{block_on, Operation, SimulatedDriver};
+pub use harness::{class_request, descriptors, ClassHarness};
+pub use host::{Enumeration, HostOs, HostSimulator, Response, Violation};
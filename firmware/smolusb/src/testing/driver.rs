@@ -0,0 +1,892 @@
+//! Simulated USB peripheral driver
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::vec::Vec;
+
+use crate::control::Control;
+use crate::device::Speed;
+use crate::error::ErrorKind;
+use crate::event::UsbEvent;
+use crate::setup::{Direction, SetupPacket, TestMode};
+use crate::traits::{
+    AsyncUsbDriver, IsochronousEndpoint, ReadControl, ReadEndpoint, UnsafeUsbDriverOperations,
+    UsbDriver, UsbDriverOperations, WriteEndpoint,
+};
+use crate::EP_MAX_ENDPOINTS;
+
+// - Operation ----------------------------------------------------------------
+
+/// A driver operation recorded by [`SimulatedDriver`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// The device was connected at the given speed.
+    Connect(Speed),
+    /// The device was disconnected.
+    Disconnect,
+    /// The device performed a bus reset.
+    BusReset,
+    /// The status stage of a control request was acknowledged.
+    Ack(u8, Direction),
+    /// The device address was changed.
+    SetAddress(u8),
+    /// The given IN endpoint was stalled.
+    StallIn(u8),
+    /// The given OUT endpoint was stalled.
+    StallOut(u8),
+    /// The device entered the given test mode.
+    TestMode(TestMode),
+    /// A halt condition was set on the given endpoint.
+    SetHalt(u8, Direction),
+    /// A halt condition was cleared on the given endpoint.
+    ClearHalt(u8, Direction),
+    /// The given OUT endpoint was primed to receive a packet.
+    Prime(u8),
+    /// A packet was read from the given OUT endpoint.
+    Read(u8, Vec<u8>),
+    /// A packet was written to the given IN endpoint.
+    Write(u8, Vec<u8>),
+    /// The given endpoint was configured for isochronous transfers.
+    ConfigureIsochronous(u8, Direction, usize),
+    /// The device signalled remote wakeup.
+    RemoteWakeup,
+}
+
+// - SimulatedDriver ----------------------------------------------------------
+
+/// A simulated USB peripheral implementing [`UsbDriver`].
+///
+/// Every packet written, stall, prime and address change is recorded
+/// as an [`Operation`]. Tests inject [`UsbEvent`]s and OUT data with
+/// the `host_*` methods and then [`dispatch`](Self::dispatch) them to
+/// a [`Control`] instance.
+///
+/// Each packet written to an IN endpoint queues a matching
+/// [`UsbEvent::SendComplete`] just as the hardware would raise one
+/// once the host has acknowledged it. Isochronous packets and packets
+/// written with [`AsyncUsbDriver::write_packet()`] are recorded as
+/// [`Operation::Write`] without a [`UsbEvent::SendComplete`].
+pub struct SimulatedDriver {
+    pub device_speed: Speed,
+    address: Cell<u8>,
+    frame_number: Cell<Option<u16>>,
+    operations: RefCell<Vec<Operation>>,
+    events: RefCell<VecDeque<UsbEvent>>,
+    control_packets: RefCell<VecDeque<[u8; 8]>>,
+    out_packets: RefCell<Vec<VecDeque<Vec<u8>>>>,
+    primed: RefCell<[bool; EP_MAX_ENDPOINTS]>,
+    stalled_in: RefCell<[bool; EP_MAX_ENDPOINTS]>,
+    stalled_out: RefCell<[bool; EP_MAX_ENDPOINTS]>,
+    nak_in: RefCell<[bool; EP_MAX_ENDPOINTS]>,
+    tx_ack_active: RefCell<[bool; EP_MAX_ENDPOINTS]>,
+    test_modes: RefCell<Vec<TestMode>>,
+    remote_wakeup: Cell<bool>,
+}
+
+impl SimulatedDriver {
+    /// Create a new simulated driver for a device of the given speed.
+    #[must_use]
+    pub fn new(device_speed: Speed) -> Self {
+        Self {
+            device_speed,
+            address: Cell::new(0),
+            frame_number: Cell::new(None),
+            operations: RefCell::new(Vec::new()),
+            events: RefCell::new(VecDeque::new()),
+            control_packets: RefCell::new(VecDeque::new()),
+            out_packets: RefCell::new((0..EP_MAX_ENDPOINTS).map(|_| VecDeque::new()).collect()),
+            primed: RefCell::new([false; EP_MAX_ENDPOINTS]),
+            stalled_in: RefCell::new([false; EP_MAX_ENDPOINTS]),
+            stalled_out: RefCell::new([false; EP_MAX_ENDPOINTS]),
+            nak_in: RefCell::new([false; EP_MAX_ENDPOINTS]),
+            tx_ack_active: RefCell::new([false; EP_MAX_ENDPOINTS]),
+            test_modes: RefCell::new(vec![
+                TestMode::TestJ,
+                TestMode::TestK,
+                TestMode::TestSe0Nak,
+                TestMode::TestPacket,
+                TestMode::TestForceEnable,
+            ]),
+            remote_wakeup: Cell::new(true),
+        }
+    }
+
+    /// Set the USB 2.0 test modes supported by the device.
+    ///
+    /// Defaults to every test mode defined by the specification.
+    pub fn set_supported_test_modes(&self, test_modes: &[TestMode]) {
+        self.test_modes.replace(test_modes.to_vec());
+    }
+
+    /// Set whether the device can drive remote wakeup signalling.
+    ///
+    /// Defaults to `true`.
+    pub fn set_remote_wakeup_supported(&self, supported: bool) {
+        self.remote_wakeup.set(supported);
+    }
+
+    fn record(&self, operation: Operation) {
+        self.operations.borrow_mut().push(operation);
+    }
+}
+
+// - host side ----------------------------------------------------------------
+
+impl SimulatedDriver {
+    /// Queue an event for the next call to [`dispatch`](Self::dispatch).
+    pub fn enqueue_event(&self, event: UsbEvent) {
+        self.events.borrow_mut().push_back(event);
+    }
+
+    /// Returns the next pending event, if any.
+    pub fn next_event(&self) -> Option<UsbEvent> {
+        self.events.borrow_mut().pop_front()
+    }
+
+    /// Simulate a bus reset from the host.
+    pub fn host_bus_reset(&self) {
+        self.address.set(0);
+        self.stalled_in.replace([false; EP_MAX_ENDPOINTS]);
+        self.stalled_out.replace([false; EP_MAX_ENDPOINTS]);
+        self.nak_in.replace([false; EP_MAX_ENDPOINTS]);
+        self.enqueue_event(UsbEvent::BusReset);
+    }
+
+    /// Simulate the host sending a setup packet to the given control endpoint.
+    ///
+    /// A new SETUP clears any protocol stall on the control endpoint.
+    pub fn host_setup(&self, endpoint_number: u8, setup_packet: SetupPacket) {
+        let index = usize::from(endpoint_number);
+        self.stalled_in.borrow_mut()[index] = false;
+        self.stalled_out.borrow_mut()[index] = false;
+        self.control_packets
+            .borrow_mut()
+            .push_back(SetupPacket::as_bytes(setup_packet));
+        self.enqueue_event(UsbEvent::ReceiveSetupPacket(endpoint_number, setup_packet));
+    }
+
+    /// Simulate the host sending a data packet to the given OUT endpoint.
+    pub fn host_out(&self, endpoint_number: u8, data: &[u8]) {
+        self.out_packets.borrow_mut()[usize::from(endpoint_number)].push_back(data.to_vec());
+        self.enqueue_event(UsbEvent::ReceivePacket(endpoint_number));
+    }
+
+    /// Simulate the host suspending the bus.
+    pub fn host_suspend(&self) {
+        self.enqueue_event(UsbEvent::Suspend);
+    }
+
+    /// Simulate the host resuming the bus after a suspend.
+    pub fn host_resume(&self) {
+        self.enqueue_event(UsbEvent::Resume);
+    }
+
+    /// Simulate the host no longer collecting packets from the given
+    /// IN endpoint.
+    ///
+    /// While set, writes to the endpoint fail with
+    /// [`ErrorKind::Timeout`] and nothing is sent. Cleared by
+    /// [`host_bus_reset()`](Self::host_bus_reset).
+    pub fn host_nak_in(&self, endpoint_number: u8, nak: bool) {
+        self.nak_in.borrow_mut()[usize::from(endpoint_number)] = nak;
+    }
+
+    /// Simulate the host sending a start-of-frame packet.
+    ///
+    /// Frame numbers increment with every call and wrap at 2047.
+    pub fn host_start_of_frame(&self) {
+        let frame_number = self
+            .frame_number
+            .get()
+            .map_or(1, |number| (number + 1) & 0x7ff);
+        self.frame_number.set(Some(frame_number));
+        self.enqueue_event(UsbEvent::StartOfFrame(frame_number));
+    }
+
+    /// Dispatch all pending events to the given [`Control`] instance.
+    ///
+    /// Returns the setup packets that were not handled by [`Control`].
+    pub fn dispatch<const RX_BUFFER_SIZE: usize>(
+        &self,
+        control: &mut Control<'_, Self, RX_BUFFER_SIZE>,
+    ) -> Vec<SetupPacket> {
+        let mut unhandled = Vec::new();
+        while let Some(event) = self.next_event() {
+            if let Some(setup_packet) = control.dispatch_event(self, event) {
+                unhandled.push(setup_packet);
+            }
+        }
+        unhandled
+    }
+}
+
+// - inspection ---------------------------------------------------------------
+
+impl SimulatedDriver {
+    /// Returns the current device address.
+    #[must_use]
+    pub fn address(&self) -> u8 {
+        self.address.get()
+    }
+
+    /// Returns a copy of all operations recorded so far.
+    #[must_use]
+    pub fn operations(&self) -> Vec<Operation> {
+        self.operations.borrow().clone()
+    }
+
+    /// Returns and clears all operations recorded so far.
+    pub fn take_operations(&self) -> Vec<Operation> {
+        self.operations.take()
+    }
+
+    /// Returns the packets written to the given IN endpoint so far.
+    #[must_use]
+    pub fn packets_written(&self, endpoint_number: u8) -> Vec<Vec<u8>> {
+        self.operations
+            .borrow()
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::Write(number, packet) if *number == endpoint_number => {
+                    Some(packet.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns all data written to the given IN endpoint so far.
+    #[must_use]
+    pub fn data_written(&self, endpoint_number: u8) -> Vec<u8> {
+        self.packets_written(endpoint_number).concat()
+    }
+
+    /// Returns `true` if the given OUT endpoint is primed to receive.
+    #[must_use]
+    pub fn is_primed(&self, endpoint_number: u8) -> bool {
+        self.primed.borrow()[usize::from(endpoint_number)]
+    }
+
+    /// Returns `true` if the given endpoint is stalled.
+    #[must_use]
+    pub fn is_stalled(&self, endpoint_number: u8, direction: Direction) -> bool {
+        let index = usize::from(endpoint_number);
+        match direction {
+            Direction::HostToDevice => self.stalled_out.borrow()[index],
+            Direction::DeviceToHost => self.stalled_in.borrow()[index],
+        }
+    }
+}
+
+// - trait: UsbDriverOperations -----------------------------------------------
+
+impl UsbDriverOperations for SimulatedDriver {
+    fn connect(&mut self, device_speed: Speed) {
+        self.device_speed = device_speed;
+        self.record(Operation::Connect(device_speed));
+    }
+
+    fn disconnect(&mut self) {
+        self.address.set(0);
+        self.record(Operation::Disconnect);
+    }
+
+    fn bus_reset(&self) {
+        self.address.set(0);
+        self.tx_ack_active.replace([false; EP_MAX_ENDPOINTS]);
+        self.record(Operation::BusReset);
+    }
+
+    fn ack(&self, endpoint_number: u8, direction: Direction) {
+        self.record(Operation::Ack(endpoint_number, direction));
+        match direction {
+            Direction::DeviceToHost => self.ep_out_prime_receive(endpoint_number),
+            Direction::HostToDevice => {
+                self.write(endpoint_number, [].into_iter());
+            }
+        }
+    }
+
+    fn set_address(&self, address: u8) {
+        self.address.set(address & 0x7f);
+        self.record(Operation::SetAddress(address & 0x7f));
+    }
+
+    fn stall_endpoint_in(&self, endpoint_number: u8) {
+        self.stalled_in.borrow_mut()[usize::from(endpoint_number)] = true;
+        self.record(Operation::StallIn(endpoint_number));
+    }
+
+    fn stall_endpoint_out(&self, endpoint_number: u8) {
+        self.stalled_out.borrow_mut()[usize::from(endpoint_number)] = true;
+        self.record(Operation::StallOut(endpoint_number));
+    }
+
+    fn supports_test_mode(&self, test_mode: TestMode) -> bool {
+        self.test_modes.borrow().contains(&test_mode)
+    }
+
+    fn set_test_mode(&self, test_mode: TestMode) {
+        self.record(Operation::TestMode(test_mode));
+    }
+
+    fn set_feature_endpoint_halt(&self, endpoint_number: u8, direction: Direction) {
+        let index = usize::from(endpoint_number);
+        match direction {
+            Direction::HostToDevice => self.stalled_out.borrow_mut()[index] = true,
+            Direction::DeviceToHost => self.stalled_in.borrow_mut()[index] = true,
+        }
+        self.record(Operation::SetHalt(endpoint_number, direction));
+    }
+
+    fn clear_feature_endpoint_halt(&self, endpoint_number: u8, direction: Direction) {
+        let index = usize::from(endpoint_number);
+        match direction {
+            Direction::HostToDevice => self.stalled_out.borrow_mut()[index] = false,
+            Direction::DeviceToHost => self.stalled_in.borrow_mut()[index] = false,
+        }
+        self.record(Operation::ClearHalt(endpoint_number, direction));
+    }
+
+    fn remote_wakeup(&self) -> bool {
+        if !self.remote_wakeup.get() {
+            return false;
+        }
+        // the host answers resume signalling by resuming the bus
+        self.record(Operation::RemoteWakeup);
+        self.enqueue_event(UsbEvent::Resume);
+        true
+    }
+}
+
+// - trait: UnsafeUsbDriverOperations -----------------------------------------
+
+impl UnsafeUsbDriverOperations for SimulatedDriver {
+    unsafe fn set_tx_ack_active(&self, endpoint_number: u8) {
+        self.tx_ack_active.borrow_mut()[usize::from(endpoint_number)] = true;
+    }
+    unsafe fn clear_tx_ack_active(&self, endpoint_number: u8) {
+        self.tx_ack_active.borrow_mut()[usize::from(endpoint_number)] = false;
+    }
+    unsafe fn is_tx_ack_active(&self, endpoint_number: u8) -> bool {
+        self.tx_ack_active.borrow()[usize::from(endpoint_number)]
+    }
+}
+
+// - trait: Read/Write traits -------------------------------------------------
+
+impl ReadControl for SimulatedDriver {
+    fn read_control(&self, buffer: &mut [u8]) -> usize {
+        match self.control_packets.borrow_mut().pop_front() {
+            Some(packet) => {
+                let bytes_read = packet.len().min(buffer.len());
+                buffer[..bytes_read].copy_from_slice(&packet[..bytes_read]);
+                packet.len()
+            }
+            None => 0,
+        }
+    }
+}
+
+impl ReadEndpoint for SimulatedDriver {
+    fn ep_out_prime_receive(&self, endpoint_number: u8) {
+        self.primed.borrow_mut()[usize::from(endpoint_number)] = true;
+        self.record(Operation::Prime(endpoint_number));
+    }
+
+    fn try_read(&self, endpoint_number: u8, buffer: &mut [u8]) -> Result<usize, ErrorKind> {
+        let index = usize::from(endpoint_number);
+        self.primed.borrow_mut()[index] = false;
+        let Some(packet) = self.out_packets.borrow_mut()[index].pop_front() else {
+            return Ok(0);
+        };
+        let bytes_read = packet.len().min(buffer.len());
+        buffer[..bytes_read].copy_from_slice(&packet[..bytes_read]);
+        self.record(Operation::Read(endpoint_number, packet.clone()));
+
+        if packet.len() > buffer.len() {
+            Err(ErrorKind::Overflow(packet.len()))
+        } else {
+            Ok(bytes_read)
+        }
+    }
+}
+
+impl WriteEndpoint for SimulatedDriver {
+    fn try_write<I>(&self, endpoint_number: u8, iter: I) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let max_packet_size = crate::max_packet_size(self.device_speed, endpoint_number);
+        self.try_write_with_packet_size(endpoint_number, None, iter, max_packet_size)
+    }
+
+    fn try_write_requested<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: usize,
+        iter: I,
+    ) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let max_packet_size = crate::max_packet_size(self.device_speed, endpoint_number);
+        self.try_write_with_packet_size(
+            endpoint_number,
+            Some(requested_length),
+            iter,
+            max_packet_size,
+        )
+    }
+
+    fn try_write_with_packet_size<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: Option<usize>,
+        iter: I,
+        packet_size: usize,
+    ) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>,
+    {
+        let mut bytes_written: usize = 0;
+        let mut packet = Vec::with_capacity(packet_size);
+        for byte in iter {
+            packet.push(byte);
+            bytes_written += 1;
+            if packet.len() == packet_size {
+                self.send_packet(endpoint_number, core::mem::take(&mut packet), bytes_written)?;
+            }
+        }
+
+        // follows the same end-of-transfer rules as the lunasoc-hal driver
+        let is_partial_packet = bytes_written % packet_size != 0;
+        let is_requested_length = requested_length == Some(bytes_written);
+        let is_zlp = bytes_written == 0;
+
+        if is_partial_packet || !is_requested_length || is_zlp {
+            self.send_packet(endpoint_number, packet, bytes_written)?;
+        }
+
+        Ok(bytes_written)
+    }
+}
+
+impl SimulatedDriver {
+    fn send_packet(
+        &self,
+        endpoint_number: u8,
+        packet: Vec<u8>,
+        bytes_written: usize,
+    ) -> Result<(), ErrorKind> {
+        if self.nak_in.borrow()[usize::from(endpoint_number)] {
+            return Err(ErrorKind::Timeout(bytes_written));
+        }
+        self.record(Operation::Write(endpoint_number, packet));
+        self.enqueue_event(UsbEvent::SendComplete(endpoint_number));
+        Ok(())
+    }
+}
+
+impl IsochronousEndpoint for SimulatedDriver {
+    fn configure_isochronous(
+        &self,
+        endpoint_number: u8,
+        direction: Direction,
+        max_packet_size: usize,
+    ) {
+        self.record(Operation::ConfigureIsochronous(
+            endpoint_number,
+            direction,
+            max_packet_size,
+        ));
+    }
+
+    fn write_isochronous<I>(&self, endpoint_number: u8, iter: I) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        let packet: Vec<u8> = iter.collect();
+        let bytes_written = packet.len();
+        self.record(Operation::Write(endpoint_number, packet));
+        bytes_written
+    }
+
+    fn frame_number(&self) -> Option<u16> {
+        self.frame_number.get()
+    }
+}
+
+// mark implementation as complete
+impl UsbDriver for SimulatedDriver {}
+
+// - trait: AsyncUsbDriver ----------------------------------------------------
+
+/// The host acknowledges a packet written with
+/// [`write_packet()`](AsyncUsbDriver::write_packet) after the first
+/// [`poll_write()`](AsyncUsbDriver::poll_write) just as an interrupt
+/// would wake the polling task.
+impl AsyncUsbDriver for SimulatedDriver {
+    fn max_packet_size(&self, endpoint_number: u8) -> usize {
+        crate::max_packet_size(self.device_speed, endpoint_number)
+    }
+
+    fn write_packet<I>(&self, endpoint_number: u8, iter: I) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        let packet: Vec<u8> = iter.collect();
+        let bytes_written = packet.len();
+        self.tx_ack_active.borrow_mut()[usize::from(endpoint_number)] = true;
+        self.record(Operation::Write(endpoint_number, packet));
+        bytes_written
+    }
+
+    fn poll_write(&self, endpoint_number: u8, cx: &mut Context<'_>) -> Poll<()> {
+        let index = usize::from(endpoint_number);
+        if self.tx_ack_active.borrow()[index] {
+            self.tx_ack_active.borrow_mut()[index] = false;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    fn poll_read(&self, endpoint_number: u8, _cx: &mut Context<'_>) -> Poll<()> {
+        if self.out_packets.borrow()[usize::from(endpoint_number)].is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    fn poll_setup(&self, _endpoint_number: u8, _cx: &mut Context<'_>) -> Poll<SetupPacket> {
+        let mut buffer = [0; 8];
+        if self.read_control(&mut buffer) == 0 {
+            Poll::Pending
+        } else {
+            Poll::Ready(SetupPacket::from(buffer))
+        }
+    }
+}
+
+// - block_on -----------------------------------------------------------------
+
+struct FlagWaker(AtomicBool);
+
+impl Wake for FlagWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs a future to completion on the current thread.
+///
+/// # Panics
+///
+/// Panics if the future is pending without having been woken, as the
+/// simulated host has nothing left to send.
+pub fn block_on<F>(future: F) -> F::Output
+where
+    F: Future,
+{
+    let flag = Arc::new(FlagWaker(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        assert!(
+            flag.0.swap(false, Ordering::Relaxed),
+            "block_on: future is waiting for the host"
+        );
+    }
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::class::acm;
+    use crate::descriptor::StringTable;
+    use crate::device::Descriptors;
+
+    // - fixtures -------------------------------------------------------------
+
+    fn descriptors() -> Descriptors<'static> {
+        Descriptors {
+            device_speed: Speed::High,
+            device_descriptor: acm::DEVICE_DESCRIPTOR,
+            configuration_descriptors: &[acm::CONFIGURATION_DESCRIPTOR_0],
+            string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
+            string_descriptors: acm::STRING_DESCRIPTORS,
+            string_table: StringTable::new(),
+            device_qualifier_descriptor: Some(acm::DEVICE_QUALIFIER_DESCRIPTOR),
+            other_speed_configuration_descriptors: Some(&[
+                acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0,
+            ]),
+            bos_descriptor: None,
+            microsoft10: None,
+            microsoft20: None,
+        }
+    }
+
+    fn setup(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> SetupPacket {
+        SetupPacket::new(request_type, request, value, index, length)
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_get_device_descriptor() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+
+        usb.host_setup(0, setup(0x80, 6, 0x0100, 0, 64));
+        assert!(usb.dispatch(&mut control).is_empty());
+
+        let data = usb.data_written(0);
+        assert_eq!(data.len(), 18);
+        assert_eq!(data[0], 18);
+        assert_eq!(data[1], 0x01);
+        assert!(usb.is_primed(0));
+
+        // status stage
+        usb.host_out(0, &[]);
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert!(!usb.is_primed(0));
+    }
+
+    #[test]
+    fn test_get_configuration_descriptor() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+        let total_length = acm::CONFIGURATION_DESCRIPTOR_0.iter().count();
+
+        // header only
+        usb.host_setup(0, setup(0x80, 6, 0x0200, 0, 9));
+        usb.dispatch(&mut control);
+        let packets = usb.take_operations();
+        let header = match &packets[0] {
+            Operation::Write(0, packet) => packet.clone(),
+            operation => panic!("unexpected operation: {operation:?}"),
+        };
+        assert_eq!(header.len(), 9);
+        assert_eq!(
+            usize::from(u16::from_le_bytes([header[2], header[3]])),
+            total_length
+        );
+        usb.host_out(0, &[]);
+        usb.dispatch(&mut control);
+
+        // full descriptor
+        usb.take_operations();
+        usb.host_setup(0, setup(0x80, 6, 0x0200, 0, 0xff));
+        usb.dispatch(&mut control);
+        assert_eq!(usb.data_written(0).len(), total_length);
+    }
+
+    #[test]
+    fn test_get_string_descriptor() {
+        use crate::descriptor::{LanguageId, StringDescriptorZero, StringTable};
+
+        let mut string_table = StringTable::new();
+        string_table.insert(3, None, "1234").unwrap();
+        string_table
+            .insert(4, Some(LanguageId::German), "Konfiguration")
+            .unwrap();
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(
+            0,
+            Descriptors {
+                string_descriptor_zero: StringDescriptorZero::new(&[
+                    LanguageId::EnglishUnitedStates,
+                    LanguageId::German,
+                ]),
+                string_table,
+                ..descriptors()
+            },
+        );
+        let get_string = |control: &mut Control<'_, _, 64>, index: u16, language_id: u16| {
+            usb.take_operations();
+            usb.host_setup(0, setup(0x80, 6, 0x0300 | index, language_id, 0xff));
+            usb.dispatch(control);
+            usb.host_out(0, &[]);
+            usb.dispatch(control);
+            let data = usb.data_written(0);
+            let string: Vec<u16> = data
+                .get(2..)
+                .unwrap_or_default()
+                .chunks(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                .collect();
+            String::from_utf16(&string).unwrap()
+        };
+
+        assert_eq!(get_string(&mut control, 0, 0), "\u{0409}\u{0407}");
+        assert_eq!(get_string(&mut control, 1, 0x0409), acm::MANUFACTURER);
+        assert_eq!(get_string(&mut control, 1, 0x0407), acm::MANUFACTURER);
+        assert_eq!(get_string(&mut control, 3, 0x0409), "1234");
+        assert_eq!(get_string(&mut control, 4, 0x0407), "Konfiguration");
+        assert_eq!(get_string(&mut control, 6, 0x0409), acm::INTERFACE_1);
+
+        // out of range indices are stalled
+        usb.take_operations();
+        usb.host_setup(0, setup(0x80, 6, 0x0307, 0x0409, 0xff));
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert_eq!(usb.operations(), vec![Operation::StallIn(0)]);
+
+        // as are strings in the string table without an entry for the requested language
+        usb.take_operations();
+        usb.host_setup(0, setup(0x80, 6, 0x0304, 0x0409, 0xff));
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert_eq!(usb.operations(), vec![Operation::StallIn(0)]);
+
+        // the stall is cleared by the next request
+        assert_eq!(get_string(&mut control, 4, 0x0407), "Konfiguration");
+    }
+
+    #[test]
+    fn test_write_packetization() {
+        let usb = SimulatedDriver::new(Speed::Full);
+
+        // multiple packets, short final packet
+        usb.write(1, (0..130).map(|x| x as u8));
+        let lengths: Vec<usize> = usb.packets_written(1).iter().map(Vec::len).collect();
+        assert_eq!(lengths, vec![64, 64, 2]);
+
+        // full final packet matching the requested length needs no zlp
+        usb.take_operations();
+        usb.write_requested(1, 128, (0..128).map(|x| x as u8));
+        let lengths: Vec<usize> = usb.packets_written(1).iter().map(Vec::len).collect();
+        assert_eq!(lengths, vec![64, 64]);
+
+        // full final packet shorter than the requested length is followed by a zlp
+        usb.take_operations();
+        usb.write_requested(1, 255, (0..128).map(|x| x as u8));
+        let lengths: Vec<usize> = usb.packets_written(1).iter().map(Vec::len).collect();
+        assert_eq!(lengths, vec![64, 64, 0]);
+    }
+
+    #[test]
+    fn test_read_overflow() {
+        let usb = SimulatedDriver::new(Speed::Full);
+        let mut buffer = [0; 4];
+
+        usb.host_out(1, &[1, 2, 3]);
+        assert_eq!(usb.try_read(1, &mut buffer), Ok(3));
+
+        usb.host_out(1, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(usb.try_read(1, &mut buffer), Err(ErrorKind::Overflow(6)));
+        assert_eq!(buffer, [1, 2, 3, 4]);
+
+        // the infallible variant reports overflowing bytes as read
+        usb.host_out(1, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(usb.read(1, &mut buffer), 6);
+    }
+
+    #[test]
+    fn test_write_timeout() {
+        let usb = SimulatedDriver::new(Speed::Full);
+
+        usb.host_nak_in(1, true);
+        assert_eq!(
+            usb.try_write(1, (0..130).map(|x| x as u8)),
+            Err(ErrorKind::Timeout(64))
+        );
+        assert_eq!(usb.try_write(1, [].into_iter()), Err(ErrorKind::Timeout(0)));
+        assert!(usb.packets_written(1).is_empty());
+
+        // the infallible variant reports the bytes written before the timeout
+        assert_eq!(usb.write(1, (0..130).map(|x| x as u8)), 64);
+
+        usb.host_nak_in(1, false);
+        assert_eq!(usb.try_write(1, (0..130).map(|x| x as u8)), Ok(130));
+    }
+
+    #[test]
+    fn test_control_write_timeout() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+
+        // the host stops collecting the data stage
+        usb.host_nak_in(0, true);
+        usb.host_setup(0, setup(0x80, 6, 0x0100, 0, 64));
+        let event = usb.next_event().unwrap();
+        assert_eq!(
+            control.try_dispatch_event(&usb, event),
+            Err(ErrorKind::Timeout(18))
+        );
+        assert!(!usb.is_primed(0));
+
+        // the transfer was abandoned and the next request is handled
+        usb.host_bus_reset();
+        usb.host_setup(0, setup(0x80, 6, 0x0100, 0, 64));
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert_eq!(usb.data_written(0).len(), 18);
+        assert!(usb.is_primed(0));
+    }
+
+    #[test]
+    fn test_set_address_after_status_stage() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+
+        usb.host_setup(0, setup(0x00, 5, 42, 0, 0));
+        let event = usb.next_event().unwrap();
+        control.dispatch_event(&usb, event);
+
+        // zlp was written but the address must not change before it is acknowledged
+        assert_eq!(usb.packets_written(0), vec![Vec::<u8>::new()]);
+        assert_eq!(usb.address(), 0);
+
+        usb.dispatch(&mut control);
+        assert_eq!(usb.address(), 42);
+        assert!(usb.operations().contains(&Operation::SetAddress(42)));
+    }
+
+    #[test]
+    fn test_set_configuration() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+
+        usb.host_setup(0, setup(0x00, 9, 1, 0, 0));
+        usb.dispatch(&mut control);
+        usb.host_setup(0, setup(0x80, 8, 0, 0, 1));
+        usb.dispatch(&mut control);
+        usb.host_out(0, &[]);
+        usb.dispatch(&mut control);
+
+        assert_eq!(usb.packets_written(0), vec![vec![], vec![1]]);
+
+        // unknown configuration
+        usb.take_operations();
+        usb.host_setup(0, setup(0x00, 9, 7, 0, 0));
+        usb.dispatch(&mut control);
+        assert_eq!(usb.operations(), vec![Operation::StallIn(0)]);
+        assert!(usb.is_stalled(0, Direction::DeviceToHost));
+    }
+
+    #[test]
+    fn test_unhandled_request_with_host_data() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors());
+
+        usb.host_setup(0, setup(0x21, 0x20, 0, 0, 7));
+        usb.host_out(0, &[0x00, 0xc2, 0x01, 0x00, 0x00, 0x00, 0x08]);
+        let unhandled = usb.dispatch(&mut control);
+
+        assert_eq!(unhandled.len(), 1);
+        assert_eq!(unhandled[0].request, 0x20);
+        assert_eq!(control.data(), &[0x00, 0xc2, 0x01, 0x00, 0x00, 0x00, 0x08]);
+    }
+}
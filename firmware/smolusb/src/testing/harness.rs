@@ -0,0 +1,156 @@
+//! Test harness for class implementations
+
+use std::vec::Vec;
+
+use crate::class::UsbClass;
+use crate::control::Control;
+use crate::descriptor::{
+    ConfigurationDescriptor, DeviceDescriptor, LanguageId, StringDescriptorZero, StringTable,
+};
+use crate::device::{Descriptors, Speed, UsbDevice};
+use crate::setup::{Direction, Recipient, RequestType, SetupPacket};
+
+use super::driver::SimulatedDriver;
+
+// - fixtures -----------------------------------------------------------------
+
+/// Returns full-speed [`Descriptors`] for a device without any strings.
+#[must_use]
+pub fn descriptors(
+    device_descriptor: DeviceDescriptor,
+    configuration_descriptors: &'static [ConfigurationDescriptor<'static>],
+) -> Descriptors<'static> {
+    Descriptors {
+        device_speed: Speed::Full,
+        device_descriptor,
+        configuration_descriptors,
+        other_speed_configuration_descriptors: None,
+        device_qualifier_descriptor: None,
+        string_descriptor_zero: StringDescriptorZero::new(&[LanguageId::EnglishUnitedStates]),
+        string_descriptors: &[],
+        string_table: StringTable::new(),
+        bos_descriptor: None,
+        microsoft10: None,
+        microsoft20: None,
+    }
+}
+
+/// Returns a class request for the given recipient.
+#[must_use]
+pub fn class_request<R>(
+    direction: Direction,
+    recipient: Recipient,
+    request: R,
+    value: u16,
+    index: u16,
+    length: u16,
+) -> SetupPacket
+where
+    R: Into<u8>,
+{
+    SetupPacket::new(
+        SetupPacket::request_type_bits(direction, RequestType::Class, recipient),
+        request.into(),
+        value,
+        index,
+        length,
+    )
+}
+
+// - ClassHarness -------------------------------------------------------------
+
+/// Drives one or more classes through a [`UsbDevice`] running on a
+/// [`SimulatedDriver`], the way an application main loop would.
+///
+pub struct ClassHarness<C, const RX_BUFFER_SIZE: usize = 64> {
+    pub usb: SimulatedDriver,
+    pub device: UsbDevice<'static, SimulatedDriver, C, RX_BUFFER_SIZE>,
+}
+
+impl<C, const RX_BUFFER_SIZE: usize> ClassHarness<C, RX_BUFFER_SIZE>
+where
+    C: UsbClass<SimulatedDriver>,
+{
+    /// Create a harness for a device using control endpoint 0 and
+    /// simulate a bus reset.
+    #[must_use]
+    pub fn new(descriptors: Descriptors<'static>, classes: C) -> Self {
+        let usb = SimulatedDriver::new(descriptors.device_speed);
+        let device = UsbDevice::new(Control::new(0, descriptors), classes);
+        let mut harness = Self { usb, device };
+        harness.usb.host_bus_reset();
+        harness.dispatch();
+        harness.usb.take_operations();
+        harness
+    }
+
+    /// Returns a reference to the classes.
+    #[must_use]
+    pub fn classes(&self) -> &C {
+        self.device.classes()
+    }
+
+    /// Returns a mutable reference to the classes.
+    pub fn classes_mut(&mut self) -> &mut C {
+        self.device.classes_mut()
+    }
+
+    /// Returns the driver along with a mutable reference to the
+    /// classes for calling class methods that take the driver.
+    pub fn split(&mut self) -> (&SimulatedDriver, &mut C) {
+        (&self.usb, self.device.classes_mut())
+    }
+
+    /// Dispatch all pending events to the device.
+    ///
+    /// Returns the setup packets handled by neither [`Control`] nor
+    /// any of the classes.
+    pub fn dispatch(&mut self) -> Vec<SetupPacket> {
+        let mut unhandled = Vec::new();
+        while let Some(event) = self.usb.next_event() {
+            if let Some(setup_packet) = self.device.dispatch_event(&self.usb, event) {
+                unhandled.push(setup_packet);
+            }
+        }
+        unhandled
+    }
+
+    /// Perform a control request with an optional OUT data stage.
+    ///
+    /// Previously recorded operations are cleared first so that the
+    /// device's response can be inspected afterwards.
+    ///
+    /// Returns the data written to the control endpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the request was not handled by the device.
+    pub fn request(&mut self, setup_packet: SetupPacket, data: &[u8]) -> Vec<u8> {
+        let endpoint_number = self.device.control().endpoint_number();
+        let max_packet_size = crate::max_packet_size(self.usb.device_speed, 0);
+
+        self.usb.take_operations();
+        self.usb.host_setup(endpoint_number, setup_packet);
+        let mut unhandled = self.dispatch();
+        for packet in data.chunks(max_packet_size) {
+            self.usb.host_out(endpoint_number, packet);
+            unhandled.extend(self.dispatch());
+        }
+        assert!(unhandled.is_empty(), "unhandled request: {unhandled:?}");
+
+        self.usb.data_written(endpoint_number)
+    }
+
+    /// Select the given configuration.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration could not be selected.
+    pub fn configure(&mut self, configuration: u8) {
+        self.request(SetupPacket::set_configuration(configuration), &[]);
+        assert_eq!(
+            self.device.control().configuration(),
+            Some(configuration).filter(|configuration| *configuration != 0)
+        );
+    }
+}
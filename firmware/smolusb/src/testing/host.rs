@@ -0,0 +1,1003 @@
+//! Scripted host enumeration simulator
+
+use std::string::String;
+use std::vec::Vec;
+
+use zerocopy::byteorder::U16;
+
+use crate::control::Control;
+use crate::descriptor::{
+    microsoft20, DescriptorType, DeviceCapabilityType, StringDescriptorNumber,
+};
+use crate::setup::SetupPacket;
+
+use super::driver::{Operation, SimulatedDriver};
+
+// - HostOs -------------------------------------------------------------------
+
+/// The host enumeration sequence to simulate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HostOs {
+    /// Reads 64 bytes of the device descriptor before addressing the
+    /// device and fetches the device qualifier for USB 2.0 devices.
+    Linux,
+    /// Like Linux, but reads the whole configuration descriptor up
+    /// front and probes for Microsoft OS 1.0 descriptors.
+    Windows,
+    /// Reads 8 bytes of the device descriptor before addressing the
+    /// device and reads every string descriptor header first.
+    MacOs,
+}
+
+// - Response -----------------------------------------------------------------
+
+/// The device's response to a control transfer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Response {
+    /// The device returned data in response to an IN transfer.
+    Data(Vec<u8>),
+    /// The device acknowledged an OUT transfer.
+    Ack,
+    /// The device stalled the transfer.
+    Stall,
+    /// [`Control`] did not handle the request and returned it to the application.
+    Unhandled,
+}
+
+// - Violation ----------------------------------------------------------------
+
+/// A USB protocol violation observed by [`HostSimulator`].
+#[derive(Clone, Debug)]
+pub enum Violation {
+    /// The device stalled a request that it is required to support.
+    UnexpectedStall(SetupPacket),
+    /// [`Control`] returned a standard request to the application.
+    Unhandled(SetupPacket),
+    /// The device did not respond to the request at all.
+    NoResponse(SetupPacket),
+    /// The device returned an empty data stage instead of stalling.
+    EmptyResponse(SetupPacket),
+    /// The device returned a different amount of data than expected.
+    WrongLength {
+        setup_packet: SetupPacket,
+        expected: usize,
+        actual: usize,
+    },
+    /// The device returned a descriptor of the wrong type.
+    WrongDescriptorType {
+        setup_packet: SetupPacket,
+        expected: u8,
+        actual: u8,
+    },
+    /// The device sent a packet larger than the endpoint's max packet size.
+    PacketTooLarge {
+        setup_packet: SetupPacket,
+        max_packet_size: usize,
+        actual: usize,
+    },
+    /// The device sent more data than the host requested.
+    Overrun(SetupPacket),
+    /// The data stage ended on a full packet short of the requested
+    /// length without being terminated by a zero length packet.
+    MissingZlp(SetupPacket),
+    /// The device sent packets after the data stage was complete.
+    UnexpectedPacket(SetupPacket),
+    /// The control endpoint was not primed to receive data or a status stage.
+    NotPrimed(SetupPacket),
+    /// The device did not complete the status stage of an OUT transfer.
+    MissingStatusStage(SetupPacket),
+    /// The device address did not change after `SET_ADDRESS`.
+    AddressNotSet { expected: u8, actual: u8 },
+}
+
+// - Enumeration --------------------------------------------------------------
+
+/// The descriptors collected while enumerating a device.
+#[derive(Clone, Debug, Default)]
+pub struct Enumeration {
+    pub device_descriptor: Vec<u8>,
+    pub device_qualifier_descriptor: Option<Vec<u8>>,
+    pub bos_descriptor: Option<Vec<u8>>,
+    pub configuration_descriptor: Vec<u8>,
+    pub language_ids: Vec<u16>,
+    pub strings: Vec<(u8, String)>,
+    pub microsoft10_vendor_code: Option<u8>,
+    pub microsoft20_descriptor_set: Option<Vec<u8>>,
+    pub address: u8,
+}
+
+// - HostSimulator ------------------------------------------------------------
+
+/// Simulates a USB host performing control transfers against a
+/// [`Control`] instance running on a [`SimulatedDriver`].
+///
+/// Protocol violations are collected rather than treated as errors so
+/// that a single run can report everything that went wrong.
+pub struct HostSimulator<'a> {
+    usb: &'a SimulatedDriver,
+    endpoint_number: u8,
+    max_packet_size: usize,
+    violations: Vec<Violation>,
+}
+
+impl<'a> HostSimulator<'a> {
+    /// Create a new host simulator for the given driver's control endpoint.
+    #[must_use]
+    pub fn new(usb: &'a SimulatedDriver, endpoint_number: u8) -> Self {
+        Self {
+            usb,
+            endpoint_number,
+            max_packet_size: crate::max_packet_size(usb.device_speed, 0),
+            violations: Vec::new(),
+        }
+    }
+
+    /// Returns the protocol violations observed so far.
+    #[must_use]
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+
+    /// Returns and clears the protocol violations observed so far.
+    pub fn take_violations(&mut self) -> Vec<Violation> {
+        core::mem::take(&mut self.violations)
+    }
+
+    fn violation(&mut self, violation: Violation) {
+        log::warn!("HostSimulator violation: {:?}", violation);
+        self.violations.push(violation);
+    }
+}
+
+// - control transfers --------------------------------------------------------
+
+impl HostSimulator<'_> {
+    /// Simulate a bus reset.
+    pub fn bus_reset<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+    ) {
+        self.usb.host_bus_reset();
+        self.usb.dispatch(control);
+        self.usb.take_operations();
+    }
+
+    /// Perform a control transfer with an IN data stage.
+    pub fn control_in<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        setup_packet: SetupPacket,
+    ) -> Response {
+        self.usb.take_operations();
+
+        // setup stage
+        self.usb.host_setup(self.endpoint_number, setup_packet);
+        let unhandled = self.usb.dispatch(control);
+        let operations = self.usb.take_operations();
+
+        if self.is_stalled(&operations) {
+            return Response::Stall;
+        }
+        if !unhandled.is_empty() {
+            return Response::Unhandled;
+        }
+
+        // data stage
+        let packets = self.packets(&operations);
+        if packets.is_empty() {
+            self.violation(Violation::NoResponse(setup_packet));
+            return Response::Data(Vec::new());
+        }
+        let data = self.receive_data_stage(setup_packet, &packets);
+
+        // status stage
+        if !self.usb.is_primed(self.endpoint_number) {
+            self.violation(Violation::NotPrimed(setup_packet));
+        }
+        self.usb.host_out(self.endpoint_number, &[]);
+        self.usb.dispatch(control);
+        self.usb.take_operations();
+
+        Response::Data(data)
+    }
+
+    /// Perform a control transfer with an optional OUT data stage.
+    pub fn control_out<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        setup_packet: SetupPacket,
+        data: &[u8],
+    ) -> Response {
+        self.usb.take_operations();
+
+        // setup stage
+        self.usb.host_setup(self.endpoint_number, setup_packet);
+        let mut unhandled = self.usb.dispatch(control);
+
+        // data stage
+        for chunk in data.chunks(self.max_packet_size) {
+            if self.is_stalled(&self.usb.operations()) {
+                break;
+            }
+            if !self.usb.is_primed(self.endpoint_number) {
+                self.violation(Violation::NotPrimed(setup_packet));
+            }
+            self.usb.host_out(self.endpoint_number, chunk);
+            unhandled.extend(self.usb.dispatch(control));
+        }
+        let operations = self.usb.take_operations();
+
+        if self.is_stalled(&operations) {
+            return Response::Stall;
+        }
+
+        // status stage
+        let packets = self.packets(&operations);
+        if packets.is_empty() {
+            // unhandled requests without a data stage are acknowledged by the application
+            if unhandled.is_empty() || !data.is_empty() {
+                self.violation(Violation::MissingStatusStage(setup_packet));
+            }
+        } else if packets.len() > 1 || !packets[0].is_empty() {
+            self.violation(Violation::UnexpectedPacket(setup_packet));
+        }
+
+        if unhandled.is_empty() {
+            Response::Ack
+        } else {
+            Response::Unhandled
+        }
+    }
+
+    fn is_stalled(&self, operations: &[Operation]) -> bool {
+        operations.iter().any(|operation| {
+            matches!(
+                operation,
+                Operation::StallIn(number) | Operation::StallOut(number)
+                    if *number == self.endpoint_number
+            )
+        })
+    }
+
+    fn packets(&self, operations: &[Operation]) -> Vec<Vec<u8>> {
+        operations
+            .iter()
+            .filter_map(|operation| match operation {
+                Operation::Write(number, packet) if *number == self.endpoint_number => {
+                    Some(packet.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Receive IN packets the way a host does: until a short packet
+    /// arrives or the requested length has been transferred.
+    fn receive_data_stage(&mut self, setup_packet: SetupPacket, packets: &[Vec<u8>]) -> Vec<u8> {
+        let requested_length = usize::from(setup_packet.length());
+        let mut data = Vec::new();
+        let mut packets = packets.iter();
+
+        for packet in packets.by_ref() {
+            if packet.len() > self.max_packet_size {
+                self.violation(Violation::PacketTooLarge {
+                    setup_packet,
+                    max_packet_size: self.max_packet_size,
+                    actual: packet.len(),
+                });
+            }
+            data.extend_from_slice(packet);
+            if data.len() > requested_length {
+                self.violation(Violation::Overrun(setup_packet));
+                data.truncate(requested_length);
+                return data;
+            }
+            if packet.len() < self.max_packet_size || data.len() == requested_length {
+                if packets.next().is_some() {
+                    self.violation(Violation::UnexpectedPacket(setup_packet));
+                }
+                return data;
+            }
+        }
+
+        self.violation(Violation::MissingZlp(setup_packet));
+        data
+    }
+}
+
+// - standard requests --------------------------------------------------------
+
+impl HostSimulator<'_> {
+    /// Read a descriptor and verify its type and length.
+    ///
+    /// Stalls are only reported as violations if `optional` is `false`.
+    pub fn get_descriptor<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        descriptor_type: DescriptorType,
+        descriptor_index: u8,
+        language_id: u16,
+        length: u16,
+        optional: bool,
+    ) -> Option<Vec<u8>> {
+        let setup_packet =
+            SetupPacket::get_descriptor(descriptor_type, descriptor_index, language_id, length);
+
+        let data = match self.control_in(control, setup_packet) {
+            Response::Data(data) => data,
+            Response::Stall => {
+                if !optional {
+                    self.violation(Violation::UnexpectedStall(setup_packet));
+                }
+                return None;
+            }
+            _ => {
+                self.violation(Violation::Unhandled(setup_packet));
+                return None;
+            }
+        };
+
+        if data.is_empty() {
+            self.violation(Violation::EmptyResponse(setup_packet));
+            return None;
+        }
+
+        // check descriptor type
+        if data.len() >= 2 && data[1] != descriptor_type as u8 {
+            self.violation(Violation::WrongDescriptorType {
+                setup_packet,
+                expected: descriptor_type as u8,
+                actual: data[1],
+            });
+        }
+
+        // check descriptor length
+        let declared_length = match descriptor_type {
+            DescriptorType::Configuration
+            | DescriptorType::OtherSpeedConfiguration
+            | DescriptorType::BinaryDeviceObjectStore
+                if data.len() >= 4 =>
+            {
+                usize::from(u16::from_le_bytes([data[2], data[3]]))
+            }
+            _ => usize::from(data[0]),
+        };
+        let expected = declared_length.min(usize::from(length));
+        if data.len() != expected {
+            self.violation(Violation::WrongLength {
+                setup_packet,
+                expected,
+                actual: data.len(),
+            });
+        }
+
+        Some(data)
+    }
+
+    /// Set the device address and verify that it took effect.
+    pub fn set_address<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        address: u8,
+    ) {
+        let setup_packet = SetupPacket::set_address(address);
+        self.standard_out(control, setup_packet);
+        if self.usb.address() != address {
+            self.violation(Violation::AddressNotSet {
+                expected: address,
+                actual: self.usb.address(),
+            });
+        }
+    }
+
+    /// Select the given device configuration.
+    pub fn set_configuration<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        configuration: u8,
+    ) {
+        let setup_packet = SetupPacket::set_configuration(configuration);
+        self.standard_out(control, setup_packet);
+    }
+
+    fn standard_out<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        setup_packet: SetupPacket,
+    ) {
+        match self.control_out(control, setup_packet, &[]) {
+            Response::Ack => (),
+            Response::Stall => self.violation(Violation::UnexpectedStall(setup_packet)),
+            _ => self.violation(Violation::Unhandled(setup_packet)),
+        }
+    }
+}
+
+// - enumeration --------------------------------------------------------------
+
+impl HostSimulator<'_> {
+    /// Enumerate the device using the sequence of requests issued by
+    /// the given host operating system.
+    pub fn enumerate<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        host_os: HostOs,
+        address: u8,
+    ) -> Enumeration {
+        let mut enumeration = Enumeration::default();
+
+        // initial device descriptor read at address 0
+        let initial_length = if host_os == HostOs::MacOs { 8 } else { 64 };
+        self.bus_reset(control);
+        if let Some(data) =
+            self.get_descriptor(control, DescriptorType::Device, 0, 0, initial_length, false)
+        {
+            if data.len() >= 8 && data[7] != 0 {
+                self.max_packet_size = usize::from(data[7]);
+            }
+        }
+        self.bus_reset(control);
+
+        self.set_address(control, address);
+        enumeration.address = self.usb.address();
+
+        let Some(device_descriptor) =
+            self.get_descriptor(control, DescriptorType::Device, 0, 0, 18, false)
+        else {
+            return enumeration;
+        };
+        if device_descriptor.len() < 18 {
+            enumeration.device_descriptor = device_descriptor;
+            return enumeration;
+        }
+        let bcd_usb = u16::from_le_bytes([device_descriptor[2], device_descriptor[3]]);
+        enumeration.device_descriptor = device_descriptor;
+
+        if host_os == HostOs::Windows {
+            self.get_descriptor(control, DescriptorType::Configuration, 0, 0, 0xff, false);
+        }
+
+        if bcd_usb >= 0x0200 {
+            enumeration.device_qualifier_descriptor =
+                self.get_descriptor(control, DescriptorType::DeviceQualifier, 0, 0, 10, true);
+        }
+
+        // devices with a bcdUSB of 0x0201 or greater must have a bos descriptor
+        if bcd_usb >= 0x0201 {
+            enumeration.bos_descriptor = self.read_bos(control);
+        }
+
+        // windows prefers microsoft os 2.0 descriptors if advertised in the bos
+        if host_os == HostOs::Windows {
+            enumeration.microsoft20_descriptor_set = enumeration
+                .bos_descriptor
+                .as_deref()
+                .and_then(microsoft20_capability)
+                .and_then(|(vendor_code, length)| {
+                    self.read_microsoft20(control, vendor_code, length)
+                });
+            if enumeration.microsoft20_descriptor_set.is_none() {
+                enumeration.microsoft10_vendor_code = self.probe_microsoft10(control);
+            }
+        }
+
+        // configuration descriptor
+        let Some(header) =
+            self.get_descriptor(control, DescriptorType::Configuration, 0, 0, 9, false)
+        else {
+            return enumeration;
+        };
+        if header.len() < 9 {
+            return enumeration;
+        }
+        let total_length = u16::from_le_bytes([header[2], header[3]]);
+        let configuration_value = header[5];
+        if let Some(configuration_descriptor) = self.get_descriptor(
+            control,
+            DescriptorType::Configuration,
+            0,
+            0,
+            total_length,
+            false,
+        ) {
+            enumeration.configuration_descriptor = configuration_descriptor;
+        }
+
+        // string descriptors
+        self.read_strings(control, host_os, &mut enumeration);
+
+        self.set_configuration(control, configuration_value);
+
+        enumeration
+    }
+
+    /// Read the bos descriptor header followed by the full descriptor.
+    fn read_bos<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+    ) -> Option<Vec<u8>> {
+        let header = self.get_descriptor(
+            control,
+            DescriptorType::BinaryDeviceObjectStore,
+            0,
+            0,
+            5,
+            false,
+        )?;
+        if header.len() < 5 {
+            return Some(header);
+        }
+        let total_length = u16::from_le_bytes([header[2], header[3]]);
+        self.get_descriptor(
+            control,
+            DescriptorType::BinaryDeviceObjectStore,
+            0,
+            0,
+            total_length,
+            false,
+        )
+    }
+
+    /// Read the string descriptor table and every string referenced
+    /// by the device and configuration descriptors.
+    fn read_strings<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        host_os: HostOs,
+        enumeration: &mut Enumeration,
+    ) {
+        let string_indices = string_indices(
+            &enumeration.device_descriptor,
+            &enumeration.configuration_descriptor,
+        );
+        if !string_indices.is_empty() {
+            if let Some(data) =
+                self.get_descriptor(control, DescriptorType::String, 0, 0, 0xff, false)
+            {
+                enumeration.language_ids = data
+                    .get(2..)
+                    .unwrap_or_default()
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect();
+            }
+        }
+        let language_id = enumeration.language_ids.first().copied().unwrap_or(0x0409);
+        for index in string_indices {
+            if host_os == HostOs::MacOs {
+                self.get_descriptor(
+                    control,
+                    DescriptorType::String,
+                    index,
+                    language_id,
+                    2,
+                    false,
+                );
+            }
+            if let Some(data) = self.get_descriptor(
+                control,
+                DescriptorType::String,
+                index,
+                language_id,
+                0xff,
+                false,
+            ) {
+                let utf16: Vec<u16> = data
+                    .get(2..)
+                    .unwrap_or_default()
+                    .chunks_exact(2)
+                    .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+                    .collect();
+                enumeration
+                    .strings
+                    .push((index, String::from_utf16_lossy(&utf16)));
+            }
+        }
+    }
+
+    /// Read the Microsoft OS 2.0 descriptor set advertised in the bos descriptor.
+    fn read_microsoft20<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+        vendor_code: u8,
+        length: u16,
+    ) -> Option<Vec<u8>> {
+        // microsoft20::VendorIndex::DescriptorSet
+        let setup_packet = SetupPacket::new(0xc0, vendor_code, 0, 0x0007, length);
+        match self.control_in(control, setup_packet) {
+            Response::Data(data) => {
+                if data.len() != usize::from(length) {
+                    self.violation(Violation::WrongLength {
+                        setup_packet,
+                        expected: usize::from(length),
+                        actual: data.len(),
+                    });
+                }
+                Some(data)
+            }
+            Response::Stall => {
+                self.violation(Violation::UnexpectedStall(setup_packet));
+                None
+            }
+            _ => {
+                self.violation(Violation::Unhandled(setup_packet));
+                None
+            }
+        }
+    }
+
+    /// Probe for Microsoft OS 1.0 descriptors the way Windows does.
+    ///
+    /// Returns the vendor code if the device supports them.
+    fn probe_microsoft10<const RX_BUFFER_SIZE: usize>(
+        &mut self,
+        control: &mut Control<'_, SimulatedDriver, RX_BUFFER_SIZE>,
+    ) -> Option<u8> {
+        let data = self.get_descriptor(
+            control,
+            DescriptorType::String,
+            StringDescriptorNumber::Microsoft,
+            0,
+            0x12,
+            true,
+        )?;
+        if data.len() < 18 || data[2..16] != *b"M\0S\0F\0T\x001\x000\x000\0" {
+            return None;
+        }
+        let vendor_code = data[16];
+
+        // compatible id feature descriptor: header first, then everything
+        let mut setup_packet = SetupPacket::new(0xc0, vendor_code, 0, 0x0004, 0x10);
+        if let Response::Data(header) = self.control_in(control, setup_packet) {
+            if header.len() >= 4 {
+                let length = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+                setup_packet.length = U16::new(u16::try_from(length).unwrap_or(u16::MAX));
+                if let Response::Data(data) = self.control_in(control, setup_packet) {
+                    if data.len() != usize::from(setup_packet.length()) {
+                        self.violation(Violation::WrongLength {
+                            setup_packet,
+                            expected: usize::from(setup_packet.length()),
+                            actual: data.len(),
+                        });
+                    }
+                }
+            }
+        }
+
+        Some(vendor_code)
+    }
+}
+
+/// Returns the vendor code and descriptor set length of the Microsoft
+/// OS 2.0 platform capability in the given bos descriptor.
+fn microsoft20_capability(bos_descriptor: &[u8]) -> Option<(u8, u16)> {
+    let mut offset = 5;
+    while offset + 3 <= bos_descriptor.len() {
+        let length = usize::from(bos_descriptor[offset]);
+        if length < 3 || offset + length > bos_descriptor.len() {
+            break;
+        }
+        let capability = &bos_descriptor[offset..offset + length];
+        if capability[2] == DeviceCapabilityType::Platform as u8
+            && length >= 28
+            && capability[4..20] == microsoft20::PLATFORM_CAPABILITY_UUID
+        {
+            let total_length = u16::from_le_bytes([capability[24], capability[25]]);
+            return Some((capability[26], total_length));
+        }
+        offset += length;
+    }
+    None
+}
+
+/// Collect the non-zero string indices referenced by the device and
+/// configuration descriptors.
+fn string_indices(device_descriptor: &[u8], configuration_descriptor: &[u8]) -> Vec<u8> {
+    let mut indices = Vec::new();
+    if device_descriptor.len() >= 17 {
+        // iManufacturer, iProduct, iSerialNumber
+        indices.extend_from_slice(&device_descriptor[14..17]);
+    }
+
+    let mut offset = 0;
+    while offset + 2 <= configuration_descriptor.len() {
+        let length = usize::from(configuration_descriptor[offset]);
+        if length < 2 || offset + length > configuration_descriptor.len() {
+            break;
+        }
+        let descriptor = &configuration_descriptor[offset..offset + length];
+        match DescriptorType::from(descriptor[1]) {
+            DescriptorType::Configuration if length >= 9 => indices.push(descriptor[6]),
+            DescriptorType::Interface if length >= 9 => indices.push(descriptor[8]),
+            DescriptorType::InterfaceAssociation if length >= 8 => indices.push(descriptor[7]),
+            _ => (),
+        }
+        offset += length;
+    }
+
+    let mut unique = Vec::new();
+    for index in indices {
+        if index != 0 && !unique.contains(&index) {
+            unique.push(index);
+        }
+    }
+    unique
+}
+
+// - tests --------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::class::acm;
+    use crate::descriptor::{
+        microsoft10, microsoft20, BinaryObjectStoreDescriptor, BinaryObjectStoreDescriptorHeader,
+        DeviceCapability, DeviceDescriptor, PlatformDescriptor, PlatformDescriptorHeader,
+        StringTable, Usb20ExtensionDescriptor,
+    };
+    use crate::device::{Descriptors, Speed};
+    use crate::setup::Direction;
+
+    // - fixtures -------------------------------------------------------------
+
+    static COMPAT_ID_FUNCTIONS: [microsoft10::CompatibleIdFeatureDescriptorFunction; 1] =
+        [microsoft10::CompatibleIdFeatureDescriptorFunction {
+            bFirstInterfaceNumber: 0,
+            aCompatibleId: *b"WINUSB\0\0",
+            ..microsoft10::CompatibleIdFeatureDescriptorFunction::new()
+        }];
+
+    static DEVICE_CAPABILITIES: [DeviceCapability; 2] = [
+        DeviceCapability::Usb20Extension(Usb20ExtensionDescriptor {
+            bmAttributes: Usb20ExtensionDescriptor::LPM | Usb20ExtensionDescriptor::BESL,
+            ..Usb20ExtensionDescriptor::new()
+        }),
+        DeviceCapability::Platform(PlatformDescriptor::new(
+            PlatformDescriptorHeader {
+                PlatformCapabilityUUID: [0xaa; 16],
+                ..PlatformDescriptorHeader::new()
+            },
+            &[0x01, 0x02, 0x03, 0x04],
+        )),
+    ];
+
+    static MICROSOFT20_DESCRIPTOR_SET: microsoft20::DescriptorSet<64> =
+        microsoft20::DescriptorSetBuilder::new()
+            .compatible_id(b"WINUSB\0\0", &[0; 8])
+            .build();
+    static MICROSOFT20_CAPABILITY_DATA: [u8; 8] = MICROSOFT20_DESCRIPTOR_SET.capability_data(0x42);
+    static MICROSOFT20_CAPABILITIES: [DeviceCapability; 1] = [microsoft20::platform_capability(
+        &MICROSOFT20_CAPABILITY_DATA,
+    )];
+
+    fn descriptors(device_speed: Speed) -> Descriptors<'static> {
+        Descriptors {
+            device_speed,
+            device_descriptor: acm::DEVICE_DESCRIPTOR,
+            configuration_descriptors: &[acm::CONFIGURATION_DESCRIPTOR_0],
+            string_descriptor_zero: acm::STRING_DESCRIPTOR_0,
+            string_descriptors: acm::STRING_DESCRIPTORS,
+            string_table: StringTable::new(),
+            device_qualifier_descriptor: Some(acm::DEVICE_QUALIFIER_DESCRIPTOR),
+            other_speed_configuration_descriptors: Some(&[
+                acm::OTHER_SPEED_CONFIGURATION_DESCRIPTOR_0,
+            ]),
+            bos_descriptor: None,
+            microsoft10: None,
+            microsoft20: None,
+        }
+    }
+
+    // - tests ----------------------------------------------------------------
+
+    #[test]
+    fn test_enumerate_linux() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors(Speed::High));
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let enumeration = host.enumerate(&mut control, HostOs::Linux, 12);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+        assert_eq!(enumeration.address, 12);
+        assert_eq!(enumeration.device_descriptor.len(), 18);
+        assert_eq!(
+            enumeration.configuration_descriptor.len(),
+            acm::CONFIGURATION_DESCRIPTOR_0.iter().count()
+        );
+        assert_eq!(enumeration.language_ids, vec![0x0409]);
+        assert!(enumeration
+            .strings
+            .contains(&(2, String::from("USB-to-serial"))));
+        assert!(enumeration.device_qualifier_descriptor.is_some());
+    }
+
+    #[test]
+    fn test_enumerate_macos() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors(Speed::High));
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let enumeration = host.enumerate(&mut control, HostOs::MacOs, 1);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+        assert_eq!(enumeration.strings.len(), 6);
+    }
+
+    #[test]
+    fn test_enumerate_windows_microsoft10() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(
+            0,
+            Descriptors {
+                microsoft10: Some(microsoft10::Descriptors {
+                    string_descriptor: microsoft10::StringDescriptor::new(
+                        microsoft10::VendorRequest::Microsoft,
+                    ),
+                    compat_id_feature_descriptor: microsoft10::CompatibleIdFeatureDescriptor::new(
+                        &COMPAT_ID_FUNCTIONS,
+                    ),
+                    extended_properties_feature_descriptor:
+                        microsoft10::ExtendedPropertiesFeatureDescriptor::new(),
+                }),
+                ..descriptors(Speed::High)
+            },
+        );
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let enumeration = host.enumerate(&mut control, HostOs::Windows, 3);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+        assert_eq!(
+            enumeration.microsoft10_vendor_code,
+            Some(microsoft10::VendorRequest::Microsoft)
+        );
+    }
+
+    #[test]
+    fn test_enumerate_windows_without_microsoft10() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors(Speed::High));
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let enumeration = host.enumerate(&mut control, HostOs::Windows, 3);
+
+        // the 0xee probe is stalled and enumeration carries on
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+        assert_eq!(enumeration.microsoft10_vendor_code, None);
+        assert_eq!(enumeration.strings.len(), 6);
+    }
+
+    #[test]
+    fn test_full_speed_device_qualifier_is_reported() {
+        let usb = SimulatedDriver::new(Speed::Full);
+        let mut control: Control<'_, _, 64> = Control::new(0, descriptors(Speed::Full));
+        let mut host = HostSimulator::new(&usb, 0);
+
+        host.enumerate(&mut control, HostOs::Linux, 5);
+
+        // full-speed devices should stall the request rather than return nothing
+        let violations = host.take_violations();
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            violations[0],
+            Violation::EmptyResponse(setup_packet) if setup_packet.value() == 0x0600
+        ));
+    }
+
+    #[test]
+    fn test_missing_zlp_is_reported() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut host = HostSimulator::new(&usb, 0);
+        let setup_packet = SetupPacket::new(0xc0, 0x01, 0, 0, 0xff);
+
+        host.receive_data_stage(setup_packet, &[vec![0; 64]]);
+        assert!(matches!(
+            host.take_violations()[..],
+            [Violation::MissingZlp(_)]
+        ));
+
+        host.receive_data_stage(setup_packet, &[vec![0; 64], vec![]]);
+        assert!(host.violations().is_empty());
+
+        host.receive_data_stage(setup_packet, &[vec![0; 8], vec![]]);
+        assert!(matches!(
+            host.take_violations()[..],
+            [Violation::UnexpectedPacket(_)]
+        ));
+    }
+
+    #[test]
+    fn test_enumerate_bos() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let device_descriptor = DeviceDescriptor {
+            bcdUSB: 0x0201,
+            ..acm::DEVICE_DESCRIPTOR
+        };
+
+        // devices with a bcdUSB of 0x0201 must provide a bos descriptor
+        let mut control: Control<'_, _, 64> = Control::new(
+            0,
+            Descriptors {
+                device_descriptor,
+                ..descriptors(Speed::High)
+            },
+        );
+        let mut host = HostSimulator::new(&usb, 0);
+        let enumeration = host.enumerate(&mut control, HostOs::Windows, 12);
+        assert!(enumeration.bos_descriptor.is_none());
+        assert!(matches!(
+            host.take_violations().as_slice(),
+            [Violation::UnexpectedStall(setup_packet)] if setup_packet.value() == 0x0f00
+        ));
+
+        // stalled requests are not returned to the application
+        usb.host_setup(
+            0,
+            SetupPacket::get_descriptor(DescriptorType::BinaryDeviceObjectStore, 0, 0, 5),
+        );
+        assert!(usb.dispatch(&mut control).is_empty());
+        assert!(usb.is_stalled(0, Direction::DeviceToHost));
+
+        let mut control: Control<'_, _, 64> = Control::new(
+            0,
+            Descriptors {
+                device_descriptor,
+                bos_descriptor: Some(BinaryObjectStoreDescriptor::new(
+                    BinaryObjectStoreDescriptorHeader::new(),
+                    &DEVICE_CAPABILITIES,
+                )),
+                ..descriptors(Speed::High)
+            },
+        );
+        let mut host = HostSimulator::new(&usb, 0);
+        let enumeration = host.enumerate(&mut control, HostOs::Windows, 12);
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+
+        let bos_descriptor = enumeration.bos_descriptor.unwrap();
+        assert_eq!(bos_descriptor.len(), 5 + 7 + 24);
+        assert_eq!(bos_descriptor[..5], [5, 0x0f, 36, 0, 2]);
+        assert_eq!(bos_descriptor[5..12], [7, 0x10, 0x02, 0x06, 0, 0, 0]);
+        assert_eq!(bos_descriptor[12..16], [24, 0x10, 0x05, 0]);
+        assert_eq!(bos_descriptor[16..32], [0xaa; 16]);
+        assert_eq!(bos_descriptor[32..], [0x01, 0x02, 0x03, 0x04]);
+    }
+
+    #[test]
+    fn test_enumerate_windows_microsoft20() {
+        let usb = SimulatedDriver::new(Speed::High);
+        let mut control: Control<'_, _, 64> = Control::new(
+            0,
+            Descriptors {
+                device_descriptor: DeviceDescriptor {
+                    bcdUSB: 0x0201,
+                    ..acm::DEVICE_DESCRIPTOR
+                },
+                bos_descriptor: Some(BinaryObjectStoreDescriptor::new(
+                    BinaryObjectStoreDescriptorHeader::new(),
+                    &MICROSOFT20_CAPABILITIES,
+                )),
+                microsoft20: Some(microsoft20::Descriptors {
+                    vendor_code: 0x42,
+                    descriptor_set: MICROSOFT20_DESCRIPTOR_SET.as_bytes(),
+                }),
+                ..descriptors(Speed::High)
+            },
+        );
+        let mut host = HostSimulator::new(&usb, 0);
+
+        let enumeration = host.enumerate(&mut control, HostOs::Windows, 12);
+
+        assert!(host.violations().is_empty(), "{:?}", host.violations());
+        assert_eq!(
+            enumeration.microsoft20_descriptor_set.as_deref(),
+            Some(MICROSOFT20_DESCRIPTOR_SET.as_bytes())
+        );
+        assert_eq!(enumeration.microsoft10_vendor_code, None);
+
+        // only the descriptor set index is supported
+        let setup_packet = SetupPacket::new(0xc0, 0x42, 0, 0x0008, 0);
+        assert_eq!(host.control_in(&mut control, setup_packet), Response::Stall);
+    }
+}
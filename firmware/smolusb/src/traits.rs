@@ -1,9 +1,11 @@
 use crate::device::Speed;
-use crate::setup::Direction;
+use crate::error::ErrorKind;
+use crate::setup::{Direction, SetupPacket, TestMode};
 
 use zerocopy::AsBytes;
 
 use core::slice;
+use core::task::{Context, Poll};
 
 // - UsbDriverOperations ------------------------------------------------------
 
@@ -26,8 +28,31 @@ pub trait UsbDriverOperations {
     /// Stall the given OUT endpoint number.
     fn stall_endpoint_out(&self, endpoint_number: u8);
 
+    /// Returns `true` if the device can enter the given USB 2.0 test mode.
+    ///
+    /// `SET_FEATURE` requests for unsupported test selectors are
+    /// stalled instead of being acknowledged.
+    fn supports_test_mode(&self, test_mode: TestMode) -> bool;
+    /// Put the device into the given USB 2.0 test mode.
+    ///
+    /// Called after the status stage of the `SET_FEATURE` request has
+    /// completed. Devices leave test mode only when power cycled.
+    fn set_test_mode(&self, test_mode: TestMode);
+
+    /// Set a halt condition on the target endpoint address.
+    fn set_feature_endpoint_halt(&self, endpoint_number: u8, direction: Direction);
     /// Clear a halt condition on the target endpoint address.
     fn clear_feature_endpoint_halt(&self, endpoint_number: u8, direction: Direction);
+
+    /// Drive resume signalling on the bus to wake a suspended host.
+    ///
+    /// Only permitted while the bus is suspended and the host has
+    /// enabled the `DEVICE_REMOTE_WAKEUP` feature, see
+    /// [`Control::remote_wakeup()`](crate::control::Control::remote_wakeup).
+    ///
+    /// Returns `false` if the device does not support driving resume
+    /// signalling.
+    fn remote_wakeup(&self) -> bool;
 }
 
 /// These are used to deal with the situation where we need to block
@@ -36,10 +61,8 @@ pub trait UsbDriverOperations {
 /// [`UsbEvent::SendComplete`](crate::event::UsbEvent::SendComplete)
 /// interrupt events.
 ///
-/// Not having to do this is a powerful argument for implementing
-/// async support.
-///
-/// This is not a particularly safe approach.
+/// This is not a particularly safe approach. Async applications can
+/// use [`AsyncUsbDriver`] instead.
 pub trait UnsafeUsbDriverOperations {
     /// Sets an atomic flag for the given endpoint number in order to
     /// be able to block on an event in an interrupt handler.
@@ -82,24 +105,78 @@ pub trait ReadEndpoint {
 
     /// Read a packet from the given endpoint.
     ///
-    /// Returns the number of bytes read from the endpoint.
-    fn read(&self, endpoint_number: u8, buffer: &mut [u8]) -> usize;
+    /// Returns the number of bytes read from the endpoint or
+    /// [`ErrorKind::Overflow`] with the length of the packet if it did
+    /// not fit in `buffer`. The overflowing bytes are discarded.
+    fn try_read(&self, endpoint_number: u8, buffer: &mut [u8]) -> Result<usize, ErrorKind>;
+
+    /// Read a packet from the given endpoint.
+    ///
+    /// Returns the number of bytes read from the endpoint, including
+    /// any bytes that overflowed `buffer`.
+    fn read(&self, endpoint_number: u8, buffer: &mut [u8]) -> usize {
+        self.try_read(endpoint_number, buffer)
+            .unwrap_or_else(ErrorKind::byte_count)
+    }
 }
 
 pub trait WriteEndpoint {
+    /// Write iterator to endpoint
+    ///
+    /// Returns the number of bytes written to the endpoint or
+    /// [`ErrorKind::Timeout`] if the host did not collect a packet.
+    fn try_write<I>(&self, endpoint_number: u8, iter: I) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>;
+
+    /// Write the requested number of bytes from the iterator to endpoint
+    ///
+    /// Returns the number of bytes written to the endpoint or
+    /// [`ErrorKind::Timeout`] if the host did not collect a packet.
+    fn try_write_requested<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: usize,
+        iter: I,
+    ) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>;
+
+    /// Write iterator to endpoint using the given packet size
+    ///
+    /// Returns the number of bytes written to the endpoint or
+    /// [`ErrorKind::Timeout`] if the host did not collect a packet.
+    fn try_write_with_packet_size<I>(
+        &self,
+        endpoint_number: u8,
+        requested_length: Option<usize>,
+        iter: I,
+        packet_size: usize,
+    ) -> Result<usize, ErrorKind>
+    where
+        I: Iterator<Item = u8>;
+
     /// Write iterator to endpoint
     ///
     /// Returns the number of bytes written to the endpoint.
     fn write<I>(&self, endpoint_number: u8, iter: I) -> usize
     where
-        I: Iterator<Item = u8>;
+        I: Iterator<Item = u8>,
+    {
+        self.try_write(endpoint_number, iter)
+            .unwrap_or_else(ErrorKind::byte_count)
+    }
 
     /// Write the requested number of bytes from the iterator to endpoint
     ///
     /// Returns the number of bytes written to the endpoint.
     fn write_requested<I>(&self, endpoint_number: u8, requested_length: usize, iter: I) -> usize
     where
-        I: Iterator<Item = u8>;
+        I: Iterator<Item = u8>,
+    {
+        self.try_write_requested(endpoint_number, requested_length, iter)
+            .unwrap_or_else(ErrorKind::byte_count)
+    }
 
     /// Write iterator to endpoint using the given packet size
     ///
@@ -111,8 +188,96 @@ pub trait WriteEndpoint {
         iter: I,
         packet_size: usize,
     ) -> usize
+    where
+        I: Iterator<Item = u8>,
+    {
+        self.try_write_with_packet_size(endpoint_number, requested_length, iter, packet_size)
+            .unwrap_or_else(ErrorKind::byte_count)
+    }
+}
+
+// - IsochronousEndpoint ------------------------------------------------------
+
+/// Isochronous transfer support.
+///
+/// Isochronous packets are never acknowledged or retried by the host:
+/// a packet written to an IN endpoint is sent in the next (micro)frame
+/// the host polls the endpoint and packets that are not ready in time
+/// are lost. Drivers that detect start-of-frame packets raise
+/// [`UsbEvent::StartOfFrame`](crate::event::UsbEvent::StartOfFrame)
+/// at the start of every (micro)frame so that applications can keep
+/// their endpoints fed, applications using other drivers have to pace
+/// their writes themselves.
+pub trait IsochronousEndpoint {
+    /// Configure the given endpoint for isochronous transfers of up to
+    /// `max_packet_size` bytes per (micro)frame.
+    fn configure_isochronous(
+        &self,
+        endpoint_number: u8,
+        direction: Direction,
+        max_packet_size: usize,
+    );
+
+    /// Write a single packet to the given isochronous IN endpoint.
+    ///
+    /// Does not wait for the packet to be sent and does not raise a
+    /// [`UsbEvent::SendComplete`](crate::event::UsbEvent::SendComplete).
+    ///
+    /// Returns the number of bytes written to the endpoint, which is
+    /// zero if the packet was dropped because the driver could not
+    /// queue it without discarding data for another endpoint.
+    fn write_isochronous<I>(&self, endpoint_number: u8, iter: I) -> usize
     where
         I: Iterator<Item = u8>;
+
+    /// Returns the frame number of the last start-of-frame packet.
+    ///
+    /// Returns `None` if the driver does not detect start-of-frame
+    /// packets or none has been received yet.
+    fn frame_number(&self) -> Option<u16>;
+}
+
+// - AsyncUsbDriver -----------------------------------------------------------
+
+/// Interrupt-driven endpoint operations for async applications.
+///
+/// Rather than busy-waiting for the host to acknowledge a packet,
+/// drivers register the waker of the polling task and wake it from the
+/// USB interrupt handler once the endpoint operation has completed.
+/// The async [`write`](crate::asynch::AsyncUsb::write),
+/// [`read`](crate::asynch::AsyncUsb::read) and
+/// [`wait_setup`](crate::asynch::AsyncUsb::wait_setup) operations are
+/// provided by [`AsyncUsb`](crate::asynch::AsyncUsb) on top of this
+/// trait and work with any executor.
+pub trait AsyncUsbDriver: UsbDriver {
+    /// Returns the max packet size of the given endpoint.
+    fn max_packet_size(&self, endpoint_number: u8) -> usize;
+
+    /// Write a single packet to the given IN endpoint.
+    ///
+    /// Does not wait for the packet to be sent, use
+    /// [`poll_write()`](Self::poll_write) to wait for the host to
+    /// acknowledge it. The endpoint must not have a packet in flight.
+    ///
+    /// Returns the number of bytes written to the endpoint.
+    fn write_packet<I>(&self, endpoint_number: u8, iter: I) -> usize
+    where
+        I: Iterator<Item = u8>;
+
+    /// Polls for the host to acknowledge the last packet written to the
+    /// given IN endpoint.
+    ///
+    /// Returns [`Poll::Ready`] immediately if no packet is in flight.
+    fn poll_write(&self, endpoint_number: u8, cx: &mut Context<'_>) -> Poll<()>;
+
+    /// Polls for a packet received on the given OUT endpoint.
+    ///
+    /// Once ready, the packet can be read with
+    /// [`ReadEndpoint::read()`].
+    fn poll_read(&self, endpoint_number: u8, cx: &mut Context<'_>) -> Poll<()>;
+
+    /// Polls for a setup packet received on the given control endpoint.
+    fn poll_setup(&self, endpoint_number: u8, cx: &mut Context<'_>) -> Poll<SetupPacket>;
 }
 
 // - AsIterator ---------------------------------------------------------------